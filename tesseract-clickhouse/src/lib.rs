@@ -5,12 +5,13 @@ use futures::{future, Future, Stream};
 use log::*;
 use std::time::{Duration, Instant};
 use tesseract_core::{Backend, DataFrame, QueryIr};
+use tesseract_core::query_ir::DateKey;
 
 mod df;
 mod sql;
 
 use self::df::{block_to_df};
-use self::sql::clickhouse_sql;
+use self::sql::{clickhouse_sql, date_key_sql};
 
 // Ping timeout in millis
 const PING_TIMEOUT: u64 = 100_000;
@@ -84,5 +85,9 @@ impl Backend for Clickhouse {
             &query_ir
         )
     }
+
+    fn date_key_sql(&self, date_key: &DateKey) -> String {
+        date_key_sql(date_key)
+    }
 }
 
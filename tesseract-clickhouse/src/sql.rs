@@ -1,10 +1,12 @@
 mod aggregator;
 mod cuts;
+mod date_key;
 mod growth;
 mod options;
 mod primary_agg;
 mod rate;
 mod rca;
+mod window;
 
 use tesseract_core::query_ir::{
     TableSql,
@@ -14,11 +16,19 @@ use tesseract_core::query_ir::{
     HiddenDrilldownSql,
     RcaSql,
     GrowthSql,
+    CumulativeSql,
+    ResetSql,
+    MovingAvgSql,
+    IndexSql,
+    RankSql,
+    TrendSql,
     RateSql,
+    BinsSql,
     dim_subquery,
 };
 use tesseract_core::{QueryIr};
 
+pub use self::date_key::date_key_sql;
 use self::options::wrap_options;
 use self::primary_agg::primary_agg;
 use self::rate::rate_calculation;
@@ -47,13 +57,51 @@ pub fn clickhouse_sql(
         } else if let Some(rate) = rate {
             rate_calculation(table, cuts, drills, meas, rate)
         } else {
-            primary_agg(table, cuts, drills, meas, Some(&query_ir.hidden_drills))
+            primary_agg(table, cuts, drills, meas, Some(&query_ir.hidden_drills), &query_ir.totals, query_ir.bins.as_ref())
         }
     };
+    // columns after the drills, tracked so that later calculations can
+    // pack and unpack them. Rate output is not tracked; combining rate with
+    // calculations below growth is disallowed before this point.
+    let mut value_cols = vec![];
+    if let Some(rca) = &query_ir.rca {
+        if rca.debug {
+            value_cols.extend(["a", "b", "c", "d"].iter().map(|c| c.to_string()));
+        }
+        value_cols.push("rca".to_owned());
+        value_cols.extend((1..meas.len()+1).map(|i| format!("final_m{}", i)));
+    } else {
+        value_cols.extend((0..meas.len()).map(|i| format!("final_m{}", i)));
+    }
+
     if let Some(growth) = &query_ir.growth {
-        let (sql, drill_cols) = growth::calculate(final_sql, &final_drill_cols, meas.len(), growth);
+        let (sql, drill_cols) = growth::calculate(final_sql, &final_drill_cols, &value_cols, growth);
         final_sql = sql;
         final_drill_cols = drill_cols;
+        value_cols = growth::value_cols(&value_cols, growth);
+    }
+
+    // window calculations keep the column names from the previous step,
+    // appending the calculated column.
+    if let Some(cumulative) = &query_ir.cumulative {
+        final_sql = window::cumulative(final_sql, &final_drill_cols, &value_cols, cumulative);
+        value_cols.push("cumulative".to_owned());
+    }
+    if let Some(moving_avg) = &query_ir.moving_avg {
+        final_sql = window::moving_avg(final_sql, &final_drill_cols, &value_cols, moving_avg);
+        value_cols.push("moving_avg".to_owned());
+    }
+    if let Some(index) = &query_ir.index {
+        final_sql = window::index(final_sql, &final_drill_cols, &value_cols, index);
+        value_cols.push("indexed".to_owned());
+    }
+    // rank comes before top, so that it's not limited to the top members
+    if let Some(rank) = &query_ir.rank {
+        final_sql = window::rank(final_sql, &final_drill_cols, &value_cols, rank);
+        value_cols.push("ranked".to_owned());
+    }
+    if let Some(trend) = &query_ir.trend {
+        final_sql = window::trend(final_sql, &final_drill_cols, &value_cols, trend);
     }
 
     final_sql = wrap_options(final_sql, &final_drill_cols, &query_ir, meas.len());
@@ -85,6 +133,8 @@ mod test {
                 member_type: MemberType::Text,
                 mask: Mask::Include,
                 for_match: false,
+                range: None,
+                date_key: None,
             },
             CutSql {
                 foreign_key: "".into(),
@@ -96,6 +146,8 @@ mod test {
                 member_type: MemberType::NonText,
                 mask: Mask::Include,
                 for_match: false,
+                range: None,
+                date_key: None,
             },
         ];
 
@@ -123,10 +175,12 @@ mod test {
                 LevelColumn {
                     key_column: "product_group_id".into(),
                     name_column: Some("product_group_label".into()),
+                    date_key: None,
                 },
                 LevelColumn {
                     key_column: "product_id_raw".into(),
                     name_column: Some("product_label".into()),
+                    date_key: None,
                 },
             ],
             property_columns: vec!["hexcode".to_owned(), "form".to_owned()],
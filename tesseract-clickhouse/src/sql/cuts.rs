@@ -1,10 +1,56 @@
 use super::CutSql;
+use super::date_key::date_key_sql;
 
 pub fn cut_sql_string(cut: &CutSql) -> String {
-    if cut.for_match {
+    if let Some(ref range) = cut.range {
+        cut.range_string(&cut.col_string(date_key_sql), range)
+    } else if cut.for_match {
         format!("{}", cut.members_like_string())
     } else {
         // col not in ('', '',...)
-        format!("{} {} ({})", cut.column, cut.mask_sql_in_string(), cut.members_string())
+        format!("{} {} ({})", cut.col_string(date_key_sql), cut.mask_sql_in_string(), cut.members_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::Table;
+    use tesseract_core::names::{Mask, MemberRange};
+    use tesseract_core::query_ir::MemberType;
+
+    fn range_cut(member_type: MemberType, mask: Mask, start: Option<&str>, end: Option<&str>) -> CutSql {
+        CutSql {
+            foreign_key: "".into(),
+            primary_key: "".into(),
+            inline_table: None,
+            table: Table { name: "".into(), schema: None, primary_key: None },
+            column: "year".into(),
+            members: vec![],
+            member_type,
+            mask,
+            for_match: false,
+            range: Some(MemberRange {
+                start: start.map(|s| s.to_owned()),
+                end: end.map(|s| s.to_owned()),
+            }),
+            date_key: None,
+        }
+    }
+
+    #[test]
+    fn cut_range() {
+        assert_eq!(
+            cut_sql_string(&range_cut(MemberType::NonText, Mask::Include, Some("2010"), Some("2015"))),
+            "year between 2010 and 2015",
+        );
+        assert_eq!(
+            cut_sql_string(&range_cut(MemberType::Text, Mask::Include, Some("2015"), None)),
+            "year >= '2015'",
+        );
+        assert_eq!(
+            cut_sql_string(&range_cut(MemberType::NonText, Mask::Exclude, None, Some("2015"))),
+            "not (year <= 2015)",
+        );
     }
 }
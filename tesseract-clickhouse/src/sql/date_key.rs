@@ -0,0 +1,40 @@
+use tesseract_core::query_ir::DateKey;
+use tesseract_core::schema::TimeGranularity;
+
+/// Key of a level derived from a date column, in clickhouse's date functions.
+pub fn date_key_sql(date_key: &DateKey) -> String {
+    let date = &date_key.column;
+    let fiscal_date = format!("addMonths({}, {})", date, date_key.fiscal_offset);
+
+    match date_key.granularity {
+        TimeGranularity::Year => format!("toYear({})", date),
+        TimeGranularity::Quarter => format!("(toYear({date}) * 10 + toQuarter({date}))", date = date),
+        TimeGranularity::Month => format!("toYYYYMM({})", date),
+        TimeGranularity::Day => format!("toYYYYMMDD({})", date),
+        TimeGranularity::FiscalYear => format!("toYear({})", fiscal_date),
+        TimeGranularity::FiscalQuarter => format!("(toYear({date}) * 10 + toQuarter({date}))", date = fiscal_date),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date_key(granularity: TimeGranularity) -> DateKey {
+        DateKey {
+            column: "spent_on".into(),
+            granularity,
+            fiscal_offset: 6,
+        }
+    }
+
+    #[test]
+    fn date_keys() {
+        assert_eq!(date_key_sql(&date_key(TimeGranularity::Year)), "toYear(spent_on)");
+        assert_eq!(date_key_sql(&date_key(TimeGranularity::Month)), "toYYYYMM(spent_on)");
+        assert_eq!(
+            date_key_sql(&date_key(TimeGranularity::FiscalQuarter)),
+            "(toYear(addMonths(spent_on, 6)) * 10 + toQuarter(addMonths(spent_on, 6)))",
+        );
+    }
+}
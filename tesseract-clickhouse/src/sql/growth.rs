@@ -6,155 +6,288 @@
 //! Near the bottom of this issue, there is a link to how array Join is used
 //! to bring the groupArray back to full rows.
 //! https://github.com/yandex/ClickHouse/issues/1469
+//!
+//! Measures are packed as tuples, because groupArray skips nulls and
+//! would otherwise misalign the measure arrays with the time arrays.
 
 use itertools::join;
 
+use tesseract_core::query::GrowthMode;
+use tesseract_core::schema::TimeGranularity;
+
 use super::GrowthSql;
 
+/// Output columns are the drills (time cols moved to back), the value cols
+/// that aren't growth measures, then for each growth measure: the measure,
+/// `growth`, `growth_value`. Growth cols after the first are postfixed with
+/// their position: `growth_1`.
+///
+/// The earlier period is found by its key (key period number minus lag), so
+/// a missing period gives null growth instead of comparing the wrong periods.
+/// CAGR is annualized using the years between the first period and each period.
 pub fn calculate(
     final_sql: String,
     final_drill_cols: &str,
-    num_measures: usize,
+    value_cols: &[String],
     growth: &GrowthSql,
     ) -> (String, String)
 {
-    // A whole section to string manipulate to remove references to growth cols
-    let mut all_drill_cols_except_growth = final_drill_cols.to_owned();
+    // time drill may include parent levels and properties, all get packed
+    let time_cols: Vec<String> = growth.time_drill.col_alias_only_vec().iter()
+        .flat_map(|cols| cols.split(", ").map(|c| c.trim().to_owned()).collect::<Vec<_>>())
+        .collect();
+
+    // key of the drilled level, which comes after its parents
+    let time_key = growth.time_drill.level_columns.last()
+        .map(|l| format!("{}_{}", l.key_column, growth.time_drill.alias_postfix))
+        .unwrap_or_else(|| time_cols[0].clone());
 
-    let time_cols = growth.time_drill.col_alias_only_vec();
+    // Group by everything besides the time cols
+    let other_drill_cols: Vec<String> = final_drill_cols.split(", ")
+        .map(|c| c.trim().to_owned())
+        .filter(|c| !c.is_empty() && !time_cols.contains(c))
+        .collect();
+
+    // inner select: pack
+    let mut packed = vec![];
+    for col in &time_cols {
+        packed.push(format!("groupArray({}) as {}_s", col, col));
+    }
+    for col in value_cols {
+        packed.push(format!("arrayMap(x -> tupleElement(x, 1), groupArray(tuple({}))) as {}_s", col, col));
+    }
 
+    let granularity = growth.granularity.as_ref();
+    packed.push(format!("arrayMap(k -> {}, {}_s) as growth_period_s", period_number("k", granularity), time_key));
 
-    let mut growth_cols = time_cols.clone();
-    growth_cols.push(growth.mea.clone());
+    // ids come from the first time col, which is never null
+    let ids = format!("arrayEnumerate({}_s)", time_cols[0]);
 
-    // slow for now, but it's a small string
-    for col in growth_cols {
-        all_drill_cols_except_growth = all_drill_cols_except_growth.replace(&col, "").replace(", ,", ",").to_owned();
+    if growth.mode == GrowthMode::PeriodOverPeriod {
+        // position of the period `lag` back, 0 if it's missing
+        packed.push(format!("arrayMap(p -> indexOf(growth_period_s, p - {}), growth_period_s) as growth_prev_s", growth.lag));
     }
 
-    all_drill_cols_except_growth = all_drill_cols_except_growth.trim().trim_matches(',').to_owned();
+    for (k, mea) in growth.meas.iter().enumerate() {
+        let (growth_expr, value_expr) = match growth.mode {
+            GrowthMode::PeriodOverPeriod => {
+                (
+                    format!("arrayMap((i, j) -> (j > 0 and {m}_s[j] != 0) ? ({m}_s[i] - {m}_s[j]) / {m}_s[j] : NULL, {ids}, growth_prev_s)",
+                        m = mea, ids = ids),
+                    format!("arrayMap((i, j) -> j > 0 ? {m}_s[i] - {m}_s[j] : NULL, {ids}, growth_prev_s)",
+                        m = mea, ids = ids),
+                )
+            },
+            GrowthMode::Cagr => {
+                (
+                    format!("arrayMap(i -> (i > 1 and {m}_s[1] > 0 and {m}_s[i] >= 0) ? pow({m}_s[i] / {m}_s[1], {per_year} / (growth_period_s[i] - growth_period_s[1])) - 1 : NULL, {ids})",
+                        m = mea, per_year = periods_per_year(granularity), ids = ids),
+                    format!("arrayMap(i -> i > 1 ? {m}_s[i] - {m}_s[1] : NULL, {ids})",
+                        m = mea, ids = ids),
+                )
+            },
+        };
 
-    // Group by everything besides the time cols
-    // The time columns need to each be packed and unpacked individually; handles cases when
-    // there's a time col that has parents.
-    // TODO this does not handle a time column that has properties!!!
+        packed.push(format!("{} as {}_s", growth_expr, growth_col("growth", k)));
+        packed.push(format!("{} as {}_s", value_expr, growth_col("growth_value", k)));
+    }
 
-    let time_drill_len = time_cols.len();
+    // outer select: unpack
+    let mut unpacked = vec![];
+    for col in time_cols.iter().chain(value_cols.iter()) {
+        unpacked.push(format!("{}_s as {}", col, col));
+    }
+    for k in 0..growth.meas.len() {
+        unpacked.push(format!("{}_s as {}", growth_col("growth", k), growth_col("growth", k)));
+        unpacked.push(format!("{}_s as {}", growth_col("growth_value", k), growth_col("growth_value", k)));
+    }
 
-    let final_times = join((0..time_drill_len).map(|i| format!("final_times_{}", i)), ", ");
-    let times = join((0..time_drill_len).map(|i| format!("times_{}", i)), ", ");
-    let times_as_final_times = join((0..time_drill_len).map(|i| format!("times_{} as final_times_{}", i, i)), ", ");
+    let mut final_cols: Vec<String> = other_drill_cols.clone();
+    final_cols.extend(time_cols.iter().cloned());
+    final_cols.extend(self::value_cols(value_cols, growth));
 
-    let grouparray_times = join(
-        time_cols.iter().enumerate()
-            .map(|(i, col)| format!("groupArray({}) as times_{}", col, i))
-            , ", "
-    );
+    let other_drill_cols = join(&other_drill_cols, ", ");
 
-    // TODO fix hack for parsing growth mea idx, probably by passing in a usize and then
-    // constructing the name later
-    let growth_mea_idx = growth.mea.chars()
-        .last()
-        .expect("must be a last char for growth.mea")
-        .to_digit(10)
-        .expect("last char of growth.mea must be integer");
-
-    let final_other_meas = (0..num_measures)
-        .filter(|i| {
-            *i != growth_mea_idx as usize
-        }).map(|i| format!("final_other_m{}", i));
-    let mut final_other_meas = join(final_other_meas, ", ");
-    if final_other_meas != "" {
-        final_other_meas = format!("{}, ", final_other_meas);
-    }
-
-    let other_meas = (0..num_measures)
-        .filter(|i| {
-            *i != growth_mea_idx as usize
-        }).map(|i| format!("other_m{}", i));
-    let mut other_meas = join(other_meas, ", ");
-    if other_meas != "" {
-        other_meas = format!("{}, ", other_meas);
-    }
-
-    let grouparray_other_meas = (0..num_measures)
-        .filter(|i| {
-            *i != growth_mea_idx as usize
-        }).map(|i| format!("groupArray(final_m{}) as other_m{}", i, i));
-    let mut grouparray_other_meas = join(grouparray_other_meas, ", ");
-    if grouparray_other_meas != "" {
-        grouparray_other_meas = format!("{}, ", grouparray_other_meas);
-    }
-
-    let other_meas_as_final_other_meas = (0..num_measures)
-        .filter(|i| {
-            *i != growth_mea_idx as usize
-        }).map(|i| format!("other_m{} as final_other_m{}", i, i));
-    let mut other_meas_as_final_other_meas = join(other_meas_as_final_other_meas, ", ");
-    if other_meas_as_final_other_meas != "" {
-        other_meas_as_final_other_meas = format!(",{}", other_meas_as_final_other_meas);
-    }
-
-    let final_sql = format!("\
-        select \
-            {all_drill_cols_except_growth}{comma_for_all_drill_cols_except_growth} \
-            {final_times}, \
-            {final_other_meas} \
-            final_m, \
-            (final_m - final_m_diff) > 0?(final_m_diff / (final_m - final_m_diff)):(final_m_diff / 0) as growth, \
-            final_m_diff \
-        from (\
-            with \
-                {grouparray_times}, \
-                {grouparray_other_meas} \
-                groupArray({growth_mea}) as all_m_in_group, \
-                arrayEnumerate(all_m_in_group) as all_m_in_group_ids, \
-                arrayMap( i -> i > 1 ? all_m_in_group[i] - all_m_in_group[i-1]: NULL, all_m_in_group_ids) as m_diff \
-            select \
-                {all_drill_cols_except_growth}{comma_for_all_drill_cols_except_growth} \
-                {other_meas} \
-                {times}, \
-                all_m_in_group, \
-                m_diff \
-            from ({fnl_sql} \
-                order by \
-                    {growth_time_drill_alias} \
-            ) \
-            {group_by_for_all_drill_cols_except_growth} \
-                {all_drill_cols_except_growth} \
+    let final_sql = format!("select {final_cols} from (\
+            select {other_drill_cols}{other_comma}{packed} \
+            from ({final_sql} order by {time_cols}) \
+            {group_by}{other_drill_cols}\
         ) \
-        array Join \
-            m_diff as final_m_diff, \
-            all_m_in_group as final_m, \
-            {times_as_final_times} \
-            {other_meas_as_final_other_meas}",
-        all_drill_cols_except_growth = all_drill_cols_except_growth,
-        comma_for_all_drill_cols_except_growth = if all_drill_cols_except_growth.is_empty() {""} else {","},
-        group_by_for_all_drill_cols_except_growth = if all_drill_cols_except_growth.is_empty() {""} else {"group by"},
-        growth_mea = growth.mea,
-        fnl_sql = final_sql,
-        growth_time_drill_alias = growth.time_drill.col_alias_only_string(),
-        final_times = final_times,
-        grouparray_times = grouparray_times,
-        times = times,
-        times_as_final_times = times_as_final_times,
-        other_meas = other_meas,
-        final_other_meas = final_other_meas,
-        grouparray_other_meas = grouparray_other_meas,
-        other_meas_as_final_other_meas = other_meas_as_final_other_meas,
+        array Join {unpacked}",
+        final_cols = join(&final_cols, ", "),
+        other_drill_cols = other_drill_cols,
+        other_comma = if other_drill_cols.is_empty() { "" } else { ", " },
+        packed = join(&packed, ", "),
+        final_sql = final_sql,
+        time_cols = join(&time_cols, ", "),
+        group_by = if other_drill_cols.is_empty() { "" } else { "group by " },
+        unpacked = join(&unpacked, ", "),
     );
 
-    // Externally, remember to switch out order of time cols. Internally, don't care, number
-    // is the same
-    let final_drill_cols = format!("{}{} {}, {} final_m, growth",
-        all_drill_cols_except_growth,
-        if all_drill_cols_except_growth.is_empty() {""} else {","},
-        final_times,
-        final_other_meas,
+    // Externally, remember to switch out order of time cols.
+    let final_drill_cols = format!("{}{}{}",
+        other_drill_cols,
+        if other_drill_cols.is_empty() { "" } else { ", " },
+        join(&time_cols, ", "),
     );
 
     (final_sql, final_drill_cols)
 }
 
-// example growth sql
-//            "select  language, framework, ex_complete, final_times_0, final_other_m0,  final_m, (final_m_diff / (final_m - final_m_diff)) as growth, final_m_diff from (with groupArray(date) as times_0, groupArray(final_m0) as other_m0,  groupArray(mea_1) as all_m_in_group, arrayEnumerate(all_m_in_group) as all_m_in_group_ids, arrayMap( i -> i > 1 ? all_m_in_group[i] - all_m_in_group[i-1]: 0, all_m_in_group_ids) as m_diff select  language, framework, ex_complete, other_m0,  times_0, all_m_in_group, m_diff from (select * from test order by date ) group by  language, framework, ex_complete ) array Join m_diff as final_m_diff, all_m_in_group as final_m, times_0 as final_times_0 ,other_m0 as final_other_m0".to_owned(),
+/// Consecutive periods get consecutive numbers, e.g. `201912` and `202001`
+/// are months 24240 and 24241.
+fn period_number(key: &str, granularity: Option<&TimeGranularity>) -> String {
+    match granularity {
+        Some(TimeGranularity::Quarter) | Some(TimeGranularity::FiscalQuarter) => {
+            format!("intDiv({k}, 10) * 4 + {k} % 10", k = key)
+        },
+        Some(TimeGranularity::Month) => {
+            format!("intDiv({k}, 100) * 12 + {k} % 100", k = key)
+        },
+        Some(TimeGranularity::Day) => {
+            format!("toRelativeDayNum(toDate(parseDateTimeBestEffort(toString({}))))", key)
+        },
+        Some(TimeGranularity::Year) | Some(TimeGranularity::FiscalYear) | None => key.to_owned(),
+    }
+}
+
+fn periods_per_year(granularity: Option<&TimeGranularity>) -> &'static str {
+    match granularity {
+        Some(TimeGranularity::Quarter) | Some(TimeGranularity::FiscalQuarter) => "4",
+        Some(TimeGranularity::Month) => "12",
+        Some(TimeGranularity::Day) => "365.25",
+        Some(TimeGranularity::Year) | Some(TimeGranularity::FiscalYear) | None => "1",
+    }
+}
+
+/// Columns after the drills in the growth output, from the value cols
+/// before growth.
+pub fn value_cols(value_cols: &[String], growth: &GrowthSql) -> Vec<String> {
+    let mut cols: Vec<String> = value_cols.iter()
+        .filter(|m| !growth.meas.contains(m))
+        .cloned()
+        .collect();
+
+    for (k, mea) in growth.meas.iter().enumerate() {
+        cols.push(mea.clone());
+        cols.push(growth_col("growth", k));
+        cols.push(growth_col("growth_value", k));
+    }
+
+    cols
+}
+
+fn growth_col(name: &str, k: usize) -> String {
+    if k == 0 {
+        name.to_owned()
+    } else {
+        format!("{}_{}", name, k)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::Table;
+    use tesseract_core::query_ir::{DrilldownSql, LevelColumn};
+
+    fn growth_sql(meas: Vec<&str>, lag: u64, mode: GrowthMode, granularity: Option<TimeGranularity>) -> GrowthSql {
+        GrowthSql {
+            time_drill: DrilldownSql {
+                alias_postfix: "Month".into(),
+                foreign_key: "month".into(),
+                primary_key: "month".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "month".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            meas: meas.into_iter().map(|m| m.to_owned()).collect(),
+            lag,
+            mode,
+            granularity,
+        }
+    }
+
+    fn final_meas(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("final_m{}", i)).collect()
+    }
+
+    #[test]
+    fn growth_single_measure() {
+        let growth = growth_sql(vec!["final_m0"], 1, GrowthMode::PeriodOverPeriod, None);
+        let (sql, drill_cols) = calculate("select 1".into(), "month_Month, product_id_Product", &final_meas(1), &growth);
+
+        assert_eq!(drill_cols, "product_id_Product, month_Month");
+        assert_eq!(
+            sql,
+            "select product_id_Product, month_Month, final_m0, growth, growth_value from (\
+            select product_id_Product, \
+            groupArray(month_Month) as month_Month_s, \
+            arrayMap(x -> tupleElement(x, 1), groupArray(tuple(final_m0))) as final_m0_s, \
+            arrayMap(k -> k, month_Month_s) as growth_period_s, \
+            arrayMap(p -> indexOf(growth_period_s, p - 1), growth_period_s) as growth_prev_s, \
+            arrayMap((i, j) -> (j > 0 and final_m0_s[j] != 0) ? (final_m0_s[i] - final_m0_s[j]) / final_m0_s[j] : NULL, arrayEnumerate(month_Month_s), growth_prev_s) as growth_s, \
+            arrayMap((i, j) -> j > 0 ? final_m0_s[i] - final_m0_s[j] : NULL, arrayEnumerate(month_Month_s), growth_prev_s) as growth_value_s \
+            from (select 1 order by month_Month) \
+            group by product_id_Product\
+            ) \
+            array Join month_Month_s as month_Month, final_m0_s as final_m0, growth_s as growth, growth_value_s as growth_value"
+        );
+    }
+
+    #[test]
+    fn growth_lag_multiple_measures() {
+        let growth = growth_sql(vec!["final_m2", "final_m0"], 12, GrowthMode::PeriodOverPeriod, Some(TimeGranularity::Month));
+        let (sql, drill_cols) = calculate("select 1".into(), "month_Month", &final_meas(3), &growth);
+
+        assert_eq!(drill_cols, "month_Month");
+        // no other drills, so no group by
+        assert!(sql.starts_with("select month_Month, final_m1, final_m2, growth, growth_value, final_m0, growth_1, growth_value_1 from (\
+            select groupArray(month_Month) as month_Month_s, "));
+        assert!(!sql.contains("group by"));
+        // the period 12 months back is looked up by key, e.g. 201903 -> 201803
+        assert!(sql.contains("arrayMap(k -> intDiv(k, 100) * 12 + k % 100, month_Month_s) as growth_period_s"));
+        assert!(sql.contains("arrayMap(p -> indexOf(growth_period_s, p - 12), growth_period_s) as growth_prev_s"));
+        assert!(sql.contains("arrayMap((i, j) -> j > 0 ? final_m2_s[i] - final_m2_s[j] : NULL, arrayEnumerate(month_Month_s), growth_prev_s) as growth_value_s"));
+        assert!(sql.contains("arrayMap((i, j) -> j > 0 ? final_m0_s[i] - final_m0_s[j] : NULL, arrayEnumerate(month_Month_s), growth_prev_s) as growth_value_1_s"));
+        // a null measure in a period keeps its position
+        assert!(sql.contains("groupArray(tuple(final_m1))"));
+    }
+
+    #[test]
+    fn growth_after_rca() {
+        let growth = growth_sql(vec!["final_m1"], 1, GrowthMode::PeriodOverPeriod, None);
+        let value_cols = vec!["rca".to_owned(), "final_m1".to_owned()];
+        let (sql, _) = calculate("select 1".into(), "month_Month, product_id_Product", &value_cols, &growth);
+
+        assert!(sql.starts_with("select product_id_Product, month_Month, rca, final_m1, growth, growth_value from ("));
+        assert!(sql.contains("arrayMap(x -> tupleElement(x, 1), groupArray(tuple(rca))) as rca_s"));
+        assert_eq!(super::value_cols(&value_cols, &growth), vec!["rca", "final_m1", "growth", "growth_value"]);
+    }
+
+    #[test]
+    fn growth_period_numbers() {
+        // missing periods leave a gap in the numbers, so they're not matched
+        assert_eq!(period_number("k", None), "k");
+        assert_eq!(period_number("k", Some(&TimeGranularity::Quarter)), "intDiv(k, 10) * 4 + k % 10");
+        assert_eq!(period_number("k", Some(&TimeGranularity::Day)), "toRelativeDayNum(toDate(parseDateTimeBestEffort(toString(k))))");
+    }
+
+    #[test]
+    fn growth_cagr() {
+        let growth = growth_sql(vec!["final_m0"], 1, GrowthMode::Cagr, Some(TimeGranularity::Month));
+        let (sql, _) = calculate("select 1".into(), "month_Month", &final_meas(1), &growth);
+
+        // annualized over the years between periods, which may have gaps
+        assert!(sql.contains("arrayMap(i -> (i > 1 and final_m0_s[1] > 0 and final_m0_s[i] >= 0) ? pow(final_m0_s[i] / final_m0_s[1], 12 / (growth_period_s[i] - growth_period_s[1])) - 1 : NULL, arrayEnumerate(month_Month_s)) as growth_s"));
+        assert!(sql.contains("arrayMap(i -> i > 1 ? final_m0_s[i] - final_m0_s[1] : NULL, arrayEnumerate(month_Month_s)) as growth_value_s"));
+        assert!(!sql.contains("growth_prev_s"));
+    }
+}
@@ -1,5 +1,6 @@
 use itertools::join;
 use tesseract_core::{QueryIr};
+use tesseract_core::query_ir::{GroupSql, TopSql, filter_clauses};
 
 
 pub fn wrap_options(
@@ -14,11 +15,16 @@ pub fn wrap_options(
     let top_where = &query_ir.top_where;
     let sort = &query_ir.sort;
     let limit = &query_ir.limit;
-    let filters = &query_ir.filters;
+    let filter_clauses = filter_clauses(&query_ir.filters, query_ir.filter.as_ref());
+    // Member groups come first, so that top, filter and sort apply to groups
+    if let Some(ref group) = query_ir.group {
+        final_sql = group_members(final_sql, group, query_ir);
+    }
+
     // Now that final groupings are done, do wrapping options
     // like top, filter, sort
     if let Some(top) = top {
-        final_sql = format!("select * from ({}) {} order by {} {} limit {} by {}",
+        let top_sql = format!("select * from ({}) {} order by {} {} limit {} by {}",
             final_sql,
             if let Some(tw) = top_where { format!("where {} {}", tw.by_column, tw.constraint.sql_string()) } else { "".into() },
             join(&top.sort_columns, ", "),
@@ -26,6 +32,12 @@ pub fn wrap_options(
             top.n,
             top.by_column,
         );
+
+        final_sql = if top.others {
+            top_others(&final_sql, top_sql, top, query_ir)
+        } else {
+            top_sql
+        };
     }
 
     // There's a final wrapper clause no matter what.
@@ -66,17 +78,8 @@ pub fn wrap_options(
         }
     };
 
-    let mut filters_sql = if !filters.is_empty() {
-        let filter_clauses = filters.iter()
-            .map(|filter| {
-                if let Some(operator) = &filter.operator {
-                    let constraint2 = filter.constraint2.as_ref().unwrap();
-                    format!("({} {} {} {} {})", filter.by_column, filter.constraint.sql_string(), operator.sql_string(), filter.by_column, constraint2.sql_string())
-                } else {
-                    format!("{} {}", filter.by_column, filter.constraint.sql_string())
-                }
-            });
-        format!("where {}", join(filter_clauses, " and "))
+    let mut filters_sql = if !filter_clauses.is_empty() {
+        format!("where {}", join(&filter_clauses, " and "))
     }
     else {
         "".into()
@@ -86,9 +89,9 @@ pub fn wrap_options(
     {
         let sparse_clauses = (0..num_measures).into_iter().map(|i| format!("isNotNull(final_m{})", i));
         let sparse_filter_sql = join(sparse_clauses, " and ");
-        if filters.is_empty() && query_ir.sparse {
+        if filter_clauses.is_empty() && query_ir.sparse {
             filters_sql = format!("where {}", sparse_filter_sql);
-        } else if !filters.is_empty() && query_ir.sparse {
+        } else if !filter_clauses.is_empty() && query_ir.sparse {
             filters_sql = format!("{} and {}", filters_sql, sparse_filter_sql);
         }
     }
@@ -103,3 +106,302 @@ pub fn wrap_options(
 
     final_sql
 }
+
+/// Collapses the members outside of the top into an "Other" member for
+/// each group. Key columns of the top drill become null, name columns
+/// become 'Other', and measures are re-aggregated.
+///
+/// Checks for calculations and measure aggregators are done before this point.
+fn top_others(
+    final_sql: &str,
+    top_sql: String,
+    top: &TopSql,
+    query_ir: &QueryIr,
+    ) -> String
+{
+    let top_drill = query_ir.drills.iter()
+        .find(|d| d.col_alias_only_vec().contains(&top.by_column));
+
+    let top_drill = match top_drill {
+        Some(d) => d,
+        None => return top_sql,
+    };
+
+    let mut group_cols = vec![];
+    let mut inner_cols = vec![];
+    let mut outer_cols = vec![];
+
+    for drill in &query_ir.drills {
+        if drill == top_drill {
+            for l in &drill.level_columns {
+                let key_col = format!("{}_{}", l.key_column, drill.alias_postfix);
+                inner_cols.push(format!("if(is_top, {}, NULL) as {}_other", key_col, key_col));
+                outer_cols.push(format!("{}_other as {}", key_col, key_col));
+
+                if let Some(ref name_col) = l.name_column {
+                    let name_col = format!("{}_{}", name_col, drill.alias_postfix);
+                    inner_cols.push(format!("if(is_top, {}, 'Other') as {}_other", name_col, name_col));
+                    outer_cols.push(format!("{}_other as {}", name_col, name_col));
+                }
+            }
+            for p in &drill.property_columns {
+                inner_cols.push(format!("if(is_top, {}, NULL) as {}_other", p, p));
+                outer_cols.push(format!("{}_other as {}", p, p));
+            }
+        } else {
+            for col in drill.col_alias_only_vec() {
+                // properties are joined into one string
+                for col in col.split(", ") {
+                    inner_cols.push(col.to_owned());
+                    outer_cols.push(col.to_owned());
+                    group_cols.push(col.to_owned());
+                }
+            }
+        }
+    }
+
+    let mut is_top_cols = group_cols.clone();
+    is_top_cols.push(top.by_column.clone());
+    let is_top_cols = join(&is_top_cols, ", ");
+
+    let mut group_by_cols = group_cols;
+    group_by_cols.extend(
+        top_drill.col_alias_only_vec().iter()
+            .flat_map(|c| c.split(", ").map(|c| format!("{}_other", c)).collect::<Vec<_>>())
+    );
+
+    for (i, mea) in query_ir.meas.iter().enumerate() {
+        inner_cols.push(format!("final_m{} as other_m{}", i, i));
+        outer_cols.push(format!("{}(other_m{}) as final_m{}", mea.reaggregate_fn().unwrap_or("any"), i, i));
+    }
+
+    format!("select {} from (\
+            select {} from (\
+                select *, ({}) in (select {} from ({})) as is_top from ({})\
+            )\
+        ) group by {}",
+        join(&outer_cols, ", "),
+        join(&inner_cols, ", "),
+        is_top_cols,
+        is_top_cols,
+        top_sql,
+        final_sql,
+        join(&group_by_cols, ", "),
+    )
+}
+
+/// Aggregates the members of the grouped drill into their groups. Key and
+/// name columns become the group name, other columns of the drill are null
+/// for grouped rows, and measures are re-aggregated.
+///
+/// Checks for calculations and measure aggregators are done before this point.
+fn group_members(
+    final_sql: String,
+    group: &GroupSql,
+    query_ir: &QueryIr,
+    ) -> String
+{
+    let group_drill = query_ir.drills.iter()
+        .find(|d| d.col_alias_only_vec().contains(&group.by_column));
+
+    let group_drill = match group_drill {
+        Some(d) => d,
+        None => return final_sql,
+    };
+
+    let grouped = group.grouped_string();
+    let cases = join(group.groups.iter()
+        .map(|g| format!("{}, {}", group.in_string(&g.members), GroupSql::name_string(g))),
+        ", ");
+
+    let mut group_cols = vec![];
+    let mut inner_cols = vec![];
+    let mut outer_cols = vec![];
+
+    for drill in &query_ir.drills {
+        if drill == group_drill {
+            let mut push_col = |col: &str, expr: String| {
+                inner_cols.push(format!("{} as {}_group", expr, col));
+                outer_cols.push(format!("{}_group as {}", col, col));
+                group_cols.push(format!("{}_group", col));
+            };
+            let null_if_grouped = |col: &str| format!("if({}, NULL, {})", grouped, col);
+
+            for l in &drill.level_columns {
+                let key_col = format!("{}_{}", l.key_column, drill.alias_postfix);
+                let name_col = l.name_column.as_ref().map(|n| format!("{}_{}", n, drill.alias_postfix));
+
+                if key_col == group.by_column {
+                    push_col(&key_col, format!("multiIf({}, toString({}))", cases, key_col));
+                    if let Some(name_col) = name_col {
+                        push_col(&name_col, format!("multiIf({}, {})", cases, name_col));
+                    }
+                } else {
+                    push_col(&key_col, null_if_grouped(&key_col));
+                    if let Some(name_col) = name_col {
+                        push_col(&name_col, null_if_grouped(&name_col));
+                    }
+                }
+            }
+            for p in &drill.property_columns {
+                push_col(p, null_if_grouped(p));
+            }
+        } else {
+            for col in drill.col_alias_only_vec() {
+                // properties are joined into one string
+                for col in col.split(", ") {
+                    inner_cols.push(col.to_owned());
+                    outer_cols.push(col.to_owned());
+                    group_cols.push(col.to_owned());
+                }
+            }
+        }
+    }
+
+    for (i, mea) in query_ir.meas.iter().enumerate() {
+        inner_cols.push(format!("final_m{} as group_m{}", i, i));
+        outer_cols.push(format!("{}(group_m{}) as final_m{}", mea.reaggregate_fn().unwrap_or("any"), i, i));
+    }
+
+    format!("select {} from (select {} from ({}){}) group by {}",
+        join(&outer_cols, ", "),
+        join(&inner_cols, ", "),
+        final_sql,
+        if group.keep_others { "".to_owned() } else { format!(" where {}", grouped) },
+        join(&group_cols, ", "),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::{Aggregator, Table};
+    use tesseract_core::query::{Comparison, Constraint, MemberGroup, Operator, SortDirection};
+    use tesseract_core::query_ir::{DrilldownSql, FilterExprSql, FilterSql, LevelColumn, MeasureSql, MemberType, TableSql};
+
+    fn drill(key_column: &str, name_column: Option<&str>, alias_postfix: &str) -> DrilldownSql {
+        DrilldownSql {
+            alias_postfix: alias_postfix.into(),
+            foreign_key: key_column.into(),
+            primary_key: key_column.into(),
+            inline_table: None,
+            table: Table { name: "sales".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: key_column.into(),
+                    name_column: name_column.map(|n| n.to_owned()),
+                    date_key: None,
+                },
+            ],
+            property_columns: vec![],
+        }
+    }
+
+    fn query_ir(top: Option<TopSql>) -> QueryIr {
+        QueryIr {
+            table: TableSql { name: "sales".into(), primary_key: None },
+            cuts: vec![],
+            drills: vec![
+                drill("product_id", Some("product_name"), "Product"),
+                drill("year", None, "Year"),
+            ],
+            meas: vec![
+                MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() },
+                MeasureSql { aggregator: Aggregator::Max, column: "price".into() },
+            ],
+            hidden_drills: vec![],
+            filters: vec![],
+            filter: None,
+            top,
+            top_where: None,
+            sort: None,
+            limit: None,
+            rca: None,
+            growth: None,
+            cumulative: None,
+            moving_avg: None,
+            index: None,
+            rank: None,
+            trend: None,
+            totals: vec![],
+            rate: None,
+            group: None,
+            bins: None,
+            sparse: false,
+        }
+    }
+
+    #[test]
+    fn top_others_sql() {
+        let top = TopSql {
+            n: 3,
+            by_column: "product_id_Product".into(),
+            sort_columns: vec!["final_m0".into()],
+            sort_direction: SortDirection::Desc,
+            others: true,
+        };
+        let query_ir = query_ir(Some(top.clone()));
+
+        assert_eq!(
+            top_others("select 1", "select 2".into(), &top, &query_ir),
+            "select product_id_Product_other as product_id_Product, product_name_Product_other as product_name_Product, year_Year, \
+            sum(other_m0) as final_m0, max(other_m1) as final_m1 from (\
+            select if(is_top, product_id_Product, NULL) as product_id_Product_other, if(is_top, product_name_Product, 'Other') as product_name_Product_other, year_Year, \
+            final_m0 as other_m0, final_m1 as other_m1 from (\
+            select *, (year_Year, product_id_Product) in (select year_Year, product_id_Product from (select 2)) as is_top from (select 1)\
+            )\
+            ) group by year_Year, product_id_Product_other, product_name_Product_other".to_owned()
+        );
+    }
+
+    #[test]
+    fn filters_sql() {
+        let mut query_ir = query_ir(None);
+        query_ir.filters = vec![FilterSql {
+            by_column: "final_m0".into(),
+            constraint: Constraint { comparison: Comparison::LessThan, n: 10.0 },
+            operator: Some(Operator::Or),
+            constraint2: Some(Constraint { comparison: Comparison::GreaterThan, n: 20.0 }),
+        }];
+        query_ir.filter = Some(FilterExprSql::Or(
+            Box::new(FilterExprSql::Between("final_m1".into(), 1.0, 2.5)),
+            Box::new(FilterExprSql::Not(Box::new(FilterExprSql::IsNull("final_m0".into())))),
+        ));
+        query_ir.sparse = true;
+
+        assert_eq!(
+            wrap_options("select 1".into(), "product_id_Product, year_Year", &query_ir, 2),
+            "select * from (select 1) \
+            where (final_m0 < 10 or final_m0 > 20) and (final_m1 between 1 and 2.5 or not (final_m0 is null)) \
+            and isNotNull(final_m0) and isNotNull(final_m1) \
+            order by product_id_Product, year_Year ".to_owned()
+        );
+    }
+
+    #[test]
+    fn group_members_sql() {
+        let group = GroupSql {
+            by_column: "product_id_Product".into(),
+            member_type: MemberType::Text,
+            groups: vec![
+                MemberGroup { name: "Books".into(), members: vec!["a".into(), "b".into()] },
+                MemberGroup { name: "Videos".into(), members: vec!["c".into()] },
+            ],
+            keep_others: true,
+        };
+
+        let cases = "product_id_Product in ('a', 'b'), 'Books', product_id_Product in ('c'), 'Videos'";
+
+        assert_eq!(
+            group_members("select 1".into(), &group, &query_ir(None)),
+            format!("select product_id_Product_group as product_id_Product, product_name_Product_group as product_name_Product, year_Year, \
+                sum(group_m0) as final_m0, max(group_m1) as final_m1 from (\
+                select multiIf({}, toString(product_id_Product)) as product_id_Product_group, multiIf({}, product_name_Product) as product_name_Product_group, year_Year, \
+                final_m0 as group_m0, final_m1 as group_m1 from (select 1)\
+                ) group by product_id_Product_group, product_name_Product_group, year_Year",
+                cases,
+                cases,
+            ),
+        );
+    }
+}
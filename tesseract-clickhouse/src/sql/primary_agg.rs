@@ -6,14 +6,17 @@ use super::aggregator::{
     agg_sql_string_select_mea,
 };
 use super::cuts::cut_sql_string;
+use super::date_key::date_key_sql;
 use super::{
     TableSql,
     CutSql,
     DrilldownSql,
     MeasureSql,
     HiddenDrilldownSql,
+    BinsSql,
     dim_subquery,
 };
+use tesseract_core::query::Bins;
 
 
 /// Error checking is done before this point. This string formatter
@@ -24,6 +27,8 @@ pub fn primary_agg(
     drills: &[DrilldownSql],
     meas: &[MeasureSql],
     hidden_drills: Option<&[HiddenDrilldownSql]>,
+    totals: &[DrilldownSql],
+    bins: Option<&BinsSql>,
     ) -> (String, String)
 {
     // Before first section, need to separate out inline dims.
@@ -67,7 +72,7 @@ pub fn primary_agg(
     // For each of the external drilldowns, we will need to add a subquery
     while let Some(drill) = ext_drills.pop() {
         dim_subqueries.push(
-            dim_subquery(Some(drill), None)
+            dim_subquery(Some(drill), None, date_key_sql)
         );
     }
 
@@ -99,8 +104,14 @@ pub fn primary_agg(
         });
     let mea_cols = join(mea_cols, ", ");
 
-    let inline_dim_cols = inline_drills.iter().map(|d| d.col_alias_string());
-    let inline_dim_aliass = inline_drills.iter().map(|d| d.col_alias_only_string());
+    // bins are computed from the fact table, so they go with the inline dims
+    let bins_cols = bins.map(|b| {
+        join(bins_sql(b).iter().zip(BinsSql::ALIASES.iter()).map(|(col, alias)| format!("{} AS {}", col, alias)), ", ")
+    });
+    let bins_aliass = bins.map(|_| join(BinsSql::ALIASES.iter(), ", "));
+
+    let inline_dim_cols = inline_drills.iter().map(|d| d.col_alias_string(date_key_sql)).chain(bins_cols);
+    let inline_dim_aliass = inline_drills.iter().map(|d| d.col_alias_only_string()).chain(bins_aliass.clone());
 
     let dim_idx_cols = dim_subqueries.iter().map(|d| d.foreign_key.clone());
 
@@ -109,7 +120,7 @@ pub fn primary_agg(
 
     // TODO remove allocation
     let hidden_drills = hidden_drills.map(|ds| ds.to_vec()).unwrap_or(vec![]);
-    let hidden_dim_cols = join(hidden_drills.iter().map(|d| d.drilldown_sql.col_alias_string()), ", ");
+    let hidden_dim_cols = join(hidden_drills.iter().map(|d| d.drilldown_sql.col_alias_string(date_key_sql)), ", ");
 
     let mut fact_sql = format!("SELECT {}", all_fact_dim_cols);
 
@@ -136,7 +147,7 @@ pub fn primary_agg(
                     None => c.table.full_name()
                 };
 
-                if c.members.is_empty() {
+                if c.members.is_empty() && c.range.is_none() {
                     // this case is for default hierarchy
                     // in multiple hierarchies
                     format!("{} in (SELECT {} FROM {})",
@@ -213,7 +224,7 @@ pub fn primary_agg(
     }
 
     // Finally, wrap with final agg and result
-    let final_drill_cols = drills.iter().map(|drill| drill.col_alias_only_string());
+    let final_drill_cols = drills.iter().map(|drill| drill.col_alias_only_string()).chain(bins_aliass);
     let final_drill_cols = join(final_drill_cols, ", ");
 
     let final_mea_cols = meas.iter().enumerate().map(|(i, mea)| {
@@ -223,12 +234,226 @@ pub fn primary_agg(
     let final_mea_cols = join(final_mea_cols, ", ");
 
     // This is the final result of the groupings.
-    let final_sql = format!("SELECT {}, {} FROM ({}) GROUP BY {}",
-        final_drill_cols,
-        final_mea_cols,
-        sub_queries,
-        final_drill_cols,
-    );
+    let final_sql = if totals.is_empty() {
+        format!("SELECT {}, {} FROM ({}) GROUP BY {}",
+            final_drill_cols,
+            final_mea_cols,
+            sub_queries,
+            final_drill_cols,
+        )
+    } else {
+        totals_sql(drills, meas, &sub_queries, totals)
+    };
 
     (final_sql, final_drill_cols)
 }
+
+/// Label, lower and upper bound of the bucket of each row.
+fn bins_sql(bins: &BinsSql) -> Vec<String> {
+    match bins.bins {
+        Bins::Edges(ref edges) => {
+            let mut labels = vec![];
+            let mut lowers = vec![];
+            let mut uppers = vec![];
+
+            for bucket in BinsSql::buckets(edges) {
+                let condition = bins.bucket_condition(&bucket);
+                let bound = |b: Option<f64>| b.map(|b| b.to_string()).unwrap_or_else(|| "NULL".to_owned());
+
+                labels.push(format!("{}, '{}'", condition, bucket.label));
+                lowers.push(format!("{}, {}", condition, bound(bucket.lower)));
+                uppers.push(format!("{}, {}", condition, bound(bucket.upper)));
+            }
+
+            vec![labels, lowers, uppers].into_iter()
+                .map(|cases| format!("multiIf({}, NULL)", join(cases, ", ")))
+                .collect()
+        },
+        Bins::EqualWidth(n) => {
+            let (min, width) = bins.min_width_strings(n);
+            let idx = format!("if({w} = 0, 0, least(floor(({col} - {min}) / {w}), {last}))",
+                w = width,
+                col = bins.column,
+                min = min,
+                last = n - 1,
+            );
+            let lower = format!("{} + {} * {}", min, idx, width);
+            let upper = format!("{} + ({} + 1) * {}", min, idx, width);
+
+            vec![
+                format!("concat(toString({}), ' - ', toString({}))", lower, upper),
+                lower,
+                upper,
+            ]
+        },
+    }
+}
+
+/// Rollup over the totals drills: grouping set 0 is the full grouping,
+/// and each following set also rolls up the next totals drill from the
+/// end. If that doesn't roll up all drills, one more set is the grand
+/// total. Rows are repeated for each grouping set with array Join, and the
+/// final pass aggregates them.
+///
+/// Rolled up drills have null keys and properties, and 'Total' names, and
+/// the `total` column is 1 on the rows of rolled up grouping sets.
+fn totals_sql(
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    sub_queries: &str,
+    totals: &[DrilldownSql],
+    ) -> String
+{
+    let grand_total_set = if drills.iter().all(|d| totals.contains(d)) {
+        None
+    } else {
+        Some(totals.len() + 1)
+    };
+
+    let mut grouping_cols = vec![];
+    let mut outer_cols = vec![];
+
+    for drill in drills {
+        // rolled up starting from this grouping set
+        let rolled_up_from = totals.iter()
+            .position(|t| t == drill)
+            .map(|idx| totals.len() - idx)
+            .or(grand_total_set);
+
+        let mut cols = vec![];
+        for l in &drill.level_columns {
+            cols.push((format!("{}_{}", l.key_column, drill.alias_postfix), "NULL"));
+            if let Some(ref name_col) = l.name_column {
+                cols.push((format!("{}_{}", name_col, drill.alias_postfix), "'Total'"));
+            }
+        }
+        for p in &drill.property_columns {
+            cols.push((p.clone(), "NULL"));
+        }
+
+        for (col, placeholder) in cols {
+            match rolled_up_from {
+                Some(set) => {
+                    grouping_cols.push(format!("if(grouping_set >= {}, {}, {}) AS {}_total", set, placeholder, col, col));
+                    outer_cols.push(format!("{}_total AS {}", col, col));
+                },
+                None => {
+                    grouping_cols.push(format!("{} AS {}_total", col, col));
+                    outer_cols.push(format!("{}_total AS {}", col, col));
+                },
+            }
+        }
+    }
+
+    let grouping_aliases = join(outer_cols.iter().map(|c| c.split(" AS ").next().unwrap_or("")), ", ");
+
+    // not all aggregators alias their final measure
+    let grouping_meas = meas.iter().enumerate().map(|(i, mea)| {
+        let mea_col = agg_sql_string_pass_2(&mea.aggregator, i);
+        if mea_col.ends_with(&format!(" as final_m{}", i)) {
+            mea_col
+        } else {
+            format!("{} as final_m{}", mea_col, i)
+        }
+    });
+    let final_meas = join((0..meas.len()).map(|i| format!("final_m{}", i)), ", ");
+
+    format!("SELECT {}, {}, total FROM (\
+            SELECT {}, {}, grouping_set > 0 AS total FROM ({}) ARRAY JOIN range({}) AS grouping_set GROUP BY grouping_set, {}\
+        )",
+        join(&outer_cols, ", "),
+        final_meas,
+        join(&grouping_cols, ", "),
+        join(grouping_meas, ", "),
+        sub_queries,
+        grand_total_set.unwrap_or(totals.len()) + 1,
+        grouping_aliases,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::{Aggregator, Table};
+    use tesseract_core::query_ir::{LevelColumn, TableSql};
+
+    fn drill(key_column: &str, name_column: Option<&str>, alias_postfix: &str) -> DrilldownSql {
+        DrilldownSql {
+            alias_postfix: alias_postfix.into(),
+            foreign_key: key_column.into(),
+            primary_key: key_column.into(),
+            inline_table: None,
+            table: Table { name: "sales".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: key_column.into(),
+                    name_column: name_column.map(|n| n.to_owned()),
+                    date_key: None,
+                },
+            ],
+            property_columns: vec![],
+        }
+    }
+
+    #[test]
+    fn totals_rollup() {
+        let drills = vec![
+            drill("year", None, "Year"),
+            drill("product_id", Some("product_name"), "Product"),
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() },
+        ];
+
+        assert_eq!(
+            totals_sql(&drills, &meas, "SELECT 1", &drills),
+            "SELECT year_Year_total AS year_Year, product_id_Product_total AS product_id_Product, product_name_Product_total AS product_name_Product, final_m0, total FROM (\
+            SELECT if(grouping_set >= 2, NULL, year_Year) AS year_Year_total, \
+            if(grouping_set >= 1, NULL, product_id_Product) AS product_id_Product_total, \
+            if(grouping_set >= 1, 'Total', product_name_Product) AS product_name_Product_total, \
+            sum(m0) as final_m0, grouping_set > 0 AS total FROM (SELECT 1) ARRAY JOIN range(3) AS grouping_set \
+            GROUP BY grouping_set, year_Year_total, product_id_Product_total, product_name_Product_total\
+            )".to_owned()
+        );
+
+        // the grand total is added when not all drills are in totals
+        assert_eq!(
+            totals_sql(&drills, &meas, "SELECT 1", &drills[..1]),
+            "SELECT year_Year_total AS year_Year, product_id_Product_total AS product_id_Product, product_name_Product_total AS product_name_Product, final_m0, total FROM (\
+            SELECT if(grouping_set >= 1, NULL, year_Year) AS year_Year_total, \
+            if(grouping_set >= 2, NULL, product_id_Product) AS product_id_Product_total, \
+            if(grouping_set >= 2, 'Total', product_name_Product) AS product_name_Product_total, \
+            sum(m0) as final_m0, grouping_set > 0 AS total FROM (SELECT 1) ARRAY JOIN range(3) AS grouping_set \
+            GROUP BY grouping_set, year_Year_total, product_id_Product_total, product_name_Product_total\
+            )".to_owned()
+        );
+    }
+
+    #[test]
+    fn bins_inline() {
+        let table = TableSql { name: "sales".into(), primary_key: None };
+        let drills = vec![drill("year", None, "Year")];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() },
+        ];
+        let bins = BinsSql {
+            column: "age".into(),
+            table: "sales".into(),
+            bins: Bins::Edges(vec![f64::NEG_INFINITY, 18.0, 65.5]),
+        };
+
+        let (sql, final_drill_cols) = primary_agg(&table, &[], &drills, &meas, None, &[], Some(&bins));
+
+        assert_eq!(final_drill_cols, "year_Year, bin_label, bin_lower, bin_upper");
+        assert_eq!(
+            sql,
+            "SELECT year_Year, bin_label, bin_lower, bin_upper, sum(m0) as final_m0 FROM (\
+            SELECT year as year_Year, \
+            multiIf(age < 18, '-inf - 18', age >= 18 and age < 65.5, '18 - 65.5', NULL) AS bin_label, \
+            multiIf(age < 18, NULL, age >= 18 and age < 65.5, 18, NULL) AS bin_lower, \
+            multiIf(age < 18, 18, age >= 18 and age < 65.5, 65.5, NULL) AS bin_upper, \
+            sum(quantity) as m0 FROM sales GROUP BY year_Year, bin_label, bin_lower, bin_upper\
+            ) GROUP BY year_Year, bin_label, bin_lower, bin_upper".to_owned()
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Calculations over one drilldown (cumulative sums, moving averages, indexes,
+//! ranks, trends),
+//! within each combination of the other drilldowns.
+//!
+//! Uses the same technique as growth: group by everything besides the
+//! drilldown's columns, pack its columns and the measures into arrays
+//! ordered by the drilldown, calculate over the measure array, then array
+//! Join back to full rows.
+//!
+//! Unlike growth, the original column names and order are preserved; the
+//! calculated column is appended at the end.
+
+use itertools::join;
+
+use tesseract_core::query::{RankMethod, SortDirection};
+use tesseract_core::schema::TimeGranularity;
+
+use super::{CumulativeSql, DrilldownSql, IndexSql, MovingAvgSql, RankSql, ResetSql, TrendSql};
+
+pub fn cumulative(
+    final_sql: String,
+    final_drill_cols: &str,
+    value_cols: &[String],
+    cumulative: &CumulativeSql,
+    ) -> String
+{
+    array_calculate(
+        final_sql,
+        final_drill_cols,
+        value_cols,
+        &cumulative.time_drill,
+        &format!("arrayCumSum({}_s)", cumulative.mea),
+        "cumulative",
+        cumulative.reset.as_ref(),
+    )
+}
+
+pub fn moving_avg(
+    final_sql: String,
+    final_drill_cols: &str,
+    value_cols: &[String],
+    moving_avg: &MovingAvgSql,
+    ) -> String
+{
+    // window start is clamped to the first element of the group
+    let start = format!("greatest(1, toInt64(i) - {} + 1)", moving_avg.window);
+
+    array_calculate(
+        final_sql,
+        final_drill_cols,
+        value_cols,
+        &moving_avg.time_drill,
+        &format!("arrayMap(i -> arrayReduce('avg', arraySlice({mea}_s, {start}, toInt64(i) - {start} + 1)), arrayEnumerate({mea}_s))",
+            mea = moving_avg.mea,
+            start = start,
+        ),
+        "moving_avg",
+        None,
+    )
+}
+
+/// A missing or zero base gives nulls.
+pub fn index(
+    final_sql: String,
+    final_drill_cols: &str,
+    value_cols: &[String],
+    index: &IndexSql,
+    ) -> String
+{
+    let base = format!("nullIf(arrayElement({mea}_s, indexOf({by_column}_s, {base})), 0)",
+        mea = index.mea,
+        by_column = index.by_column,
+        base = index.base_string(),
+    );
+
+    array_calculate(
+        final_sql,
+        final_drill_cols,
+        value_cols,
+        &index.drill,
+        &format!("arrayMap(x -> x * 100 / {}, {}_s)", base, index.mea),
+        "indexed",
+        None,
+    )
+}
+
+/// Standard rank skips after ties (1, 1, 3), dense doesn't (1, 1, 2).
+pub fn rank(
+    final_sql: String,
+    final_drill_cols: &str,
+    value_cols: &[String],
+    rank: &RankSql,
+    ) -> String
+{
+    let comparison = match rank.direction {
+        SortDirection::Desc => ">",
+        SortDirection::Asc => "<",
+    };
+    let compared = match rank.method {
+        RankMethod::Standard => format!("{}_s", rank.mea),
+        RankMethod::Dense => format!("arrayDistinct({}_s)", rank.mea),
+    };
+
+    array_calculate(
+        final_sql,
+        final_drill_cols,
+        value_cols,
+        &rank.drill,
+        &format!("arrayMap(x -> arrayCount(y -> y {} x, {}) + 1, {}_s)",
+            comparison,
+            compared,
+            rank.mea,
+        ),
+        "ranked",
+        None,
+    )
+}
+
+/// Least squares line of the measure over periods numbered from 1, same as
+/// standard sql. Packed arrays are extended with nulls by `periods`, and
+/// unpacked as forecast rows; the drilled level's key is extended with the
+/// projected keys instead.
+pub fn trend(
+    final_sql: String,
+    final_drill_cols: &str,
+    value_cols: &[String],
+    trend: &TrendSql,
+    ) -> String
+{
+    let over_cols: Vec<String> = trend.time_drill.col_alias_only_vec().iter()
+        .flat_map(|cols| cols.split(", ").map(|c| c.trim().to_owned()).collect::<Vec<_>>())
+        .collect();
+
+    let other_drill_cols: Vec<String> = final_drill_cols.split(", ")
+        .map(|c| c.trim().to_owned())
+        .filter(|c| !c.is_empty() && !over_cols.contains(c))
+        .collect();
+    let other_drill_cols = join(&other_drill_cols, ", ");
+
+    let time_key = trend.time_drill.level_columns.last()
+        .map(|l| format!("{}_{}", l.key_column, trend.time_drill.alias_postfix));
+
+    let padded = |arr: String| {
+        if trend.periods == 0 {
+            arr
+        } else {
+            format!("arrayResize({}, count() + {}, NULL)", arr, trend.periods)
+        }
+    };
+
+    let over_array = |c: &String| {
+        match projected_key("k", "h", trend.granularity.as_ref()) {
+            Some(ref key) if trend.periods > 0 && time_key.as_ref() == Some(c) => {
+                format!("arrayConcat(groupArray({c}), arrayMap((k, h) -> {key}, arrayWithConstant({n}, max({c})), range(1, {n} + 1)))",
+                    c = c, key = key, n = trend.periods)
+            },
+            _ => padded(format!("groupArray({})", c)),
+        }
+    };
+
+    let grouparray_cols = join(
+        over_cols.iter()
+            .map(|c| format!("{} as {}_s", over_array(c), c))
+            .chain(value_cols.iter().map(|c| format!("{} as {}_s", padded(format!("arrayMap(x -> tupleElement(x, 1), groupArray(tuple({})))", c)), c))),
+        ", "
+    );
+    let unpack_cols = join(over_cols.iter().chain(value_cols.iter()).map(|c| format!("{}_s as {}", c, c)), ", ");
+
+    // padded and null periods are not fitted
+    let fit_cols = format!("arrayFilter((x, y) -> isNotNull(y), arrayEnumerate({mea}_s), {mea}_s) as trend_xs, \
+        arrayFilter(y -> isNotNull(y), {mea}_s) as trend_ys, \
+        length(trend_xs) as trend_n, \
+        arraySum(trend_xs) as trend_sx, \
+        arraySum(trend_ys) as trend_sy, \
+        arraySum(arrayMap((x, y) -> x * y, trend_xs, trend_ys)) as trend_sxy, \
+        arraySum(arrayMap(x -> x * x, trend_xs)) as trend_sxx, \
+        (trend_n * trend_sxy - trend_sx * trend_sy) / nullIf(trend_n * trend_sxx - trend_sx * trend_sx, 0) as trend_slope, \
+        (trend_sy * trend_sxx - trend_sx * trend_sxy) / nullIf(trend_n * trend_sxx - trend_sx * trend_sx, 0) as trend_intercept, \
+        arrayMap(i -> trend_intercept + trend_slope * i, arrayEnumerate({mea}_s)) as trend_s, \
+        count() as trend_periods, \
+        arrayMap(i -> toUInt64(greatest(toInt64(i) - toInt64(trend_periods), 0)), arrayEnumerate({mea}_s)) as forecast_s",
+        mea = trend.mea,
+    );
+
+    format!("select {final_drill_cols}, {value_cols}, trend_slope, trend_intercept, trend, forecast from (\
+            select {other_drill_cols}{other_comma}{grouparray_cols}, {fit_cols} \
+            from ({final_sql} order by {over_cols}) \
+            {group_by}{other_drill_cols}\
+        ) \
+        array Join {unpack_cols}, trend_s as trend, forecast_s as forecast",
+        final_drill_cols = final_drill_cols,
+        value_cols = join(value_cols, ", "),
+        other_drill_cols = other_drill_cols,
+        other_comma = if other_drill_cols.is_empty() { "" } else { ", " },
+        grouparray_cols = grouparray_cols,
+        fit_cols = fit_cols,
+        final_sql = final_sql,
+        over_cols = join(&over_cols, ", "),
+        group_by = if other_drill_cols.is_empty() { "" } else { "group by " },
+        unpack_cols = unpack_cols,
+    )
+}
+
+/// Key of the period `h` periods after key `k`, by period number as in
+/// growth. Day keys are not projected.
+fn projected_key(k: &str, h: &str, granularity: Option<&TimeGranularity>) -> Option<String> {
+    let from_period = |per_year: u32, key_base: u32| {
+        let n = format!("intDiv({k}, {b}) * {y} + {k} % {b} - 1 + {h}", k = k, b = key_base, y = per_year, h = h);
+        format!("intDiv({n}, {y}) * {b} + ({n}) % {y} + 1", n = n, y = per_year, b = key_base)
+    };
+
+    match granularity {
+        Some(TimeGranularity::Quarter) | Some(TimeGranularity::FiscalQuarter) => Some(from_period(4, 10)),
+        Some(TimeGranularity::Month) => Some(from_period(12, 100)),
+        Some(TimeGranularity::Day) => None,
+        Some(TimeGranularity::Year) | Some(TimeGranularity::FiscalYear) | None => Some(format!("{} + {}", k, h)),
+    }
+}
+
+/// `array_expr` refers to packed columns as `{col}_s`, and must return an
+/// array of the same length.
+///
+/// Value cols are packed as tuples, because groupArray skips nulls.
+///
+/// `reset` also groups by the period that a period-to-date calculation
+/// restarts on.
+fn array_calculate(
+    final_sql: String,
+    final_drill_cols: &str,
+    value_cols: &[String],
+    over_drill: &DrilldownSql,
+    array_expr: &str,
+    calc_col: &str,
+    reset: Option<&ResetSql>,
+    ) -> String
+{
+    // a reset level col stays out of the packed cols, so it's grouped by
+    let reset_col = reset.map(|ResetSql::Column(col)| col);
+
+    // drill may include parent levels and properties, all get packed
+    let over_cols: Vec<String> = over_drill.col_alias_only_vec().iter()
+        .flat_map(|cols| cols.split(", ").map(|c| c.trim().to_owned()).collect::<Vec<_>>())
+        .filter(|c| Some(c) != reset_col)
+        .collect();
+
+    let other_drill_cols: Vec<String> = final_drill_cols.split(", ")
+        .map(|c| c.trim().to_owned())
+        .filter(|c| !c.is_empty() && !over_cols.contains(c))
+        .collect();
+    let other_drill_cols = join(&other_drill_cols, ", ");
+
+    let packed_cols: Vec<&String> = over_cols.iter().chain(value_cols.iter()).collect();
+
+    let grouparray_cols = join(
+        over_cols.iter()
+            .map(|c| format!("groupArray({}) as {}_s", c, c))
+            .chain(value_cols.iter().map(|c| format!("arrayMap(x -> tupleElement(x, 1), groupArray(tuple({}))) as {}_s", c, c))),
+        ", "
+    );
+    let unpack_cols = join(packed_cols.iter().map(|c| format!("{}_s as {}", c, c)), ", ");
+
+    format!("select {final_drill_cols}, {value_cols}, {calc_col} from (\
+            select {other_drill_cols}{other_comma}{grouparray_cols}, {array_expr} as {calc_col}_s \
+            from ({final_sql} order by {over_cols}) \
+            {group_by}{other_drill_cols}\
+        ) \
+        array Join {unpack_cols}, {calc_col}_s as {calc_col}",
+        final_drill_cols = final_drill_cols,
+        value_cols = join(value_cols, ", "),
+        calc_col = calc_col,
+        other_drill_cols = other_drill_cols,
+        other_comma = if other_drill_cols.is_empty() { "" } else { ", " },
+        grouparray_cols = grouparray_cols,
+        array_expr = array_expr,
+        final_sql = final_sql,
+        over_cols = join(&over_cols, ", "),
+        group_by = if other_drill_cols.is_empty() { "" } else { "group by " },
+        unpack_cols = unpack_cols,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::Table;
+    use tesseract_core::query_ir::{LevelColumn, MemberType};
+
+    fn time_drill() -> DrilldownSql {
+        DrilldownSql {
+            alias_postfix: "Year".into(),
+            foreign_key: "year".into(),
+            primary_key: "year".into(),
+            inline_table: None,
+            table: Table { name: "sales".into(), schema: None, primary_key: None },
+            level_columns: vec![
+                LevelColumn {
+                    key_column: "year".into(),
+                    name_column: None,
+                    date_key: None,
+                },
+            ],
+            property_columns: vec![],
+        }
+    }
+
+    #[test]
+    fn cumulative_sql() {
+        let cumulative = CumulativeSql {
+            time_drill: time_drill(),
+            mea: "final_m0".into(),
+            reset: None,
+        };
+
+        assert_eq!(
+            super::cumulative("select 1".into(), "product_id_Product, year_Year", &["final_m0".to_owned()], &cumulative),
+            "select product_id_Product, year_Year, final_m0, cumulative from (\
+            select product_id_Product, groupArray(year_Year) as year_Year_s, arrayMap(x -> tupleElement(x, 1), groupArray(tuple(final_m0))) as final_m0_s, arrayCumSum(final_m0_s) as cumulative_s \
+            from (select 1 order by year_Year) \
+            group by product_id_Product\
+            ) \
+            array Join year_Year_s as year_Year, final_m0_s as final_m0, cumulative_s as cumulative".to_owned(),
+        );
+    }
+
+    #[test]
+    fn cumulative_period_to_date() {
+        let mut time_drill = time_drill();
+        time_drill.level_columns.push(LevelColumn {
+            key_column: "day".into(),
+            name_column: None,
+            date_key: None,
+        });
+
+        let cumulative = CumulativeSql {
+            time_drill,
+            mea: "final_m0".into(),
+            reset: Some(ResetSql::Column("year_Year".into())),
+        };
+        let sql = super::cumulative("select 1".into(), "year_Year, day_Year", &["final_m0".to_owned()], &cumulative);
+
+        assert!(sql.contains("select year_Year, groupArray(day_Year) as day_Year_s"));
+        assert!(sql.contains("from (select 1 order by day_Year) group by year_Year)"));
+    }
+
+    #[test]
+    fn moving_avg_without_other_drills() {
+        let moving_avg = MovingAvgSql {
+            time_drill: time_drill(),
+            mea: "final_m0".into(),
+            window: 3,
+        };
+
+        let sql = super::moving_avg("select 1".into(), "year_Year", &["final_m0".to_owned()], &moving_avg);
+
+        assert!(sql.contains("select groupArray(year_Year) as year_Year_s"));
+        assert!(sql.contains("arraySlice(final_m0_s, greatest(1, toInt64(i) - 3 + 1)"));
+        assert!(!sql.contains("group by"));
+    }
+
+    #[test]
+    fn rank_sql() {
+        let rank = RankSql {
+            drill: time_drill(),
+            mea: "final_m1".into(),
+            direction: SortDirection::Desc,
+            method: RankMethod::Dense,
+        };
+        let value_cols = vec!["final_m0".to_owned(), "final_m1".to_owned()];
+
+        let sql = super::rank("select 1".into(), "product_id_Product, year_Year", &value_cols, &rank);
+
+        assert!(sql.starts_with("select product_id_Product, year_Year, final_m0, final_m1, ranked from ("));
+        assert!(sql.contains("arrayMap(x -> arrayCount(y -> y > x, arrayDistinct(final_m1_s)) + 1, final_m1_s) as ranked_s"));
+        assert!(sql.contains("group by product_id_Product"));
+    }
+
+    #[test]
+    fn trend_sql() {
+        let trend = TrendSql {
+            time_drill: time_drill(),
+            mea: "final_m0".into(),
+            periods: 2,
+            granularity: None,
+        };
+        let value_cols = vec!["final_m0".to_owned()];
+
+        let sql = super::trend("select 1".into(), "product_id_Product, year_Year", &value_cols, &trend);
+
+        assert!(sql.starts_with("select product_id_Product, year_Year, final_m0, trend_slope, trend_intercept, trend, forecast from ("));
+        assert!(sql.contains("arrayConcat(groupArray(year_Year), arrayMap((k, h) -> k + h, arrayWithConstant(2, max(year_Year)), range(1, 2 + 1))) as year_Year_s"));
+        assert!(sql.contains("arrayResize(arrayMap(x -> tupleElement(x, 1), groupArray(tuple(final_m0))), count() + 2, NULL) as final_m0_s"));
+        assert!(sql.contains("arrayFilter((x, y) -> isNotNull(y), arrayEnumerate(final_m0_s), final_m0_s) as trend_xs"));
+        assert!(sql.contains("group by product_id_Product"));
+        assert!(sql.ends_with("array Join year_Year_s as year_Year, final_m0_s as final_m0, trend_s as trend, forecast_s as forecast"));
+    }
+
+    #[test]
+    fn trend_projected_keys() {
+        let mut trend = TrendSql {
+            time_drill: time_drill(),
+            mea: "final_m0".into(),
+            periods: 1,
+            granularity: Some(TimeGranularity::Quarter),
+        };
+        let value_cols = vec!["final_m0".to_owned()];
+
+        let sql = super::trend("select 1".into(), "year_Year", &value_cols, &trend);
+        assert!(sql.contains("arrayMap((k, h) -> intDiv(intDiv(k, 10) * 4 + k % 10 - 1 + h, 4) * 10 + (intDiv(k, 10) * 4 + k % 10 - 1 + h) % 4 + 1, \
+            arrayWithConstant(1, max(year_Year)), range(1, 1 + 1))"));
+
+        trend.granularity = Some(TimeGranularity::Day);
+        let sql = super::trend("select 1".into(), "year_Year", &value_cols, &trend);
+        assert!(sql.contains("arrayResize(groupArray(year_Year), count() + 1, NULL) as year_Year_s"));
+
+        trend.periods = 0;
+        trend.granularity = None;
+        let sql = super::trend("select 1".into(), "year_Year", &value_cols, &trend);
+        assert!(sql.contains("groupArray(year_Year) as year_Year_s"));
+        assert!(!sql.contains("arrayConcat"));
+    }
+
+    #[test]
+    fn index_sql() {
+        let index = IndexSql {
+            drill: time_drill(),
+            by_column: "year_Year".into(),
+            member_type: MemberType::NonText,
+            base: "2010".into(),
+            mea: "final_m0".into(),
+        };
+        let value_cols = vec!["final_m0".to_owned()];
+
+        let sql = super::index("select 1".into(), "product_id_Product, year_Year", &value_cols, &index);
+
+        assert!(sql.starts_with("select product_id_Product, year_Year, final_m0, indexed from ("));
+        assert!(sql.contains("arrayMap(x -> x * 100 / nullIf(arrayElement(final_m0_s, indexOf(year_Year_s, 2010)), 0), final_m0_s) as indexed_s"));
+        assert!(sql.contains("group by product_id_Product"));
+    }
+}
@@ -2,7 +2,7 @@ use failure::Error;
 use futures::{Future, Stream};
 
 use crate::dataframe::DataFrame;
-use crate::query_ir::QueryIr;
+use crate::query_ir::{DateKey, QueryIr};
 use crate::sql;
 
 
@@ -13,13 +13,20 @@ pub trait Backend {
 
     /// Takes in a SQL string, outputs a stream of
     /// DataFrames, which will go on to be formatted into the
-    /// desired query output format.
+    /// desired query output format. Backends that can't stream
+    /// return the whole result as one DataFrame.
     fn exec_sql_stream(&self, sql: String) -> Box<dyn Stream<Item=Result<DataFrame, Error>, Error=Error>> {
-        unimplemented!()
+        Box::new(self.exec_sql(sql).map(Ok).into_stream())
     }
 
     fn box_clone(&self) -> Box<dyn Backend + Send + Sync>;
 
+    /// Key of a level derived from a date column, for sql that isn't
+    /// generated from a `QueryIr`, like members.
+    fn date_key_sql(&self, date_key: &DateKey) -> String {
+        date_key.standard_sql(None)
+    }
+
     /// Receives an intermediate representation of the Query
     /// (the table, col, and relationship info needed for each drill,
     /// mea, cut, etc.) and generates a `String` of sql. Cannot error,
@@ -31,11 +38,19 @@ pub trait Backend {
             &query_ir.cuts,
             &query_ir.drills,
             &query_ir.meas,
-            &query_ir.top,
-            &query_ir.sort,
-            &query_ir.limit,
-            &query_ir.rca,
-            &query_ir.growth,
+            &sql::CalcSql {
+                filters: &query_ir.filters,
+                filter: query_ir.filter.as_ref(),
+                top: query_ir.top.as_ref(),
+                cumulative: query_ir.cumulative.as_ref(),
+                moving_avg: query_ir.moving_avg.as_ref(),
+                index: query_ir.index.as_ref(),
+                rank: query_ir.rank.as_ref(),
+                trend: query_ir.trend.as_ref(),
+                totals: &query_ir.totals,
+                group: query_ir.group.as_ref(),
+                bins: query_ir.bins.as_ref(),
+            },
         )
     }
 }
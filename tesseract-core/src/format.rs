@@ -1,9 +1,11 @@
 use csv;
-use failure::{Error, format_err};
+use failure::{Error, bail, format_err};
 use indexmap::IndexMap;
 use serde::Serializer;
 use serde::ser::{SerializeSeq};
-use serde_json::{Value};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::schema::metadata::SourceMetadata;
 use crate::dataframe::{DataFrame, ColumnData};
@@ -13,6 +15,19 @@ pub enum FormatType{
     Csv,
     JsonRecords,
     JsonArrays,
+    Sdmx,
+    JsonStat,
+}
+
+impl FormatType {
+    /// Formats that need the whole result, to describe its dimensions
+    /// before the data, can't be streamed.
+    pub fn is_streamable(&self) -> bool {
+        match self {
+            FormatType::Csv | FormatType::JsonRecords | FormatType::JsonArrays => true,
+            FormatType::Sdmx | FormatType::JsonStat => false,
+        }
+    }
 }
 
 impl std::str::FromStr for FormatType {
@@ -23,6 +38,8 @@ impl std::str::FromStr for FormatType {
             "csv" => Ok(FormatType::Csv),
             "jsonrecords" => Ok(FormatType::JsonRecords),
             "jsonarrays" => Ok(FormatType::JsonArrays),
+            "sdmx" => Ok(FormatType::Sdmx),
+            "jsonstat" => Ok(FormatType::JsonStat),
             _ => Err(format_err!("{} is not a supported format", s)),
         }
     }
@@ -40,6 +57,8 @@ pub fn format_records(
         FormatType::Csv => Ok(format_csv(headers, df)?),
         FormatType::JsonRecords => Ok(format_jsonrecords(headers, df, source_data, error)?),
         FormatType::JsonArrays => Ok(format_jsonarrays(headers, df, error)?),
+        FormatType::Sdmx => Ok(format_sdmx(headers, df, source_data)?),
+        FormatType::JsonStat => Ok(format_jsonstat(headers, df, source_data)?),
     }
 }
 
@@ -255,3 +274,414 @@ fn format_jsonarrays(headers: &[String], df: DataFrame, error: bool) -> Result<S
 //        "data": rows,
 //    });
 }
+
+
+// ids of the dimension that has the measures as its members, in JSON-stat
+// and SDMX-JSON
+const JSONSTAT_MEASURE_DIMENSION: &str = "Measure";
+const SDMX_MEASURE_DIMENSION: &str = "MEASURE";
+
+/// Formats response `DataFrame` to a JSON-stat 2.0 dataset.
+///
+/// Each drilldown level is a dimension, and the measures are the
+/// categories of a `Measure` metric dimension. Values are in a dense array,
+/// or in an object by index when less than half of them are in the result.
+fn format_jsonstat(headers: &[String], df: DataFrame, source_data: Option<SourceMetadata>) -> Result<String, Error> {
+    let source_data = source_data
+        .ok_or_else(|| format_err!("jsonstat format is only supported for aggregate queries"))?;
+    let (dimensions, measures_idx) = result_dimensions(headers, &df, &source_data)?;
+    let measures = &headers[measures_idx..];
+
+    let mut ids: Vec<&str> = dimensions.iter().map(|dimension| dimension.id).collect();
+    ids.push(JSONSTAT_MEASURE_DIMENSION);
+    let mut sizes: Vec<usize> = dimensions.iter().map(|dimension| dimension.codes.len()).collect();
+    sizes.push(measures.len());
+
+    let mut dimension_objs = Map::new();
+    for dimension in &dimensions {
+        let mut dimension_obj = json!({
+            "label": dimension.id,
+            "category": {
+                "index": dimension.codes.keys().collect::<Vec<_>>(),
+                "label": dimension.codes,
+            },
+        });
+        if let Some(annotations) = dimension.annotations {
+            dimension_obj["extension"] = json!({ "annotations": sorted_annotations(annotations) });
+        }
+        dimension_objs.insert(dimension.id.to_owned(), dimension_obj);
+    }
+    dimension_objs.insert(JSONSTAT_MEASURE_DIMENSION.to_owned(), json!({
+        "label": JSONSTAT_MEASURE_DIMENSION,
+        "category": {
+            "index": measures,
+            "label": measures.iter().map(|m| (m, m)).collect::<IndexMap<_, _>>(),
+        },
+    }));
+
+    // value index is row-major, in the order of the dimension ids
+    let value_idx = |row_idx: usize, measure_idx: usize| {
+        dimensions.iter()
+            .fold(0, |idx, dimension| idx * dimension.codes.len() + dimension.positions[row_idx])
+            * measures.len() + measure_idx
+    };
+
+    let total = sizes.iter().fold(1usize, |total, size| total.saturating_mul(*size));
+    let values = if total <= 2 * df.len() * measures.len() {
+        let mut values = vec![Value::Null; total];
+        for row_idx in 0..df.len() {
+            for measure_idx in 0..measures.len() {
+                values[value_idx(row_idx, measure_idx)] =
+                    cell_value(&df.columns[measures_idx + measure_idx].column_data, row_idx);
+            }
+        }
+        Value::Array(values)
+    } else {
+        let mut values = Map::new();
+        for row_idx in 0..df.len() {
+            for measure_idx in 0..measures.len() {
+                values.insert(
+                    value_idx(row_idx, measure_idx).to_string(),
+                    cell_value(&df.columns[measures_idx + measure_idx].column_data, row_idx),
+                );
+            }
+        }
+        Value::Object(values)
+    };
+
+    let mut res = json!({
+        "version": "2.0",
+        "class": "dataset",
+        "label": source_data.name,
+        "id": ids,
+        "size": sizes,
+        "role": { "metric": [JSONSTAT_MEASURE_DIMENSION] },
+        "dimension": dimension_objs,
+        "value": values,
+    });
+    if let Some(ref annotations) = source_data.annotations {
+        res["extension"] = json!({ "annotations": sorted_annotations(annotations) });
+    }
+
+    Ok(serde_json::to_string(&res)?)
+}
+
+/// Formats response `DataFrame` to an SDMX-JSON 1.0 data message.
+///
+/// Each drilldown level is an observation dimension, and the measures are
+/// the values of a last `MEASURE` dimension, so that each observation has
+/// one value. Ids are made of letters, digits, `_`, `@`, `$` and `-`, other
+/// characters are replaced by `_`; names are kept as they are.
+fn format_sdmx(headers: &[String], df: DataFrame, source_data: Option<SourceMetadata>) -> Result<String, Error> {
+    let source_data = source_data
+        .ok_or_else(|| format_err!("sdmx format is only supported for aggregate queries"))?;
+    let (dimensions, measures_idx) = result_dimensions(headers, &df, &source_data)?;
+    let measures = &headers[measures_idx..];
+
+    let mut observations = Map::new();
+    for row_idx in 0..df.len() {
+        let mut key: Vec<String> = dimensions.iter()
+            .map(|dimension| dimension.positions[row_idx].to_string())
+            .collect();
+        for measure_idx in 0..measures.len() {
+            let value = cell_value(&df.columns[measures_idx + measure_idx].column_data, row_idx);
+            key.push(measure_idx.to_string());
+            observations.insert(key.join(":"), json!([value]));
+            key.pop();
+        }
+    }
+
+    let mut observation_dims: Vec<Value> = dimensions.iter()
+        .enumerate()
+        .map(|(position, dimension)| {
+            json!({
+                "id": sdmx_id(dimension.id),
+                "name": dimension.id,
+                "keyPosition": position,
+                "values": dimension.codes.iter()
+                    .map(|(id, name)| json!({ "id": sdmx_id(id), "name": name }))
+                    .collect::<Vec<_>>(),
+                "annotations": sdmx_annotations(dimension.annotations),
+            })
+        })
+        .collect();
+    observation_dims.push(json!({
+        "id": SDMX_MEASURE_DIMENSION,
+        "name": JSONSTAT_MEASURE_DIMENSION,
+        "keyPosition": dimensions.len(),
+        "values": measures.iter()
+            .map(|measure| json!({ "id": sdmx_id(measure), "name": measure }))
+            .collect::<Vec<_>>(),
+    }));
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    let res = json!({
+        "header": {
+            "id": format!("tesseract-{}", now),
+            "test": false,
+            "prepared": utc_timestamp(now),
+            "sender": { "id": "tesseract" },
+        },
+        "dataSets": [{
+            "action": "Information",
+            "observations": observations,
+        }],
+        "structure": {
+            "name": source_data.name,
+            "dimensions": {
+                "dataSet": [],
+                "series": [],
+                "observation": observation_dims,
+            },
+            "attributes": {
+                "dataSet": [],
+                "series": [],
+                "observation": [],
+            },
+            "annotations": sdmx_annotations(source_data.annotations.as_ref()),
+        },
+    });
+
+    Ok(serde_json::to_string(&res)?)
+}
+
+/// A dimension of a result, for formats that describe the dimensions: a
+/// drilldown column, or a level's ID column and name column together.
+struct ResultDimension<'a> {
+    /// The header of the (name) column
+    id: &'a str,
+    annotations: Option<&'a HashMap<String, String>>,
+    /// Member ids and captions, in order of appearance
+    codes: IndexMap<String, String>,
+    /// The position in `codes` of the member in each row
+    positions: Vec<usize>,
+}
+
+/// Splits a result into its dimensions, and the index of the first measure
+/// column. Drilldown columns come before the measures, which start at the
+/// first measure of the cube; calculations after them are measures too.
+fn result_dimensions<'a>(
+    headers: &'a [String],
+    df: &DataFrame,
+    source_data: &'a SourceMetadata,
+    ) -> Result<(Vec<ResultDimension<'a>>, usize), Error>
+{
+    if headers.len() != df.columns.len() {
+        bail!("{} headers for {} columns", headers.len(), df.columns.len());
+    }
+
+    let measures_idx = headers.iter()
+        .position(|header| source_data.measures.contains(header))
+        .ok_or_else(|| format_err!("The query has no measures"))?;
+
+    let mut dimensions = vec![];
+    let mut col_idx = 0;
+
+    while col_idx < measures_idx {
+        // a level with a name column has its ID column first
+        let name_idx = if col_idx + 1 < measures_idx && headers[col_idx] == format!("{} ID", headers[col_idx + 1]) {
+            col_idx + 1
+        } else {
+            col_idx
+        };
+
+        let header = &headers[name_idx];
+        let annotations = source_data.dimensions.iter()
+            .find(|dimension| dimension.levels.contains(header))
+            .and_then(|dimension| dimension.annotations.as_ref());
+
+        let mut codes = IndexMap::new();
+        let positions = (0..df.len())
+            .map(|row_idx| {
+                let code = cell_string(&df.columns[col_idx].column_data, row_idx);
+                let caption = cell_string(&df.columns[name_idx].column_data, row_idx);
+                codes.insert_full(code, caption).0
+            })
+            .collect();
+
+        dimensions.push(ResultDimension { id: header, annotations, codes, positions });
+        col_idx = name_idx + 1;
+    }
+
+    Ok((dimensions, measures_idx))
+}
+
+fn cell_string(column_data: &ColumnData, row_idx: usize) -> String {
+    match cell_value(column_data, row_idx) {
+        Value::String(s) => s,
+        Value::Null => "".to_owned(),
+        value => value.to_string(),
+    }
+}
+
+fn cell_value(column_data: &ColumnData, row_idx: usize) -> Value {
+    match column_data {
+        ColumnData::Int8(ns) =>    ns[row_idx].into(),
+        ColumnData::Int16(ns) =>   ns[row_idx].into(),
+        ColumnData::Int32(ns) =>   ns[row_idx].into(),
+        ColumnData::Int64(ns) =>   ns[row_idx].into(),
+        ColumnData::UInt8(ns) =>   ns[row_idx].into(),
+        ColumnData::UInt16(ns) =>  ns[row_idx].into(),
+        ColumnData::UInt32(ns) =>  ns[row_idx].into(),
+        ColumnData::UInt64(ns) =>  ns[row_idx].into(),
+        ColumnData::Float32(ns) => ns[row_idx].into(),
+        ColumnData::Float64(ns) => ns[row_idx].into(),
+        ColumnData::Text(ss) =>    ss[row_idx].clone().into(),
+        ColumnData::NullableInt8(ns) =>    ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableInt16(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableInt32(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableInt64(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt8(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt16(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt32(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableUInt64(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableFloat32(ns) => ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableFloat64(ns) => ns[row_idx].map(|n| n.into()).unwrap_or(Value::Null),
+        ColumnData::NullableText(ss) =>    ss[row_idx].clone().map(|s| s.into()).unwrap_or(Value::Null),
+    }
+}
+
+fn sorted_annotations(annotations: &HashMap<String, String>) -> BTreeMap<&String, &String> {
+    annotations.iter().collect()
+}
+
+fn sdmx_annotations(annotations: Option<&HashMap<String, String>>) -> Vec<Value> {
+    annotations
+        .map(|annotations| {
+            sorted_annotations(annotations).into_iter()
+                .map(|(name, text)| json!({ "type": name, "text": text }))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn sdmx_id(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "_@$-".contains(c) { c } else { '_' })
+        .collect()
+}
+
+/// Seconds since the epoch as `YYYY-MM-DDThh:mm:ssZ`.
+fn utc_timestamp(secs: u64) -> String {
+    // civil date from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60,
+    )
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dataframe::Column;
+    use crate::schema::metadata::SourceDimensionMetadata;
+
+    fn sales() -> (Vec<String>, DataFrame, SourceMetadata) {
+        let headers = vec!["State ID".to_owned(), "State".to_owned(), "Year".to_owned(), "Quantity".to_owned()];
+        let df = DataFrame::from_vec(vec![
+            Column::new("state_id".to_owned(), ColumnData::Text(vec!["ca".into(), "ny".into(), "ca".into()])),
+            Column::new("state_name".to_owned(), ColumnData::Text(vec!["California".into(), "New York".into(), "California".into()])),
+            Column::new("year".to_owned(), ColumnData::Int32(vec![2018, 2018, 2019])),
+            Column::new("quantity".to_owned(), ColumnData::NullableFloat64(vec![Some(1.5), None, Some(3.0)])),
+        ]);
+        let source_data = SourceMetadata {
+            name: "sales".to_owned(),
+            measures: vec!["Quantity".to_owned()],
+            annotations: None,
+            dimensions: vec![
+                SourceDimensionMetadata {
+                    name: "Geography".to_owned(),
+                    levels: vec!["State".to_owned()],
+                    annotations: Some(vec![("source".to_owned(), "census".to_owned())].into_iter().collect()),
+                },
+            ],
+        };
+        (headers, df, source_data)
+    }
+
+    #[test]
+    fn test_format_jsonstat() {
+        let (headers, df, source_data) = sales();
+        let res = format_records(&headers, df, FormatType::JsonStat, Some(source_data), false).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&res).unwrap(), json!({
+            "version": "2.0",
+            "class": "dataset",
+            "label": "sales",
+            "id": ["State", "Year", "Measure"],
+            "size": [2, 2, 1],
+            "role": { "metric": ["Measure"] },
+            "dimension": {
+                "State": {
+                    "label": "State",
+                    "category": {
+                        "index": ["ca", "ny"],
+                        "label": { "ca": "California", "ny": "New York" },
+                    },
+                    "extension": { "annotations": { "source": "census" } },
+                },
+                "Year": {
+                    "label": "Year",
+                    "category": {
+                        "index": ["2018", "2019"],
+                        "label": { "2018": "2018", "2019": "2019" },
+                    },
+                },
+                "Measure": {
+                    "label": "Measure",
+                    "category": {
+                        "index": ["Quantity"],
+                        "label": { "Quantity": "Quantity" },
+                    },
+                },
+            },
+            "value": [1.5, 3.0, null, null],
+        }));
+
+        // measures are needed to tell dimensions from values
+        let (headers, df, _) = sales();
+        assert!(format_records(&headers, df, FormatType::JsonStat, None, false).is_err());
+    }
+
+    #[test]
+    fn test_format_sdmx() {
+        let (headers, df, source_data) = sales();
+        let res = format_records(&headers, df, FormatType::Sdmx, Some(source_data), false).unwrap();
+        let res: Value = serde_json::from_str(&res).unwrap();
+
+        assert_eq!(res["header"]["sender"], json!({ "id": "tesseract" }));
+        assert_eq!(res["dataSets"], json!([{
+            "action": "Information",
+            "observations": { "0:0:0": [1.5], "1:0:0": [null], "0:1:0": [3.0] },
+        }]));
+        assert_eq!(res["structure"]["dimensions"]["observation"][0], json!({
+            "id": "State",
+            "name": "State",
+            "keyPosition": 0,
+            "values": [{ "id": "ca", "name": "California" }, { "id": "ny", "name": "New York" }],
+            "annotations": [{ "type": "source", "text": "census" }],
+        }));
+        assert_eq!(res["structure"]["dimensions"]["observation"][2], json!({
+            "id": "MEASURE",
+            "name": "Measure",
+            "keyPosition": 2,
+            "values": [{ "id": "Quantity", "name": "Quantity" }],
+        }));
+
+        assert_eq!(sdmx_id("New York, NY"), "New_York__NY");
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_868_799), "2000-02-29T23:59:59Z");
+    }
+}
@@ -3,7 +3,9 @@ mod dataframe;
 mod sql;
 pub mod format;
 pub mod format_stream;
+pub mod mdx;
 pub mod names;
+mod parse;
 pub mod schema;
 pub mod query;
 pub mod query_ir;
@@ -30,6 +32,7 @@ use self::names::{
     Mask,
 };
 pub use self::schema::{Schema, Cube, Dimension, Table, Aggregator};
+use self::schema::{DimensionType, TimeGranularity};
 use self::schema::metadata::{SchemaMetadata, CubeMetadata};
 use self::query_ir::{
     CutSql,
@@ -44,14 +47,26 @@ use self::query_ir::{
     SortSql,
     RcaSql,
     GrowthSql,
+    CumulativeSql,
+    DateKey,
+    ResetSql,
+    MovingAvgSql,
+    IndexSql,
+    RankSql,
+    TrendSql,
     RateSql,
+    GroupSql,
+    BinsSql,
     FilterSql,
+    FilterExprSql,
+    check_member,
 };
 pub use self::query::{Query, MeaOrCalc, FilterQuery};
+use self::query::{FilterExpr, GrowthMode, PeriodToDate};
 pub use self::query_ir::QueryIr;
 macro_rules! mea_or_calc {
     ($m_or_c:expr, $query:expr) => {
-        match $m_or_c {
+        match &$m_or_c.resolve(&$query.measures) {
             MeaOrCalc::Mea(m) => {
                 $query.measures.iter()
                     .position(|col| col == m )
@@ -114,6 +129,52 @@ impl Schema {
             }
         };
 
+        // fiscal years only make sense on time dimensions
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                if let Some(month) = dim.fiscal_year_start_month {
+                    if dim.dim_type != DimensionType::Time {
+                        bail!("Fiscal year start month is only allowed on time dimensions, found on cube: {} dimension: {}", cube.name, dim.name);
+                    }
+                    if !(1..=12).contains(&month) {
+                        bail!("Fiscal year start month must be 1-12, found {} on cube: {} dimension: {}", month, cube.name, dim.name);
+                    }
+                }
+            }
+        }
+
+        // levels derived from a date column need a date column and a time dimension,
+        // and all levels of such a hierarchy must be derived.
+        for cube in &self.cubes {
+            for dim in &cube.dimensions {
+                for hier in &dim.hierarchies {
+                    if hier.date_column.is_some() && dim.dim_type != DimensionType::Time {
+                        bail!("Date column is only allowed on time dimensions, found on cube: {} dimension: {}", cube.name, dim.name);
+                    }
+                    for level in &hier.levels {
+                        match &level.granularity {
+                            Some(granularity) => {
+                                if hier.date_column.is_none() {
+                                    bail!("Level granularity requires a date column on the hierarchy, found on cube: {} level: {}", cube.name, level.name);
+                                }
+                                if granularity.is_fiscal() && dim.fiscal_year_start_month.is_none() {
+                                    bail!("Fiscal granularity requires a fiscal year start month, found on cube: {} level: {}", cube.name, level.name);
+                                }
+                            },
+                            None => {
+                                if hier.date_column.is_some() {
+                                    bail!("Levels of a hierarchy with a date column must have a granularity, found on cube: {} level: {}", cube.name, level.name);
+                                }
+                                if level.key_column.is_empty() {
+                                    bail!("Level key column is required, found on cube: {} level: {}", cube.name, level.name);
+                                }
+                            },
+                        }
+                    }
+                }
+            }
+        }
+
         // if there's multiple hierarchies in a dim, there must be a default hierarchy.
         // also, the default hierarchy must match names with an actual hierarchy.
         //
@@ -208,14 +269,21 @@ impl Schema {
         CubeHasUniqueLevelsAndProperties::True
     }
 
+    /// `backend` derives the keys of levels with a date column.
     pub fn members_sql(
         &self,
         cube: &str,
         level_name: &LevelName,
+        backend: &dyn Backend,
         ) -> Result<(String, Vec<String>), Error> // Sql and then Header
     {
         let members_query_ir = self.get_dim_col_table(cube, level_name)?;
 
+        let key_sql = match members_query_ir.date_key {
+            Some(ref date_key) => backend.date_key_sql(date_key),
+            None => members_query_ir.key_column.clone(),
+        };
+
         let header = if members_query_ir.name_column.is_some() {
             vec!["ID".into(), "Label".into()]
         } else {
@@ -229,7 +297,7 @@ impl Schema {
         };
 
         let sql = format!("select distinct {}{}{} from {}",
-            members_query_ir.key_column,
+            key_sql,
             if members_query_ir.name_column.is_some() { ", " } else { "" },
             name_col,
             members_query_ir.table_sql,
@@ -244,7 +312,8 @@ impl Schema {
         &self,
         cube_name: &str,
         level_name: &LevelName,
-        locale: &str
+        locale: &str,
+        backend: &dyn Backend,
     ) -> Result<(String, Vec<String>), Error> // Sql and then Header
     {
         let locales: Vec<String> = locale.split(",").map(|s| s.to_string()).collect();
@@ -265,7 +334,10 @@ impl Schema {
 
         let table = hier.table.clone().unwrap_or_else(|| cube.table.clone());
 
-        let key_column = level.key_column.clone();
+        let key_column = match dim.date_key(hier, level) {
+            Some(date_key) => backend.date_key_sql(&date_key),
+            None => level.key_column.clone(),
+        };
         let mut header = vec!["ID".into()];
         let mut name_columns: Vec<String> = vec![];
 
@@ -400,11 +472,13 @@ impl Schema {
 
         // First do checks, like making sure there's a measure, and that there's
         // either a cut or drilldown
+        query.validate()?;
+
         if query.measures.is_empty() && query.rca.is_none() {
             return Err(format_err!("No measure found; please specify at least one"));
         }
-        if query.drilldowns.is_empty() && query.cuts.is_empty(){
-            return Err(format_err!("Either a drilldown or cut is required"));
+        if query.drilldowns.is_empty() && query.cuts.is_empty() && query.bins.is_none() {
+            return Err(format_err!("Either a drilldown, bins or cut is required"));
         }
 
         // also check that properties have a matching drilldown
@@ -478,11 +552,113 @@ impl Schema {
             if !query.drilldowns.contains(&growth.time_drill) {
                 bail!("Growth time drilldown {} is not in drilldowns", growth.time_drill);
             }
-            if !query.measures.contains(&growth.mea) {
-                bail!("Growth measure {} is not in measures", growth.mea);
+            for mea in &growth.meas {
+                if !query.measures.contains(mea) {
+                    bail!("Growth measure {} is not in measures", mea);
+                }
+            }
+        }
+
+        // for window calculations, same checks as growth. They keep the
+        // column layout of the previous step, so can't follow growth or rate.
+        if let Some(ref cumulative) = query.cumulative {
+            if !query.drilldowns.contains(&cumulative.time_drill) {
+                bail!("Cumulative time drilldown {} is not in drilldowns", cumulative.time_drill);
+            }
+            if !query.measures.contains(&cumulative.mea) {
+                bail!("Cumulative measure {} is not in measures", cumulative.mea);
+            }
+        }
+        if let Some(ref moving_avg) = query.moving_avg {
+            if !query.drilldowns.contains(&moving_avg.time_drill) {
+                bail!("Moving avg time drilldown {} is not in drilldowns", moving_avg.time_drill);
+            }
+            if !query.measures.contains(&moving_avg.mea) {
+                bail!("Moving avg measure {} is not in measures", moving_avg.mea);
+            }
+        }
+        if let Some(ref index) = query.index {
+            if !query.drilldowns.contains(&index.drill) {
+                bail!("Index drilldown {} is not in drilldowns", index.drill);
+            }
+            if !query.measures.contains(&index.mea) {
+                bail!("Index measure {} is not in measures", index.mea);
+            }
+        }
+        if (query.cumulative.is_some() || query.moving_avg.is_some() || query.index.is_some()) &&
+            (query.growth.is_some() || query.rate.is_some())
+        {
+            bail!("Cumulative, moving avg and index cannot be combined with growth or rate");
+        }
+
+        // for rank, same checks as window calculations, but it can follow growth
+        if let Some(ref rank) = query.rank {
+            if !query.drilldowns.contains(&rank.drill) {
+                bail!("Rank drilldown {} is not in drilldowns", rank.drill);
+            }
+            if !query.measures.contains(&rank.mea) {
+                bail!("Rank measure {} is not in measures", rank.mea);
+            }
+            if query.rate.is_some() {
+                bail!("Rank cannot be combined with rate");
             }
         }
 
+        // trend adds forecast rows with null measures, which only window
+        // calculations before it can handle.
+        if let Some(ref trend) = query.trend {
+            if !query.drilldowns.contains(&trend.time_drill) {
+                bail!("Trend time drilldown {} is not in drilldowns", trend.time_drill);
+            }
+            if !query.measures.contains(&trend.mea) {
+                bail!("Trend measure {} is not in measures", trend.mea);
+            }
+            if query.rca.is_some() || query.growth.is_some() || query.rate.is_some() || query.top.is_some() {
+                bail!("Trend cannot be combined with rca, growth, rate or top");
+            }
+        }
+
+        // for totals, levels must be drilled down on. Subtotal rows have null
+        // keys, so calculations and top over them don't make sense.
+        for total in &query.totals {
+            if !query.drilldowns.iter().any(|d| d.0 == *total) {
+                bail!("Totals level {} is not in drilldowns", total);
+            }
+        }
+        if !query.totals.is_empty() && (
+            query.rca.is_some() || query.growth.is_some() || query.rate.is_some() ||
+            query.cumulative.is_some() || query.moving_avg.is_some() || query.index.is_some() || query.rank.is_some() || query.trend.is_some() ||
+            query.top.is_some())
+        {
+            bail!("Totals cannot be combined with calculations or top");
+        }
+
+        // groups are re-aggregated from the aggregated rows, same as top others
+        if let Some(ref group) = query.group {
+            if !query.drilldowns.iter().any(|d| d.0 == group.level_name) {
+                bail!("Group level {} is not in drilldowns", group.level_name);
+            }
+            if query.rca.is_some() || query.growth.is_some() || query.rate.is_some() ||
+                query.cumulative.is_some() || query.moving_avg.is_some() || query.index.is_some() || query.rank.is_some() || query.trend.is_some() ||
+                !query.totals.is_empty()
+            {
+                bail!("Groups cannot be combined with calculations or totals");
+            }
+            if group.groups.is_empty() {
+                bail!("Group on {} has no groups", group.level_name);
+            }
+        }
+
+        // bins are extra drilldown columns that only the primary aggregation
+        // knows about
+        if query.bins.is_some() && (
+            query.rca.is_some() || query.growth.is_some() || query.rate.is_some() ||
+            query.cumulative.is_some() || query.moving_avg.is_some() || query.index.is_some() || query.rank.is_some() || query.trend.is_some() ||
+            query.top.is_some() || query.group.is_some() || !query.totals.is_empty())
+        {
+            bail!("Bins cannot be combined with calculations, top, groups or totals");
+        }
+
         // for rca, disallow cuts on the second drilldown for now, until better system
         // is figured out.
         // There is internal filtering of cuts internally also, which should follow the
@@ -584,11 +760,25 @@ impl Schema {
                     .ok_or(format_err!("Top by_dimension must be in drilldowns"))?;
             }
 
+            // others are re-aggregated from the aggregated rows, so
+            // calculations and non-additive measures aren't supported.
+            if t.others {
+                if query.rca.is_some() || query.growth.is_some() || query.rate.is_some() ||
+                    query.cumulative.is_some() || query.moving_avg.is_some() || query.index.is_some() || query.rank.is_some() || query.trend.is_some()
+                {
+                    bail!("Top others cannot be combined with calculations");
+                }
+                if let Some(mea) = mea_cols.iter().find(|m| m.reaggregate_fn().is_none()) {
+                    bail!("Top others is not supported for measure column {}, aggregator must be sum, count, max or min", mea.column);
+                }
+            }
+
             Some(TopSql {
                 n: t.n,
                 by_column: self.get_dim_col_alias(&cube, &t.by_dimension)?,
                 sort_columns: top_sort_columns,
                 sort_direction: t.sort_direction.clone(),
+                others: t.others,
             })
         } else {
             None
@@ -623,6 +813,10 @@ impl Schema {
             .collect::<Result<Vec<_>,_>>();
         let filters = filters?;
 
+        let filter = query.filter.as_ref()
+            .map(|expr| filter_expr_sql(expr, query))
+            .transpose()?;
+
         let sort = if let Some(ref s) = query.sort {
             // sort column needs to be named by alias
             let sort_column = mea_or_calc!(&s.measure, query)?;
@@ -661,19 +855,151 @@ impl Schema {
                 .clone();
 
             // just want the measure id, not the actual measure col
-            let mea = query.measures.iter()
-                    .position(|mea| *mea == growth.mea )
-                    .map(|idx| format!("final_m{}", idx))
-                    .ok_or(format_err!("measure for Growth must be in measures"))?;
+            let meas = growth.meas.iter()
+                .map(|growth_mea| mea_or_calc!(&MeaOrCalc::Mea(growth_mea.clone()), query))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // periods are matched by key, so the key must be a number
+            let time_level = schema_cube.get_level(&growth.time_drill.0)
+                .ok_or(format_err!("could not find level for growth time drill {}", growth.time_drill))?;
+            if time_level.key_type == Some(MemberType::Text) && time_level.granularity.is_none() {
+                bail!("Growth time level {} must have an integer key", growth.time_drill);
+            }
 
             Some(GrowthSql {
+                time_drill,
+                meas,
+                lag: growth.lag,
+                mode: growth.mode.clone(),
+                granularity: time_level.time_granularity(),
+            })
+        } else {
+            None
+        };
+
+        let cumulative = if let Some(ref cumulative) = query.cumulative {
+            let time_drill = self.cube_drill_cols(cube, std::slice::from_ref(&cumulative.time_drill), &query.properties, &query.captions, query.parents)?
+                .first()
+                .ok_or(format_err!("no drilldown found for cumulative"))?
+                .clone();
+
+            let mea = mea_or_calc!(&MeaOrCalc::Mea(cumulative.mea.clone()), query)?;
+
+            let reset = if let Some(ref to_date) = cumulative.to_date {
+                Some(self.cube_reset_col(cube, &cumulative.time_drill, to_date, &time_drill)?)
+            } else {
+                None
+            };
+
+            Some(CumulativeSql {
                 time_drill,
                 mea,
+                reset,
             })
         } else {
             None
         };
 
+        let moving_avg = if let Some(ref moving_avg) = query.moving_avg {
+            let time_drill = self.cube_drill_cols(cube, std::slice::from_ref(&moving_avg.time_drill), &query.properties, &query.captions, query.parents)?
+                .first()
+                .ok_or(format_err!("no drilldown found for moving avg"))?
+                .clone();
+
+            let mea = mea_or_calc!(&MeaOrCalc::Mea(moving_avg.mea.clone()), query)?;
+
+            Some(MovingAvgSql {
+                time_drill,
+                mea,
+                window: moving_avg.window,
+            })
+        } else {
+            None
+        };
+
+        let index = if let Some(ref index) = query.index {
+            let drill = self.cube_drill_cols(cube, std::slice::from_ref(&index.drill), &query.properties, &query.captions, query.parents)?
+                .first()
+                .ok_or(format_err!("no drilldown found for index"))?
+                .clone();
+
+            let level = schema_cube.get_level(&index.drill.0)
+                .ok_or(format_err!("could not find level for index {}", index.drill))?;
+            let member_type = level.key_type.unwrap_or(MemberType::NonText);
+
+            // base is inlined into the sql
+            check_member(&index.base, &member_type)
+                .map_err(|err| format_err!("Could not use index base for {}: {}", index.drill, err))?;
+
+            let mea = mea_or_calc!(&MeaOrCalc::Mea(index.mea.clone()), query)?;
+
+            Some(IndexSql {
+                by_column: self.get_dim_col_alias(cube, &index.drill.0)?,
+                drill,
+                member_type,
+                base: index.base.clone(),
+                mea,
+            })
+        } else {
+            None
+        };
+
+        let rank = if let Some(ref rank) = query.rank {
+            let drill = self.cube_drill_cols(cube, std::slice::from_ref(&rank.drill), &query.properties, &query.captions, query.parents)?
+                .first()
+                .ok_or(format_err!("no drilldown found for rank"))?
+                .clone();
+
+            let mea = mea_or_calc!(&MeaOrCalc::Mea(rank.mea.clone()), query)?;
+
+            Some(RankSql {
+                drill,
+                mea,
+                direction: rank.direction.clone(),
+                method: rank.method.clone(),
+            })
+        } else {
+            None
+        };
+
+        let trend = if let Some(ref trend) = query.trend {
+            let time_drill = self.cube_drill_cols(cube, std::slice::from_ref(&trend.time_drill), &query.properties, &query.captions, query.parents)?
+                .first()
+                .ok_or(format_err!("no drilldown found for trend"))?
+                .clone();
+
+            let mea = mea_or_calc!(&MeaOrCalc::Mea(trend.mea.clone()), query)?;
+
+            // forecast keys are projected from the last key, so it must be a number
+            let time_level = schema_cube.get_level(&trend.time_drill.0)
+                .ok_or(format_err!("could not find level for trend time drill {}", trend.time_drill))?;
+            if trend.periods > 0 && time_level.key_type == Some(MemberType::Text) && time_level.granularity.is_none() {
+                bail!("Trend forecast time level {} must have an integer key", trend.time_drill);
+            }
+
+            Some(TrendSql {
+                time_drill,
+                mea,
+                periods: trend.periods,
+                granularity: time_level.time_granularity(),
+            })
+        } else {
+            None
+        };
+
+        let totals = query.totals.iter()
+            .map(|level_name| {
+                let drill = query.drilldowns.iter()
+                    .find(|d| d.0 == *level_name)
+                    .ok_or(format_err!("Totals level {} is not in drilldowns", level_name))?;
+
+                self.cube_drill_cols(cube, std::slice::from_ref(drill), &query.properties, &query.captions, query.parents)?
+                    .first()
+                    .cloned()
+                    .ok_or(format_err!("no drilldown found for totals"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         let rate = if let Some(ref rate) = query.rate {
             // For now at least, we'll allow drilldowns and cuts on the level
             // used for the rate calculation. Drilldowns will always result in
@@ -705,10 +1031,52 @@ impl Schema {
             None
         };
 
+        let group = if let Some(ref group) = query.group {
+            if let Some(mea) = mea_cols.iter().find(|m| m.reaggregate_fn().is_none()) {
+                bail!("Groups are not supported for measure column {}, aggregator must be sum, count, max or min", mea.column);
+            }
+
+            let level = schema_cube.get_level(&group.level_name)
+                .ok_or(format_err!("could not find level for group {}", group.level_name))?;
+            let member_type = level.key_type.unwrap_or(MemberType::NonText);
+
+            // members and names are inlined into the sql
+            for g in &group.groups {
+                check_member(&g.name, &MemberType::Text)
+                    .and_then(|_| g.members.iter().try_for_each(|m| check_member(m, &member_type)))
+                    .map_err(|err| format_err!("Could not use group {}: {}", g.name, err))?;
+            }
+
+            Some(GroupSql {
+                by_column: self.get_dim_col_alias(cube, &group.level_name)?,
+                member_type,
+                groups: group.groups.clone(),
+                keep_others: group.keep_others,
+            })
+        } else {
+            None
+        };
+
+        let bins = if let Some(ref bins) = query.bins {
+            Some(BinsSql {
+                column: self.get_mea_col(cube, &bins.measure)?,
+                table: table.name.clone(),
+                bins: bins.bins.clone(),
+            })
+        } else {
+            None
+        };
+
         // getting headers, not for sql but needed for formatting
         let mut drill_headers = self.cube_drill_headers(&cube, &query.drilldowns, &query.properties, query.parents, unique_header_map)
             .map_err(|err| format_err!("Error getting drill headers: {}", err))?;
 
+        if let Some(ref bins) = query.bins {
+            drill_headers.push(format!("{} Bin", bins.measure.0));
+            drill_headers.push(format!("{} Bin Lower", bins.measure.0));
+            drill_headers.push(format!("{} Bin Upper", bins.measure.0));
+        }
+
         let mut mea_headers = self.cube_mea_headers(&cube, &query.measures)
             .map_err(|err| format_err!("Error getting mea headers: {}", err))?;
 
@@ -730,15 +1098,32 @@ impl Schema {
         // Be careful with other calculations.
         // TODO figure out a more composable system.
         let mut headers = if let Some(ref growth) = query.growth {
-            // swapping around measure headers. growth mea moves to back.
-            let g_mea_idx = query.measures.iter()
-                    .position(|mea| *mea == growth.mea )
-                    .ok_or(format_err!("measure for Growth must be in measures"))?;
+            // swapping around measure headers. growth meas move to back,
+            // each followed by its growth and growth value.
+            let (growth_label, value_label) = match growth.mode {
+                GrowthMode::PeriodOverPeriod => ("Growth", "Growth Value"),
+                GrowthMode::Cagr => ("CAGR", "CAGR Value"),
+            };
+
+            // rca header comes before the measure headers
+            let rca_offset = if query.rca.is_some() { 1 } else { 0 };
 
-            let moved_mea = mea_headers.remove(g_mea_idx);
-            mea_headers.push(moved_mea);
-            mea_headers.push(format!("{} Growth", growth.mea.0));
-            mea_headers.push(format!("{} Growth Value", growth.mea.0));
+            let g_mea_headers = mea_headers.split_off(rca_offset);
+            mea_headers.extend(query.measures.iter()
+                .zip(g_mea_headers.iter())
+                .filter(|(mea, _)| !growth.meas.contains(mea))
+                .map(|(_, hdr)| hdr.clone())
+            );
+
+            for g_mea in &growth.meas {
+                let g_mea_idx = query.measures.iter()
+                        .position(|mea| mea == g_mea)
+                        .ok_or(format_err!("measure for Growth must be in measures"))?;
+
+                mea_headers.push(g_mea_headers[g_mea_idx].clone());
+                mea_headers.push(format!("{} {}", g_mea.0, growth_label));
+                mea_headers.push(format!("{} {}", g_mea.0, value_label));
+            }
 
             // swapping around drilldown headers. Move time to back
             let time_headers = self.cube_drill_headers(&cube, &[growth.time_drill.clone()], &[], query.parents, unique_header_map)
@@ -759,13 +1144,43 @@ impl Schema {
                 let moved_hdr = drill_headers.remove(*idx);
                 temp_time_headers.insert(0, moved_hdr);
             }
+            // rca debug cols are values, so they stay after the time cols
+            let debug_headers = match query.rca {
+                Some(_) if query.debug => drill_headers.split_off(drill_headers.len() - 4),
+                _ => vec![],
+            };
             drill_headers.extend_from_slice(&temp_time_headers);
+            drill_headers.extend_from_slice(&debug_headers);
 
             [&drill_headers[..], &mea_headers[..]].concat()
         } else {
             [&drill_headers[..], &mea_headers[..]].concat()
         };
 
+        // Window calculations keep column order, and are appended
+        if let Some(ref cumulative) = query.cumulative {
+            headers.push(format!("{} Cumulative", cumulative.mea.0));
+        }
+        if let Some(ref moving_avg) = query.moving_avg {
+            headers.push(format!("{} Moving Avg", moving_avg.mea.0));
+        }
+        if let Some(ref index) = query.index {
+            headers.push(format!("{} Index", index.mea.0));
+        }
+        if let Some(ref rank) = query.rank {
+            headers.push(format!("{} Rank", rank.mea.0));
+        }
+        if let Some(ref trend) = query.trend {
+            headers.push(format!("{} Trend Slope", trend.mea.0));
+            headers.push(format!("{} Trend Intercept", trend.mea.0));
+            headers.push(format!("{} Trend", trend.mea.0));
+            headers.push("Forecast".to_owned());
+        }
+        // flags subtotal and grand total rows
+        if !query.totals.is_empty() {
+            headers.push("Total".to_owned());
+        }
+
         // Rate calculations always come last
         if query.rate.is_some() {
             headers.push("Rate".to_string());
@@ -779,13 +1194,22 @@ impl Schema {
                 meas: mea_cols,
                 hidden_drills: hidden_drill_cols,
                 filters,
+                filter,
                 top,
                 top_where,
                 sort,
                 limit,
                 rca,
                 growth,
+                cumulative,
+                moving_avg,
+                index,
+                rank,
+                trend,
+                totals,
                 rate,
+                group,
+                bins,
                 sparse: query.sparse,
             },
             headers,
@@ -793,6 +1217,26 @@ impl Schema {
     }
 }
 
+/// Resolves the measures and calculations of a filter expression to columns.
+fn filter_expr_sql(expr: &FilterExpr, query: &Query) -> Result<FilterExprSql, Error> {
+    Ok(match expr {
+        FilterExpr::Compare(m_or_c, constraint) => {
+            FilterExprSql::Compare(mea_or_calc!(m_or_c, query)?, constraint.clone())
+        },
+        FilterExpr::Between(m_or_c, low, high) => {
+            FilterExprSql::Between(mea_or_calc!(m_or_c, query)?, *low, *high)
+        },
+        FilterExpr::IsNull(m_or_c) => FilterExprSql::IsNull(mea_or_calc!(m_or_c, query)?),
+        FilterExpr::Not(e) => FilterExprSql::Not(Box::new(filter_expr_sql(e, query)?)),
+        FilterExpr::And(l, r) => {
+            FilterExprSql::And(Box::new(filter_expr_sql(l, query)?), Box::new(filter_expr_sql(r, query)?))
+        },
+        FilterExpr::Or(l, r) => {
+            FilterExprSql::Or(Box::new(filter_expr_sql(l, query)?), Box::new(filter_expr_sql(r, query)?))
+        },
+    })
+}
+
 impl Schema {
     fn cube_table(&self, cube_name: &str) -> Option<TableSql> {
         self.cubes.iter()
@@ -836,18 +1280,47 @@ impl Schema {
                 .clone()
                 .ok_or(format_err!("No foreign key; it's required for now (until inline dim implemented)"))?;
 
-            let column = if cut.for_match {
-                level.name_column.clone().unwrap_or(level.key_column.clone())
+            // properties have no type in the schema, so their members are
+            // always treated as text
+            let (column, member_type) = if let Some(ref property) = cut.property {
+                let property = level.properties.iter()
+                    .flatten()
+                    .find(|p| &p.name == property)
+                    .ok_or(format_err!("could not find property {} for cut {}", property, cut.level_name))?;
+
+                (property.column.clone(), MemberType::Text)
+            } else if cut.for_match {
+                if level.name_column.is_none() && level.granularity.is_some() {
+                    bail!("match cut on {} needs a name column, keys derived from a date can't be matched", cut.level_name);
+                }
+                (level.name_column.clone().unwrap_or(level.key_column.clone()), MemberType::Text)
             } else {
-                level.key_column.clone()
+                (level.key_column.clone(), level.key_type.clone().unwrap_or(MemberType::NonText))
             };
 
-            let member_type = if cut.for_match {
-                MemberType::Text
+            // members are inlined into the sql
+            for member in &cut.members {
+                check_member(member, &member_type)
+                    .map_err(|err| format_err!("Could not use cut on {}: {}", cut.level_name, err))?;
+            }
+
+            let date_key = if cut.property.is_none() && !cut.for_match {
+                dim.date_key(hier, level)
             } else {
-                level.key_type.clone().unwrap_or(MemberType::NonText)
+                None
             };
 
+            if let Some(ref range) = cut.range {
+                if cut.for_match {
+                    bail!("range cut on {} cannot use match", cut.level_name);
+                }
+                // non-text keys are compared as numbers, so bounds must be numbers too
+                for bound in range.start.iter().chain(range.end.iter()) {
+                    check_member(bound, &member_type)
+                        .map_err(|err| format_err!("Could not use range cut on {}: {}", cut.level_name, err))?;
+                }
+            }
+
             res.push(CutSql {
                 table,
                 primary_key,
@@ -857,7 +1330,9 @@ impl Schema {
                 members: cut.members.clone(),
                 mask: cut.mask.clone(),
                 for_match: cut.for_match,
+                range: cut.range.clone(),
                 inline_table: hier.inline_table.clone(),
+                date_key,
             });
         }
 
@@ -987,6 +1462,7 @@ impl Schema {
                     level_columns.push(LevelColumn {
                         key_column: levels[i].key_column.clone(),
                         name_column: caption,
+                        date_key: dim.date_key(hier, &levels[i]),
                     });
                 }
             } else {
@@ -1000,6 +1476,7 @@ impl Schema {
                 level_columns.push(LevelColumn {
                     key_column: levels[level_idx].key_column.clone(),
                     name_column: caption,
+                    date_key: dim.date_key(hier, &levels[level_idx]),
                 });
             }
 
@@ -1233,6 +1710,7 @@ impl Schema {
             table_sql,
             key_column,
             name_column,
+            date_key: dim.date_key(hier, level),
         })
     }
 
@@ -1257,6 +1735,63 @@ impl Schema {
         Ok(column)
     }
 
+    /// For period-to-date, the col that the running total restarts on.
+    ///
+    /// If the time dimension has a fiscal year, years and quarters are
+    /// derived from the time drill key, which must then be a date.
+    /// Otherwise it's the key col of the `Year`, `Quarter` or `Month` level,
+    /// which must be in the time drill cols (the drilled level, or a parent
+    /// when `parents` is set).
+    fn cube_reset_col(
+        &self,
+        cube_name: &str,
+        time_drill: &Drilldown,
+        to_date: &PeriodToDate,
+        time_drill_sql: &DrilldownSql,
+        ) -> Result<ResetSql, Error>
+    {
+        let cube = self.get_cube_by_name(cube_name)?;
+
+        let dim = cube.dimensions.iter()
+            .find(|dim| dim.name == time_drill.0.dimension)
+            .ok_or(format_err!("could not find dimension for drill {}", time_drill.0))?;
+        let hier = dim.hierarchies.iter()
+            .find(|hier| hier.name == time_drill.0.hierarchy)
+            .ok_or(format_err!("could not find hierarchy for drill {}", time_drill.0))?;
+
+        let fiscal = dim.fiscal_year_start_month
+            .filter(|month| *month != 1)
+            .is_some();
+
+        let granularity = match (to_date, fiscal) {
+            (PeriodToDate::Year, true) => TimeGranularity::FiscalYear,
+            (PeriodToDate::Quarter, true) => TimeGranularity::FiscalQuarter,
+            (PeriodToDate::Year, false) => TimeGranularity::Year,
+            (PeriodToDate::Quarter, false) => TimeGranularity::Quarter,
+            (PeriodToDate::Month, _) => TimeGranularity::Month,
+        };
+
+        // fiscal periods can't be told from the keys of other levels, only
+        // derived from a date
+        if granularity.is_fiscal() && hier.date_column.is_none() {
+            bail!("Fiscal period to date on {} needs a hierarchy with a date column, to derive a {} level from", time_drill, granularity.name());
+        }
+
+        // the reset level is found by the granularity of derived levels, or
+        // the `level` annotation of other levels
+        let reset_level = hier.levels.iter()
+            .find(|lvl| lvl.time_granularity().as_ref() == Some(&granularity))
+            .ok_or(format_err!("Time hierarchy {} has no {} level for period to date", hier.name, granularity.name()))?;
+
+        // also rules out reset levels below the drilldown, since parents
+        // only include the levels above
+        if !time_drill_sql.level_columns.iter().any(|l| l.key_column == reset_level.key_column) {
+            bail!("Period to date needs the {} level in the result, at or above drilldown {}; set parents to include it", reset_level.name, time_drill);
+        }
+
+        Ok(ResetSql::Column(format!("{}_{}", reset_level.key_column, time_drill_sql.alias_postfix)))
+    }
+
     fn get_mea_col(&self, cube_name: &str, measure: &Measure) -> Result<String, Error> {
         let cube = self.cubes.iter()
             .find(|cube| &cube.name == &cube_name)
@@ -1283,6 +1818,7 @@ struct MembersQueryIR {
     table_sql: String,
     key_column: String,
     name_column: Option<String>,
+    date_key: Option<DateKey>,
 }
 
 
@@ -1516,6 +2052,7 @@ mod test {
             measures: [Measure("Price Total".to_string())].to_vec(),
             properties: vec![],
             filters: vec![],
+            filter: None,
             captions: vec![],
             parents: false,
             top: None,
@@ -1539,7 +2076,15 @@ mod test {
                 mea: Measure("Price Total".to_string())
             }),
             growth: None,
+            cumulative: None,
+            moving_avg: None,
+            index: None,
+            trend: None,
+            rank: None,
+            totals: vec![],
             rate: None,
+            group: None,
+            bins: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
@@ -1677,6 +2222,7 @@ mod test {
                 constraint2: None,
             }
             ].to_vec(),
+            filter: Some("Quantity between 1 and 2 or not rca is null".parse().unwrap()),
             captions: vec![],
             parents: false,
             top: None,
@@ -1700,7 +2246,15 @@ mod test {
                 mea: Measure("Price Total".to_string())
             }),
             growth: None,
+            cumulative: None,
+            moving_avg: None,
+            index: None,
+            trend: None,
+            rank: None,
+            totals: vec![],
             rate: None,
+            group: None,
+            bins: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
@@ -1740,6 +2294,435 @@ mod test {
             },
             operator: None,
             constraint2: None,
-        }].to_vec())
+        }].to_vec());
+        assert_eq!(
+            query_ir.filter.unwrap().sql_string(),
+            "(final_m2 between 1 and 2 or not (rca is null))"
+        );
+    }
+
+    #[test]
+    fn test_range_cut_bounds() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Product" foreign_key="product">
+                    <Hierarchy name="Product">
+                        <Level name="Product" key_column="product" key_type="text" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Year.Year".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap()];
+
+        query.cuts = vec!["Product.Product.[a:it's]".parse().unwrap()];
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        let cut = &query_ir.cuts[0];
+        assert_eq!(cut.range_string("product", cut.range.as_ref().unwrap()), "product between 'a' and 'it''s'");
+
+        query.cuts = vec!["Year.Year.[2010:2015]".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_ok());
+
+        query.cuts = vec!["Year.Year.[2010) or (1=1:2015]".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        query.cuts = vec!["Year.Year.[inf:]".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        query.cuts = vec!["Product.Product.[a\\\\:b]".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_group_members() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+                <Dimension name="Geography" foreign_key="country_id">
+                    <Hierarchy name="Geography">
+                        <Level name="Country" key_column="country_id" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Geography.Country".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap()];
+
+        query.group = Some("Geography.Country:[Americas']=1,2;Europe=3".parse().unwrap());
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        let group = query_ir.group.unwrap();
+        assert_eq!(group.grouped_string(), "country_id_Geography in (1, 2, 3)");
+        assert_eq!(GroupSql::name_string(&group.groups[0]), "'Americas'''");
+
+        query.group = Some("Geography.Country:Americas=1,2) or (1=1".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        query.group = Some("Geography.Country:[Americas\\\\]=1".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_index_base() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Year.Year".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap()];
+
+        query.index = Some("Year.Year,2010,Quantity".parse().unwrap());
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.index.unwrap().base_string(), "2010");
+
+        for base in &["2010 or 1=1", "inf", "[]"] {
+            query.index = Some(IndexQuery::new("Year", "Year", "Year", base, "Quantity"));
+            assert!(schema.sql_query("Sales", &query, None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_trend_forecast() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+                <Dimension name="Time" foreign_key="month_id">
+                    <Hierarchy name="Time">
+                        <Level name="Month" key_column="month_id" key_type="nontext">
+                            <Annotation name="level">Month</Annotation>
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Product" foreign_key="product_code">
+                    <Hierarchy name="Product">
+                        <Level name="Product" key_column="product_code" key_type="text" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Time.Month".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap()];
+        query.trend = Some("Time.Month,Quantity,2".parse().unwrap());
+
+        let (query_ir, headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.trend.unwrap().granularity, Some(TimeGranularity::Month));
+        assert_eq!(headers[headers.len() - 4..], ["Quantity Trend Slope", "Quantity Trend Intercept", "Quantity Trend", "Forecast"]);
+
+        query.top = Some("1,Time.Month,Quantity,desc".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        query.top = None;
+
+        // forecast keys can't be projected from text keys
+        query.drilldowns = vec!["Product.Product".parse().unwrap()];
+        query.trend = Some("Product.Product,Quantity,2".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        query.trend = Some("Product.Product,Quantity".parse().unwrap());
+        assert!(schema.sql_query("Sales", &query, None).is_ok());
+    }
+
+    #[test]
+    fn test_calculation_named_measure() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+                <Dimension name="Year" foreign_key="year">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Rank" column="rank" aggregator="avg" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut schema = Schema::from_xml(s).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Year.Year".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap(), "Rank".parse().unwrap()];
+        query.sort = Some("Rank.desc".parse().unwrap());
+        query.filters = vec!["rank.gt.2".parse().unwrap()];
+
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.sort.unwrap().column, "final_m1");
+        assert_eq!(query_ir.filters[0].by_column, "final_m1");
+
+        // without the measure, the name is the calculation
+        query.measures = vec!["Quantity".parse().unwrap()];
+        query.filters = vec![];
+        query.rank = Some("Year.Year,Quantity,desc".parse().unwrap());
+        let (query_ir, _) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.sort.unwrap().column, "ranked");
+    }
+
+    #[test]
+    fn test_totals() {
+        let mut schema = Schema::from_json(SCHEMA_STR_MULTIPLE_HIER_DEFAULT).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Geography.Tract.State".parse().unwrap(), "Geography.Tract.County".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap()];
+        query.totals = vec!["Geography.Tract.County".parse().unwrap()];
+
+        let (query_ir, headers) = schema.sql_query("sales", &query, None).unwrap();
+        assert_eq!(query_ir.totals.len(), 1);
+        assert_eq!(headers.last().unwrap(), "Total");
+
+        query.totals = vec!["Geography.Tract.Tract".parse().unwrap()];
+        assert!(schema.sql_query("sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_property_cut() {
+        let s = r##"
+        <Schema name="Webshop">
+            <Cube name="Sales">
+                <Table name="sales" />
+                <Dimension name="Geography" foreign_key="country_id">
+                    <Hierarchy name="Geography" primary_key="country_id">
+                        <Table name="geographies" />
+                        <Level name="Country" key_column="country_id" key_type="nontext">
+                            <Property name="Continent Code" column="continent_code" />
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Geography.Country".parse().unwrap()];
+        query.measures = vec!["Quantity".parse().unwrap()];
+        query.cuts = vec!["Geography.Geography.Country.Continent Code.eu,sa".parse().unwrap()];
+
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.cuts[0].column, "continent_code");
+        assert_eq!(query_ir.cuts[0].table.name, "geographies");
+        assert_eq!(query_ir.cuts[0].members_string(), "'eu', 'sa'");
+
+        query.cuts = vec!["Geography.Geography.Country.Continent Code.[red') or 1=1 --]".parse().unwrap()];
+        let (query_ir, _headers) = schema.sql_query("Sales", &query, None).unwrap();
+        assert_eq!(query_ir.cuts[0].members_string(), "'red'') or 1=1 --'");
+
+        query.cuts = vec!["Geography.Geography.Country.Continent Code.[eu\\\\']".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+        query.cuts = vec!["Geography.Geography.Country.1) or (1=1".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+
+        query.cuts = vec!["Geography.Geography.Country.Color.red".parse().unwrap()];
+        assert!(schema.sql_query("Sales", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_period_to_date() {
+        let s = r##"
+        <Schema name="Finance">
+            <Cube name="Spending">
+                <Table name="spending" />
+                <Dimension name="Date" foreign_key="day" type="time" fiscal_year_start_month="7">
+                    <Hierarchy name="Date" primary_key="day">
+                        <Table name="dates" />
+                        <Level name="Year" key_column="year" key_type="nontext">
+                            <Annotation name="level">Year</Annotation>
+                        </Level>
+                        <Level name="Mes" key_column="month" key_type="nontext">
+                            <Annotation name="level">Month</Annotation>
+                        </Level>
+                        <Level name="Day" key_column="day" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Amount" column="amount" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut schema = Schema::from_xml(s).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Date.Day".parse().unwrap()];
+        query.measures = vec!["Amount".parse().unwrap()];
+
+        // fiscal years need levels derived from a date column
+        query.cumulative = Some("Date.Date.Day,Amount,ytd".parse().unwrap());
+        let err = schema.sql_query("Spending", &query, None).unwrap_err();
+        assert!(err.to_string().contains("needs a hierarchy with a date column"));
+
+        // months come from the parent level annotated as a month, which must
+        // be in the result
+        query.cumulative = Some("Date.Date.Day,Amount,mtd".parse().unwrap());
+        assert!(schema.sql_query("Spending", &query, None).is_err());
+        query.parents = true;
+        let (query_ir, _headers) = schema.sql_query("Spending", &query, None).unwrap();
+        match query_ir.cumulative.unwrap().reset {
+            Some(ResetSql::Column(col)) => assert_eq!(col, "month_Date"),
+            reset => panic!("expected column reset, got {:?}", reset),
+        }
+
+        let (_query_ir, headers) = schema.sql_query("Spending", &query, None).unwrap();
+        assert_eq!(headers, vec!["Year", "Mes", "Day", "Amount", "Amount Cumulative"]);
+
+        // the reset level can't be below the drilldown
+        query.drilldowns = vec!["Date.Year".parse().unwrap()];
+        query.cumulative = Some("Date.Date.Year,Amount,mtd".parse().unwrap());
+        assert!(schema.sql_query("Spending", &query, None).is_err());
+
+        // levels named after a period aren't reset levels without an annotation
+        query.cumulative = Some("Date.Date.Year,Amount,ytd".parse().unwrap());
+        let s_calendar = s.replace(" fiscal_year_start_month=\"7\"", "")
+            .replace("<Annotation name=\"level\">Year</Annotation>", "");
+        let schema_calendar = Schema::from_xml(&s_calendar).unwrap();
+        assert!(schema_calendar.sql_query("Spending", &query, None).is_err());
+
+        let s = s.replace("type=\"time\" ", "");
+        let mut schema = Schema::from_xml(&s).unwrap();
+        assert!(schema.validate().is_err());
+    }
+
+    #[test]
+    fn test_growth_time_key() {
+        let s = r##"
+        <Schema name="Trade">
+            <Cube name="Exports">
+                <Table name="exports" />
+                <Dimension name="Date" foreign_key="month_id" type="time">
+                    <Hierarchy name="Date">
+                        <Level name="Period" key_column="month_id">
+                            <Annotation name="level">Month</Annotation>
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Product" foreign_key="product_id">
+                    <Hierarchy name="Product">
+                        <Level name="Product" key_column="product_id" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Country" foreign_key="country_id">
+                    <Hierarchy name="Country">
+                        <Level name="Country" key_column="country_id" key_type="text" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Value" column="value" aggregator="sum" />
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let mut schema = Schema::from_xml(s).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Date.Period".parse().unwrap(), "Product.Product".parse().unwrap()];
+        query.measures = vec!["Value".parse().unwrap(), "Quantity".parse().unwrap()];
+        query.growth = Some("Date.Date.Period,Quantity,lag.12".parse().unwrap());
+
+        // keys are read as months from the annotation
+        let (query_ir, headers) = schema.sql_query("Exports", &query, None).unwrap();
+        let growth = query_ir.growth.unwrap();
+        assert_eq!(growth.granularity, Some(TimeGranularity::Month));
+        assert_eq!(growth.meas, vec!["final_m1"]);
+        assert_eq!(headers, vec!["Product", "Period", "Value", "Quantity", "Quantity Growth", "Quantity Growth Value"]);
+
+        // measures are after the rca col
+        query.rca = Some("Product.Product,Country.Country,Value".parse().unwrap());
+        query.drilldowns.push("Country.Country".parse().unwrap());
+        let (query_ir, headers) = schema.sql_query("Exports", &query, None).unwrap();
+        assert_eq!(query_ir.growth.unwrap().meas, vec!["final_m2"]);
+        assert_eq!(&headers[headers.len() - 5..], &["Value RCA", "Value", "Quantity", "Quantity Growth", "Quantity Growth Value"]);
+
+        // text keys can't be matched
+        query.rca = None;
+        query.growth = Some("Country.Country.Country,Quantity".parse().unwrap());
+        assert!(schema.sql_query("Exports", &query, None).is_err());
+    }
+
+    #[test]
+    fn test_date_column_levels() {
+        let s = r##"
+        <Schema name="Finance">
+            <Cube name="Spending">
+                <Table name="spending" />
+                <Dimension name="Date" foreign_key="spent_on" type="time" fiscal_year_start_month="7">
+                    <Hierarchy name="Date" date_column="spent_on">
+                        <Level name="Year" granularity="year" />
+                        <Level name="Month" granularity="month" />
+                    </Hierarchy>
+                    <Hierarchy name="Fiscal" date_column="spent_on">
+                        <Level name="Fiscal Year" granularity="fiscal_year" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Amount" column="amount" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let s = s.replace("<Dimension name=\"Date\"", "<Dimension name=\"Date\" default_hierarchy=\"Date\"");
+        let mut schema = Schema::from_xml(&s).unwrap();
+        schema.validate().unwrap();
+
+        let mut query = Query::new();
+        query.drilldowns = vec!["Date.Date.Month".parse().unwrap()];
+        query.cuts = vec!["Date.Date.Year.2019".parse().unwrap()];
+        query.measures = vec!["Amount".parse().unwrap()];
+        query.parents = true;
+
+        let (query_ir, _headers) = schema.sql_query("Spending", &query, None).unwrap();
+        let levels = &query_ir.drills[0].level_columns;
+        assert_eq!(levels[0].key_column, "spent_on_year");
+        assert_eq!(levels[1].key_qual_string("spending"), "(extract(year from spending.spent_on) * 100 + extract(month from spending.spent_on))");
+        assert_eq!(query_ir.cuts[0].col_qual_string(), "extract(year from spending.spent_on)");
+        assert_eq!(query_ir.cuts[0].col_string(|d| d.standard_sql(None)), "extract(year from spent_on)");
+
+        // the reset level is found by granularity
+        query.cumulative = Some("Date.Date.Month,Amount,mtd".parse().unwrap());
+        let (query_ir, _headers) = schema.sql_query("Spending", &query, None).unwrap();
+        match query_ir.cumulative.unwrap().reset {
+            Some(ResetSql::Column(col)) => assert_eq!(col, "spent_on_month_Date"),
+            reset => panic!("expected column reset, got {:?}", reset),
+        }
+
+        // fiscal levels need a fiscal year start month
+        let s = s.replace(" fiscal_year_start_month=\"7\"", "");
+        let mut schema = Schema::from_xml(&s).unwrap();
+        assert!(schema.validate().is_err());
+
+        // derived levels need a time dimension
+        let s = s.replace(" type=\"time\"", "");
+        let mut schema = Schema::from_xml(&s).unwrap();
+        assert!(schema.validate().is_err());
     }
 }
@@ -0,0 +1,440 @@
+//! Parsing for a subset of MDX, translated into a `Query`.
+//!
+//! Grammar, roughly (keywords are case-insensitive):
+//!
+//! ```text
+//! query := "SELECT" axis ("," axis)* "FROM" name ["WHERE" set]
+//! axis  := ["NON" "EMPTY"] set "ON" ("COLUMNS" | "ROWS" | n | "AXIS" "(" n ")")
+//! set   := term ("*" term)*
+//! term  := "{" [set ("," set)*] "}" | "(" set ("," set)* ")"
+//!        | "CROSSJOIN" "(" set "," set ")" | name
+//! ```
+//!
+//! Names are quoted and escaped as in query params, see `parse`, so a
+//! member is `[Dimension].[Hierarchy].[Level].&[member]`. On an axis, a
+//! name can be:
+//! - a measure, `[Measures].[Quantity]`
+//! - all members of a level, `[Geography].[State].Members`, which is a
+//!   drilldown
+//! - members of a level, which are a drilldown and a cut
+//!
+//! In the slicer (`WHERE`), a name is a measure or members to cut on.
+//!
+//! Sets are flattened, since drilldowns are always crossjoined; the axis a
+//! level is on doesn't change the result.
+
+use failure::{Error, bail, ensure};
+use std::str::FromStr;
+
+use crate::names::{Cut, Drilldown, LevelName, Measure};
+use crate::parse;
+use crate::query::Query;
+
+const RESERVED: &[&str] = &[
+    "SELECT", "FROM", "WHERE", "ON", "NON", "EMPTY", "COLUMNS", "ROWS",
+    "AXIS", "CROSSJOIN", "WITH", "MEMBER", "SET",
+];
+
+
+/// A MDX query, with the cube it's on.
+#[derive(Debug, Clone)]
+pub struct MdxQuery {
+    pub cube: String,
+    pub query: Query,
+}
+
+impl FromStr for MdxQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(s)?;
+
+        parser.expect_keyword("SELECT")?;
+
+        let mut axes = vec![];
+        let mut axis_items = vec![];
+        loop {
+            if parser.eat_keyword("NON") {
+                parser.expect_keyword("EMPTY")?;
+            }
+            parser.set(&mut axis_items)?;
+            parser.expect_keyword("ON")?;
+
+            let (idx, axis) = parser.axis()?;
+            if axes.contains(&axis) {
+                return Err(parse::error_at(s, idx, "Axis is used more than once"));
+            }
+            axes.push(axis);
+
+            if !parser.eat_punct(',') {
+                break;
+            }
+        }
+
+        parser.expect_keyword("FROM")?;
+        let cube = parse::unquote(parser.name()?)?;
+
+        let mut slicer_items = vec![];
+        if parser.eat_keyword("WHERE") {
+            parser.set(&mut slicer_items)?;
+        }
+
+        parser.expect_end()?;
+
+        let mut query = Query::new();
+
+        for (idx, name) in axis_items {
+            match Item::parse(name)? {
+                Item::Measure(measure) => add_measure(&mut query, measure),
+                Item::Level(level_name) => add_drilldown(&mut query, level_name),
+                Item::Members(cut) => {
+                    add_drilldown(&mut query, cut.level_name.clone());
+                    add_cut(&mut query, cut).map_err(|err| parse::error_at(s, idx, &err.to_string()))?;
+                },
+            }
+        }
+
+        for (idx, name) in slicer_items {
+            match Item::parse(name)? {
+                Item::Measure(measure) => add_measure(&mut query, measure),
+                Item::Level(_) => {
+                    return Err(parse::error_at(s, idx, "All members of a level can't be in WHERE"));
+                },
+                Item::Members(cut) => {
+                    add_cut(&mut query, cut).map_err(|err| parse::error_at(s, idx, &err.to_string()))?;
+                },
+            }
+        }
+
+        Ok(MdxQuery { cube, query })
+    }
+}
+
+
+/// A name in a set, classified.
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Measure(Measure),
+    Level(LevelName),
+    Members(Cut),
+}
+
+impl Item {
+    fn parse(name: &str) -> Result<Self, Error> {
+        let parts = parse::split(name, '.')?;
+        let last = parts[parts.len() - 1];
+
+        let is_measure = parse::unquote(&parts[0])
+            .map(|first| first.eq_ignore_ascii_case("Measures"))
+            .unwrap_or(false);
+
+        if is_measure {
+            ensure!(parts.len() == 2, "Measure must be [Measures].[name] in '{}'", name);
+            Ok(Item::Measure(parts[1].parse()?))
+        } else if last.eq_ignore_ascii_case("Members") || last.eq_ignore_ascii_case("AllMembers") {
+            let level = &name[..name.len() - last.len() - 1];
+            Ok(Item::Level(level.parse()?))
+        } else if last.starts_with('&') {
+            Ok(Item::Members(name.parse()?))
+        } else {
+            bail!("'{}' is not a measure, the members of a level, or a member", name);
+        }
+    }
+}
+
+
+fn add_measure(query: &mut Query, measure: Measure) {
+    if !query.measures.contains(&measure) {
+        query.measures.push(measure);
+    }
+}
+
+fn add_drilldown(query: &mut Query, level_name: LevelName) {
+    let drilldown = Drilldown(level_name);
+    if !query.drilldowns.contains(&drilldown) {
+        query.drilldowns.push(drilldown);
+    }
+}
+
+/// Members of the same level are collected into one cut.
+fn add_cut(query: &mut Query, cut: Cut) -> Result<(), Error> {
+    let existing = query.cuts.iter_mut()
+        .find(|c| c.level_name == cut.level_name && c.property == cut.property);
+
+    match existing {
+        Some(existing) => {
+            ensure!(
+                existing.range.is_none() && cut.range.is_none()
+                    && existing.mask == cut.mask && existing.for_match == cut.for_match,
+                "Members of level {} can't be combined", cut.level_name
+            );
+            for member in cut.members {
+                if !existing.members.contains(&member) {
+                    existing.members.push(member);
+                }
+            }
+        },
+        None => query.cuts.push(cut),
+    }
+
+    Ok(())
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Name(&'a str),
+    Punct(char),
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    tokens: Vec<(usize, Token<'a>)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Result<Self, Error> {
+        Ok(Parser {
+            s,
+            tokens: tokenize(s)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<(usize, Token<'a>)> {
+        self.tokens.get(self.pos).cloned()
+    }
+
+    /// Position of the next token, or the end of the query.
+    fn idx(&self) -> usize {
+        self.peek().map(|(idx, _)| idx).unwrap_or_else(|| self.s.len())
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        parse::error_at(self.s, self.idx(), msg)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some((_, Token::Name(name))) if name.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {}", keyword)))
+        }
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        if self.peek().map(|(_, token)| token) == Some(Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), Error> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", c)))
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), Error> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error("Unexpected characters after query")),
+        }
+    }
+
+    /// A name which isn't a keyword.
+    fn name(&mut self) -> Result<&'a str, Error> {
+        match self.peek() {
+            Some((_, Token::Name(name))) if !is_reserved(name) => {
+                self.pos += 1;
+                Ok(name)
+            },
+            _ => Err(self.error("Expected a name")),
+        }
+    }
+
+    fn axis(&mut self) -> Result<(usize, u32), Error> {
+        let idx = self.idx();
+
+        let axis = if self.eat_keyword("COLUMNS") {
+            0
+        } else if self.eat_keyword("ROWS") {
+            1
+        } else if self.eat_keyword("AXIS") {
+            self.expect_punct('(')?;
+            let axis = self.axis_number()?;
+            self.expect_punct(')')?;
+            axis
+        } else {
+            self.axis_number()?
+        };
+
+        Ok((idx, axis))
+    }
+
+    fn axis_number(&mut self) -> Result<u32, Error> {
+        match self.peek() {
+            Some((_, Token::Name(name))) if name.parse::<u32>().is_ok() => {
+                self.pos += 1;
+                Ok(name.parse().unwrap())
+            },
+            _ => Err(self.error("Expected an axis")),
+        }
+    }
+
+    /// Collects the names in a set, with their positions.
+    fn set(&mut self, items: &mut Vec<(usize, &'a str)>) -> Result<(), Error> {
+        self.term(items)?;
+        while self.eat_punct('*') {
+            self.term(items)?;
+        }
+        Ok(())
+    }
+
+    fn term(&mut self, items: &mut Vec<(usize, &'a str)>) -> Result<(), Error> {
+        if self.eat_punct('{') {
+            if !self.eat_punct('}') {
+                self.set_list(items, '}')?;
+            }
+        } else if self.eat_punct('(') {
+            self.set_list(items, ')')?;
+        } else if self.eat_keyword("CROSSJOIN") {
+            self.expect_punct('(')?;
+            self.set(items)?;
+            self.expect_punct(',')?;
+            self.set(items)?;
+            self.expect_punct(')')?;
+        } else {
+            let idx = self.idx();
+            let name = self.name().map_err(|_| self.error("Expected a set"))?;
+            items.push((idx, name));
+        }
+        Ok(())
+    }
+
+    fn set_list(&mut self, items: &mut Vec<(usize, &'a str)>, close: char) -> Result<(), Error> {
+        loop {
+            self.set(items)?;
+            if !self.eat_punct(',') {
+                return self.expect_punct(close);
+            }
+        }
+    }
+}
+
+
+fn is_reserved(name: &str) -> bool {
+    RESERVED.iter().any(|keyword| name.eq_ignore_ascii_case(keyword))
+}
+
+fn is_punct(c: char) -> bool {
+    "{}(),*".contains(c)
+}
+
+/// Splits into punctuation and names. A name runs until whitespace or
+/// punctuation outside of brackets.
+fn tokenize(s: &str) -> Result<Vec<(usize, Token<'_>)>, Error> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if is_punct(c) {
+            chars.next();
+            tokens.push((start, Token::Punct(c)));
+        } else {
+            let mut end = s.len();
+            let mut bracket_start = None;
+
+            while let Some(&(i, c)) = chars.peek() {
+                if bracket_start.is_none() && (c.is_whitespace() || is_punct(c)) {
+                    end = i;
+                    break;
+                }
+                chars.next();
+
+                match c {
+                    '\\' => {
+                        chars.next()
+                            .ok_or_else(|| parse::error_at(s, i, "Trailing '\\' escapes nothing"))?;
+                    },
+                    '[' if bracket_start.is_none() => bracket_start = Some(i),
+                    ']' if bracket_start.is_some() => bracket_start = None,
+                    _ => (),
+                }
+            }
+
+            if let Some(i) = bracket_start {
+                return Err(parse::error_at(s, i, "Unclosed '['"));
+            }
+
+            tokens.push((start, Token::Name(&s[start..end])));
+        }
+    }
+
+    Ok(tokens)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::names::Mask;
+
+    #[test]
+    fn test_mdx() {
+        let mdx = "SELECT {[Measures].[Quantity], [Measures].[Price]} ON COLUMNS, \
+            NON EMPTY {[Geography].[State].Members} * {[Year].[Year].&[2017], [Year].[Year].&[2018]} ON ROWS \
+            FROM [Sales] \
+            WHERE ([Product].[Product].[Category].&[Toys], [Product].[Product].[Category].&[Games])";
+        let mdx: MdxQuery = mdx.parse().unwrap();
+
+        assert_eq!(mdx.cube, "Sales");
+        assert_eq!(mdx.query.measures, vec![Measure::new("Quantity"), Measure::new("Price")]);
+        assert_eq!(mdx.query.drilldowns, vec![
+            Drilldown::new("Geography", "Geography", "State"),
+            Drilldown::new("Year", "Year", "Year"),
+        ]);
+        assert_eq!(mdx.query.cuts, vec![
+            Cut::new("Year", "Year", "Year", vec!["2017", "2018"], Mask::Include, false),
+            Cut::new("Product", "Product", "Category", vec!["Toys", "Games"], Mask::Include, false),
+        ]);
+
+        let mdx: MdxQuery = "select CrossJoin([Geography].[State].Members, [Year].[Year].Members) on 0 from Sales where [Measures].[Quantity]"
+            .parse().unwrap();
+        assert_eq!(mdx.cube, "Sales");
+        assert_eq!(mdx.query.drilldowns.len(), 2);
+        assert_eq!(mdx.query.measures, vec![Measure::new("Quantity")]);
+    }
+
+    #[test]
+    fn test_mdx_bad() {
+        let err = "SELECT {[Measures].[Quantity]} FROM [Sales]".parse::<MdxQuery>().unwrap_err();
+        assert_eq!(err.to_string(), "Expected ON at position 31 in 'SELECT {[Measures].[Quantity]} FROM [Sales]'");
+
+        let err = "SELECT [Year].Members ON ROWS, [Measures].[Quantity] ON ROWS FROM [Sales]".parse::<MdxQuery>().unwrap_err();
+        assert_eq!(err.to_string(), "Axis is used more than once at position 56 in 'SELECT [Year].Members ON ROWS, [Measures].[Quantity] ON ROWS FROM [Sales]'");
+
+        assert!("SELECT [Year].[Year] ON COLUMNS FROM [Sales]".parse::<MdxQuery>().is_err());
+        assert!("SELECT [Measures].[Quantity] ON COLUMNS FROM [Sales] WHERE [Year].Members".parse::<MdxQuery>().is_err());
+        assert!("SELECT {[Measures].[Quantity] ON COLUMNS FROM [Sales]".parse::<MdxQuery>().is_err());
+        assert!("SELECT [Measures].[Quantity] ON COLUMNS FROM [Sales".parse::<MdxQuery>().is_err());
+        assert!("WITH MEMBER [Measures].[X] AS 1 SELECT [Measures].[X] ON COLUMNS FROM [Sales]".parse::<MdxQuery>().is_err());
+    }
+}
@@ -18,13 +18,17 @@
 // - [Dimension].[Hierarchy].[Level]
 // - Dimension.Hierarchy.Level
 // - Dimension.Level
-// etc.
+// - Dimension.[Level.With.Dots]
+// - Dimension.Level\,With\,Commas
+// etc. See `parse` for quoting and escaping.
 
 use failure::{Error, bail, format_err, ensure};
 use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 
+use crate::parse::{self, Part};
+
 
 /// Fully qualified name of Dimension, Hierarchy, and Level
 /// Basis for other names.
@@ -82,32 +86,24 @@ impl LevelName {
 
 impl fmt::Display for LevelName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[{}].[{}].[{}]", self.dimension, self.hierarchy, self.level)
+        write!(f, "{}.{}.{}",
+            parse::quote(&self.dimension),
+            parse::quote(&self.hierarchy),
+            parse::quote(&self.level),
+        )
     }
 }
 
 impl FromStr for LevelName {
     type Err = Error;
 
-    /// NAIVE IMPL, does not deal with escaped brackets,
-    /// unpaired brackets, etc. Just checks whether the
-    /// first char is a bracket to determine whether to
-    /// parse using brackets or not.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let name_iter = if s.chars().nth(0).unwrap() == '[' {
-            // check if starts with '[', then assume
-            // that this means that it's a qualified name
-            // with [] wrappers. This means that can't just
-            // split on any periods, only periods that fall
-            // outside the []
-            let pattern: &[_] = &['[', ']'];
-            let s = s.trim_matches(pattern);
-            s.split("].[")
-        } else {
-            s.split(".")
-        };
+        let names = parse::args(s, '.', 2, 3, "a level name")?;
+        let names: Vec<_> = names.into_iter()
+            .map(|name| parse::unquote_in(s, name))
+            .collect::<Result<_, _>>()?;
 
-        LevelName::from_vec(name_iter.collect())
+        LevelName::from_vec(names)
     }
 }
 
@@ -131,7 +127,7 @@ impl Drilldown {
 
 impl fmt::Display for Drilldown {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[{}].[{}].[{}]", self.0.dimension, self.0.hierarchy, self.0.level)
+        write!(f, "{}", self.0)
     }
 }
 
@@ -144,8 +140,8 @@ impl FromStr for Drilldown {
 }
 
 
-/// Naive impl, does not check that [Measure]. is NOT
-/// prepended. But does remove brackets on FromStr
+/// Does not check that [Measure]. is NOT prepended.
+/// But does remove brackets and escapes on FromStr
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Measure(pub String);
 
@@ -165,9 +161,7 @@ impl FromStr for Measure {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let pattern: &[_] = &['[', ']'];
-        let s = s.trim_matches(pattern);
-        Ok(Measure(s.to_owned()))
+        Ok(Measure(parse::unquote(s)?))
     }
 }
 
@@ -183,6 +177,13 @@ pub struct Cut {
     pub members: Vec<String>,
     pub mask: Mask,
     pub for_match: bool,
+    // if range, members is empty and the cut is on the range instead.
+    #[serde(default)]
+    pub range: Option<MemberRange>,
+    // if property, members are matched against this property of the level
+    // instead of the level's key.
+    #[serde(default)]
+    pub property: Option<String>,
 }
 
 impl Cut {
@@ -200,6 +201,8 @@ impl Cut {
             members: members.into_iter().map(|s| s.into()).collect(),
             mask,
             for_match,
+            range: None,
+            property: None,
         }
     }
 
@@ -216,6 +219,8 @@ impl Cut {
                     members: members.clone().into_iter().map(|s| s.into()).collect(),
                     mask,
                     for_match,
+                    range: None,
+                    property: None,
                 }
             })
             .map_err(|err| {
@@ -231,7 +236,7 @@ impl Cut {
     /// bool and the final cut string.
     pub fn parse_cut(cut: &str) -> (Mask, bool, String) {
         // Check for mask
-        let is_exclude = cut.chars().nth(0).unwrap() == '~';
+        let is_exclude = cut.starts_with('~');
         let mask = if is_exclude {
             Mask::Exclude
         } else {
@@ -245,7 +250,7 @@ impl Cut {
         };
 
         // Then check for match (*)
-        let for_match = cut.chars().nth(0).unwrap() == '*';
+        let for_match = cut.starts_with('*');
         let cut = if for_match {
             // ok to slice string, because '*' is definitely one char
             &cut[1..]
@@ -260,25 +265,35 @@ impl Cut {
 // TODO fix this, it only displays "keys" and not "labels"
 impl fmt::Display for Cut {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = match self.property {
+            Some(ref property) => format!("{}.{}", self.level_name, parse::quote(property)),
+            None => self.level_name.to_string(),
+        };
+        let for_match = if self.for_match { "*" } else { "" };
+
+        if let Some(ref range) = self.range {
+            return write!(f, "{}{}.{}", self.mask, target, range);
+        }
+
         // members must be more than 0, checked by assert on serialization
         if self.members.len() == 1 {
-            write!(f, "{}{}.&[{}]", self.mask, self.level_name, self.members[0])
+            write!(f, "{}{}{}.&{}", self.mask, for_match, target, parse::quote_member(&self.members[0]))
         } else {
             let mut out = String::new();
-            out.push_str(&format!("{}", self.mask));
+            out.push_str(&format!("{}{}", self.mask, for_match));
             out.push('{');
 
             let mut members = self.members.iter();
             out.push_str(
                 format!(
-                    "{}.&[{}]",
-                    self.level_name, members.next().unwrap()
+                    "{}.&{}",
+                    target, parse::quote_member(members.next().unwrap())
                 ).as_str()
             );
 
             for member in members {
                 out.push_str(",");
-                out.push_str(format!("{}.&[{}]", self.level_name, member).as_str());
+                out.push_str(format!("{}.&{}", target, parse::quote_member(member)).as_str());
             }
             out.push('}');
 
@@ -287,76 +302,100 @@ impl fmt::Display for Cut {
     }
 }
 
-// TODO I should use a parser for this, just splitting is kind of
-// a pain
+/// The last name holds the members, see `parse::members_in`, or a range
+/// of members, see `MemberRange`.
+///
+/// A cut on a level property needs the full level name before the
+/// property, `Dimension.Hierarchy.Level.Property.members`, since
+/// `Dimension.Level.Property` can't be told apart from a level name.
 impl FromStr for Cut {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // first check for mask value (~)
-        let is_exclude = s.chars().nth(0).unwrap() == '~';
-        let mask = if is_exclude {
-            Mask::Exclude
-        } else {
-            Mask::Include
-        };
-        let s = if is_exclude {
-            // ok to slice string, because '~' is definitely one char
-            &s[1..]
-        } else {
-            s
-        };
+        let (mask, for_match, cut) = Cut::parse_cut(s);
+        // `parse_cut` only strips a prefix, so the rest is the end of `s`
+        let start = s.len() - cut.len();
+        let cut = Part { start, text: &s[start..] };
 
-        // then check for match (*)
-        let for_match = s.chars().nth(0).unwrap() == '*';
-        let s = if for_match {
-            // ok to slice string, because '~' is definitely one char
-            &s[1..]
-        } else {
-            s
-        };
+        let name_vec = parse::split_in(s, cut, '.')?;
 
-        // then do rest of processing normally
-        let name_vec: Vec<_> = if s.chars().nth(0).unwrap() == '[' {
-            // check if starts with '[', then assume
-            // that this means that it's a qualified name
-            // with [] wrappers. This means that can't just
-            // split on any periods, only periods that fall
-            // outside the []
-            let pattern: &[_] = &['[', ']'];
-
-            // TODO maybe this should be split into
-            // left and right trims
-            //
-            // This is to deal with case where
-            // & is inserted before member, which
-            // "].[" won't match
-            let s = s.trim_matches(pattern);
-            s.split("].")
-                .map(|s| s.trim_start_matches('['))
-                .collect()
-        } else {
-            s.split(".")
-                .collect()
+        let (members, level) = match name_vec.split_last() {
+            Some((members, level)) if level.len() >= 2 => (*members, level),
+            _ => return Err(parse::error_at(s, s.len(), "Could not parse a cut, expected a level name and members")),
         };
 
-        let members: Vec<_> = name_vec[name_vec.len()-1]
-            .trim_start_matches('&')
-            .trim_start_matches('[')
-            .split(',')
-            .map(|s| s.trim_start_matches('&').to_owned())
-            .collect();
+        if let Some(extra) = level.get(4) {
+            return Err(parse::error_at(s, extra.start, "Could not parse a cut, too many names before the members"));
+        }
+
+        let mut level: Vec<_> = level.iter()
+            .map(|&name| parse::unquote_in(s, name))
+            .collect::<Result<_, _>>()?;
+        let property = if level.len() == 4 { level.pop() } else { None };
+        let level_name = LevelName::from_vec(level)?;
+
+        if let Some((start, end)) = parse::range_in(s, members)? {
+            if for_match {
+                return Err(parse::error_at(s, cut.start - 1, "Cut on a range cannot use match (*)"));
+            }
+
+            return Ok(Cut {
+                level_name,
+                members: vec![],
+                mask,
+                for_match,
+                range: Some(MemberRange { start, end }),
+                property,
+            });
+        }
 
         Ok(Cut {
-            level_name: LevelName::from_vec(name_vec[0..name_vec.len()-1].to_vec())?,
-            members,
+            level_name,
+            members: parse::members_in(s, members)?,
             mask,
             for_match,
+            range: None,
+            property,
         })
     }
 }
 
 
+/// Inclusive range of members for a cut on an ordered level:
+/// `[2010:2015]`, or open-ended `[2015:]` and `[:2015]`.
+/// Members are compared as numbers for non-text levels, otherwise as text.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MemberRange {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+impl MemberRange {
+    /// `Ok(None)` if `s` is not a range, so it can be parsed as members.
+    pub fn parse(s: &str) -> Result<Option<Self>, Error> {
+        Ok(parse::range(s)?.map(|(start, end)| MemberRange { start, end }))
+    }
+}
+
+impl fmt::Display for MemberRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}:{}]",
+            self.start.as_deref().map(parse::escape_member).unwrap_or_default(),
+            self.end.as_deref().map(parse::escape_member).unwrap_or_default(),
+        )
+    }
+}
+
+impl FromStr for MemberRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MemberRange::parse(s)?
+            .ok_or_else(|| format_err!("'{}' is not a range of members", s))
+    }
+}
+
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum Mask {
     Include,
@@ -395,6 +434,8 @@ impl Property {
     /// Names must have already been trimmed of [] delimiters.
     pub fn from_vec<S: Into<String> + Clone>(property: Vec<S>) -> Result<Self, Error> 
     {
+        ensure!(property.len() > 1, "Property must follow a level name");
+
         Ok(LevelName::from_vec(property[0..property.len()-1].to_vec())
             .map(|level_name| {
                 Property {
@@ -420,35 +461,20 @@ impl Property {
 
 impl fmt::Display for Property {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}.[{}]", self.level_name, self.property)
+        write!(f, "{}.{}", self.level_name, parse::quote(&self.property))
     }
 }
 
 impl FromStr for Property {
     type Err = Error;
 
-    /// NAIVE IMPL, does not deal with escaped brackets,
-    /// unpaired brackets, etc. Just checks whether the
-    /// first char is a bracket to determine whether to
-    /// parse using brackets or not.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let name_vec: Vec<_> = (if s.chars().nth(0).unwrap() == '[' {
-            // check if starts with '[', then assume
-            // that this means that it's a qualified name
-            // with [] wrappers. This means that can't just
-            // split on any periods, only periods that fall
-            // outside the []
-            let pattern: &[_] = &['[', ']'];
-            let s = s.trim_matches(pattern);
-            s.split("].[")
-        } else {
-            s.split(".")
-        }).collect();
+        let names = parse::args(s, '.', 3, 4, "a property")?;
+        let names: Vec<_> = names.into_iter()
+            .map(|name| parse::unquote_in(s, name))
+            .collect::<Result<_, _>>()?;
 
-        Ok(Property {
-            level_name: LevelName::from_vec(name_vec[0..name_vec.len() - 1].to_vec())?,
-            property: name_vec[name_vec.len() - 1].to_owned(),
-        })
+        Property::from_vec(names)
     }
 }
 
@@ -594,5 +620,110 @@ mod test {
         assert_eq!(property, property_test_2);
         assert_eq!(property, property_test_3);
     }
-}
 
+    #[test]
+    fn test_parse_quoted() {
+        // members with dots and commas
+        let cut = "Geography.County.[1.5],2\\,3".parse::<Cut>().unwrap();
+        assert_eq!(cut.members, vec!["1.5", "2,3"]);
+        let cut = "~*[Geo.graphy].[County].&[1\\,5]".parse::<Cut>().unwrap();
+        assert_eq!(cut.level_name, LevelName::new("Geo.graphy", "Geo.graphy", "County"));
+        assert_eq!(cut.members, vec!["1,5"]);
+        assert_eq!(cut.mask, Mask::Exclude);
+        assert!(cut.for_match);
+
+        let property = "Geography.County.[name.en]".parse::<Property>().unwrap();
+        assert_eq!(property.property, "name.en");
+
+        // errors instead of panics
+        assert!("".parse::<LevelName>().is_err());
+        assert!("".parse::<Cut>().is_err());
+        assert!("~".parse::<Cut>().is_err());
+        assert!("Geography".parse::<Cut>().is_err());
+        assert!("".parse::<Property>().is_err());
+        assert!("County".parse::<Property>().is_err());
+        assert!("".parse::<Measure>().is_err());
+
+        let err = "Geography.[County.1".parse::<Cut>().unwrap_err();
+        assert_eq!(err.to_string(), "Unclosed '[' at position 10 in 'Geography.[County.1'");
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let cut = "Year.Year.[2010:2015]".parse::<Cut>().unwrap();
+        assert_eq!(cut.level_name, LevelName::new("Year", "Year", "Year"));
+        assert!(cut.members.is_empty());
+        assert_eq!(cut.range, Some(MemberRange { start: Some("2010".into()), end: Some("2015".into()) }));
+
+        let cut = "~[Date].[Year].&[2015:]".parse::<Cut>().unwrap();
+        assert_eq!(cut.mask, Mask::Exclude);
+        assert_eq!(cut.range, Some(MemberRange { start: Some("2015".into()), end: None }));
+        assert_eq!(cut.to_string(), "~[Date].[Date].[Year].[2015:]");
+
+        assert!("*Year.Year.[2010:2015]".parse::<Cut>().is_err());
+        assert!("Year.Year.[:]".parse::<Cut>().is_err());
+        assert!("2010:2015".parse::<MemberRange>().is_err());
+    }
+
+    #[test]
+    fn test_parse_property_cut() {
+        let cut = "Product.Product.Product.Color.red,blue".parse::<Cut>().unwrap();
+        assert_eq!(cut.level_name, LevelName::new("Product", "Product", "Product"));
+        assert_eq!(cut.property, Some("Color".into()));
+        assert_eq!(cut.members, vec!["red", "blue"]);
+
+        let cut = "[Geography].[Geography].[Country].[Continent Code].&[eu]".parse::<Cut>().unwrap();
+        assert_eq!(cut.property, Some("Continent Code".into()));
+        assert_eq!(cut.to_string(), "[Geography].[Geography].[Country].[Continent Code].&[eu]");
+
+        // three names is still a level
+        let cut = "Geography.Country.Continent.eu".parse::<Cut>().unwrap();
+        assert_eq!(cut.level_name, LevelName::new("Geography", "Country", "Continent"));
+        assert_eq!(cut.property, None);
+
+        let err = "A.B.C.D.E.1".parse::<Cut>().unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse a cut, too many names before the members at position 8 in 'A.B.C.D.E.1'");
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let level = LevelName::new("Geo.graphy", "Geo]graphy", "County\\");
+        assert_eq!(level.to_string(), "[Geo.graphy].[Geo\\]graphy].[County\\\\]");
+        assert_eq!(level.to_string().parse::<LevelName>().unwrap(), level);
+
+        let drilldown = Drilldown(level.clone());
+        assert_eq!(drilldown.to_string().parse::<Drilldown>().unwrap(), drilldown);
+
+        let property = Property::new("Geography", "Geography", "County", "name [en]");
+        assert_eq!(property.to_string().parse::<Property>().unwrap(), property);
+
+        let cut = Cut::new("Geography", "Geography", "County", vec!["1,5]"], Mask::Exclude, true);
+        assert_eq!(cut.to_string(), "~*[Geography].[Geography].[County].&[1\\,5\\]]");
+        assert_eq!(cut.to_string().parse::<Cut>().unwrap(), cut);
+
+        let mut cut = "Product.Product.Product.[Color].red".parse::<Cut>().unwrap();
+        cut.members = vec!["a:b".into()];
+        assert_eq!(cut.to_string().parse::<Cut>().unwrap(), cut);
+
+        let cut = "[Date].[Year].[\\:2010:]".parse::<Cut>().unwrap();
+        assert_eq!(cut.range, Some(MemberRange { start: Some(":2010".into()), end: None }));
+        assert_eq!(cut.to_string().parse::<Cut>().unwrap(), cut);
+    }
+
+    #[test]
+    fn test_parse_error_positions() {
+        let err = "A.B.C.D".parse::<LevelName>().unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse a level name, expected 2 to 3 parts separated by '.', found 4 at position 6 in 'A.B.C.D'");
+        let err = "County".parse::<Property>().unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse a property, expected 3 to 4 parts separated by '.', found 1 at position 6 in 'County'");
+
+        let err = "Geography.County.1,,2".parse::<Cut>().unwrap_err();
+        assert_eq!(err.to_string(), "Empty name at position 19 in 'Geography.County.1,,2'");
+        let err = "*Year.Year.[2010:2015]".parse::<Cut>().unwrap_err();
+        assert_eq!(err.to_string(), "Cut on a range cannot use match (*) at position 0 in '*Year.Year.[2010:2015]'");
+        let err = "~Year.Year.[2010:2015:2]".parse::<Cut>().unwrap_err();
+        assert_eq!(err.to_string(), "Range must have only one ':' at position 21 in '~Year.Year.[2010:2015:2]'");
+        let err = "~Geography".parse::<Cut>().unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse a cut, expected a level name and members at position 10 in '~Geography'");
+    }
+}
@@ -0,0 +1,400 @@
+//! Tokenizing for the query param mini-language used by the `FromStr`
+//! impls in `names` and `query`.
+//!
+//! Grammar, roughly:
+//!
+//! ```text
+//! list    := part ("," part)*         (or "." for qualified names)
+//! part    := (bare | bracketed)*
+//! bare    := any char besides the delimiter, "[", "]" and "\"; or "\" any char
+//! bracket := "[" (any char besides "]" and "\"; or "\" any char)* "]"
+//! ```
+//!
+//! So `.` and `,` can be used in names by wrapping the name in `[]`, or
+//! by escaping with `\`. A `]` inside brackets must be escaped.
+//!
+//! Errors report the char position in the string being parsed, and the
+//! string itself (which may be a fragment of the whole query param).
+
+use failure::{Error, format_err};
+use std::fmt::Display;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// A slice of the string being parsed, with the byte index where it
+/// starts in that string, so that errors can be reported at its position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Part<'a> {
+    pub start: usize,
+    pub text: &'a str,
+}
+
+impl<'a> Part<'a> {
+    /// The whole string.
+    pub fn whole(s: &'a str) -> Self {
+        Part { start: 0, text: s }
+    }
+
+    /// Bytes `from..to` of this part.
+    pub fn slice(self, from: usize, to: usize) -> Self {
+        Part { start: self.start + from, text: &self.text[from..to] }
+    }
+
+    pub fn trim_start_matches(self, c: char) -> Self {
+        let text = self.text.trim_start_matches(c);
+        self.slice(self.text.len() - text.len(), self.text.len())
+    }
+
+    pub fn strip_prefix(self, prefix: &str) -> Option<Self> {
+        self.text.strip_prefix(prefix)
+            .map(|_| self.slice(prefix.len(), self.text.len()))
+    }
+
+    /// Byte index in the string being parsed right after this part.
+    pub fn end(self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+impl Deref for Part<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.text
+    }
+}
+
+impl PartialEq<&str> for Part<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+/// Splits on `delim` outside of brackets and escapes. Returned parts are
+/// raw, brackets and escapes are kept for `unquote`.
+pub(crate) fn split(s: &str, delim: char) -> Result<Vec<Part<'_>>, Error> {
+    splitn(s, usize::MAX, delim)
+}
+
+/// As `split`, but returns at most `n` parts; the last part holds the rest.
+pub(crate) fn splitn(s: &str, n: usize, delim: char) -> Result<Vec<Part<'_>>, Error> {
+    splitn_in(s, Part::whole(s), n, delim)
+}
+
+/// As `split`, for `part` of `s`; errors are reported relative to `s`.
+pub(crate) fn split_in<'a>(s: &str, part: Part<'a>, delim: char) -> Result<Vec<Part<'a>>, Error> {
+    splitn_in(s, part, usize::MAX, delim)
+}
+
+/// A single name, either bare or wrapped in `[]`, with escapes removed.
+pub(crate) fn unquote(s: &str) -> Result<String, Error> {
+    unquote_in(s, Part::whole(s))
+}
+
+/// Names separated by `.`, each unquoted.
+pub(crate) fn names(s: &str) -> Result<Vec<String>, Error> {
+    split(s, '.')?.into_iter()
+        .map(|name| unquote_in(s, name))
+        .collect()
+}
+
+/// Members separated by `,`, each optionally prefixed with `&`.
+///
+/// For backwards compatibility, `,` inside brackets also separates members
+/// (`&[1,2]`), so a member containing `,` must escape it.
+///
+/// `part` is a part of `s`; errors are reported relative to `s`.
+pub(crate) fn members_in(s: &str, part: Part) -> Result<Vec<String>, Error> {
+    let mut res = vec![];
+
+    for member in split_in(s, part, ',')? {
+        let member = member.trim_start_matches('&');
+
+        if member.starts_with('[') && member.ends_with(']') && member.len() > 1 {
+            let content = member.slice(1, member.len() - 1);
+            for m in split_in(s, content, ',')? {
+                res.push(unquote_in(s, m.trim_start_matches('&'))?);
+            }
+        } else {
+            res.push(unquote_in(s, member)?);
+        }
+    }
+
+    Ok(res)
+}
+
+/// As `split`, but with between `min` and `max` parts. Too many parts is
+/// reported at the first extra part, too few at the end of `s`.
+pub(crate) fn args<'a>(s: &'a str, delim: char, min: usize, max: usize, what: &str) -> Result<Vec<Part<'a>>, Error> {
+    let parts = split(s, delim)?;
+
+    if parts.len() < min || parts.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else if max == usize::MAX {
+            format!("at least {}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        let idx = parts.get(max).map(|part| part.start).unwrap_or(s.len());
+        let msg = format!("Could not parse {}, expected {} parts separated by '{}', found {}", what, expected, delim, parts.len());
+
+        return Err(error_at(s, idx, &msg));
+    }
+
+    Ok(parts)
+}
+
+/// Parses `part` of `s` as a single value such as a number or keyword.
+/// Errors are reported at the position of `part` in `s`.
+pub(crate) fn value<T>(s: &str, part: Part, what: &str) -> Result<T, Error>
+    where T: FromStr, T::Err: Display
+{
+    part.trim().parse::<T>()
+        .map_err(|err| error_at(s, part.start, &format!("Could not parse {} '{}': {}", what, part.text, err)))
+}
+
+/// Part of `s` from the start of `first` to the end of `last`.
+pub(crate) fn span<'a>(s: &'a str, first: Part, last: Part) -> Part<'a> {
+    Part { start: first.start, text: &s[first.start..last.end()] }
+}
+
+/// Start and end of a range, `None` if open.
+pub(crate) type Bounds = (Option<String>, Option<String>);
+
+/// Bounds of a range of members, `[start:end]`. Either bound may be
+/// empty for an open range, but not both. `Ok(None)` if `s` is not a range.
+pub(crate) fn range(s: &str) -> Result<Option<Bounds>, Error> {
+    range_in(s, Part::whole(s))
+}
+
+/// `part` is a part of `s`; errors are reported relative to `s`.
+pub(crate) fn range_in(s: &str, part: Part) -> Result<Option<Bounds>, Error> {
+    let trimmed = part.trim_start_matches('&');
+
+    let content = match trimmed.strip_prefix("[").filter(|t| t.ends_with(']')) {
+        Some(content) => content.slice(0, content.len() - 1),
+        None => return Ok(None),
+    };
+    if find_unescaped(&content, ']').is_some() {
+        return Ok(None);
+    }
+
+    let sep = match find_unescaped(&content, ':') {
+        Some(sep) => sep,
+        None => return Ok(None),
+    };
+
+    if let Some(i) = find_unescaped(&content[sep + 1..], ':') {
+        return Err(error_at(s, content.start + sep + 1 + i, "Range must have only one ':'"));
+    }
+
+    let bound = |b: &str| if b.is_empty() { None } else { Some(unescape(b)) };
+    let start = bound(&content[..sep]);
+    let end = bound(&content[sep + 1..]);
+
+    if start.is_none() && end.is_none() {
+        return Err(error_at(s, content.start + sep, "Range must have at least one bound"));
+    }
+
+    Ok(Some((start, end)))
+}
+
+/// `part` is a part of `s`; errors are reported relative to `s`.
+fn splitn_in<'a>(s: &str, part: Part<'a>, n: usize, delim: char) -> Result<Vec<Part<'a>>, Error> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut bracket_start = None;
+    let mut chars = part.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next()
+                    .ok_or_else(|| error_at(s, part.start + i, "Trailing '\\' escapes nothing"))?;
+            },
+            '[' if bracket_start.is_none() => bracket_start = Some(i),
+            ']' if bracket_start.is_some() => bracket_start = None,
+            ']' => return Err(error_at(s, part.start + i, "Unmatched ']'")),
+            c if c == delim && bracket_start.is_none() && parts.len() + 1 < n => {
+                parts.push(part.slice(start, i));
+                start = i + c.len_utf8();
+            },
+            _ => (),
+        }
+    }
+
+    if let Some(i) = bracket_start {
+        return Err(error_at(s, part.start + i, "Unclosed '['"));
+    }
+
+    parts.push(part.slice(start, part.len()));
+    Ok(parts)
+}
+
+/// `part` is a part of `s`; errors are reported relative to `s`.
+pub(crate) fn unquote_in(s: &str, part: Part) -> Result<String, Error> {
+    let res = if let Some(content) = part.strip_prefix("[") {
+        let end = find_unescaped(&content, ']')
+            .ok_or_else(|| error_at(s, part.start, "Unclosed '['"))?;
+
+        if end + 2 != part.len() {
+            return Err(error_at(s, part.start + end + 2, "Unexpected characters after ']'"));
+        }
+
+        unescape(&content[..end])
+    } else {
+        if let Some(i) = find_unescaped(&part, '[').or_else(|| find_unescaped(&part, ']')) {
+            return Err(error_at(s, part.start + i, "Brackets must wrap the whole name"));
+        }
+
+        unescape(&part)
+    };
+
+    if res.is_empty() {
+        return Err(error_at(s, part.start, "Empty name"));
+    }
+
+    Ok(res)
+}
+
+/// Wraps a name in `[]`, escaping `\\` and `]`, so that it parses back
+/// with `unquote`.
+pub(crate) fn quote(name: &str) -> String {
+    format!("[{}]", escape(name, &['\\', ']']))
+}
+
+/// As `quote`, for a member in a cut.
+pub(crate) fn quote_member(member: &str) -> String {
+    format!("[{}]", escape_member(member))
+}
+
+/// Escapes `[`, `,` and `:` as well as `\\` and `]`, since in a cut they
+/// would start a name, separate members or make a range.
+pub(crate) fn escape_member(member: &str) -> String {
+    escape(member, &['\\', '[', ']', ',', ':'])
+}
+
+fn escape(s: &str, chars: &[char]) -> String {
+    let mut res = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if chars.contains(&c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+
+    res
+}
+
+fn find_unescaped(s: &str, target: char) -> Option<usize> {
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c == target {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+fn unescape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                res.push(escaped);
+            }
+        } else {
+            res.push(c);
+        }
+    }
+
+    res
+}
+
+/// `idx` is a byte index, reported as a char position.
+pub(crate) fn error_at(s: &str, idx: usize, msg: &str) -> Error {
+    format_err!("{} at position {} in '{}'", msg, s[..idx].chars().count(), s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("a,b,c", ',').unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(split("[a,b],c\\,d", ',').unwrap(), vec!["[a,b]", "c\\,d"]);
+        assert_eq!(splitn("a.b.c", 2, '.').unwrap(), vec!["a", "b.c"]);
+        assert_eq!(split("", ',').unwrap(), vec![""]);
+
+        let err = split("a,[b,c", ',').unwrap_err();
+        assert_eq!(err.to_string(), "Unclosed '[' at position 2 in 'a,[b,c'");
+        let err = split("a]", ',').unwrap_err();
+        assert_eq!(err.to_string(), "Unmatched ']' at position 1 in 'a]'");
+        assert!(split("a\\", ',').is_err());
+    }
+
+    #[test]
+    fn test_args() {
+        assert_eq!(args("a,b", ',', 2, 3, "x").unwrap(), vec!["a", "b"]);
+
+        let err = args("a,b,c,d", ',', 2, 3, "x").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse x, expected 2 to 3 parts separated by ',', found 4 at position 6 in 'a,b,c,d'");
+        let err = args("a", ',', 2, usize::MAX, "x").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse x, expected at least 2 parts separated by ',', found 1 at position 1 in 'a'");
+
+        let s = "a,12,x";
+        let parts = split(s, ',').unwrap();
+        assert_eq!(value::<u64>(s, parts[1], "n").unwrap(), 12);
+        let err = value::<u64>(s, parts[2], "n").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse n 'x': invalid digit found in string at position 5 in 'a,12,x'");
+        assert_eq!(span(s, parts[0], parts[1]), "a,12");
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(names("Geography.County").unwrap(), vec!["Geography", "County"]);
+        assert_eq!(names("[Geo.graphy].[County]").unwrap(), vec!["Geo.graphy", "County"]);
+        assert_eq!(names("Geo\\.graphy.[Cou\\]nty]").unwrap(), vec!["Geo.graphy", "Cou]nty"]);
+
+        let err = names("Geography..County").unwrap_err();
+        assert_eq!(err.to_string(), "Empty name at position 10 in 'Geography..County'");
+        assert!(names("[Geo]graphy.County").is_err());
+        assert!(names("Geo[graphy].County").is_err());
+        assert!(names("").is_err());
+    }
+
+    #[test]
+    fn test_members() {
+        let members = |s: &str| members_in(s, Part::whole(s));
+        assert_eq!(members("1,2").unwrap(), vec!["1", "2"]);
+        assert_eq!(members("&1,&2").unwrap(), vec!["1", "2"]);
+        assert_eq!(members("&[1,2]").unwrap(), vec!["1", "2"]);
+        assert_eq!(members("&[1.5],[a\\,b]").unwrap(), vec!["1.5", "a,b"]);
+        let err = members("1,&[2,x]y").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected characters after ']' at position 8 in '1,&[2,x]y'");
+        assert!(members("1,,2").is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(range("[2010:2015]").unwrap(), Some((Some("2010".into()), Some("2015".into()))));
+        assert_eq!(range("&[2015:]").unwrap(), Some((Some("2015".into()), None)));
+        assert_eq!(range("[:b]").unwrap(), Some((None, Some("b".into()))));
+        assert_eq!(range("[10\\:30]").unwrap(), None);
+        assert_eq!(range("2010:2015").unwrap(), None);
+        assert_eq!(range("[1,2]").unwrap(), None);
+
+        let err = range("[1:2:3]").unwrap_err();
+        assert_eq!(err.to_string(), "Range must have only one ':' at position 4 in '[1:2:3]'");
+        assert!(range("[:]").is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use itertools::join;
 
-use failure::{Error, format_err, bail};
+use failure::{Error, format_err, bail, ensure};
 use std::str::FromStr;
 
+use crate::parse::{self, Part};
 use crate::names::{
     Cut,
     Drilldown,
@@ -18,6 +19,7 @@ pub struct Query {
     pub measures: Vec<Measure>,
     pub properties: Vec<Property>,
     pub filters: Vec<FilterQuery>,
+    pub filter: Option<FilterExpr>,
     pub captions: Vec<Property>,
     pub parents: bool,
     pub top: Option<TopQuery>,
@@ -26,7 +28,15 @@ pub struct Query {
     pub limit: Option<LimitQuery>,
     pub rca: Option<RcaQuery>,
     pub growth: Option<GrowthQuery>,
+    pub cumulative: Option<CumulativeQuery>,
+    pub moving_avg: Option<MovingAvgQuery>,
+    pub index: Option<IndexQuery>,
+    pub rank: Option<RankQuery>,
+    pub trend: Option<TrendQuery>,
+    pub totals: Vec<LevelName>,
     pub rate: Option<RateQuery>,
+    pub group: Option<GroupQuery>,
+    pub bins: Option<BinsQuery>,
     pub debug: bool,
     pub sparse: bool,
     pub exclude_default_members: bool,
@@ -40,6 +50,7 @@ impl Query {
             measures: vec![],
             properties: vec![],
             filters: vec![],
+            filter: None,
             captions: vec![],
             parents: false,
             top: None,
@@ -48,12 +59,40 @@ impl Query {
             limit: None,
             rca: None,
             growth: None,
+            cumulative: None,
+            moving_avg: None,
+            index: None,
+            rank: None,
+            trend: None,
+            totals: vec![],
             rate: None,
+            group: None,
+            bins: None,
             debug: false,
             sparse: false,
             exclude_default_members: false,
         }
     }
+
+    /// Checks values that their types allow but queries don't, like a
+    /// growth lag of 0. Parsing checks these too, with positions, so this
+    /// is for queries built some other way. Called by `Schema::sql_query`.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(ref growth) = self.growth {
+            growth.validate()?;
+        }
+        if let Some(ref moving_avg) = self.moving_avg {
+            MovingAvgQuery::check_window(moving_avg.window)?;
+        }
+        if let Some(ref trend) = self.trend {
+            TrendQuery::check_periods(trend.periods)?;
+        }
+        if let Some(ref bins) = self.bins {
+            bins.bins.validate()?;
+        }
+
+        Ok(())
+    }
 }
 
 // TODO: Move ClickHouse specific queries away from ts-core
@@ -61,12 +100,16 @@ impl Query {
 /// ClickHouse:
 /// select * from table_name order by sort_measures sort_direction
 /// limit n by by_dimension
+///
+/// If `others` is set, the members outside the top n are collapsed into
+/// an "Other" member for each group, with a null ID.
 #[derive(Debug, Clone)]
 pub struct TopQuery {
     pub n: u64,
     pub by_dimension: LevelName,
     pub sort_mea_or_calc: Vec<MeaOrCalc>,
     pub sort_direction: SortDirection,
+    pub others: bool,
 }
 
 impl TopQuery  {
@@ -78,7 +121,8 @@ impl TopQuery  {
             n,
             by_dimension,
             sort_mea_or_calc,
-            sort_direction
+            sort_direction,
+            others: false,
         }
     }
 }
@@ -88,35 +132,47 @@ impl FromStr for TopQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
-            [n, by_dimension, sort_measure, sort_direction] => {
-
-                let n = n.parse::<u64>()?;
-                let by_dimension = by_dimension.parse::<LevelName>()?;
-                let sort_mea_or_calc = vec![sort_measure.parse::<MeaOrCalc>()?];
-                let sort_direction = sort_direction.parse::<SortDirection>()?;
-
-                Ok(TopQuery {
-                    n,
-                    by_dimension,
-                    sort_mea_or_calc,
-                    sort_direction,
-                })
-            },
-            _ => bail!("Could not parse a top query"),
-        }
+        let args = parse::args(s, ',', 4, 4, "a top query")?;
+
+        let n = parse::value::<u64>(s, args[0], "top n")?;
+        let by_dimension = args[1].parse::<LevelName>()?;
+        let sort_mea_or_calc = vec![args[2].parse::<MeaOrCalc>()?];
+        let sort_direction = parse::value::<SortDirection>(s, args[3], "sort direction")?;
+
+        Ok(TopQuery {
+            n,
+            by_dimension,
+            sort_mea_or_calc,
+            sort_direction,
+            others: false,
+        })
     }
 }
 
 // Just for TopQuery
-/// Currently rca and growth will be reserved keywords. This may be changed in the future,
-/// to allow measures that are named rca and growth
+/// Currently rca, growth, cumulative, moving_avg, index, rank and trend are parsed as
+/// calculations. A measure with one of those names is resolved by `resolve`.
 #[derive(Debug, Clone, PartialEq)]
 pub enum MeaOrCalc {
     Mea(Measure),
     Calc(Calculation),
 }
 
+impl MeaOrCalc {
+    /// A calculation named like one of `measures` is that measure instead,
+    /// so measures named after calculations can still be sorted and filtered on.
+    pub fn resolve(&self, measures: &[Measure]) -> MeaOrCalc {
+        if let MeaOrCalc::Calc(calc) = self {
+            let measure = measures.iter()
+                .find(|m| m.0.parse::<Calculation>().ok().as_ref() == Some(calc));
+            if let Some(measure) = measure {
+                return MeaOrCalc::Mea(measure.clone());
+            }
+        }
+        self.clone()
+    }
+}
+
 impl FromStr for MeaOrCalc {
     type Err = Error;
 
@@ -127,7 +183,7 @@ impl FromStr for MeaOrCalc {
                 s.parse::<Measure>()
                     .map(|m| MeaOrCalc::Mea(m))
             })
-            .map_err(|_| format_err!("Could not parse '{}' to measure name or built-in calculation name", s))
+            .map_err(|err| format_err!("Could not parse '{}' to measure name or built-in calculation name: {}", s, err))
     }
 }
 
@@ -135,6 +191,11 @@ impl FromStr for MeaOrCalc {
 pub enum Calculation {
     Rca,
     Growth,
+    Cumulative,
+    MovingAvg,
+    Index,
+    Rank,
+    Trend,
 }
 
 impl Calculation {
@@ -142,6 +203,13 @@ impl Calculation {
         match self {
             Calculation::Rca => "rca".to_owned(),
             Calculation::Growth => "growth".to_owned(),
+            Calculation::Cumulative => "cumulative".to_owned(),
+            Calculation::MovingAvg => "moving_avg".to_owned(),
+            // `index` is reserved in mysql
+            Calculation::Index => "indexed".to_owned(),
+            // `rank` is reserved in mysql 8
+            Calculation::Rank => "ranked".to_owned(),
+            Calculation::Trend => "trend".to_owned(),
         }
     }
 }
@@ -153,6 +221,11 @@ impl FromStr for Calculation {
         match &s.to_lowercase()[..] {
             "rca" => Ok(Calculation::Rca),
             "growth" => Ok(Calculation::Growth),
+            "cumulative" => Ok(Calculation::Cumulative),
+            "moving_avg" => Ok(Calculation::MovingAvg),
+            "index" => Ok(Calculation::Index),
+            "rank" => Ok(Calculation::Rank),
+            "trend" => Ok(Calculation::Trend),
             _ => Err(format_err!("'{}' is not a supported calculation", s)),
         }
     }
@@ -170,19 +243,15 @@ impl FromStr for TopWhereQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
-            [by_mea, constraint] => {
+        let args = parse::args(s, ',', 2, 2, "a top_where query")?;
 
-                let by_mea_or_calc = by_mea.parse::<MeaOrCalc>()?;
-                let constraint = constraint.parse::<Constraint>()?;
+        let by_mea_or_calc = args[0].parse::<MeaOrCalc>()?;
+        let constraint = args[1].parse::<Constraint>()?;
 
-                Ok(TopWhereQuery {
-                    by_mea_or_calc,
-                    constraint,
-                })
-            },
-            _ => bail!("Could not parse a top_where query"),
-        }
+        Ok(TopWhereQuery {
+            by_mea_or_calc,
+            constraint,
+        })
     }
 }
 
@@ -199,24 +268,30 @@ impl Constraint {
     pub fn sql_string(&self) -> String {
         format!("{} {}", self.comparison.sql_string(), self.n)
     }
+
+    /// `comparison` and `n` are parts of `s`; errors are reported
+    /// relative to `s`.
+    fn parse_in(s: &str, comparison: Part, n: Part) -> Result<Self, Error> {
+        let comparison = parse::value(s, comparison, "comparison")?;
+        let value = parse::value::<f64>(s, n, "number")?;
+        if !value.is_finite() {
+            return Err(parse::error_at(s, n.start, &format!("Could not parse '{}' to a finite number", n.text)));
+        }
+
+        Ok(Constraint {
+            comparison,
+            n: value,
+        })
+    }
 }
 
 impl FromStr for Constraint {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.splitn(2, ".").collect::<Vec<_>>()[..] {
-            [comparison, n] => {
-
-                let comparison = comparison.parse::<Comparison>()?;
-                let n = n.parse::<f64>()?;
-
-                Ok(Constraint {
-                    comparison,
-                    n,
-                })
-            },
-            _ => bail!("Could not parse a Constraint"),
+        match parse::splitn(s, 2, '.')?[..] {
+            [comparison, n] => Constraint::parse_in(s, comparison, n),
+            _ => Err(parse::error_at(s, s.len(), "Could not parse a constraint, expected 'comparison.number'")),
         }
     }
 }
@@ -255,7 +330,7 @@ impl FromStr for Comparison {
             "lte" => Ok(Comparison::LessThanOrEqual),
             "gt" => Ok(Comparison::GreaterThan),
             "gte" => Ok(Comparison::GreaterThanOrEqual),
-            _ => bail!("Could not parse a comparison operator"),
+            _ => bail!("expected 'eq', 'neq', 'lt', 'lte', 'gt' or 'gte'"),
         }
     }
 }
@@ -270,21 +345,17 @@ impl FromStr for LimitQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
-            [offset, n] => {
-                Ok(LimitQuery {
-                    offset: Some(offset.parse::<u64>()?),
-                    n: n.parse::<u64>()?,
-                })
-            },
-            [n] => {
-                Ok(LimitQuery {
-                    offset: None,
-                    n: n.parse::<u64>()?,
-                })
-            },
-            _ => bail!("Could not parse a limit query"),
-        }
+        let args = parse::args(s, ',', 1, 2, "a limit query")?;
+
+        let (offset, n) = match args[..] {
+            [offset, n] => (Some(parse::value(s, offset, "limit offset")?), n),
+            _ => (None, args[0]),
+        };
+
+        Ok(LimitQuery {
+            offset,
+            n: parse::value(s, n, "limit")?,
+        })
     }
 }
 
@@ -298,17 +369,15 @@ impl FromStr for SortQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(".").collect::<Vec<_>>()[..] {
-            [measure, direction] => {
-                let measure = measure.parse::<MeaOrCalc>()?;
-                let direction = direction.parse::<SortDirection>()?;
-                Ok(SortQuery {
-                    direction,
-                    measure,
-                })
-            },
-            _ => bail!("Could not parse a sort query"),
-        }
+        let args = parse::args(s, '.', 2, 2, "a sort query")?;
+
+        let measure = args[0].parse::<MeaOrCalc>()?;
+        let direction = parse::value(s, args[1], "sort direction")?;
+
+        Ok(SortQuery {
+            direction,
+            measure,
+        })
 
     }
 }
@@ -335,7 +404,74 @@ impl FromStr for SortDirection {
         Ok(match s {
             "asc" => SortDirection::Asc,
             "desc" => SortDirection::Desc,
-            _ => bail!("Could not parse sort direction"),
+            _ => bail!("expected 'asc' or 'desc'"),
+        })
+    }
+}
+
+/// Rank of each member of a drilldown by a measure, within each
+/// combination of the other drilldowns. Calculated before top, so ranks
+/// are not limited to the top members.
+#[derive(Debug, Clone)]
+pub struct RankQuery {
+    pub drill: Drilldown,
+    pub mea: Measure,
+    pub direction: SortDirection,
+    pub method: RankMethod,
+}
+
+impl RankQuery {
+    pub fn new<S: Into<String>>(
+        dimension: S, hierarchy: S, level: S, measure: S,
+        direction: SortDirection, method: RankMethod
+    ) -> Self {
+        let drill = Drilldown::new(dimension, hierarchy, level);
+        let mea = Measure::new(measure);
+
+        RankQuery {
+            drill,
+            mea,
+            direction,
+            method,
+        }
+    }
+}
+
+impl FromStr for RankQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = parse::args(s, ',', 3, 4, "a rank query")?;
+
+        let method = match args.get(3) {
+            Some(&method) => parse::value(s, method, "rank method")?,
+            None => RankMethod::Standard,
+        };
+
+        Ok(RankQuery {
+            drill: args[0].parse::<Drilldown>()?,
+            mea: args[1].parse::<Measure>()?,
+            direction: parse::value(s, args[2], "sort direction")?,
+            method,
+        })
+    }
+}
+
+/// Standard ranking leaves gaps after ties (1, 1, 3); dense does not (1, 1, 2).
+#[derive(Debug, Clone, PartialEq)]
+pub enum RankMethod {
+    Standard,
+    Dense,
+}
+
+impl FromStr for RankMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "standard" => RankMethod::Standard,
+            "dense" => RankMethod::Dense,
+            _ => bail!("expected 'standard' or 'dense'"),
         })
     }
 }
@@ -369,28 +505,44 @@ impl FromStr for RcaQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
-            [drill_1, drill_2, measure] => {
-                let drill_1 = drill_1.parse::<Drilldown>()?;
-                let drill_2 = drill_2.parse::<Drilldown>()?;
-                let mea = measure.parse::<Measure>()?;
-
-                Ok(RcaQuery {
-                    drill_1,
-                    drill_2,
-                    mea,
-                })
-            },
-            _ => bail!("Could not parse an rca query, wrong number of args"),
-        }
+        let args = parse::args(s, ',', 3, 3, "an rca query")?;
+
+        let drill_1 = args[0].parse::<Drilldown>()?;
+        let drill_2 = args[1].parse::<Drilldown>()?;
+        let mea = args[2].parse::<Measure>()?;
+
+        Ok(RcaQuery {
+            drill_1,
+            drill_2,
+            mea,
+        })
 
     }
 }
 
+/// Growth of one or more measures over the time drilldown.
+///
+/// Args after the time drilldown are measures, optionally followed by
+/// `lag.n` (compare with the period `n` periods back, default 1) and
+/// `cagr` (compound annual growth rate since the first period, annualized
+/// by the time level's granularity). `lag` and `cagr` are reserved, and
+/// cannot be used together.
+///
+/// Periods are matched by key, so a missing earlier period gives a null
+/// growth. Keys of levels without a time granularity are counted as
+/// consecutive integers, like years.
 #[derive(Debug, Clone)]
 pub struct GrowthQuery {
     pub time_drill: Drilldown,
-    pub mea: Measure,
+    pub meas: Vec<Measure>,
+    pub lag: u64,
+    pub mode: GrowthMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrowthMode {
+    PeriodOverPeriod,
+    Cagr,
 }
 
 impl GrowthQuery {
@@ -400,28 +552,283 @@ impl GrowthQuery {
 
         GrowthQuery {
             time_drill,
-            mea,
+            meas: vec![mea],
+            lag: 1,
+            mode: GrowthMode::PeriodOverPeriod,
         }
     }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        ensure!(!self.meas.is_empty(), "Growth has no measures");
+        GrowthQuery::check_lag(self.lag)?;
+        ensure!(self.mode == GrowthMode::PeriodOverPeriod || self.lag == 1, "Growth lag cannot be used with cagr");
+        Ok(())
+    }
+
+    fn check_lag(lag: u64) -> Result<(), Error> {
+        ensure!(lag >= 1, "Growth lag must be at least 1");
+        Ok(())
+    }
+
+    /// Parses the args after the time drilldown: measures, then options.
+    pub fn from_args(time_drill: Drilldown, args: &[&str]) -> Result<Self, Error> {
+        let s = args.join(",");
+        GrowthQuery::from_args_in(&s, time_drill, &parse::split(&s, ',')?)
+    }
+
+    /// `args` are parts of `s`; errors are reported relative to `s`.
+    fn from_args_in(s: &str, time_drill: Drilldown, args: &[Part]) -> Result<Self, Error> {
+        let mut meas = vec![];
+        let mut lag = None;
+        let mut mode = GrowthMode::PeriodOverPeriod;
+
+        for &arg in args {
+            let pos = arg.start;
+
+            if let Some(n) = arg.strip_prefix("lag.") {
+                let n = parse::value::<u64>(s, n, "growth lag")?;
+                GrowthQuery::check_lag(n)
+                    .map_err(|err| parse::error_at(s, pos, &err.to_string()))?;
+                lag = Some(n);
+            } else if arg.to_lowercase() == "cagr" {
+                mode = GrowthMode::Cagr;
+            } else if lag.is_some() || mode == GrowthMode::Cagr {
+                return Err(parse::error_at(s, pos, "Growth measures must come before lag and cagr options"));
+            } else {
+                meas.push(arg.parse::<Measure>()?);
+            }
+
+            if mode == GrowthMode::Cagr && lag.is_some() {
+                return Err(parse::error_at(s, pos, "Growth lag cannot be used with cagr"));
+            }
+        }
+
+        if meas.is_empty() {
+            return Err(parse::error_at(s, s.len(), "Could not parse a growth query, no measures"));
+        }
+
+        Ok(GrowthQuery {
+            time_drill,
+            meas,
+            lag: lag.unwrap_or(1),
+            mode,
+        })
+    }
 }
 
 impl FromStr for GrowthQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match &s.split(",").collect::<Vec<_>>()[..] {
-            [time_drill, measure] => {
-                let time_drill = time_drill.parse::<Drilldown>()?;
-                let mea = measure.parse::<Measure>()?;
-
-                Ok(GrowthQuery {
-                    time_drill,
-                    mea,
-                })
-            },
-            _ => bail!("Could not parse a growth query, wrong number of args"),
+        let args = parse::args(s, ',', 2, usize::MAX, "a growth query")?;
+        let time_drill = args[0].parse::<Drilldown>()?;
+
+        GrowthQuery::from_args_in(s, time_drill, &args[1..])
+    }
+}
+
+/// Running total of a measure over the time drilldown, partitioned by
+/// the other drilldowns.
+///
+/// With `to_date`, the total restarts every year, quarter or month
+/// (`ytd`, `qtd`, `mtd`), e.g. `Date.Date.Month,Quantity,ytd`.
+#[derive(Debug, Clone)]
+pub struct CumulativeQuery {
+    pub time_drill: Drilldown,
+    pub mea: Measure,
+    pub to_date: Option<PeriodToDate>,
+}
+
+impl CumulativeQuery {
+    pub fn new<S: Into<String>>(dimension: S, hierarchy: S, level: S, measure: S) -> Self {
+        let time_drill = Drilldown::new(dimension, hierarchy, level);
+        let mea = Measure::new(measure);
+
+        CumulativeQuery {
+            time_drill,
+            mea,
+            to_date: None,
         }
+    }
+}
 
+impl FromStr for CumulativeQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = parse::args(s, ',', 2, 3, "a cumulative query")?;
+
+        let to_date = match args.get(2) {
+            Some(&to_date) => Some(parse::value(s, to_date, "period to date")?),
+            None => None,
+        };
+
+        Ok(CumulativeQuery {
+            time_drill: args[0].parse::<Drilldown>()?,
+            mea: args[1].parse::<Measure>()?,
+            to_date,
+        })
+    }
+}
+
+/// Period that a period-to-date running total restarts on.
+///
+/// The reset happens at the level of the time hierarchy with that time
+/// granularity, either derived from a date column or set by the level's
+/// `level` annotation. If the dimension has a fiscal year start month,
+/// years and quarters are fiscal, and must be derived from a date column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeriodToDate {
+    Year,
+    Quarter,
+    Month,
+}
+
+impl FromStr for PeriodToDate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "ytd" => Ok(PeriodToDate::Year),
+            "qtd" => Ok(PeriodToDate::Quarter),
+            "mtd" => Ok(PeriodToDate::Month),
+            _ => bail!("expected 'ytd', 'qtd' or 'mtd'"),
+        }
+    }
+}
+
+/// Trailing moving average of a measure over the time drilldown, partitioned
+/// by the other drilldowns. The window includes the current period.
+#[derive(Debug, Clone)]
+pub struct MovingAvgQuery {
+    pub time_drill: Drilldown,
+    pub mea: Measure,
+    pub window: u64,
+}
+
+impl MovingAvgQuery {
+    pub fn new<S: Into<String>>(dimension: S, hierarchy: S, level: S, measure: S, window: u64) -> Self {
+        let time_drill = Drilldown::new(dimension, hierarchy, level);
+        let mea = Measure::new(measure);
+
+        MovingAvgQuery {
+            time_drill,
+            mea,
+            window,
+        }
+    }
+
+    fn check_window(window: u64) -> Result<(), Error> {
+        ensure!(window >= 1, "Moving average window must be at least 1");
+        Ok(())
+    }
+}
+
+impl FromStr for MovingAvgQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = parse::args(s, ',', 3, 3, "a moving_avg query")?;
+
+        let window = parse::value::<u64>(s, args[2], "moving average window")?;
+        MovingAvgQuery::check_window(window)
+            .map_err(|err| parse::error_at(s, args[2].start, &err.to_string()))?;
+
+        Ok(MovingAvgQuery {
+            time_drill: args[0].parse::<Drilldown>()?,
+            mea: args[1].parse::<Measure>()?,
+            window,
+        })
+    }
+}
+
+/// Measure as a percentage of its value at a base member of a drilldown,
+/// within each combination of the other drilldowns. The base member is a key.
+#[derive(Debug, Clone)]
+pub struct IndexQuery {
+    pub drill: Drilldown,
+    pub base: String,
+    pub mea: Measure,
+}
+
+impl IndexQuery {
+    pub fn new<S: Into<String>>(dimension: S, hierarchy: S, level: S, base: S, measure: S) -> Self {
+        let drill = Drilldown::new(dimension, hierarchy, level);
+        let mea = Measure::new(measure);
+
+        IndexQuery {
+            drill,
+            base: base.into(),
+            mea,
+        }
+    }
+}
+
+impl FromStr for IndexQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = parse::args(s, ',', 3, 3, "an index query")?;
+
+        Ok(IndexQuery {
+            drill: args[0].parse::<Drilldown>()?,
+            base: parse::unquote_in(s, args[1])?,
+            mea: args[2].parse::<Measure>()?,
+        })
+    }
+}
+
+/// Least squares line of a measure over the periods of the time drilldown,
+/// within each combination of the other drilldowns, projected `periods`
+/// ahead as forecast rows.
+#[derive(Debug, Clone)]
+pub struct TrendQuery {
+    pub time_drill: Drilldown,
+    pub mea: Measure,
+    pub periods: u64,
+}
+
+impl TrendQuery {
+    pub const MAX_PERIODS: u64 = 100;
+
+    pub fn new<S: Into<String>>(dimension: S, hierarchy: S, level: S, measure: S, periods: u64) -> Self {
+        let time_drill = Drilldown::new(dimension, hierarchy, level);
+        let mea = Measure::new(measure);
+
+        TrendQuery {
+            time_drill,
+            mea,
+            periods,
+        }
+    }
+
+    fn check_periods(periods: u64) -> Result<(), Error> {
+        ensure!(periods <= TrendQuery::MAX_PERIODS, "Trend periods must be at most {}", TrendQuery::MAX_PERIODS);
+        Ok(())
+    }
+}
+
+impl FromStr for TrendQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = parse::args(s, ',', 2, 3, "a trend query")?;
+
+        let periods = match args.get(2) {
+            Some(&periods) => {
+                let n = parse::value::<u64>(s, periods, "trend periods")?;
+                TrendQuery::check_periods(n)
+                    .map_err(|err| parse::error_at(s, periods.start, &err.to_string()))?;
+                n
+            },
+            None => 0,
+        };
+
+        Ok(TrendQuery {
+            time_drill: args[0].parse::<Drilldown>()?,
+            mea: args[1].parse::<Measure>()?,
+            periods,
+        })
     }
 }
 
@@ -449,22 +856,23 @@ impl FromStr for Operator {
         Ok(match s {
             "and" => Operator::And,
             "or" => Operator::Or,
-            _ => bail!("Could not parse sort direction"),
+            _ => bail!("expected 'and' or 'or'"),
         })
     }
 }
 
-fn get_filter(filter_split: Vec<String>, split_int: usize) -> Result<FilterQuery, Error> {
-    let by_mea_or_calc = filter_split[0].parse::<MeaOrCalc>()?;
-    let constraint = join(&filter_split[1..split_int], ".").parse::<Constraint>()?;
-    let operator = Some(filter_split[split_int].parse::<Operator>()?);
-    let constraint2 = Some(join(&filter_split[split_int+1..], ".").parse::<Constraint>()?);
-    Ok(FilterQuery {
-        by_mea_or_calc,
-        constraint,
-        operator,
-        constraint2,
-    })
+/// Parses `comparison.number` from the start of `parts`, which are parts
+/// of `s`, and returns the parts after it. A number with a fraction spans
+/// two parts, e.g. `1.5` or `.5`.
+fn filter_constraint<'a, 'b>(s: &'a str, parts: &'b [Part<'a>]) -> Result<(Constraint, &'b [Part<'a>]), Error> {
+    match *parts {
+        [comparison, int, frac, ref rest @ ..] if !frac.is_empty() && frac.chars().all(|c| c.is_ascii_digit()) => {
+            let n = parse::span(s, int, frac);
+            Ok((Constraint::parse_in(s, comparison, n)?, rest))
+        },
+        [comparison, n, ref rest @ ..] => Ok((Constraint::parse_in(s, comparison, n)?, rest)),
+        _ => Err(parse::error_at(s, s.len(), "Could not parse a filter query, expected 'comparison.number'")),
+    }
 }
 
 /// For filtering on a measure after Top is calculated (wrapper around end aggregation)
@@ -487,36 +895,325 @@ impl PartialEq for FilterQuery {
 impl Eq for FilterQuery {}
 
 
-// Currently only allows one sort_measure
+/// `measure.comparison.number`, optionally followed by
+/// `.operator.comparison.number`, e.g. `Quantity.lt.1.5.or.gt.200`.
 impl FromStr for FilterQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.contains(".and.") || s.contains(".or.") {
-            let filter_split: Vec<String> = s.split(".").map(|f| f.to_string()).collect();
-            let length = filter_split.len();
-            if length >= 6 || length <= 8 {
-                let op_index = filter_split.iter().position(|s| s == "and" || s=="or").unwrap();
-                get_filter(filter_split, op_index)
-            } else {
-                bail!("Could not parse a filter query")
-            }
+        let parts = parse::split(s, '.')?;
+
+        let by_mea_or_calc = parts[0].parse::<MeaOrCalc>()?;
+        let (constraint, rest) = filter_constraint(s, &parts[1..])?;
+
+        let (operator, constraint2) = match rest.split_first() {
+            Some((&operator, rest)) => {
+                let operator = parse::value::<Operator>(s, operator, "filter operator")?;
+                let (constraint2, rest) = filter_constraint(s, rest)?;
+
+                if let Some(part) = rest.first() {
+                    return Err(parse::error_at(s, part.start, "Unexpected part after the second constraint"));
+                }
+
+                (Some(operator), Some(constraint2))
+            },
+            None => (None, None),
+        };
+
+        Ok(FilterQuery {
+            by_mea_or_calc,
+            constraint,
+            operator,
+            constraint2,
+        })
+    }
+}
+
+
+/// Boolean expression over measures and calculations, for filtering the
+/// end aggregation. Parsed from e.g.
+/// `Quantity > 100 and not ([Price Total] between 10 and 20 or rca is null)`
+///
+/// `not` binds tighter than `and`, which binds tighter than `or`. Keywords
+/// are case-insensitive. Measure names may be bare words, or wrapped in `[]`
+/// if they contain keywords or symbols.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    Compare(MeaOrCalc, Constraint),
+    Between(MeaOrCalc, f64, f64),
+    IsNull(MeaOrCalc),
+    Not(Box<FilterExpr>),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+}
+
+impl FilterExpr {
+    /// All measures and calculations referenced in the expression.
+    pub fn mea_or_calcs(&self) -> Vec<&MeaOrCalc> {
+        match self {
+            FilterExpr::Compare(m, _) => vec![m],
+            FilterExpr::Between(m, _, _) => vec![m],
+            FilterExpr::IsNull(m) => vec![m],
+            FilterExpr::Not(e) => e.mea_or_calcs(),
+            FilterExpr::And(l, r) | FilterExpr::Or(l, r) => {
+                let mut res = l.mea_or_calcs();
+                res.extend(r.mea_or_calcs());
+                res
+            },
+        }
+    }
+}
+
+impl FromStr for FilterExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = filter_tokens(s)?;
+        let mut parser = FilterParser { s, tokens, pos: 0 };
+
+        let expr = parser.or_expr()?;
+
+        if let Some(token) = parser.peek() {
+            return Err(parser.error_here(&format!("Unexpected '{}' in filter expression", token.text())));
+        }
+
+        Ok(expr)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FilterToken {
+    LParen,
+    RParen,
+    /// Comparison operator, symbolic or word (`>`, `gt`)
+    Cmp(Comparison),
+    /// Bare word: keyword, number, or part of a measure name
+    Word(String),
+    /// Name wrapped in []
+    Quoted(String),
+}
+
+impl FilterToken {
+    fn text(&self) -> String {
+        match self {
+            FilterToken::LParen => "(".to_owned(),
+            FilterToken::RParen => ")".to_owned(),
+            FilterToken::Cmp(c) => c.sql_string(),
+            FilterToken::Word(w) => w.clone(),
+            FilterToken::Quoted(q) => format!("[{}]", q),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self {
+            FilterToken::Word(w) => w.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+}
+
+/// Tokens with the byte index where each starts in `s`.
+fn filter_tokens(s: &str) -> Result<Vec<(usize, FilterToken)>, Error> {
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let byte_idx = |i: usize| chars.get(i).map(|&(idx, _)| idx).unwrap_or(s.len());
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+
+        match c {
+            c if c.is_whitespace() => { i += 1; },
+            '(' => { tokens.push((start, FilterToken::LParen)); i += 1; },
+            ')' => { tokens.push((start, FilterToken::RParen)); i += 1; },
+            '[' => {
+                let end = chars[i..].iter().position(|&(_, c)| c == ']')
+                    .ok_or_else(|| parse::error_at(s, start, "Unclosed '[' in filter expression"))?;
+                tokens.push((start, FilterToken::Quoted(s[byte_idx(i + 1)..byte_idx(i + end)].to_owned())));
+                i += end + 1;
+            },
+            '=' | '<' | '>' | '!' => {
+                let next = chars.get(i + 1).map(|&(_, c)| c);
+                let (comparison, len) = match (c, next) {
+                    ('<', Some('=')) => (Comparison::LessThanOrEqual, 2),
+                    ('>', Some('=')) => (Comparison::GreaterThanOrEqual, 2),
+                    ('<', Some('>')) => (Comparison::NotEqual, 2),
+                    ('!', Some('=')) => (Comparison::NotEqual, 2),
+                    ('<', _) => (Comparison::LessThan, 1),
+                    ('>', _) => (Comparison::GreaterThan, 1),
+                    ('=', _) => (Comparison::Equal, 1),
+                    _ => return Err(parse::error_at(s, start, &format!("Unexpected '{}' in filter expression", c))),
+                };
+                tokens.push((start, FilterToken::Cmp(comparison)));
+                i += len;
+            },
+            _ => {
+                while i < chars.len() && !chars[i].1.is_whitespace() && !"()[]=<>!".contains(chars[i].1) {
+                    i += 1;
+                }
+                let word = s[start..byte_idx(i)].to_owned();
+
+                // word forms of the comparisons, as in `filters`
+                match word.to_lowercase().parse::<Comparison>() {
+                    Ok(comparison) => tokens.push((start, FilterToken::Cmp(comparison))),
+                    Err(_) => tokens.push((start, FilterToken::Word(word))),
+                }
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent over the grammar:
+///
+/// ```text
+/// or_expr   := and_expr ("or" and_expr)*
+/// and_expr  := not_expr ("and" not_expr)*
+/// not_expr  := "not" not_expr | "(" or_expr ")" | predicate
+/// predicate := operand (cmp number | "between" number "and" number | "is" ["not"] "null")
+/// ```
+struct FilterParser<'a> {
+    s: &'a str,
+    tokens: Vec<(usize, FilterToken)>,
+    pos: usize,
+}
+
+impl<'a> FilterParser<'a> {
+    fn peek(&self) -> Option<&FilterToken> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    /// Error at the next token, or at the end of the expression.
+    fn error_here(&self, msg: &str) -> Error {
+        let idx = self.tokens.get(self.pos).map(|&(idx, _)| idx).unwrap_or(self.s.len());
+        parse::error_at(self.s, idx, msg)
+    }
+
+    /// Error at the token just taken with `next`.
+    fn error_prev(&self, msg: &str) -> Error {
+        parse::error_at(self.s, self.tokens[self.pos - 1].0, msg)
+    }
+
+    fn next(&mut self) -> Result<FilterToken, Error> {
+        let token = self.peek()
+            .cloned()
+            .ok_or_else(|| self.error_here("Unexpected end of filter expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().map(|t| t.is_keyword(keyword)).unwrap_or(false) {
+            self.pos += 1;
+            true
         } else {
-            match &s.splitn(2, ".").collect::<Vec<_>>()[..] {
-                [by_mea, constraint] => {
-                    let by_mea_or_calc = by_mea.parse::<MeaOrCalc>()?;
-                    let constraint = constraint.parse::<Constraint>()?;
-
-                    Ok(FilterQuery {
-                        by_mea_or_calc,
-                        constraint,
-                        operator: None,
-                        constraint2: None
-                    })
-                },
-                _ => bail!("Could not parse a filter query"),
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            let found = self.peek().map(|t| t.text()).unwrap_or_else(|| "end of expression".to_owned());
+            Err(self.error_here(&format!("Expected '{}' in filter expression, found '{}'", keyword, found)))
+        }
+    }
+
+    fn or_expr(&mut self) -> Result<FilterExpr, Error> {
+        let mut expr = self.and_expr()?;
+        while self.eat_keyword("or") {
+            expr = FilterExpr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<FilterExpr, Error> {
+        let mut expr = self.not_expr()?;
+        while self.eat_keyword("and") {
+            expr = FilterExpr::And(Box::new(expr), Box::new(self.not_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn not_expr(&mut self) -> Result<FilterExpr, Error> {
+        if self.eat_keyword("not") {
+            return Ok(FilterExpr::Not(Box::new(self.not_expr()?)));
+        }
+
+        if self.peek() == Some(&FilterToken::LParen) {
+            self.pos += 1;
+            let expr = self.or_expr()?;
+            match self.next()? {
+                FilterToken::RParen => return Ok(expr),
+                token => return Err(self.error_prev(&format!("Expected ')' in filter expression, found '{}'", token.text()))),
             }
         }
+
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<FilterExpr, Error> {
+        let mea_or_calc = self.operand()?;
+
+        match self.next()? {
+            FilterToken::Cmp(comparison) => {
+                let n = self.number()?;
+                Ok(FilterExpr::Compare(mea_or_calc, Constraint { comparison, n }))
+            },
+            ref token if token.is_keyword("between") => {
+                let low = self.number()?;
+                self.expect_keyword("and")?;
+                let high = self.number()?;
+                Ok(FilterExpr::Between(mea_or_calc, low, high))
+            },
+            ref token if token.is_keyword("is") => {
+                let negated = self.eat_keyword("not");
+                self.expect_keyword("null")?;
+                let expr = FilterExpr::IsNull(mea_or_calc);
+                if negated {
+                    Ok(FilterExpr::Not(Box::new(expr)))
+                } else {
+                    Ok(expr)
+                }
+            },
+            token => Err(self.error_prev(&format!("Expected a comparison, 'between' or 'is' in filter expression, found '{}'", token.text()))),
+        }
+    }
+
+    /// Bare measure names may span several words, up to the comparison.
+    fn operand(&mut self) -> Result<MeaOrCalc, Error> {
+        let name = match self.next()? {
+            FilterToken::Quoted(name) => name,
+            FilterToken::Word(word) => {
+                let mut words = vec![word];
+                while let Some(FilterToken::Word(w)) = self.peek() {
+                    if w.eq_ignore_ascii_case("between") || w.eq_ignore_ascii_case("is") {
+                        break;
+                    }
+                    words.push(w.clone());
+                    self.pos += 1;
+                }
+                join(words, " ")
+            },
+            token => return Err(self.error_prev(&format!("Expected a measure or calculation in filter expression, found '{}'", token.text()))),
+        };
+
+        name.parse::<MeaOrCalc>()
+    }
+
+    fn number(&mut self) -> Result<f64, Error> {
+        match self.next()? {
+            // inf and NaN parse, but aren't valid in sql
+            FilterToken::Word(w) => {
+                match w.parse::<f64>() {
+                    Ok(n) if n.is_finite() => Ok(n),
+                    _ => Err(self.error_prev(&format!("Expected a number in filter expression, found '{}'", w))),
+                }
+            },
+            token => Err(self.error_prev(&format!("Expected a number in filter expression, found '{}'", token.text()))),
+        }
     }
 }
 
@@ -540,16 +1237,14 @@ impl FromStr for RateQuery {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let rate_split: Vec<String> = s.split(".").map(|x| x.to_string()).collect();
+        let rate_split = parse::args(s, '.', 3, 4, "a rate query")?;
         let n = rate_split.len();
 
-        if n <= 2 || n >= 5 {
-            return Err(format_err!("Malformatted RateQuery"));
-        }
-
-        let level = join(rate_split[0..n-1].iter(), ".");
-        let level_name = level.parse::<LevelName>()?;
-        let values: Vec<String> = rate_split[n-1].split(",").map(|s| s.to_string()).collect();
+        let level: Result<Vec<_>, _> = rate_split[0..n-1].iter()
+            .map(|&name| parse::unquote_in(s, name))
+            .collect();
+        let level_name = LevelName::from_vec(level?)?;
+        let values = parse::members_in(s, rate_split[n-1])?;
 
         Ok(RateQuery{
             level_name,
@@ -559,12 +1254,167 @@ impl FromStr for RateQuery {
 }
 
 
+/// Aggregates members of a drilldown level into new members named by each
+/// group, e.g. custom regions out of countries. Members that aren't in any
+/// group are kept as they are, unless `keep_others` is false.
+///
+/// `Dimension.Level:Americas=1,2;Europe=3,4`
+#[derive(Debug, Clone)]
+pub struct GroupQuery {
+    pub level_name: LevelName,
+    pub groups: Vec<MemberGroup>,
+    pub keep_others: bool,
+}
+
+impl GroupQuery {
+    pub fn new(level_name: LevelName, groups: Vec<MemberGroup>) -> Self {
+        GroupQuery {
+            level_name,
+            groups,
+            keep_others: true,
+        }
+    }
+}
+
+impl FromStr for GroupQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = parse::splitn(s, 2, ':')?;
+
+        match split[..] {
+            [level, groups] => {
+                let groups = parse::split_in(s, groups, ';')?.into_iter()
+                    .map(|group| group.parse())
+                    .collect::<Result<_, _>>()?;
+
+                Ok(GroupQuery::new(level.parse()?, groups))
+            },
+            _ => Err(parse::error_at(s, s.len(), "Could not parse a group query, expected 'level:name=members;...'")),
+        }
+    }
+}
+
+/// `name=m1,m2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberGroup {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+impl FromStr for MemberGroup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = parse::splitn(s, 2, '=')?;
+
+        match split[..] {
+            [name, members] => {
+                Ok(MemberGroup {
+                    name: parse::unquote_in(s, name)?,
+                    members: parse::members_in(s, members)?,
+                })
+            },
+            _ => Err(parse::error_at(s, s.len(), "Could not parse a member group, expected 'name=members'")),
+        }
+    }
+}
+
+
+/// Drilldown on buckets of the values of a measure's column, before
+/// aggregation. Buckets are `[lower, upper)`.
+///
+/// - Edges: `Age,0,18,35,65,inf`, bounds may be `-inf` or `inf`.
+/// - Equal width: `Income,10`, 10 buckets between the min and max of the
+///   column in the whole fact table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinsQuery {
+    pub measure: Measure,
+    pub bins: Bins,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bins {
+    Edges(Vec<f64>),
+    EqualWidth(u64),
+}
+
+impl Bins {
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            Bins::Edges(edges) => {
+                ensure!(edges.len() > 1, "Bins need at least two edges");
+                for (i, &edge) in edges.iter().enumerate() {
+                    Bins::check_edge(i.checked_sub(1).map(|prev| edges[prev]), edge)?;
+                }
+            },
+            Bins::EqualWidth(n) => Bins::check_width(*n)?,
+        }
+
+        Ok(())
+    }
+
+    fn check_width(n: u64) -> Result<(), Error> {
+        ensure!(n > 0, "Number of bins must be more than 0");
+        Ok(())
+    }
+
+    /// `prev` is the edge before `edge`, if any.
+    fn check_edge(prev: Option<f64>, edge: f64) -> Result<(), Error> {
+        ensure!(!edge.is_nan(), "Bin edge must be a number");
+        ensure!(prev.map(|prev| prev < edge).unwrap_or(true), "Bin edges must be increasing");
+        Ok(())
+    }
+}
+
+impl FromStr for BinsQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = parse::args(s, ',', 2, usize::MAX, "bins")?;
+
+        let measure = split[0].parse::<Measure>()?;
+        let bins = &split[1..];
+
+        let bins = if let [n] = *bins {
+            let n = parse::value::<u64>(s, n, "number of bins")?;
+            Bins::check_width(n)
+                .map_err(|err| parse::error_at(s, bins[0].start, &err.to_string()))?;
+
+            Bins::EqualWidth(n)
+        } else {
+            let mut edges = vec![];
+
+            for &edge in bins {
+                let n = parse::value::<f64>(s, edge, "bin edge")?;
+                Bins::check_edge(edges.last().copied(), n)
+                    .map_err(|err| parse::error_at(s, edge.start, &err.to_string()))?;
+                edges.push(n);
+            }
+
+            Bins::Edges(edges)
+        };
+
+        Ok(BinsQuery {
+            measure,
+            bins,
+        })
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
-    use super::FilterQuery;
+    use super::{FilterQuery, FilterExpr};
     use super::Measure;
     use crate::query::MeaOrCalc;
     use crate::query::{Constraint, Comparison};
+    use crate::query::{CumulativeQuery, MovingAvgQuery, IndexQuery, TrendQuery, Calculation, PeriodToDate};
+    use crate::query::{GrowthQuery, GrowthMode};
+    use crate::query::{RankQuery, RankMethod, SortDirection};
+    use crate::query::{GroupQuery, MemberGroup};
+    use crate::query::{BinsQuery, Bins};
+    use crate::names::LevelName;
     use std::str::FromStr;
 
     #[test]
@@ -600,4 +1450,244 @@ mod tests {
         };
         assert_eq!(filter, target);
     }
+
+    #[test]
+    fn test_moving_avg() {
+        let moving_avg = MovingAvgQuery::from_str("Date.Date.Year,Quantity,3").unwrap();
+        assert_eq!(moving_avg.mea, Measure("Quantity".to_owned()));
+        assert_eq!(moving_avg.window, 3);
+
+        assert!(MovingAvgQuery::from_str("Date.Date.Year,Quantity,0").is_err());
+        assert!(MovingAvgQuery::from_str("Date.Date.Year,Quantity").is_err());
+        assert!(CumulativeQuery::from_str("Date.Date.Year,Quantity,3").is_err());
+    }
+
+    #[test]
+    fn test_cumulative_to_date() {
+        let cumulative = CumulativeQuery::from_str("Date.Date.Month,Quantity").unwrap();
+        assert_eq!(cumulative.to_date, None);
+
+        let cumulative = CumulativeQuery::from_str("Date.Date.Month,Quantity,ytd").unwrap();
+        assert_eq!(cumulative.to_date, Some(PeriodToDate::Year));
+
+        assert!(CumulativeQuery::from_str("Date.Date.Month,Quantity,wtd").is_err());
+        assert!(CumulativeQuery::from_str("Date.Date.Month,Quantity,ytd,mtd").is_err());
+    }
+
+    #[test]
+    fn test_growth() {
+        let growth = GrowthQuery::from_str("Date.Date.Month,Quantity").unwrap();
+        assert_eq!(growth.meas, vec![Measure("Quantity".to_owned())]);
+        assert_eq!(growth.lag, 1);
+        assert_eq!(growth.mode, GrowthMode::PeriodOverPeriod);
+
+        let growth = GrowthQuery::from_str("Date.Date.Month,Quantity,Price,lag.12").unwrap();
+        assert_eq!(growth.meas, vec![Measure("Quantity".to_owned()), Measure("Price".to_owned())]);
+        assert_eq!(growth.lag, 12);
+
+        let growth = GrowthQuery::from_str("Date.Date.Year,Quantity,cagr").unwrap();
+        assert_eq!(growth.mode, GrowthMode::Cagr);
+
+        assert!(GrowthQuery::from_str("Date.Date.Year").is_err());
+        assert!(GrowthQuery::from_str("Date.Date.Year,lag.2").is_err());
+        assert!(GrowthQuery::from_str("Date.Date.Year,Quantity,lag.0").is_err());
+        assert!(GrowthQuery::from_str("Date.Date.Year,Quantity,lag.2,cagr").is_err());
+        assert!(GrowthQuery::from_str("Date.Date.Year,Quantity,lag.2,Price").is_err());
+    }
+
+    #[test]
+    fn test_index() {
+        let index = IndexQuery::from_str("Date.Date.Year,2010,Quantity").unwrap();
+        assert_eq!(index.base, "2010");
+        assert_eq!(index.mea, Measure("Quantity".to_owned()));
+        assert_eq!("index".parse::<MeaOrCalc>().unwrap(), MeaOrCalc::Calc(Calculation::Index));
+
+        assert!(IndexQuery::from_str("Date.Date.Year,Quantity").is_err());
+    }
+
+    #[test]
+    fn test_trend() {
+        let trend = TrendQuery::from_str("Date.Date.Year,Quantity").unwrap();
+        assert_eq!(trend.mea, Measure("Quantity".to_owned()));
+        assert_eq!(trend.periods, 0);
+
+        let trend = TrendQuery::from_str("Date.Date.Year,Quantity,3").unwrap();
+        assert_eq!(trend.periods, 3);
+
+        assert!(TrendQuery::from_str("Date.Date.Year,Quantity,-1").is_err());
+        assert!(TrendQuery::from_str("Date.Date.Year,Quantity,1000").is_err());
+        assert!(TrendQuery::from_str("Date.Date.Year").is_err());
+    }
+
+    #[test]
+    fn test_rank() {
+        let rank = RankQuery::from_str("Geography.Geography.State,Quantity,desc").unwrap();
+        assert_eq!(rank.mea, Measure("Quantity".to_owned()));
+        assert_eq!(rank.direction, SortDirection::Desc);
+        assert_eq!(rank.method, RankMethod::Standard);
+
+        let rank = RankQuery::from_str("Geography.Geography.State,Quantity,asc,dense").unwrap();
+        assert_eq!(rank.direction, SortDirection::Asc);
+        assert_eq!(rank.method, RankMethod::Dense);
+
+        assert!(RankQuery::from_str("Geography.Geography.State,Quantity").is_err());
+        assert!(RankQuery::from_str("Geography.Geography.State,Quantity,desc,sparse").is_err());
+    }
+
+    #[test]
+    fn test_and_filter() {
+        let filter = FilterQuery::from_str("Hello.lt.1.5.or.gt.200").unwrap();
+        assert_eq!(filter.constraint.n, 1.5);
+        assert_eq!(filter.operator, Some(super::Operator::Or));
+        assert_eq!(filter.constraint2.unwrap().n, 200.0);
+
+        assert!(FilterQuery::from_str("Hello.and.lt.1").is_err());
+        assert!(FilterQuery::from_str("Hello.lt.1.or.gt").is_err());
+
+        let err = FilterQuery::from_str("Hello.lt.1.or.gt.2.and.lt.3").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected part after the second constraint at position 19 in 'Hello.lt.1.or.gt.2.and.lt.3'");
+        let err = FilterQuery::from_str("Hello.gt.abc").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse number 'abc': invalid float literal at position 9 in 'Hello.gt.abc'");
+    }
+
+    #[test]
+    fn test_filter_expr() {
+        let quantity = || MeaOrCalc::Mea(Measure("Quantity".to_owned()));
+        let price = || MeaOrCalc::Mea(Measure("Price Total".to_owned()));
+
+        let expr = FilterExpr::from_str("Quantity > 100 or Quantity between 1 and 5 and not rca is null").unwrap();
+        assert_eq!(expr, FilterExpr::Or(
+            Box::new(FilterExpr::Compare(quantity(), Constraint { comparison: Comparison::GreaterThan, n: 100.0 })),
+            Box::new(FilterExpr::And(
+                Box::new(FilterExpr::Between(quantity(), 1.0, 5.0)),
+                Box::new(FilterExpr::Not(Box::new(FilterExpr::IsNull(MeaOrCalc::Calc(super::Calculation::Rca))))),
+            )),
+        ));
+
+        // parens, multi-word and bracketed names, word comparisons
+        let expr = FilterExpr::from_str("(Price Total <= -2.5 OR [Price Total] gte 10) and Quantity is not null").unwrap();
+        assert_eq!(expr, FilterExpr::And(
+            Box::new(FilterExpr::Or(
+                Box::new(FilterExpr::Compare(price(), Constraint { comparison: Comparison::LessThanOrEqual, n: -2.5 })),
+                Box::new(FilterExpr::Compare(price(), Constraint { comparison: Comparison::GreaterThanOrEqual, n: 10.0 })),
+            )),
+            Box::new(FilterExpr::Not(Box::new(FilterExpr::IsNull(quantity())))),
+        ));
+
+        assert!(FilterExpr::from_str("").is_err());
+        assert!(FilterExpr::from_str("Quantity >").is_err());
+        assert!(FilterExpr::from_str("Quantity > abc").is_err());
+        assert!(FilterExpr::from_str("(Quantity > 1").is_err());
+        assert!(FilterExpr::from_str("Quantity > 1)").is_err());
+        assert!(FilterExpr::from_str("Quantity between 1").is_err());
+        assert!(FilterExpr::from_str("Quantity is 1").is_err());
+        assert!(FilterExpr::from_str("[Quantity > 1").is_err());
+        assert!(FilterExpr::from_str("Quantity > inf").is_err());
+        assert!(FilterExpr::from_str("Quantity between -inf and 1").is_err());
+        assert!(FilterExpr::from_str("Quantity < NaN").is_err());
+        assert!(Constraint::from_str("gt.inf").is_err());
+    }
+
+    #[test]
+    fn test_quoted_args() {
+        let top = super::TopQuery::from_str("10,[Geography].[County],[Price, Total],desc").unwrap();
+        assert_eq!(top.sort_mea_or_calc, vec![MeaOrCalc::Mea(Measure("Price, Total".to_owned()))]);
+
+        let rank = RankQuery::from_str("Geography.[State.Code],Quantity\\,Sold,desc").unwrap();
+        assert_eq!(rank.drill.0.level, "State.Code");
+        assert_eq!(rank.mea, Measure("Quantity,Sold".to_owned()));
+
+        let rate = super::RateQuery::from_str("Geography.County.[1.5],2").unwrap();
+        assert_eq!(rate.values, vec!["1.5", "2"]);
+
+        assert!(super::TopQuery::from_str("").is_err());
+        assert!(super::RateQuery::from_str("").is_err());
+        let err = super::SortQuery::from_str("[Quantity.desc").unwrap_err();
+        assert_eq!(err.to_string(), "Unclosed '[' at position 0 in '[Quantity.desc'");
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let err = RankQuery::from_str("Geography.State,Quantity").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse a rank query, expected 3 to 4 parts separated by ',', found 2 at position 24 in 'Geography.State,Quantity'");
+        let err = super::TopQuery::from_str("10,Geography.State,Quantity,desc,x").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse a top query, expected 4 parts separated by ',', found 5 at position 33 in '10,Geography.State,Quantity,desc,x'");
+
+        let err = super::SortQuery::from_str("Quantity.up").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse sort direction 'up': expected 'asc' or 'desc' at position 9 in 'Quantity.up'");
+        let err = TrendQuery::from_str("Date.Year,Quantity,x").unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse trend periods 'x': invalid digit found in string at position 19 in 'Date.Year,Quantity,x'");
+
+        let err = GrowthQuery::from_str("Date.Year,Quantity,lag.0").unwrap_err();
+        assert_eq!(err.to_string(), "Growth lag must be at least 1 at position 19 in 'Date.Year,Quantity,lag.0'");
+        let err = GrowthQuery::from_args(super::Drilldown::new("Date", "Date", "Year"), &["Quantity", "lag.x"]).unwrap_err();
+        assert_eq!(err.to_string(), "Could not parse growth lag 'x': invalid digit found in string at position 13 in 'Quantity,lag.x'");
+
+        let err = BinsQuery::from_str("Age,18,0").unwrap_err();
+        assert_eq!(err.to_string(), "Bin edges must be increasing at position 7 in 'Age,18,0'");
+
+        let err = FilterExpr::from_str("Quantity > 1)").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected ')' in filter expression at position 12 in 'Quantity > 1)'");
+        let err = FilterExpr::from_str("Quantity >").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected end of filter expression at position 10 in 'Quantity >'");
+        let err = FilterExpr::from_str("Quantity between 1 or 2").unwrap_err();
+        assert_eq!(err.to_string(), "Expected 'and' in filter expression, found 'or' at position 19 in 'Quantity between 1 or 2'");
+    }
+
+    #[test]
+    fn test_group() {
+        let group = GroupQuery::from_str("Geography.Country:Americas=1,2;[Rest; of world]=3").unwrap();
+        assert_eq!(group.level_name, LevelName::new("Geography", "Geography", "Country"));
+        assert_eq!(group.groups, vec![
+            MemberGroup { name: "Americas".into(), members: vec!["1".into(), "2".into()] },
+            MemberGroup { name: "Rest; of world".into(), members: vec!["3".into()] },
+        ]);
+        assert!(group.keep_others);
+
+        assert!(GroupQuery::from_str("Geography.Country").is_err());
+        assert!(GroupQuery::from_str("Geography.Country:Americas").is_err());
+        assert!(GroupQuery::from_str("Geography.Country:Americas=").is_err());
+    }
+
+    #[test]
+    fn test_bins() {
+        let bins = BinsQuery::from_str("Age,0,18,35,65,inf").unwrap();
+        assert_eq!(bins.measure, Measure::new("Age"));
+        assert_eq!(bins.bins, Bins::Edges(vec![0.0, 18.0, 35.0, 65.0, f64::INFINITY]));
+
+        let bins = BinsQuery::from_str("[Income, USD],10").unwrap();
+        assert_eq!(bins.measure, Measure::new("Income, USD"));
+        assert_eq!(bins.bins, Bins::EqualWidth(10));
+
+        assert!(BinsQuery::from_str("Age").is_err());
+        assert!(BinsQuery::from_str("Age,0").is_err());
+        assert!(BinsQuery::from_str("Age,18,0").is_err());
+        assert!(BinsQuery::from_str("Age,0,nan").is_err());
+        assert!(BinsQuery::from_str("Age,1.5").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut query = super::Query::new();
+        query.trend = Some(TrendQuery::new("Date", "Date", "Year", "Quantity", 3));
+        query.bins = Some(BinsQuery { measure: Measure::new("Age"), bins: Bins::EqualWidth(10) });
+        assert!(query.validate().is_ok());
+
+        let mut growth = GrowthQuery::new("Date", "Date", "Year", "Quantity");
+        growth.lag = 0;
+        query.growth = Some(growth);
+        assert_eq!(query.validate().unwrap_err().to_string(), "Growth lag must be at least 1");
+        query.growth = None;
+
+        query.moving_avg = Some(MovingAvgQuery::new("Date", "Date", "Year", "Quantity", 0));
+        assert_eq!(query.validate().unwrap_err().to_string(), "Moving average window must be at least 1");
+        query.moving_avg = None;
+
+        query.trend = Some(TrendQuery::new("Date", "Date", "Year", "Quantity", 1000));
+        assert_eq!(query.validate().unwrap_err().to_string(), "Trend periods must be at most 100");
+        query.trend = None;
+
+        query.bins = Some(BinsQuery { measure: Measure::new("Age"), bins: Bins::Edges(vec![10.0, 0.0]) });
+        assert_eq!(query.validate().unwrap_err().to_string(), "Bin edges must be increasing");
+    }
 }
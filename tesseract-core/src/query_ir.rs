@@ -1,9 +1,10 @@
+use failure::{Error, bail};
 use itertools::join;
 use serde_derive::{Deserialize, Serialize};
 
-use crate::names::Mask;
-use crate::query::{LimitQuery, SortDirection, Constraint, Operator};
-use crate::schema::{Table, InlineTable};
+use crate::names::{Mask, MemberRange};
+use crate::query::{LimitQuery, SortDirection, Constraint, Operator, GrowthMode, RankMethod, MemberGroup, Bins};
+use crate::schema::{Table, InlineTable, TimeGranularity};
 use crate::schema::aggregator::Aggregator;
 
 
@@ -15,6 +16,8 @@ pub struct QueryIr {
     pub meas: Vec<MeasureSql>,
     pub hidden_drills: Vec<HiddenDrilldownSql>,
     pub filters: Vec<FilterSql>,
+    /// ANDed with `filters`
+    pub filter: Option<FilterExprSql>,
     // TODO put Filters and Calculations into own structs
     pub top: Option<TopSql>,
     pub top_where: Option<TopWhereSql>,
@@ -22,7 +25,16 @@ pub struct QueryIr {
     pub limit: Option<LimitSql>,
     pub rca: Option<RcaSql>,
     pub growth: Option<GrowthSql>,
+    pub cumulative: Option<CumulativeSql>,
+    pub moving_avg: Option<MovingAvgSql>,
+    pub index: Option<IndexSql>,
+    pub rank: Option<RankSql>,
+    pub trend: Option<TrendSql>,
+    /// Drills to roll up into subtotals, in order, ending with a grand total
+    pub totals: Vec<DrilldownSql>,
     pub rate: Option<RateSql>,
+    pub group: Option<GroupSql>,
+    pub bins: Option<BinsSql>,
     pub sparse: bool,
 }
 
@@ -44,8 +56,10 @@ pub struct DrilldownSql {
 }
 
 impl DrilldownSql {
-    pub fn col_alias_string(&self) -> String {
-        let cols = self.col_alias_vec();
+    /// `date_key_sql` renders keys derived from a date column in the
+    /// backend's dialect.
+    pub fn col_alias_string(&self, date_key_sql: fn(&DateKey) -> String) -> String {
+        let cols = self.col_alias_vec(date_key_sql);
         join(cols, ", ")
     }
 
@@ -53,12 +67,17 @@ impl DrilldownSql {
     // because it's only used in col_alias_string.
     // In big refactor, push onto col vec, like
     // in col_alias_only_vec to match behavior
-    fn col_alias_vec(&self) -> Vec<String> {
+    fn col_alias_vec(&self, date_key_sql: fn(&DateKey) -> String) -> Vec<String> {
         let mut cols: Vec<_> = self.level_columns.iter()
             .map(|l| {
+                let key_sql = match l.date_key {
+                    Some(ref date_key) => date_key_sql(date_key),
+                    None => l.key_column.clone(),
+                };
+
                 if let Some(ref name_col) = l.name_column {
                     format!("{} as {}_{}, {} as {}_{}",
-                        l.key_column,
+                        key_sql,
                         l.key_column,
                         self.alias_postfix,
                         name_col,
@@ -67,7 +86,7 @@ impl DrilldownSql {
                     )
                 } else {
                     format!("{} as {}_{}",
-                        l.key_column,
+                        key_sql,
                         l.key_column,
                         self.alias_postfix,
                     )
@@ -126,10 +145,12 @@ impl DrilldownSql {
     fn col_qual_vec(&self) -> Vec<String> {
         let mut cols: Vec<_> = self.level_columns.iter()
             .map(|l| {
+                let key_sql = l.key_qual_string(&self.table.name);
+
                 if let Some(ref name_col) = l.name_column {
-                    format!("{}.{}, {}.{}", self.table.name, l.key_column, self.table.name, name_col)
+                    format!("{}, {}.{}", key_sql, self.table.name, name_col)
                 } else {
-                    format!("{}.{}", self.table.name, l.key_column)
+                    key_sql
                 }
             }).collect();
 
@@ -159,6 +180,54 @@ pub struct HiddenDrilldownSql {
 pub struct LevelColumn {
     pub key_column: String,
     pub name_column: Option<String>,
+    /// If set, the key is derived from a date column, and `key_column`
+    /// is only used for the alias.
+    pub date_key: Option<DateKey>,
+}
+
+impl LevelColumn {
+    /// Key col qualified by `table`, or the key derived from the date column.
+    pub fn key_qual_string(&self, table: &str) -> String {
+        match self.date_key {
+            Some(ref date_key) => date_key.standard_sql(Some(table)),
+            None => format!("{}.{}", table, self.key_column),
+        }
+    }
+}
+
+/// Level key derived from a date column, see `TimeGranularity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateKey {
+    pub column: String,
+    pub granularity: TimeGranularity,
+    /// Months added to the date for fiscal granularities.
+    pub fiscal_offset: u32,
+}
+
+impl DateKey {
+    /// `table` qualifies the date column.
+    pub fn standard_sql(&self, table: Option<&str>) -> String {
+        let date = match table {
+            Some(table) => format!("{}.{}", table, self.column),
+            None => self.column.clone(),
+        };
+        let fiscal_date = format!("{} + interval '{}' month", date, self.fiscal_offset);
+
+        let extract = |part: &str, date: &str| format!("extract({} from {})", part, date);
+
+        match self.granularity {
+            TimeGranularity::Year => extract("year", &date),
+            TimeGranularity::Quarter => format!("({} * 10 + {})", extract("year", &date), extract("quarter", &date)),
+            TimeGranularity::Month => format!("({} * 100 + {})", extract("year", &date), extract("month", &date)),
+            TimeGranularity::Day => format!("({} * 10000 + {} * 100 + {})",
+                extract("year", &date),
+                extract("month", &date),
+                extract("day", &date),
+            ),
+            TimeGranularity::FiscalYear => extract("year", &fiscal_date),
+            TimeGranularity::FiscalQuarter => format!("({} * 10 + {})", extract("year", &fiscal_date), extract("quarter", &fiscal_date)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -173,16 +242,21 @@ pub struct CutSql {
     pub mask: Mask,
     // if for_match, then use LIKE syntax
     pub for_match: bool,
+    // if range, members is empty
+    pub range: Option<MemberRange>,
     pub inline_table: Option<InlineTable>,
+    // if set, the column is derived from a date column
+    pub date_key: Option<DateKey>,
 }
 
 impl CutSql {
+    /// Members must have passed `check_member`.
     pub fn members_string(&self) -> String {
         let members = match self.member_type {
             MemberType::NonText => join(&self.members, ", "),
             MemberType::Text => {
                 let quoted = self.members.iter()
-                .map(|m| format!("'{}'", m));
+                .map(|m| quote(m));
                 join(quoted, ", ")
             }
         };
@@ -205,7 +279,7 @@ impl CutSql {
             },
             MemberType::Text => {
                 let quoted = self.members.iter()
-                    .map(|m| format!("{} {} '%{}%'", self.column, self.mask_sql_like_string(), m));
+                    .map(|m| format!("{} {} {}", self.column, self.mask_sql_like_string(), quote(&format!("%{}%", m))));
 
                 match self.mask {
                     Mask::Include => format!("({})", join(quoted, " or ")),
@@ -216,7 +290,42 @@ impl CutSql {
     }
 
     pub fn col_qual_string(&self) -> String {
-        format!("{}.{}", self.table.name, self.column)
+        match self.date_key {
+            Some(ref date_key) => date_key.standard_sql(Some(&self.table.name)),
+            None => format!("{}.{}", self.table.name, self.column),
+        }
+    }
+
+    /// Unqualified col, or the key derived from the date column rendered
+    /// by `date_key_sql`.
+    pub fn col_string(&self, date_key_sql: fn(&DateKey) -> String) -> String {
+        match self.date_key {
+            Some(ref date_key) => date_key_sql(date_key),
+            None => self.column.clone(),
+        }
+    }
+
+    /// For a range cut on `column`. Exclude negates the range.
+    /// Bounds are quoted for text levels, so text compares as text.
+    /// Bounds must have passed `check_member`.
+    pub fn range_string(&self, column: &str, range: &MemberRange) -> String {
+        let quote = |m: &String| match self.member_type {
+            MemberType::NonText => m.clone(),
+            MemberType::Text => quote(m),
+        };
+
+        let range_sql = match (&range.start, &range.end) {
+            (Some(start), Some(end)) => format!("{} between {} and {}", column, quote(start), quote(end)),
+            (Some(start), None) => format!("{} >= {}", column, quote(start)),
+            (None, Some(end)) => format!("{} <= {}", column, quote(end)),
+            // checked on parse
+            (None, None) => "1 = 1".to_owned(),
+        };
+
+        match self.mask {
+            Mask::Include => range_sql,
+            Mask::Exclude => format!("not ({})", range_sql),
+        }
     }
 
     pub fn mask_sql_in_string(&self) -> String {
@@ -248,6 +357,19 @@ pub struct MeasureSql {
     pub column: String,
 }
 
+impl MeasureSql {
+    /// Aggregate function to roll up values that were already aggregated,
+    /// if the aggregator allows it. Used for collapsing members, e.g. top others.
+    pub fn reaggregate_fn(&self) -> Option<&'static str> {
+        match self.aggregator {
+            Aggregator::Sum | Aggregator::Count | Aggregator::WeightedSum { .. } => Some("sum"),
+            Aggregator::Max => Some("max"),
+            Aggregator::Min => Some("min"),
+            _ => None,
+        }
+    }
+}
+
 // NOTE: This is now specific to each db, because of the custom aggregators
 // e.g. median
 //impl MeasureSql {
@@ -262,6 +384,133 @@ pub struct TopSql {
     pub by_column: String,
     pub sort_columns: Vec<String>,
     pub sort_direction: SortDirection,
+    pub others: bool,
+}
+
+/// Members of the drill with key column `by_column` aggregated into groups.
+/// The key and name columns of a grouped row are the group name.
+#[derive(Debug, Clone)]
+pub struct GroupSql {
+    pub by_column: String,
+    pub member_type: MemberType,
+    pub groups: Vec<MemberGroup>,
+    pub keep_others: bool,
+}
+
+impl GroupSql {
+    /// `by_column in (members)`, members quoted by member type. Members
+    /// must have passed `check_member`.
+    pub fn in_string(&self, members: &[String]) -> String {
+        let members = match self.member_type {
+            MemberType::NonText => join(members, ", "),
+            MemberType::Text => join(members.iter().map(|m| quote(m)), ", "),
+        };
+
+        format!("{} in ({})", self.by_column, members)
+    }
+
+    /// Whether the row's member is in any group.
+    pub fn grouped_string(&self) -> String {
+        let members: Vec<_> = self.groups.iter()
+            .flat_map(|g| g.members.iter().cloned())
+            .collect();
+
+        self.in_string(&members)
+    }
+
+    pub fn name_string(group: &MemberGroup) -> String {
+        quote(&group.name)
+    }
+}
+
+/// Text member as a sql string literal. Doesn't escape `\`, which is an
+/// escape char in some backends, so members must pass `check_member`.
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Checks a member from the query before it's inlined into sql. Non-text
+/// members are inlined as they are, so they must be numbers; text members
+/// are quoted, and can't contain `\`.
+pub fn check_member(member: &str, member_type: &MemberType) -> Result<(), Error> {
+    match member_type {
+        MemberType::NonText => {
+            let is_number = !member.is_empty()
+                && member.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c))
+                && member.parse::<f64>().is_ok();
+
+            if !is_number {
+                bail!("Member '{}' must be a number", member);
+            }
+        },
+        MemberType::Text => {
+            if member.contains('\\') {
+                bail!("Member '{}' cannot contain '\\'", member);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Buckets of a fact table column, as drilldown columns `bin_label`,
+/// `bin_lower` and `bin_upper` after the other drilldowns.
+#[derive(Debug, Clone)]
+pub struct BinsSql {
+    pub column: String,
+    pub table: String,
+    pub bins: Bins,
+}
+
+/// An explicit bucket; bounds are `None` if infinite.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub label: String,
+}
+
+impl BinsSql {
+    pub const ALIASES: [&'static str; 3] = ["bin_label", "bin_lower", "bin_upper"];
+
+    /// Buckets between each pair of edges.
+    pub fn buckets(edges: &[f64]) -> Vec<Bucket> {
+        let bound = |edge: f64| if edge.is_finite() { Some(edge) } else { None };
+
+        edges.windows(2)
+            .map(|w| Bucket {
+                lower: bound(w[0]),
+                upper: bound(w[1]),
+                label: format!("{} - {}", w[0], w[1]),
+            })
+            .collect()
+    }
+
+    /// Condition for a row to be in the bucket.
+    pub fn bucket_condition(&self, bucket: &Bucket) -> String {
+        let mut conditions = vec![];
+        if let Some(lower) = bucket.lower {
+            conditions.push(format!("{} >= {}", self.column, lower));
+        }
+        if let Some(upper) = bucket.upper {
+            conditions.push(format!("{} < {}", self.column, upper));
+        }
+
+        if conditions.is_empty() {
+            "1 = 1".to_owned()
+        } else {
+            join(conditions, " and ")
+        }
+    }
+
+    /// Min of the column and width of each equal width bucket, as scalar
+    /// subqueries over the whole fact table.
+    pub fn min_width_strings(&self, n: u64) -> (String, String) {
+        (
+            format!("(select min({}) from {})", self.column, self.table),
+            format!("(select (max({}) - min({})) / {} from {})", self.column, self.column, n, self.table),
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -279,6 +528,55 @@ pub struct FilterSql {
 
 }
 
+impl FilterSql {
+    pub fn sql_string(&self) -> String {
+        if let (Some(operator), Some(constraint2)) = (&self.operator, &self.constraint2) {
+            format!("({} {} {} {} {})",
+                self.by_column,
+                self.constraint.sql_string(),
+                operator.sql_string(),
+                self.by_column,
+                constraint2.sql_string(),
+            )
+        } else {
+            format!("{} {}", self.by_column, self.constraint.sql_string())
+        }
+    }
+}
+
+/// `FilterExpr` with measures and calculations resolved to columns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExprSql {
+    Compare(String, Constraint),
+    Between(String, f64, f64),
+    IsNull(String),
+    Not(Box<FilterExprSql>),
+    And(Box<FilterExprSql>, Box<FilterExprSql>),
+    Or(Box<FilterExprSql>, Box<FilterExprSql>),
+}
+
+impl FilterExprSql {
+    /// Standard sql, so it's shared between backends.
+    pub fn sql_string(&self) -> String {
+        match self {
+            FilterExprSql::Compare(col, constraint) => format!("{} {}", col, constraint.sql_string()),
+            FilterExprSql::Between(col, low, high) => format!("{} between {} and {}", col, low, high),
+            FilterExprSql::IsNull(col) => format!("{} is null", col),
+            FilterExprSql::Not(expr) => format!("not ({})", expr.sql_string()),
+            FilterExprSql::And(l, r) => format!("({} and {})", l.sql_string(), r.sql_string()),
+            FilterExprSql::Or(l, r) => format!("({} or {})", l.sql_string(), r.sql_string()),
+        }
+    }
+}
+
+/// All filter clauses for the end aggregation, to be ANDed.
+pub fn filter_clauses(filters: &[FilterSql], filter: Option<&FilterExprSql>) -> Vec<String> {
+    filters.iter()
+        .map(|f| f.sql_string())
+        .chain(filter.iter().map(|f| f.sql_string()))
+        .collect()
+}
+
 
 #[derive(Debug, Clone)]
 pub struct LimitSql {
@@ -313,8 +611,85 @@ pub struct RcaSql {
 
 #[derive(Debug, Clone)]
 pub struct GrowthSql {
+    pub time_drill: DrilldownSql,
+    pub meas: Vec<String>,
+    pub lag: u64,
+    pub mode: GrowthMode,
+    /// Periods are matched by the drilled level's key. Without a
+    /// granularity, keys are consecutive integers (e.g. years).
+    pub granularity: Option<TimeGranularity>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CumulativeSql {
     pub time_drill: DrilldownSql,
     pub mea: String,
+    pub reset: Option<ResetSql>,
+}
+
+/// Where a period-to-date running total restarts, on top of the partition
+/// by the other drilldowns.
+#[derive(Debug, Clone)]
+pub enum ResetSql {
+    /// Alias of the reset level's key col, which is one of the time drill
+    /// cols. Fiscal periods are levels derived from a date column.
+    Column(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MovingAvgSql {
+    pub time_drill: DrilldownSql,
+    pub mea: String,
+    pub window: u64,
+}
+
+/// Measure `mea` as a percentage of its value where the key column
+/// `by_column` of `drill` is `base`.
+#[derive(Debug, Clone)]
+pub struct IndexSql {
+    pub drill: DrilldownSql,
+    pub by_column: String,
+    pub member_type: MemberType,
+    pub base: String,
+    pub mea: String,
+}
+
+impl IndexSql {
+    /// `base` quoted by member type. `base` must have passed `check_member`.
+    pub fn base_string(&self) -> String {
+        match self.member_type {
+            MemberType::NonText => self.base.clone(),
+            MemberType::Text => quote(&self.base),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RankSql {
+    pub drill: DrilldownSql,
+    pub mea: String,
+    pub direction: SortDirection,
+    pub method: RankMethod,
+}
+
+/// Appends `trend_slope`, `trend_intercept`, `trend` (the fitted value) and
+/// `forecast` (0 for observed periods, otherwise the number of periods ahead).
+///
+/// Forecast rows get the drilled level's key projected from the last
+/// observed key; its other time columns and the measures are null.
+#[derive(Debug, Clone)]
+pub struct TrendSql {
+    pub time_drill: DrilldownSql,
+    pub mea: String,
+    pub periods: u64,
+    /// Keys are projected by period, like growth. Without a granularity,
+    /// keys are consecutive integers (e.g. years); day keys are not
+    /// projected.
+    pub granularity: Option<TimeGranularity>,
+}
+
+impl TrendSql {
+    pub const COLS: [&'static str; 4] = ["trend_slope", "trend_intercept", "trend", "forecast"];
 }
 
 #[derive(Debug, Clone)]
@@ -335,7 +710,8 @@ pub struct DimSubquery {
 /// Collects a drilldown and cut together to create a subquery for the dimension table
 /// Does not check for matching name, because that had to have been done
 /// before submitting to this fn.
-pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSubquery {
+/// `date_key_sql` renders keys derived from a date column.
+pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>, date_key_sql: fn(&DateKey) -> String) -> DimSubquery {
     match drill {
         Some(drill) => {
             let drill_table = match &drill.inline_table {
@@ -356,7 +732,7 @@ pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSu
             // Then don't add primary key here.
             // Also, make primary key optional?
             let sql = format!("select {}, {} as {} from {}",
-                drill.col_alias_string(),
+                drill.col_alias_string(date_key_sql),
                 drill.primary_key.clone(),
                 drill.foreign_key.clone(),
                 drill_table,
@@ -381,7 +757,7 @@ pub fn dim_subquery(drill: Option<&DrilldownSql>, cut: Option<&CutSql>) -> DimSu
                     cut.primary_key.clone(),
                     cut.foreign_key.clone(),
                     cut.table.full_name(),
-                    cut.column.clone(),
+                    cut.col_string(date_key_sql),
                     cut.members_string(),
                 );
 
@@ -4,6 +4,7 @@ use failure::{Error, format_err};
 
 pub mod aggregator;
 pub mod metadata;
+pub mod mondrian;
 mod json;
 mod xml;
 
@@ -32,7 +33,7 @@ pub use crate::schema::{
     xml::PropertyConfigXML,
 };
 use crate::names::{LevelName, Measure as MeasureName, Property as TsProperty};
-use crate::query_ir::MemberType;
+use crate::query_ir::{DateKey, MemberType};
 pub use self::aggregator::Aggregator;
 use crate::DEFAULT_ALLOWED_ACCESS;
 
@@ -116,6 +117,7 @@ impl From<SchemaConfigJson> for Schema {
                                     hierarchies,
                                     default_hierarchy: shared_dim_config.default_hierarchy.clone(),
                                     dim_type,
+                                    fiscal_year_start_month: shared_dim_config.fiscal_year_start_month,
                                     annotations: dim_annotations,
                                     is_shared: true
                                 });
@@ -347,6 +349,9 @@ pub struct Dimension {
     pub hierarchies: Vec<Hierarchy>,
     pub default_hierarchy: Option<String>,
     pub dim_type: DimensionType,
+    /// Month (1-12) that fiscal years start on, for time dimensions.
+    /// Fiscal years are named by the calendar year they end in.
+    pub fiscal_year_start_month: Option<u32>,
     pub annotations: Option<Vec<Annotation>>,
     pub is_shared: bool,
 }
@@ -371,12 +376,34 @@ impl From<DimensionConfigJson> for Dimension {
             default_hierarchy: dimension_config.default_hierarchy,
             hierarchies,
             dim_type,
+            fiscal_year_start_month: dimension_config.fiscal_year_start_month,
             annotations,
             is_shared: false
         }
     }
 }
 
+impl Dimension {
+    /// Derived key for a level with a `granularity`, in a hierarchy with a
+    /// `date_column`.
+    pub fn date_key(&self, hierarchy: &Hierarchy, level: &Level) -> Option<DateKey> {
+        let column = hierarchy.date_column.clone()?;
+        let granularity = level.granularity.clone()?;
+
+        Some(DateKey {
+            column,
+            granularity,
+            fiscal_offset: self.fiscal_offset(),
+        })
+    }
+
+    /// Months to add to a date so that the fiscal year starts in January,
+    /// and the calendar year of the result is the fiscal year.
+    pub fn fiscal_offset(&self) -> u32 {
+        (13 - self.fiscal_year_start_month.unwrap_or(1)) % 12
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DimensionType {
     #[serde(rename="standard")]
@@ -391,12 +418,53 @@ impl std::default::Default for DimensionType {
     fn default() -> Self { DimensionType::Standard }
 }
 
+/// Granularity of a time level whose key is derived from the hierarchy's
+/// `date_column`, instead of read from a `key_column`.
+///
+/// Keys are integers, e.g. `2019`, `20193` (quarter), `201907` and `20190715`.
+/// Fiscal years use the dimension's `fiscal_year_start_month`, and are
+/// named by the calendar year they end in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TimeGranularity {
+    #[serde(rename="year")]
+    Year,
+    #[serde(rename="quarter")]
+    Quarter,
+    #[serde(rename="month")]
+    Month,
+    #[serde(rename="day")]
+    Day,
+    #[serde(rename="fiscal_year")]
+    FiscalYear,
+    #[serde(rename="fiscal_quarter")]
+    FiscalQuarter,
+}
+
+impl TimeGranularity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeGranularity::Year => "year",
+            TimeGranularity::Quarter => "quarter",
+            TimeGranularity::Month => "month",
+            TimeGranularity::Day => "day",
+            TimeGranularity::FiscalYear => "fiscal_year",
+            TimeGranularity::FiscalQuarter => "fiscal_quarter",
+        }
+    }
+
+    pub fn is_fiscal(&self) -> bool {
+        *self == TimeGranularity::FiscalYear || *self == TimeGranularity::FiscalQuarter
+    }
+}
+
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Hierarchy {
     pub name: String,
     pub table: Option<Table>,
     pub primary_key: String,
+    /// Date column that the keys of levels with a `granularity` are derived from.
+    pub date_column: Option<String>,
     pub levels: Vec<Level>,
     pub annotations: Option<Vec<Annotation>>,
     pub inline_table: Option<InlineTable>,
@@ -405,10 +473,19 @@ pub struct Hierarchy {
 
 impl From<HierarchyConfigJson> for Hierarchy {
     fn from(hierarchy_config: HierarchyConfigJson) -> Self {
-        let levels: Vec<Level> = hierarchy_config.levels.into_iter()
+        let mut levels: Vec<Level> = hierarchy_config.levels.into_iter()
             .map(|l| l.into())
             .collect();
 
+        // derived keys still need a distinct name, for aliasing
+        if let Some(ref date_column) = hierarchy_config.date_column {
+            for level in levels.iter_mut() {
+                if let Some(ref granularity) = level.granularity {
+                    level.key_column = format!("{}_{}", date_column, granularity.name());
+                }
+            }
+        }
+
         let annotations = hierarchy_config.annotations
             .map(|anns| {
                 anns.into_iter()
@@ -416,7 +493,10 @@ impl From<HierarchyConfigJson> for Hierarchy {
                     .collect()
             });
 
+        let date_column = hierarchy_config.date_column;
+
         let primary_key = hierarchy_config.primary_key
+            .or_else(|| date_column.clone())
             .unwrap_or_else(|| {
                 levels.iter()
                     .last()
@@ -429,6 +509,7 @@ impl From<HierarchyConfigJson> for Hierarchy {
             name: hierarchy_config.name,
             table: hierarchy_config.table.map(|t| t.into()),
             primary_key,
+            date_column,
             levels,
             annotations,
             inline_table: hierarchy_config.inline_table.map(|t| t.into()),
@@ -560,6 +641,7 @@ pub struct Level {
     pub properties: Option<Vec<Property>>,
     pub key_type: Option<MemberType>,
     pub annotations: Option<Vec<Annotation>>,
+    pub granularity: Option<TimeGranularity>,
 }
 
 impl Level {
@@ -587,6 +669,28 @@ impl Level {
 
         captions
     }
+
+    /// The level's `granularity`, or for a level with a key column, its
+    /// `level` annotation (`Year`, `Quarter`, `Month` or `Day`, as used by
+    /// the logic layer cache). Annotated keys are expected in the same
+    /// integer format as derived keys, see `TimeGranularity`.
+    pub fn time_granularity(&self) -> Option<TimeGranularity> {
+        if self.granularity.is_some() {
+            return self.granularity.clone();
+        }
+
+        self.annotations.as_ref()?.iter()
+            .find(|ann| ann.name == "level")
+            .and_then(|ann| {
+                match ann.text.as_ref() {
+                    "Year" => Some(TimeGranularity::Year),
+                    "Quarter" => Some(TimeGranularity::Quarter),
+                    "Month" => Some(TimeGranularity::Month),
+                    "Day" => Some(TimeGranularity::Day),
+                    _ => None,
+                }
+            })
+    }
 }
 
 impl From<LevelConfigJson> for Level {
@@ -611,6 +715,7 @@ impl From<LevelConfigJson> for Level {
             properties,
             key_type: level_config.key_type,
             annotations,
+            granularity: level_config.granularity,
         }
     }
 }
@@ -772,16 +877,19 @@ mod test {
                                     properties: None,
                                     key_type: None,
                                     annotations: None,
+                                    granularity: None,
                                 },
                             ],
                             annotations: None,
                             inline_table: None,
                             default_member: None,
+                            date_column: None,
                         },
                     ],
                     default_hierarchy: None,
                     annotations: None,
                     dim_type: None,
+                    fiscal_year_start_month: None,
                 }
             ]),
             cubes: vec![
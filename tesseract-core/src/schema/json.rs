@@ -2,7 +2,7 @@ use serde_derive::Deserialize;
 
 use crate::query_ir::MemberType;
 use super::aggregator::Aggregator;
-use super::{DimensionType, MeasureType};
+use super::{DimensionType, MeasureType, TimeGranularity};
 
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -34,6 +34,7 @@ pub struct DimensionConfigJson {
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: Option<DimensionType>,
+    pub fiscal_year_start_month: Option<u32>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
@@ -44,6 +45,7 @@ pub struct SharedDimensionConfigJson {
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: Option<DimensionType>,
+    pub fiscal_year_start_month: Option<u32>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
 }
 
@@ -60,6 +62,7 @@ pub struct HierarchyConfigJson {
     pub name: String,
     pub table: Option<TableConfigJson>,
     pub primary_key: Option<String>,
+    pub date_column: Option<String>,
     pub levels: Vec<LevelConfigJson>,
     pub annotations: Option<Vec<AnnotationConfigJson>>,
     pub inline_table: Option<InlineTableJson>,
@@ -95,7 +98,9 @@ pub struct InlineTableRowValueJson {
 #[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct LevelConfigJson {
     pub name: String,
+    #[serde(default)]
     pub key_column: String,
+    pub granularity: Option<TimeGranularity>,
     pub name_column: Option<String>,
     pub properties: Option<Vec<PropertyConfigJson>>,
     pub key_type: Option<MemberType>,
@@ -67,6 +67,7 @@ pub struct DimensionMetadata {
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: DimensionType,
+    pub fiscal_year_start_month: Option<u32>,
     pub annotations: AnnotationMetadata,
 }
 
@@ -79,6 +80,7 @@ impl From<&Dimension> for DimensionMetadata {
             hierarchies: dimension.hierarchies.iter().map(|h| h.into()).collect(),
             default_hierarchy: dimension.default_hierarchy.clone(),
             dim_type: dimension.dim_type.clone(),
+            fiscal_year_start_month: dimension.fiscal_year_start_month,
             annotations,
         }
     }
@@ -248,4 +250,14 @@ pub struct SourceMetadata {
     pub name: String,
     pub measures: Vec<String>,
     pub annotations: Option<HashMap<String, String>>,
+    /// Only used by formats that describe the dimensions of a result
+    #[serde(skip)]
+    pub dimensions: Vec<SourceDimensionMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct SourceDimensionMetadata {
+    pub name: String,
+    pub levels: Vec<String>,
+    pub annotations: Option<HashMap<String, String>>,
 }
@@ -0,0 +1,1117 @@
+//! Conversion of Mondrian 3 and 4 schemas into tesseract schemas.
+//!
+//! The result is a `SchemaConfigXML`, which serializes into a tesseract JSON
+//! schema. Mondrian constructs without an equivalent in tesseract, such as
+//! calculated members, roles, or hierarchies over joined tables, are left
+//! out, and a warning is added for each.
+//!
+//! Some translations:
+//! - Mondrian 3 `column`, `nameColumn` and `type` on levels become
+//!   `key_column`, `name_column` and `key_type`.
+//! - Mondrian 4 levels take their columns from the dimension attribute they
+//!   refer to, and cubes take their fact table and measures from their
+//!   first measure group.
+//! - Dimensions without a foreign key (or with a `FactLink`) are on the fact
+//!   table, and use their first level's column as foreign key.
+//! - `caption`, `description` and `formatString` become annotations.
+//! - A virtual cube becomes a cube when all its measures come from the same
+//!   base cube, since a cube has a single fact table.
+
+use failure::{Error, bail, ensure, format_err};
+use std::collections::HashMap;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::parse;
+use crate::query_ir::MemberType;
+use super::DimensionType;
+use super::aggregator::Aggregator;
+use super::xml::{
+    AnnotationConfigXML,
+    CubeConfigXML,
+    DimensionConfigXML,
+    DimensionUsageXML,
+    HierarchyConfigXML,
+    InlineTableColumnDefinitionXML,
+    InlineTableRowValueXML,
+    InlineTableRowXML,
+    InlineTableXML,
+    LevelConfigXML,
+    MeasureConfigXML,
+    PropertyConfigXML,
+    SchemaConfigXML,
+    SharedDimensionConfigXML,
+    TableConfigXML,
+};
+
+/// Attributes that are kept as annotations.
+const ANNOTATION_ATTRIBUTES: &[(&str, &str)] = &[
+    ("caption", "caption"),
+    ("description", "description"),
+    ("formatString", "format_string"),
+];
+
+
+/// A converted schema, with warnings about what couldn't be converted.
+#[derive(Debug, Clone)]
+pub struct MondrianSchema {
+    pub schema: SchemaConfigXML,
+    pub warnings: Vec<String>,
+}
+
+impl MondrianSchema {
+    /// Converts a Mondrian schema. The version is read from the
+    /// `metamodelVersion` of `<Schema>`, and defaults to 3.
+    pub fn from_xml(raw_schema: &str) -> Result<Self, Error> {
+        let root = Element::parse(raw_schema)?;
+        ensure!(root.name == "Schema", "Mondrian schema must have a <Schema> root, found <{}>", root.name);
+
+        let is_v4 = root.attr("metamodelVersion")
+            .map(|version| version.starts_with('4'))
+            .unwrap_or(false);
+
+        let mut converter = Converter::default();
+        let schema = if is_v4 {
+            converter.schema_v4(&root)?
+        } else {
+            converter.schema_v3(&root)?
+        };
+
+        Ok(MondrianSchema {
+            schema,
+            warnings: converter.warnings,
+        })
+    }
+}
+
+
+#[derive(Debug, Default)]
+struct Converter {
+    warnings: Vec<String>,
+    /// Mondrian 4 physical schema tables, by alias.
+    tables: HashMap<String, TableConfigXML>,
+}
+
+impl Converter {
+    fn warn<S: Into<String>>(&mut self, warning: S) {
+        self.warnings.push(warning.into());
+    }
+
+    fn unsupported(&mut self, parent: &str, element: &Element) {
+        self.warn(format!("{}: <{}> is not supported", parent, element.name));
+    }
+
+    fn annotations(&mut self, element: &Element) -> Option<Vec<AnnotationConfigXML>> {
+        let mut annotations = vec![];
+
+        for (attribute, name) in ANNOTATION_ATTRIBUTES {
+            if let Some(text) = element.attr(attribute) {
+                annotations.push(AnnotationConfigXML { name: name.to_string(), text: text.to_owned() });
+            }
+        }
+
+        for annotation in element.nested("Annotations", "Annotation") {
+            match annotation.attr("name") {
+                Some(name) => {
+                    annotations.push(AnnotationConfigXML {
+                        name: name.to_owned(),
+                        text: annotation.text.trim().to_owned(),
+                    });
+                },
+                None => self.warn(format!("<{}>: an annotation without a name is skipped", element.name)),
+            }
+        }
+
+        some_if_any(annotations)
+    }
+
+    // Mondrian 3
+
+    fn schema_v3(&mut self, root: &Element) -> Result<SchemaConfigXML, Error> {
+        let mut shared_dimensions = vec![];
+        let mut cubes = vec![];
+        let mut virtual_cubes = vec![];
+
+        for child in &root.children {
+            match child.name.as_str() {
+                "Dimension" => {
+                    match self.dimension_v3(child) {
+                        Ok(dimension) => shared_dimensions.push(shared(dimension)),
+                        Err(err) => self.warn(format!("Skipped shared dimension: {}", err)),
+                    }
+                },
+                "Cube" => {
+                    match self.cube_v3(child) {
+                        Ok(cube) => cubes.push(cube),
+                        Err(err) => self.warn(format!("Skipped cube: {}", err)),
+                    }
+                },
+                "VirtualCube" => virtual_cubes.push(child),
+                "Annotations" => (),
+                _ => self.unsupported("Schema", child),
+            }
+        }
+
+        for virtual_cube in virtual_cubes {
+            match self.virtual_cube(virtual_cube, &cubes) {
+                Ok(cube) => cubes.push(cube),
+                Err(err) => self.warn(format!("Skipped virtual cube: {}", err)),
+            }
+        }
+
+        Ok(SchemaConfigXML {
+            name: root.required_attr("name")?.to_owned(),
+            shared_dimensions: some_if_any(shared_dimensions),
+            cubes,
+            annotations: self.annotations(root),
+            default_locale: None,
+        })
+    }
+
+    fn cube_v3(&mut self, element: &Element) -> Result<CubeConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        ensure!(element.attr("enabled") != Some("false"), "Cube {} is disabled", name);
+
+        let context = format!("Cube {}", name);
+        let mut table = None;
+        let mut dimensions = vec![];
+        let mut dimension_usages = vec![];
+        let mut measures = vec![];
+
+        for child in &element.children {
+            match child.name.as_str() {
+                "Table" => table = Some(table_v3(child)?),
+                "View" => bail!("Cube {} is on a <View>, only tables are supported", name),
+                "Dimension" => {
+                    match self.dimension_v3(child) {
+                        Ok(dimension) => dimensions.push(on_fact_table(dimension)),
+                        Err(err) => self.warn(format!("{}: skipped dimension: {}", context, err)),
+                    }
+                },
+                "DimensionUsage" => {
+                    match self.dimension_usage_v3(child) {
+                        Ok(usage) => dimension_usages.push(usage),
+                        Err(err) => self.warn(format!("{}: skipped dimension usage: {}", context, err)),
+                    }
+                },
+                "Measure" => {
+                    match self.measure_v3(child) {
+                        Ok(measure) => measures.push(measure),
+                        Err(err) => self.warn(format!("{}: skipped measure: {}", context, err)),
+                    }
+                },
+                "Annotations" => (),
+                _ => self.unsupported(&context, child),
+            }
+        }
+
+        let table = table.ok_or_else(|| format_err!("Cube {} has no fact table", name))?;
+        ensure!(!measures.is_empty(), "Cube {} has no measures that could be converted", name);
+
+        Ok(CubeConfigXML {
+            name: name.to_owned(),
+            public: visibility(element),
+            min_auth_level: None,
+            table,
+            dimensions: some_if_any(dimensions),
+            dimension_usages: some_if_any(dimension_usages),
+            measures,
+            annotations: self.annotations(element),
+        })
+    }
+
+    fn dimension_v3(&mut self, element: &Element) -> Result<DimensionConfigXML, Error> {
+        let name = element.required_attr("name")?;
+
+        let mut hierarchies = vec![];
+        for child in element.children_named("Hierarchy") {
+            match self.hierarchy_v3(child, name) {
+                Ok(hierarchy) => hierarchies.push(hierarchy),
+                Err(err) => self.warn(format!("Dimension {}: skipped hierarchy: {}", name, err)),
+            }
+        }
+        ensure!(!hierarchies.is_empty(), "Dimension {} has no hierarchies that could be converted", name);
+
+        let dim_type = match element.attr("type") {
+            Some("TimeDimension") => Some(DimensionType::Time),
+            _ => None,
+        };
+
+        Ok(DimensionConfigXML {
+            name: name.to_owned(),
+            foreign_key: element.attr("foreignKey").map(str::to_owned),
+            default_hierarchy: default_hierarchy(&hierarchies),
+            hierarchies,
+            dim_type,
+            fiscal_year_start_month: None,
+            annotations: self.annotations(element),
+        })
+    }
+
+    fn dimension_usage_v3(&mut self, element: &Element) -> Result<DimensionUsageXML, Error> {
+        let source = element.required_attr("source")?;
+
+        for attribute in &["level", "usagePrefix"] {
+            if element.attr(attribute).is_some() {
+                self.warn(format!("Dimension usage {}: `{}` is not supported", source, attribute));
+            }
+        }
+
+        Ok(DimensionUsageXML {
+            source: source.to_owned(),
+            name: element.attr("name").filter(|name| name != &source).map(str::to_owned),
+            foreign_key: element.required_attr("foreignKey")?.to_owned(),
+            annotations: self.annotations(element),
+        })
+    }
+
+    fn hierarchy_v3(&mut self, element: &Element, dimension: &str) -> Result<HierarchyConfigXML, Error> {
+        let name = element.attr("name").unwrap_or(dimension);
+        let context = format!("Hierarchy {}", name);
+
+        let mut table = None;
+        let mut inline_table = None;
+
+        for child in &element.children {
+            match child.name.as_str() {
+                "Table" => table = Some(table_v3(child)?),
+                "InlineTable" => inline_table = Some(inline_table_v3(child)?),
+                "Join" | "View" => bail!("Hierarchy {} is on a <{}>, only single tables are supported", name, child.name),
+                "Level" | "Annotations" => (),
+                _ => self.unsupported(&context, child),
+            }
+        }
+
+        if element.attr("defaultMember").is_some() {
+            self.warn(format!("{}: `defaultMember` is not converted", context));
+        }
+
+        let mut levels = vec![];
+        for child in element.children_named("Level") {
+            match self.level_v3(child) {
+                Ok(level) => levels.push(level),
+                Err(err) => self.warn(format!("{}: skipped level: {}", context, err)),
+            }
+        }
+        ensure!(!levels.is_empty(), "Hierarchy {} has no levels that could be converted", name);
+
+        Ok(HierarchyConfigXML {
+            name: name.to_owned(),
+            table,
+            primary_key: element.attr("primaryKey").map(str::to_owned),
+            date_column: None,
+            levels,
+            annotations: self.annotations(element),
+            inline_table,
+            default_member: None,
+        })
+    }
+
+    fn level_v3(&mut self, element: &Element) -> Result<LevelConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        let key_column = element.attr("column")
+            .ok_or_else(|| format_err!("Level {} has no column, key expressions are not supported", name))?;
+
+        for attribute in &["parentColumn", "ordinalColumn", "hideMemberIf"] {
+            if element.attr(attribute).is_some() {
+                self.warn(format!("Level {}: `{}` is not supported", name, attribute));
+            }
+        }
+
+        let mut properties = vec![];
+        for child in element.children_named("Property") {
+            match child.required_attr("name").and_then(|prop| Ok((prop, child.required_attr("column")?))) {
+                Ok((property, column)) => {
+                    properties.push(PropertyConfigXML {
+                        name: property.to_owned(),
+                        column: column.to_owned(),
+                        caption_set: None,
+                        annotations: self.annotations(child),
+                    });
+                },
+                Err(err) => self.warn(format!("Level {}: skipped property: {}", name, err)),
+            }
+        }
+
+        Ok(LevelConfigXML {
+            name: name.to_owned(),
+            key_column: key_column.to_owned(),
+            granularity: None,
+            name_column: element.attr("nameColumn").or_else(|| element.attr("captionColumn")).map(str::to_owned),
+            properties: some_if_any(properties),
+            key_type: Some(member_type(element.attr("type"))),
+            annotations: self.annotations(element),
+        })
+    }
+
+    fn measure_v3(&mut self, element: &Element) -> Result<MeasureConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        let column = element.attr("column")
+            .ok_or_else(|| format_err!("Measure {} has no column, measure expressions are not supported", name))?;
+
+        Ok(MeasureConfigXML {
+            name: name.to_owned(),
+            column: column.to_owned(),
+            aggregator: aggregator(name, element.required_attr("aggregator")?)?,
+            measure_type: None,
+            annotations: self.annotations(element),
+        })
+    }
+
+    /// A virtual cube over a single base cube is the base cube with only the
+    /// virtual cube's dimensions and measures.
+    fn virtual_cube(&mut self, element: &Element, cubes: &[CubeConfigXML]) -> Result<CubeConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        let context = format!("Virtual cube {}", name);
+
+        let mut measure_refs = vec![];
+        for child in &element.children {
+            match child.name.as_str() {
+                "VirtualCubeMeasure" => {
+                    let cube = last_name(child.required_attr("cubeName")?)?;
+                    let measure = last_name(child.required_attr("name")?)?;
+                    measure_refs.push((cube, measure));
+                },
+                "VirtualCubeDimension" | "CubeUsages" | "Annotations" => (),
+                _ => self.unsupported(&context, child),
+            }
+        }
+
+        let mut base_cubes: Vec<&str> = measure_refs.iter().map(|(cube, _)| cube.as_str()).collect();
+        base_cubes.dedup();
+        ensure!(
+            base_cubes.len() == 1,
+            "Virtual cube {} has measures from cubes {:?}, only virtual cubes over one cube can be converted",
+            name, base_cubes
+        );
+
+        let base = cubes.iter()
+            .find(|cube| cube.name == base_cubes[0])
+            .ok_or_else(|| format_err!("Virtual cube {} is over cube {}, which wasn't converted", name, base_cubes[0]))?;
+
+        let mut measures = vec![];
+        for (_, measure) in &measure_refs {
+            match base.measures.iter().find(|m| &m.name == measure) {
+                Some(m) => measures.push(m.clone()),
+                None => self.warn(format!("{}: skipped measure {}, it's not in cube {}", context, measure, base.name)),
+            }
+        }
+        ensure!(!measures.is_empty(), "Virtual cube {} has no measures that could be converted", name);
+
+        let mut dimensions = vec![];
+        let mut dimension_usages = vec![];
+        for child in element.children_named("VirtualCubeDimension") {
+            let dimension = last_name(child.required_attr("name")?)?;
+
+            let base_dimension = base.dimensions.iter().flatten()
+                .find(|dim| dim.name == dimension);
+            let base_usage = base.dimension_usages.iter().flatten()
+                .find(|usage| usage.name.as_ref().unwrap_or(&usage.source) == &dimension);
+
+            if let Some(dim) = base_dimension {
+                dimensions.push(dim.clone());
+            } else if let Some(usage) = base_usage {
+                dimension_usages.push(usage.clone());
+            } else {
+                self.warn(format!("{}: skipped dimension {}, it's not in cube {}", context, dimension, base.name));
+            }
+        }
+
+        Ok(CubeConfigXML {
+            name: name.to_owned(),
+            public: visibility(element),
+            min_auth_level: None,
+            table: base.table.clone(),
+            dimensions: some_if_any(dimensions),
+            dimension_usages: some_if_any(dimension_usages),
+            measures,
+            annotations: self.annotations(element),
+        })
+    }
+
+    // Mondrian 4
+
+    fn schema_v4(&mut self, root: &Element) -> Result<SchemaConfigXML, Error> {
+        let mut shared_dimensions = vec![];
+        let mut cubes = vec![];
+
+        if let Some(physical_schema) = root.child("PhysicalSchema") {
+            for child in &physical_schema.children {
+                match child.name.as_str() {
+                    "Table" => {
+                        let table = child.required_attr("name")?;
+                        let alias = child.attr("alias").unwrap_or(table);
+                        self.tables.insert(alias.to_owned(), TableConfigXML {
+                            name: table.to_owned(),
+                            schema: child.attr("schema").map(str::to_owned),
+                            primary_key: child.attr("keyColumn").map(str::to_owned)
+                                .or_else(|| single_column(child, "Key")),
+                        });
+                    },
+                    _ => self.unsupported("PhysicalSchema", child),
+                }
+            }
+        }
+
+        for child in &root.children {
+            match child.name.as_str() {
+                "Dimension" => {
+                    match self.dimension_v4(child) {
+                        Ok(dimension) => shared_dimensions.push(shared(dimension)),
+                        Err(err) => self.warn(format!("Skipped shared dimension: {}", err)),
+                    }
+                },
+                "Cube" => {
+                    match self.cube_v4(child) {
+                        Ok(cube) => cubes.push(cube),
+                        Err(err) => self.warn(format!("Skipped cube: {}", err)),
+                    }
+                },
+                "PhysicalSchema" | "Annotations" => (),
+                _ => self.unsupported("Schema", child),
+            }
+        }
+
+        Ok(SchemaConfigXML {
+            name: root.required_attr("name")?.to_owned(),
+            shared_dimensions: some_if_any(shared_dimensions),
+            cubes,
+            annotations: self.annotations(root),
+            default_locale: None,
+        })
+    }
+
+    fn table_v4(&self, alias: &str) -> TableConfigXML {
+        self.tables.get(alias)
+            .cloned()
+            .unwrap_or_else(|| TableConfigXML { name: alias.to_owned(), schema: None, primary_key: None })
+    }
+
+    fn cube_v4(&mut self, element: &Element) -> Result<CubeConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        ensure!(element.attr("enabled") != Some("false"), "Cube {} is disabled", name);
+
+        let context = format!("Cube {}", name);
+
+        for child in &element.children {
+            match child.name.as_str() {
+                "Dimensions" | "MeasureGroups" | "Annotations" => (),
+                _ => self.unsupported(&context, child),
+            }
+        }
+
+        let measure_groups = element.nested("MeasureGroups", "MeasureGroup");
+        let group = measure_groups.first()
+            .ok_or_else(|| format_err!("Cube {} has no measure groups", name))?;
+        if measure_groups.len() > 1 {
+            self.warn(format!("{}: only the first measure group is converted", context));
+        }
+
+        let table = self.table_v4(group.required_attr("table")?);
+
+        let mut measures = vec![];
+        for child in group.nested("Measures", "Measure") {
+            match self.measure_v4(child) {
+                Ok(measure) => measures.push(measure),
+                Err(err) => self.warn(format!("{}: skipped measure: {}", context, err)),
+            }
+        }
+        ensure!(!measures.is_empty(), "Cube {} has no measures that could be converted", name);
+
+        let mut links = HashMap::new();
+        if let Some(dimension_links) = group.child("DimensionLinks") {
+            for link in &dimension_links.children {
+                links.insert(link.required_attr("dimension")?, link);
+            }
+        }
+
+        let mut dimensions = vec![];
+        let mut dimension_usages = vec![];
+
+        for child in element.nested("Dimensions", "Dimension") {
+            let dimension = child.attr("name").or_else(|| child.attr("source"));
+            let dimension = match dimension {
+                Some(dimension) => dimension,
+                None => {
+                    self.warn(format!("{}: skipped a dimension without a name", context));
+                    continue;
+                },
+            };
+
+            let link = match links.get(dimension) {
+                Some(link) => link,
+                None => {
+                    self.warn(format!("{}: skipped dimension {}, it has no link to the measure group", context, dimension));
+                    continue;
+                },
+            };
+
+            let foreign_key = match link.name.as_str() {
+                "ForeignKeyLink" => {
+                    link.attr("foreignKeyColumn").map(str::to_owned)
+                        .or_else(|| single_column(link, "ForeignKey"))
+                },
+                "FactLink" => None,
+                _ => {
+                    self.warn(format!("{}: skipped dimension {}, <{}> is not supported", context, dimension, link.name));
+                    continue;
+                },
+            };
+
+            if let Some(source) = child.attr("source") {
+                match foreign_key {
+                    Some(foreign_key) => {
+                        dimension_usages.push(DimensionUsageXML {
+                            source: source.to_owned(),
+                            name: child.attr("name").filter(|name| name != &source).map(str::to_owned),
+                            foreign_key,
+                            annotations: self.annotations(child),
+                        });
+                    },
+                    None => self.warn(format!("{}: skipped dimension {}, a shared dimension needs a foreign key", context, dimension)),
+                }
+            } else {
+                match self.dimension_v4(child) {
+                    Ok(mut dim) => {
+                        if foreign_key.is_none() {
+                            for hierarchy in &mut dim.hierarchies {
+                                hierarchy.table = None;
+                            }
+                        }
+                        dim.foreign_key = foreign_key;
+                        dimensions.push(on_fact_table(dim));
+                    },
+                    Err(err) => self.warn(format!("{}: skipped dimension: {}", context, err)),
+                }
+            }
+        }
+
+        Ok(CubeConfigXML {
+            name: name.to_owned(),
+            public: visibility(element),
+            min_auth_level: None,
+            table,
+            dimensions: some_if_any(dimensions),
+            dimension_usages: some_if_any(dimension_usages),
+            measures,
+            annotations: self.annotations(element),
+        })
+    }
+
+    /// Levels refer to the dimension's attributes for their columns. A
+    /// dimension without hierarchies has a hierarchy for each attribute.
+    fn dimension_v4(&mut self, element: &Element) -> Result<DimensionConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        let table = element.attr("table").map(|table| self.table_v4(table));
+
+        let attributes: HashMap<&str, &Element> = element.nested("Attributes", "Attribute").into_iter()
+            .filter_map(|attribute| attribute.attr("name").map(|name| (name, attribute)))
+            .collect();
+
+        let primary_key = element.attr("key")
+            .and_then(|key| attributes.get(key))
+            .and_then(|attribute| attribute_column(attribute));
+
+        let mut hierarchies = vec![];
+        let hierarchy_elements = element.nested("Hierarchies", "Hierarchy");
+
+        if hierarchy_elements.is_empty() {
+            for attribute in element.nested("Attributes", "Attribute") {
+                match self.level_v4(attribute, &attributes) {
+                    Ok(level) => {
+                        hierarchies.push(HierarchyConfigXML {
+                            name: level.name.clone(),
+                            table: table.clone(),
+                            primary_key: primary_key.clone(),
+                            date_column: None,
+                            levels: vec![level],
+                            annotations: None,
+                            inline_table: None,
+                            default_member: None,
+                        });
+                    },
+                    Err(err) => self.warn(format!("Dimension {}: skipped attribute: {}", name, err)),
+                }
+            }
+        }
+
+        for hierarchy in hierarchy_elements {
+            let hierarchy_name = hierarchy.attr("name").unwrap_or(name);
+
+            let mut levels = vec![];
+            for level in hierarchy.nested("Levels", "Level") {
+                match self.level_v4(level, &attributes) {
+                    Ok(level) => levels.push(level),
+                    Err(err) => self.warn(format!("Hierarchy {}: skipped level: {}", hierarchy_name, err)),
+                }
+            }
+
+            if levels.is_empty() {
+                self.warn(format!("Dimension {}: skipped hierarchy {}, it has no levels that could be converted", name, hierarchy_name));
+                continue;
+            }
+
+            hierarchies.push(HierarchyConfigXML {
+                name: hierarchy_name.to_owned(),
+                table: table.clone(),
+                primary_key: primary_key.clone(),
+                date_column: None,
+                levels,
+                annotations: self.annotations(hierarchy),
+                inline_table: None,
+                default_member: None,
+            });
+        }
+        ensure!(!hierarchies.is_empty(), "Dimension {} has no hierarchies that could be converted", name);
+
+        let dim_type = match element.attr("type") {
+            Some("TIME") => Some(DimensionType::Time),
+            _ => None,
+        };
+
+        Ok(DimensionConfigXML {
+            name: name.to_owned(),
+            foreign_key: None,
+            default_hierarchy: default_hierarchy(&hierarchies),
+            hierarchies,
+            dim_type,
+            fiscal_year_start_month: None,
+            annotations: self.annotations(element),
+        })
+    }
+
+    /// A level from an `<Attribute>`, or from a `<Level>` that refers to one.
+    fn level_v4(&mut self, element: &Element, attributes: &HashMap<&str, &Element>) -> Result<LevelConfigXML, Error> {
+        let attribute = if element.name == "Attribute" {
+            element
+        } else {
+            let attribute_name = element.required_attr("attribute")?;
+            attributes.get(attribute_name)
+                .ok_or_else(|| format_err!("Level refers to attribute {}, which doesn't exist", attribute_name))?
+        };
+
+        let name = element.attr("name").map(Ok).unwrap_or_else(|| attribute.required_attr("name"))?;
+        let key_column = attribute_column(attribute)
+            .ok_or_else(|| format_err!("Level {} has no key column, composite keys are not supported", name))?;
+
+        let mut properties = vec![];
+        for property in attribute.nested("Properties", "Property") {
+            let column = property.attr("attribute")
+                .and_then(|attribute| attributes.get(attribute))
+                .and_then(|attribute| attribute_column(attribute));
+
+            match (property.attr("name").or_else(|| property.attr("attribute")), column) {
+                (Some(property_name), Some(column)) => {
+                    properties.push(PropertyConfigXML {
+                        name: property_name.to_owned(),
+                        column,
+                        caption_set: None,
+                        annotations: self.annotations(property),
+                    });
+                },
+                _ => self.warn(format!("Level {}: skipped a property without an attribute", name)),
+            }
+        }
+
+        Ok(LevelConfigXML {
+            name: name.to_owned(),
+            key_column,
+            granularity: None,
+            name_column: attribute.attr("nameColumn").map(str::to_owned)
+                .or_else(|| single_column(attribute, "Name")),
+            properties: some_if_any(properties),
+            key_type: Some(member_type(attribute.attr("datatype"))),
+            annotations: self.annotations(element),
+        })
+    }
+
+    fn measure_v4(&mut self, element: &Element) -> Result<MeasureConfigXML, Error> {
+        let name = element.required_attr("name")?;
+        let column = element.attr("column")
+            .ok_or_else(|| format_err!("Measure {} has no column, measure expressions are not supported", name))?;
+
+        Ok(MeasureConfigXML {
+            name: name.to_owned(),
+            column: column.to_owned(),
+            aggregator: aggregator(name, element.attr("aggregator").unwrap_or("sum"))?,
+            measure_type: None,
+            annotations: self.annotations(element),
+        })
+    }
+}
+
+
+fn table_v3(element: &Element) -> Result<TableConfigXML, Error> {
+    Ok(TableConfigXML {
+        name: element.required_attr("name")?.to_owned(),
+        schema: element.attr("schema").map(str::to_owned),
+        primary_key: None,
+    })
+}
+
+fn inline_table_v3(element: &Element) -> Result<InlineTableXML, Error> {
+    let column_definitions = element.nested("ColumnDefs", "ColumnDef").into_iter()
+        .map(|column| {
+            Ok(InlineTableColumnDefinitionXML {
+                name: column.required_attr("name")?.to_owned(),
+                key_type: member_type(column.attr("type")),
+                key_column_type: None,
+                caption_set: None,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let rows = element.nested("Rows", "Row").into_iter()
+        .map(|row| {
+            let row_values = row.children_named("Value")
+                .map(|value| {
+                    Ok(InlineTableRowValueXML {
+                        column: value.required_attr("column")?.to_owned(),
+                        value: value.text.clone(),
+                    })
+                })
+                .collect::<Result<_, Error>>()?;
+
+            Ok(InlineTableRowXML { row_values })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(InlineTableXML {
+        alias: element.required_attr("alias")?.to_owned(),
+        column_definitions,
+        rows,
+    })
+}
+
+fn shared(dimension: DimensionConfigXML) -> SharedDimensionConfigXML {
+    SharedDimensionConfigXML {
+        name: dimension.name,
+        hierarchies: dimension.hierarchies,
+        default_hierarchy: dimension.default_hierarchy,
+        dim_type: dimension.dim_type,
+        fiscal_year_start_month: dimension.fiscal_year_start_month,
+        annotations: dimension.annotations,
+    }
+}
+
+/// A dimension without a foreign key is on the fact table; its first level's
+/// column is the foreign key.
+fn on_fact_table(mut dimension: DimensionConfigXML) -> DimensionConfigXML {
+    if dimension.foreign_key.is_none() {
+        dimension.foreign_key = dimension.hierarchies.first()
+            .and_then(|hierarchy| hierarchy.levels.first())
+            .map(|level| level.key_column.clone());
+    }
+    dimension
+}
+
+/// Mondrian's default hierarchy is the first one.
+fn default_hierarchy(hierarchies: &[HierarchyConfigXML]) -> Option<String> {
+    if hierarchies.len() > 1 {
+        Some(hierarchies[0].name.clone())
+    } else {
+        None
+    }
+}
+
+fn visibility(element: &Element) -> Option<String> {
+    match element.attr("visible") {
+        Some("false") => Some("false".to_owned()),
+        _ => None,
+    }
+}
+
+/// Mondrian's default type is String.
+fn member_type(mondrian_type: Option<&str>) -> MemberType {
+    match mondrian_type {
+        Some("Numeric") | Some("Integer") => MemberType::NonText,
+        _ => MemberType::Text,
+    }
+}
+
+fn aggregator(measure: &str, mondrian_aggregator: &str) -> Result<Aggregator, Error> {
+    Ok(match mondrian_aggregator {
+        "sum" => Aggregator::Sum,
+        "count" => Aggregator::Count,
+        "min" => Aggregator::Min,
+        "max" => Aggregator::Max,
+        "avg" => Aggregator::Average,
+        _ => bail!("Measure {} has aggregator {}, which is not supported", measure, mondrian_aggregator),
+    })
+}
+
+/// Key column of a Mondrian 4 attribute; `None` for composite keys.
+fn attribute_column(attribute: &Element) -> Option<String> {
+    attribute.attr("keyColumn").map(str::to_owned)
+        .or_else(|| single_column(attribute, "Key"))
+}
+
+/// The column of a `<Key><Column name="..."/></Key>` style child.
+fn single_column(element: &Element, child: &str) -> Option<String> {
+    let columns: Vec<_> = element.child(child)?.children_named("Column").collect();
+    match columns.as_slice() {
+        [column] => column.attr("name").map(str::to_owned),
+        _ => None,
+    }
+}
+
+/// The last name of `[Measures].[Unit Sales]` or `[Sales]`.
+fn last_name(name: &str) -> Result<String, Error> {
+    parse::names(name)?
+        .pop()
+        .ok_or_else(|| format_err!("Empty name"))
+}
+
+fn some_if_any<T>(items: Vec<T>) -> Option<Vec<T>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+
+/// Minimal XML element tree; Mondrian 3 and 4 schemas nest the same
+/// elements differently, which is easier to handle on a tree than with
+/// serde structs.
+#[derive(Debug, Clone, Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn parse(raw: &str) -> Result<Self, Error> {
+        let mut stack: Vec<Element> = vec![];
+
+        for event in EventReader::from_str(raw) {
+            match event.map_err(|err| format_err!("Error reading Mondrian schema: {}", err))? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    stack.push(Element {
+                        name: name.local_name,
+                        attributes: attributes.into_iter()
+                            .map(|attribute| (attribute.name.local_name, attribute.value))
+                            .collect(),
+                        ..Default::default()
+                    });
+                },
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop().expect("elements are balanced");
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                },
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        bail!("Mondrian schema has no root element")
+    }
+
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required_attr(&self, name: &str) -> Result<&str, Error> {
+        self.attr(name)
+            .ok_or_else(|| format_err!("<{}> has no `{}`", self.name, name))
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Children named `name`, either directly or inside a `wrapper` child,
+    /// such as `<Annotations><Annotation>`.
+    fn nested<'a>(&'a self, wrapper: &'a str, name: &'a str) -> Vec<&'a Element> {
+        let wrapped = self.children_named(wrapper)
+            .flat_map(|wrapper| wrapper.children_named(name));
+
+        self.children_named(name).chain(wrapped).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Schema;
+
+    fn to_schema(mondrian: &MondrianSchema) -> Schema {
+        let json = serde_json::to_string(&mondrian.schema).unwrap();
+        Schema::from_json(&json).unwrap()
+    }
+
+    #[test]
+    fn test_mondrian_v3() {
+        let s = r##"
+        <Schema name="FoodMart">
+            <Dimension name="Store">
+                <Hierarchy hasAll="true" primaryKey="store_id">
+                    <Table name="store"/>
+                    <Level name="Store Country" column="store_country" uniqueMembers="true"/>
+                    <Level name="Store City" column="store_city" nameColumn="city_name">
+                        <Property name="Sqft" column="store_sqft" type="Numeric"/>
+                    </Level>
+                </Hierarchy>
+            </Dimension>
+            <Dimension name="Customers">
+                <Hierarchy primaryKey="customer_id">
+                    <Join leftKey="a" rightKey="b"><Table name="customer"/><Table name="region"/></Join>
+                    <Level name="Country" column="country"/>
+                </Hierarchy>
+            </Dimension>
+            <Cube name="Sales">
+                <Table name="sales_fact_1997"/>
+                <DimensionUsage name="Store" source="Store" foreignKey="store_id"/>
+                <Dimension name="Time" type="TimeDimension" foreignKey="time_id">
+                    <Hierarchy primaryKey="time_id">
+                        <Table name="time_by_day"/>
+                        <Level name="Year" column="the_year" type="Numeric" levelType="TimeYears"/>
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Promotion Media">
+                    <Hierarchy>
+                        <Level name="Media Type" column="media_type"/>
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Unit Sales" column="unit_sales" aggregator="sum" formatString="Standard">
+                    <Annotations><Annotation name="units">items</Annotation></Annotations>
+                </Measure>
+                <Measure name="Customer Count" column="customer_id" aggregator="distinct-count"/>
+                <Measure name="Sales Count" column="product_id" aggregator="count"/>
+                <CalculatedMember name="Profit" dimension="Measures" formula="1"/>
+            </Cube>
+            <VirtualCube name="Sales Lite">
+                <VirtualCubeDimension name="Store"/>
+                <VirtualCubeDimension cubeName="Sales" name="Time"/>
+                <VirtualCubeMeasure cubeName="Sales" name="[Measures].[Unit Sales]"/>
+            </VirtualCube>
+            <Role name="Admin"/>
+        </Schema>
+        "##;
+        let mondrian = MondrianSchema::from_xml(s).unwrap();
+        let schema = &mondrian.schema;
+
+        assert_eq!(schema.name, "FoodMart");
+        let shared_dims = schema.shared_dimensions.as_ref().unwrap();
+        assert_eq!(shared_dims.len(), 1);
+        let store = &shared_dims[0].hierarchies[0];
+        assert_eq!(store.name, "Store");
+        assert_eq!(store.primary_key, Some("store_id".to_owned()));
+        assert_eq!(store.levels[1].name_column, Some("city_name".to_owned()));
+        assert_eq!(store.levels[1].key_type, Some(MemberType::Text));
+        assert_eq!(store.levels[1].properties.as_ref().unwrap()[0].column, "store_sqft");
+
+        let sales = &schema.cubes[0];
+        assert_eq!(sales.dimension_usages.as_ref().unwrap()[0].foreign_key, "store_id");
+        let dims = sales.dimensions.as_ref().unwrap();
+        assert_eq!(dims[0].dim_type, Some(DimensionType::Time));
+        assert_eq!(dims[0].hierarchies[0].levels[0].key_type, Some(MemberType::NonText));
+        // degenerate dimension
+        assert_eq!(dims[1].foreign_key, Some("media_type".to_owned()));
+        assert_eq!(sales.measures.len(), 2);
+        assert_eq!(sales.measures[1].aggregator, Aggregator::Count);
+        let annotations = sales.measures[0].annotations.as_ref().unwrap();
+        assert_eq!(annotations[0].name, "format_string");
+        assert_eq!(annotations[1].text, "items");
+
+        let lite = &schema.cubes[1];
+        assert_eq!(lite.name, "Sales Lite");
+        assert_eq!(lite.table.name, "sales_fact_1997");
+        assert_eq!(lite.measures.len(), 1);
+        assert_eq!(lite.dimension_usages.as_ref().unwrap().len(), 1);
+        assert_eq!(lite.dimensions.as_ref().unwrap().len(), 1);
+
+        assert!(mondrian.warnings.contains(&"Dimension Customers: skipped hierarchy: Hierarchy Customers is on a <Join>, only single tables are supported".to_owned()));
+        assert!(mondrian.warnings.contains(&"Cube Sales: skipped measure: Measure Customer Count has aggregator distinct-count, which is not supported".to_owned()));
+        assert!(mondrian.warnings.contains(&"Cube Sales: <CalculatedMember> is not supported".to_owned()));
+        assert!(mondrian.warnings.contains(&"Schema: <Role> is not supported".to_owned()));
+
+        let schema = to_schema(&mondrian);
+        assert_eq!(schema.cubes.len(), 2);
+    }
+
+    #[test]
+    fn test_mondrian_v4() {
+        let s = r##"
+        <Schema name="FoodMart" metamodelVersion="4.0">
+            <PhysicalSchema>
+                <Table name="store" schema="foodmart"><Key><Column name="store_id"/></Key></Table>
+                <Table name="sales_fact_1997" alias="sales"/>
+            </PhysicalSchema>
+            <Dimension name="Store" table="store" key="Store Id">
+                <Attributes>
+                    <Attribute name="Store Id" keyColumn="store_id"/>
+                    <Attribute name="Country" keyColumn="store_country"/>
+                    <Attribute name="City" nameColumn="city_name" datatype="Integer">
+                        <Key><Column name="city_id"/></Key>
+                        <Property attribute="Country" name="In Country"/>
+                    </Attribute>
+                </Attributes>
+                <Hierarchies>
+                    <Hierarchy name="Stores">
+                        <Level attribute="Country"/>
+                        <Level attribute="City" name="Store City"/>
+                    </Hierarchy>
+                </Hierarchies>
+            </Dimension>
+            <Cube name="Sales">
+                <Dimensions>
+                    <Dimension source="Store"/>
+                    <Dimension name="Payment">
+                        <Attributes><Attribute name="Method" keyColumn="payment_method"/></Attributes>
+                    </Dimension>
+                </Dimensions>
+                <MeasureGroups>
+                    <MeasureGroup table="sales">
+                        <Measures>
+                            <Measure name="Unit Sales" column="unit_sales" aggregator="sum"/>
+                        </Measures>
+                        <DimensionLinks>
+                            <ForeignKeyLink dimension="Store" foreignKeyColumn="store_id"/>
+                            <FactLink dimension="Payment"/>
+                        </DimensionLinks>
+                    </MeasureGroup>
+                </MeasureGroups>
+            </Cube>
+        </Schema>
+        "##;
+        let mondrian = MondrianSchema::from_xml(s).unwrap();
+        let schema = &mondrian.schema;
+        assert!(mondrian.warnings.is_empty(), "{:?}", mondrian.warnings);
+
+        let store = &schema.shared_dimensions.as_ref().unwrap()[0].hierarchies[0];
+        assert_eq!(store.name, "Stores");
+        assert_eq!(store.table.as_ref().unwrap().schema, Some("foodmart".to_owned()));
+        assert_eq!(store.primary_key, Some("store_id".to_owned()));
+        assert_eq!(store.levels[0].name, "Country");
+        assert_eq!(store.levels[1].name, "Store City");
+        assert_eq!(store.levels[1].key_column, "city_id");
+        assert_eq!(store.levels[1].key_type, Some(MemberType::NonText));
+        assert_eq!(store.levels[1].properties.as_ref().unwrap()[0].column, "store_country");
+
+        let sales = &schema.cubes[0];
+        assert_eq!(sales.table.name, "sales_fact_1997");
+        assert_eq!(sales.dimension_usages.as_ref().unwrap()[0].foreign_key, "store_id");
+        let payment = &sales.dimensions.as_ref().unwrap()[0];
+        assert_eq!(payment.foreign_key, Some("payment_method".to_owned()));
+        assert!(payment.hierarchies[0].table.is_none());
+
+        let schema = to_schema(&mondrian);
+        assert_eq!(schema.cubes[0].dimensions.len(), 2);
+    }
+}
@@ -14,7 +14,7 @@ use serde_derive::Serialize;
 
 use crate::query_ir::MemberType;
 use super::aggregator::Aggregator;
-use super::{DimensionType, MeasureType};
+use super::{DimensionType, MeasureType, TimeGranularity};
 
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -56,6 +56,7 @@ pub struct DimensionConfigXML {
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: Option<DimensionType>,
+    pub fiscal_year_start_month: Option<u32>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
@@ -68,6 +69,7 @@ pub struct SharedDimensionConfigXML {
     pub default_hierarchy: Option<String>,
     #[serde(rename="type")]
     pub dim_type: Option<DimensionType>,
+    pub fiscal_year_start_month: Option<u32>,
     #[serde(rename(deserialize="Annotation"))]
     pub annotations: Option<Vec<AnnotationConfigXML>>,
 }
@@ -87,6 +89,7 @@ pub struct HierarchyConfigXML {
     #[serde(rename(deserialize="Table"))]
     pub table: Option<TableConfigXML>,
     pub primary_key: Option<String>,
+    pub date_column: Option<String>,
     #[serde(rename(deserialize="Level"))]
     pub levels: Vec<LevelConfigXML>,
     #[serde(rename(deserialize="Annotation"))]
@@ -129,7 +132,9 @@ pub struct InlineTableRowValueXML {
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct LevelConfigXML {
     pub name: String,
+    #[serde(default)]
     pub key_column: String,
+    pub granularity: Option<TimeGranularity>,
     pub name_column: Option<String>,
     #[serde(rename(deserialize="Property"))]
     pub properties: Option<Vec<PropertyConfigXML>>,
@@ -7,11 +7,37 @@ use crate::query_ir::{
     DrilldownSql,
     MeasureSql,
     TopSql,
-    SortSql,
-    LimitSql,
-    RcaSql,
-    GrowthSql,
+    CumulativeSql,
+    ResetSql,
+    MovingAvgSql,
+    IndexSql,
+    RankSql,
+    TrendSql,
+    FilterSql,
+    FilterExprSql,
+    GroupSql,
+    BinsSql,
+    filter_clauses,
 };
+use crate::query::{Bins, RankMethod};
+use crate::schema::TimeGranularity;
+
+/// Filters and calculations of a standard sql query, borrowed from the
+/// `QueryIr`. Sort, limit, rca and growth are not implemented here.
+#[derive(Debug, Default)]
+pub(crate) struct CalcSql<'a> {
+    pub filters: &'a [FilterSql],
+    pub filter: Option<&'a FilterExprSql>,
+    pub top: Option<&'a TopSql>,
+    pub cumulative: Option<&'a CumulativeSql>,
+    pub moving_avg: Option<&'a MovingAvgSql>,
+    pub index: Option<&'a IndexSql>,
+    pub rank: Option<&'a RankSql>,
+    pub trend: Option<&'a TrendSql>,
+    pub totals: &'a [DrilldownSql],
+    pub group: Option<&'a GroupSql>,
+    pub bins: Option<&'a BinsSql>,
+}
 
 /// Error checking is done before this point. This string formatter
 /// accepts any input
@@ -22,12 +48,7 @@ pub(crate) fn standard_sql(
     cuts: &[CutSql],
     drills: &[DrilldownSql],
     meas: &[MeasureSql],
-    // TODO put Filters and Calculations into own structs
-    _top: &Option<TopSql>,
-    _sort: &Option<SortSql>,
-    _limit: &Option<LimitSql>,
-    _rca: &Option<RcaSql>,
-    _growth: &Option<GrowthSql>,
+    calcs: &CalcSql,
     ) -> String
 {
     // hack for now... remove later
@@ -70,14 +91,40 @@ pub(crate) fn standard_sql(
     //    .collect();
     // --------------------------------------------------
 
-    let drill_cols = join(drills.iter().map(|d| d.col_qual_string()), ", ");
-    let mea_cols = join(meas.iter().map(|m| agg_sql_string(m)), ", ");
+    let CalcSql { filters, filter, top, cumulative, moving_avg, index, rank, trend, totals, group, bins } = *calcs;
 
-    let mut final_sql = format!("select {}, {} from {}",
-        drill_cols,
-        mea_cols,
-        table.name,
-    );
+    let filter_clauses = filter_clauses(filters, filter);
+
+    // window calculations, top and filters reference columns of the
+    // aggregation by alias, so only alias when needed.
+    let has_window = cumulative.is_some() || moving_avg.is_some() || index.is_some() || rank.is_some();
+    let aliased = has_window || trend.is_some() || top.is_some() || group.is_some() || !filter_clauses.is_empty();
+
+    let mut drill_cols = join(drills.iter().map(|d| d.col_qual_string()), ", ");
+    let mea_cols = if aliased {
+        join(meas.iter().enumerate().map(|(i, m)| format!("{} as final_m{}", agg_sql_string(m), i)), ", ")
+    } else {
+        join(meas.iter().map(|m| agg_sql_string(m)), ", ")
+    };
+
+    let mut select_drill_cols = if aliased { join(drills.iter().map(col_qual_alias_string), ", ") } else { drill_cols.clone() };
+
+    // bins are always aliased, and grouped by alias
+    if let Some(bins) = bins {
+        let bins_cols = join(bins_sql(bins).iter().zip(BinsSql::ALIASES.iter())
+            .map(|(col, alias)| format!("{} as {}", col, alias)), ", ");
+        let bins_aliases = join(BinsSql::ALIASES.iter(), ", ");
+
+        if drills.is_empty() {
+            select_drill_cols = bins_cols;
+            drill_cols = bins_aliases;
+        } else {
+            select_drill_cols = format!("{}, {}", select_drill_cols, bins_cols);
+            drill_cols = format!("{}, {}", drill_cols, bins_aliases);
+        }
+    }
+
+    let mut from_sql = format!("from {}", table.name);
 
     // join external dims
     if !ext_drills.is_empty() {
@@ -92,16 +139,498 @@ pub(crate) fn standard_sql(
                 )
         }), ", ");
 
-        final_sql = format!("{} {}", final_sql, join_ext_dim_clauses);
+        from_sql = format!("{} {}", from_sql, join_ext_dim_clauses);
     }
 
     if !cuts.is_empty() {
-        let cut_clauses = join(cuts.iter().map(|c| format!("{} in ({})", c.col_qual_string(), c.members_string())), " and ");
-        final_sql = format!("{} where {}", final_sql, cut_clauses);
+        let cut_clauses = join(cuts.iter().map(|c| {
+            match c.range {
+                Some(ref range) => c.range_string(&c.col_qual_string(), range),
+                None => format!("{} in ({})", c.col_qual_string(), c.members_string()),
+            }
+        }), " and ");
+        from_sql = format!("{} where {}", from_sql, cut_clauses);
+    }
+
+    // rows of the rolled up grouping sets are flagged in a `total` column
+    let mut final_sql = format!("select {}, {}{} {} group by {}",
+        select_drill_cols,
+        mea_cols,
+        if totals.is_empty() { "" } else { ", 0 as total" },
+        from_sql,
+        drill_cols,
+    );
+
+    // each grouping set rolls up one more totals drill, from the end, and
+    // the last one is always the grand total.
+    let mut rolled_up_sets: Vec<&[DrilldownSql]> = (1..=totals.len())
+        .map(|set| &totals[totals.len() - set..])
+        .collect();
+    if !totals.is_empty() && !drills.iter().all(|d| totals.contains(d)) {
+        rolled_up_sets.push(drills);
+    }
+
+    for rolled_up in rolled_up_sets {
+        let mut set_cols = vec![];
+        let mut group_cols = vec![];
+        for drill in drills {
+            if rolled_up.contains(drill) {
+                set_cols.extend(total_placeholders(drill));
+            } else {
+                set_cols.push(drill.col_qual_string());
+                group_cols.push(drill.col_qual_string());
+            }
+        }
+
+        final_sql = format!("{} union all select {}, {}, 1 as total {}{}",
+            final_sql,
+            join(set_cols, ", "),
+            mea_cols,
+            from_sql,
+            if group_cols.is_empty() { "".to_owned() } else { format!(" group by {}", join(group_cols, ", ")) },
+        );
+    }
+
+    if let Some(group) = group {
+        final_sql = group_sql(final_sql, drills, meas, group);
+    }
+
+    // calculated cols, appended after the measures
+    let mut calc_cols = vec![];
+
+    if has_window {
+        let mut window_cols = vec![];
+
+        if let Some(cumulative) = cumulative {
+            window_cols.push(format!("sum({}) over ({}order by {} rows between unbounded preceding and current row) as cumulative",
+                cumulative.mea,
+                cumulative_partition_by(drills, cumulative),
+                cumulative.time_drill.col_alias_only_string(),
+            ));
+            calc_cols.push("cumulative".to_owned());
+        }
+        if let Some(moving_avg) = moving_avg {
+            window_cols.push(format!("avg({}) over ({}order by {} rows between {} preceding and current row) as moving_avg",
+                moving_avg.mea,
+                partition_by(drills, &moving_avg.time_drill),
+                moving_avg.time_drill.col_alias_only_string(),
+                moving_avg.window - 1,
+            ));
+            calc_cols.push("moving_avg".to_owned());
+        }
+        if let Some(index) = index {
+            window_cols.push(format!("{mea} * 100.0 / nullif(max(case when {by_column} = {base} then {mea} end) over ({partition}), 0) as indexed",
+                mea = index.mea,
+                by_column = index.by_column,
+                base = index.base_string(),
+                partition = partition_by(drills, &index.drill).trim_end(),
+            ));
+            calc_cols.push("indexed".to_owned());
+        }
+        if let Some(rank) = rank {
+            window_cols.push(format!("{}() over ({}order by {} {}) as ranked",
+                match rank.method {
+                    RankMethod::Standard => "rank",
+                    RankMethod::Dense => "dense_rank",
+                },
+                partition_by(drills, &rank.drill),
+                rank.mea,
+                rank.direction.sql_string(),
+            ));
+            calc_cols.push("ranked".to_owned());
+        }
+
+        final_sql = format!("select *, {} from ({}) as window_base",
+            join(window_cols, ", "),
+            final_sql,
+        );
+    }
+
+    if let Some(trend) = trend {
+        final_sql = trend_sql(final_sql, drills, meas, &calc_cols, trend);
+    }
+
+    if let Some(top) = top {
+        final_sql = top_sql(final_sql, drills, meas, &calc_cols, top);
+    }
+
+    // filters are after top, same as clickhouse
+    if !filter_clauses.is_empty() {
+        final_sql = format!("select * from ({}) as filter_base where {}",
+            final_sql,
+            join(&filter_clauses, " and "),
+        );
+    }
+
+    format!("{};", final_sql)
+}
+
+/// Top n by row number within the other drilldowns. If others is set, the
+/// rest are collapsed into an "Other" member with re-aggregated measures;
+/// checks for calculations and aggregators are done before this point.
+fn top_sql(
+    final_sql: String,
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    calc_cols: &[String],
+    top: &TopSql,
+    ) -> String
+{
+    let top_drill = match drills.iter().find(|d| d.col_alias_only_vec().contains(&top.by_column)) {
+        Some(d) => d,
+        None => return final_sql,
+    };
+
+    let drill_cols: Vec<String> = drills.iter()
+        .flat_map(|d| d.col_alias_only_vec())
+        .flat_map(|c| c.split(", ").map(|c| c.to_owned()).collect::<Vec<_>>())
+        .collect();
+    let mea_cols: Vec<String> = (0..meas.len()).map(|i| format!("final_m{}", i)).collect();
+
+    let ranked_sql = format!("select *, row_number() over ({}order by {}) as top_rank from ({}) as top_base",
+        partition_by(drills, top_drill),
+        join(top.sort_columns.iter().map(|c| format!("{} {}", c, top.sort_direction.sql_string())), ", "),
+        final_sql,
+    );
+
+    let top_sql = format!("select {} from ({}) as top_ranked where top_rank <= {}",
+        join(drill_cols.iter().chain(mea_cols.iter()).chain(calc_cols.iter()), ", "),
+        ranked_sql,
+        top.n,
+    );
+
+    if !top.others {
+        return top_sql;
+    }
+
+    let mut others_cols = vec![];
+    let mut group_cols = vec![];
+    for drill in drills {
+        if drill == top_drill {
+            for l in &drill.level_columns {
+                others_cols.push(format!("null as {}_{}", l.key_column, drill.alias_postfix));
+                if let Some(ref name_col) = l.name_column {
+                    others_cols.push(format!("'Other' as {}_{}", name_col, drill.alias_postfix));
+                }
+            }
+            for p in &drill.property_columns {
+                others_cols.push(format!("null as {}", p));
+            }
+        } else {
+            for col in drill.col_alias_only_vec() {
+                for col in col.split(", ") {
+                    others_cols.push(col.to_owned());
+                    group_cols.push(col.to_owned());
+                }
+            }
+        }
+    }
+    for (i, mea) in meas.iter().enumerate() {
+        others_cols.push(format!("{}(final_m{}) as final_m{}", mea.reaggregate_fn().unwrap_or("max"), i, i));
     }
 
-    final_sql = format!("{} group by {};", final_sql, drill_cols);
-    final_sql
+    format!("{} union all select {} from ({}) as top_ranked where top_rank > {}{}",
+        top_sql,
+        join(others_cols, ", "),
+        ranked_sql,
+        top.n,
+        if group_cols.is_empty() { "".to_owned() } else { format!(" group by {}", join(group_cols, ", ")) },
+    )
+}
+
+/// Least squares line of the measure over periods numbered from 1 in order of
+/// the time drill, within the other drilldowns. Periods with a null measure
+/// are not fitted. Forecast rows are only generated for `periods` > 0; they
+/// have the projected key of the time drill, and null for its other columns
+/// and the measures.
+fn trend_sql(
+    final_sql: String,
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    calc_cols: &[String],
+    trend: &TrendSql,
+    ) -> String
+{
+    let partition = partition_by(drills, &trend.time_drill);
+    let mea = &trend.mea;
+
+    let time_key = trend.time_drill.level_columns.last()
+        .map(|l| format!("{}_{}", l.key_column, trend.time_drill.alias_postfix));
+
+    let points_sql = format!("select *, row_number() over ({}order by {}) as trend_x, count(*) over ({}) as trend_periods, {} as trend_last from ({}) as trend_base",
+        partition,
+        trend.time_drill.col_alias_only_string(),
+        partition.trim_end(),
+        time_key.as_ref().map(|k| format!("max({}) over ({})", k, partition.trim_end())).unwrap_or_else(|| "null".to_owned()),
+        final_sql,
+    );
+
+    // only periods with a measure are fitted
+    let x = format!("case when {} is not null then trend_x end", mea);
+    let sum = |expr: String| format!("sum({}) over ({})", expr, partition.trim_end());
+
+    let sums_sql = format!("select *, count({mea}) over ({p}) as trend_n, {sx} as trend_sx, {sy} as trend_sy, {sxy} as trend_sxy, {sxx} as trend_sxx from ({points}) as trend_points",
+        mea = mea,
+        p = partition.trim_end(),
+        sx = sum(x.clone()),
+        sy = sum(mea.clone()),
+        sxy = sum(format!("trend_x * {}", mea)),
+        sxx = sum(format!("({x}) * ({x})", x = x)),
+        points = points_sql,
+    );
+
+    let denominator = "nullif(trend_n * trend_sxx - trend_sx * trend_sx, 0)";
+    let fit_sql = format!("select *, \
+        (trend_n * trend_sxy - trend_sx * trend_sy) * 1.0 / {d} as trend_slope, \
+        (trend_sy * trend_sxx - trend_sx * trend_sxy) * 1.0 / {d} as trend_intercept \
+        from ({sums}) as trend_sums",
+        d = denominator,
+        sums = sums_sql,
+    );
+
+    let drill_cols: Vec<String> = drills.iter()
+        .flat_map(|d| d.col_alias_only_vec())
+        .flat_map(|c| c.split(", ").map(|c| c.to_owned()).collect::<Vec<_>>())
+        .collect();
+    let value_cols: Vec<String> = (0..meas.len()).map(|i| format!("final_m{}", i))
+        .chain(calc_cols.iter().cloned())
+        .collect();
+
+    let observed_sql = format!("select {}, {}, trend_slope, trend_intercept, trend_intercept + trend_slope * trend_x as trend, 0 as forecast from ({}) as trend_fit",
+        join(&drill_cols, ", "),
+        join(&value_cols, ", "),
+        fit_sql,
+    );
+
+    if trend.periods == 0 {
+        return observed_sql;
+    }
+
+    let time_cols: Vec<String> = trend.time_drill.col_alias_only_vec().iter()
+        .flat_map(|c| c.split(", ").map(|c| c.to_owned()).collect::<Vec<_>>())
+        .collect();
+    let projected_key = projected_key("trend_last", "trend_h", trend.granularity.as_ref());
+    let forecast_cols = drill_cols.iter()
+        .map(|c| {
+            match projected_key {
+                Some(ref key) if time_key.as_ref() == Some(c) => format!("{} as {}", key, c),
+                _ if time_cols.contains(c) => format!("null as {}", c),
+                _ => c.clone(),
+            }
+        })
+        .chain(value_cols.iter().map(|c| format!("null as {}", c)));
+    let horizon_sql = join((1..=trend.periods).map(|h| format!("select {} as trend_h", h)), " union all ");
+
+    format!("{} union all select distinct {}, trend_slope, trend_intercept, trend_intercept + trend_slope * (trend_periods + trend_h) as trend, trend_h as forecast \
+        from ({}) as trend_fit cross join ({}) as trend_horizon",
+        observed_sql,
+        join(forecast_cols, ", "),
+        fit_sql,
+        horizon_sql,
+    )
+}
+
+/// Key of the period `h` periods after key `k`, by period number as in
+/// growth. Day keys are not projected.
+fn projected_key(k: &str, h: &str, granularity: Option<&TimeGranularity>) -> Option<String> {
+    let from_period = |per_year: u32, key_base: u32| {
+        let n = format!("floor({k} / {b}) * {y} + mod({k}, {b}) - 1 + {h}", k = k, b = key_base, y = per_year, h = h);
+        format!("floor(({n}) / {y}) * {b} + mod({n}, {y}) + 1", n = n, y = per_year, b = key_base)
+    };
+
+    match granularity {
+        Some(TimeGranularity::Quarter) | Some(TimeGranularity::FiscalQuarter) => Some(from_period(4, 10)),
+        Some(TimeGranularity::Month) => Some(from_period(12, 100)),
+        Some(TimeGranularity::Day) => None,
+        Some(TimeGranularity::Year) | Some(TimeGranularity::FiscalYear) | None => Some(format!("{} + {}", k, h)),
+    }
+}
+
+/// Aggregates the members of the grouped drill into their groups. Key and
+/// name columns become the group name, other columns of the drill are null
+/// for grouped rows, and measures are re-aggregated; checks for
+/// calculations and aggregators are done before this point.
+fn group_sql(
+    final_sql: String,
+    drills: &[DrilldownSql],
+    meas: &[MeasureSql],
+    group: &GroupSql,
+    ) -> String
+{
+    let group_drill = match drills.iter().find(|d| d.col_alias_only_vec().contains(&group.by_column)) {
+        Some(d) => d,
+        None => return final_sql,
+    };
+
+    let grouped = group.grouped_string();
+    let cases = join(group.groups.iter()
+        .map(|g| format!("when {} then {}", group.in_string(&g.members), GroupSql::name_string(g))),
+        " ");
+
+    let mut inner_cols = vec![];
+    let mut outer_cols = vec![];
+    let mut group_cols = vec![];
+    for drill in drills {
+        if drill == group_drill {
+            let mut push_col = |col: &str, expr: String| {
+                inner_cols.push(format!("{} as {}_group", expr, col));
+                outer_cols.push(format!("{}_group as {}", col, col));
+                group_cols.push(format!("{}_group", col));
+            };
+            let null_if_grouped = |col: &str| format!("case when {} then null else {} end", grouped, col);
+
+            for l in &drill.level_columns {
+                let key_col = format!("{}_{}", l.key_column, drill.alias_postfix);
+                let name_col = l.name_column.as_ref().map(|n| format!("{}_{}", n, drill.alias_postfix));
+
+                if key_col == group.by_column {
+                    // concat to convert keys to text, since mysql can't cast to varchar
+                    push_col(&key_col, format!("case {} else concat({}) end", cases, key_col));
+                    if let Some(name_col) = name_col {
+                        push_col(&name_col, format!("case {} else {} end", cases, name_col));
+                    }
+                } else {
+                    push_col(&key_col, null_if_grouped(&key_col));
+                    if let Some(name_col) = name_col {
+                        push_col(&name_col, null_if_grouped(&name_col));
+                    }
+                }
+            }
+            for p in &drill.property_columns {
+                push_col(p, null_if_grouped(p));
+            }
+        } else {
+            for col in drill.col_alias_only_vec() {
+                for col in col.split(", ") {
+                    inner_cols.push(col.to_owned());
+                    outer_cols.push(col.to_owned());
+                    group_cols.push(col.to_owned());
+                }
+            }
+        }
+    }
+    for (i, mea) in meas.iter().enumerate() {
+        inner_cols.push(format!("final_m{} as group_m{}", i, i));
+        outer_cols.push(format!("{}(group_m{}) as final_m{}", mea.reaggregate_fn().unwrap_or("max"), i, i));
+    }
+
+    format!("select {} from (select {} from ({}) as group_base{}) as group_inner group by {}",
+        join(outer_cols, ", "),
+        join(inner_cols, ", "),
+        final_sql,
+        if group.keep_others { "".to_owned() } else { format!(" where {}", grouped) },
+        join(group_cols, ", "),
+    )
+}
+
+/// Label, lower and upper bound of the bucket of each row.
+fn bins_sql(bins: &BinsSql) -> Vec<String> {
+    match bins.bins {
+        Bins::Edges(ref edges) => {
+            let mut labels = vec![];
+            let mut lowers = vec![];
+            let mut uppers = vec![];
+
+            for bucket in BinsSql::buckets(edges) {
+                let when = format!("when {} then", bins.bucket_condition(&bucket));
+                let bound = |b: Option<f64>| b.map(|b| b.to_string()).unwrap_or_else(|| "null".to_owned());
+
+                labels.push(format!("{} '{}'", when, bucket.label));
+                lowers.push(format!("{} {}", when, bound(bucket.lower)));
+                uppers.push(format!("{} {}", when, bound(bucket.upper)));
+            }
+
+            vec![labels, lowers, uppers].into_iter()
+                .map(|cases| format!("case {} else null end", join(cases, " ")))
+                .collect()
+        },
+        Bins::EqualWidth(n) => {
+            let (min, width) = bins.min_width_strings(n);
+            let idx = format!("case when {w} = 0 then 0 else least(floor(({col} - {min}) / {w}), {last}) end",
+                w = width,
+                col = bins.column,
+                min = min,
+                last = n - 1,
+            );
+            let lower = format!("{} + {} * {}", min, idx, width);
+            let upper = format!("{} + ({} + 1) * {}", min, idx, width);
+
+            vec![
+                format!("concat({}, ' - ', {})", lower, upper),
+                lower,
+                upper,
+            ]
+        },
+    }
+}
+
+/// Columns for a drilldown rolled up into a total: null keys and
+/// properties, 'Total' names.
+fn total_placeholders(drill: &DrilldownSql) -> Vec<String> {
+    let mut cols = vec![];
+
+    for l in drill.level_columns.iter() {
+        cols.push("null".to_owned());
+        if l.name_column.is_some() {
+            cols.push("'Total'".to_owned());
+        }
+    }
+    for _ in drill.property_columns.iter() {
+        cols.push("null".to_owned());
+    }
+
+    cols
+}
+
+/// Qualified drilldown columns, aliased the same way as `col_alias_only_vec`.
+fn col_qual_alias_string(drill: &DrilldownSql) -> String {
+    let mut cols = vec![];
+
+    for l in drill.level_columns.iter() {
+        cols.push(format!("{} as {}_{}", l.key_qual_string(&drill.table.name), l.key_column, drill.alias_postfix));
+
+        if let Some(ref name_col) = l.name_column {
+            cols.push(format!("{}.{} as {}_{}", drill.table.name, name_col, name_col, drill.alias_postfix));
+        }
+    }
+
+    for p in drill.property_columns.iter() {
+        cols.push(format!("{}.{} as {}", drill.table.name, p, p));
+    }
+
+    join(cols, ", ")
+}
+
+/// `partition by` all drilldowns besides the one the calculation is over.
+fn partition_by(drills: &[DrilldownSql], over_drill: &DrilldownSql) -> String {
+    let other_cols = join(
+        drills.iter()
+            .filter(|d| *d != over_drill)
+            .map(|d| d.col_alias_only_string()),
+        ", "
+    );
+
+    if other_cols.is_empty() {
+        "".to_owned()
+    } else {
+        format!("partition by {} ", other_cols)
+    }
+}
+
+/// As `partition_by`, plus the period that a period-to-date total restarts on.
+fn cumulative_partition_by(drills: &[DrilldownSql], cumulative: &CumulativeSql) -> String {
+    let partition = partition_by(drills, &cumulative.time_drill);
+
+    let reset_col = match cumulative.reset {
+        Some(ResetSql::Column(ref col)) => col.clone(),
+        None => return partition,
+    };
+
+    if partition.is_empty() {
+        format!("partition by {} ", reset_col)
+    } else {
+        format!("{}, {} ", partition.trim_end(), reset_col)
+    }
 }
 
 #[cfg(test)]
@@ -109,6 +638,7 @@ mod test {
     use super::*;
     use crate::names::Mask;
     use crate::query_ir::{MemberType, LevelColumn};
+    use crate::query::{Comparison, Constraint, MemberGroup, SortDirection};
     use crate::Table;
 
     #[test]
@@ -134,7 +664,9 @@ mod test {
                 members: vec!["3".into()],
                 member_type: MemberType::NonText,
                 mask: Mask::Include,
-                for_match: false
+                for_match: false,
+                range: None,
+                date_key: None,
             },
         ];
         let drills = vec![
@@ -150,6 +682,7 @@ mod test {
                     LevelColumn {
                         key_column: "id".into(),
                         name_column: Some("name".to_owned()),
+                        date_key: None,
                     },
                 ],
                 property_columns: vec![],
@@ -160,9 +693,459 @@ mod test {
         ];
 
         assert_eq!(
-            standard_sql(&table, &cuts, &drills, &meas, &None, &None, &None, &None, &None),
+            standard_sql(&table, &cuts, &drills, &meas, &CalcSql::default()),
             "select valid_projects.id, valid_projects.name, sum(commits) from project_facts inner join valid_projects on valid_projects.id = project_facts.project_id where valid_projects.id in (3) group by valid_projects.id, valid_projects.name;".to_owned()
         );
     }
-}
 
+    #[test]
+    fn test_standard_sql_window() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Year".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            DrilldownSql {
+                alias_postfix: "Product".into(),
+                foreign_key: "product_id".into(),
+                primary_key: "product_id".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "product_id".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+        let cumulative = Some(CumulativeSql {
+            time_drill: drills[0].clone(),
+            mea: "final_m0".into(),
+            reset: None,
+        });
+        let moving_avg = Some(MovingAvgSql {
+            time_drill: drills[0].clone(),
+            mea: "final_m0".into(),
+            window: 3,
+        });
+        let index = Some(IndexSql {
+            drill: drills[0].clone(),
+            by_column: "year_Year".into(),
+            member_type: MemberType::NonText,
+            base: "2010".into(),
+            mea: "final_m0".into(),
+        });
+
+        let rank = Some(RankSql {
+            drill: drills[1].clone(),
+            mea: "final_m0".into(),
+            direction: SortDirection::Desc,
+            method: RankMethod::Dense,
+        });
+
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { cumulative: cumulative.as_ref(), moving_avg: moving_avg.as_ref(), index: index.as_ref(), rank: rank.as_ref(), ..Default::default() }),
+            "select *, \
+            sum(final_m0) over (partition by product_id_Product order by year_Year rows between unbounded preceding and current row) as cumulative, \
+            avg(final_m0) over (partition by product_id_Product order by year_Year rows between 2 preceding and current row) as moving_avg, \
+            final_m0 * 100.0 / nullif(max(case when year_Year = 2010 then final_m0 end) over (partition by product_id_Product), 0) as indexed, \
+            dense_rank() over (partition by year_Year order by final_m0 desc) as ranked \
+            from (select sales.year as year_Year, sales.product_id as product_id_Product, sum(quantity) as final_m0 from sales group by sales.year, sales.product_id) as window_base;".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_period_to_date() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Date".into(),
+                foreign_key: "day".into(),
+                primary_key: "day".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                    LevelColumn {
+                        key_column: "day".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+
+        let cumulative = Some(CumulativeSql {
+            time_drill: drills[0].clone(),
+            mea: "final_m0".into(),
+            reset: Some(ResetSql::Column("year_Date".into())),
+        });
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { cumulative: cumulative.as_ref(), ..Default::default() }),
+            "select *, \
+            sum(final_m0) over (partition by year_Date order by year_Date, day_Date rows between unbounded preceding and current row) as cumulative \
+            from (select sales.year as year_Date, sales.day as day_Date, sum(quantity) as final_m0 from sales group by sales.year, sales.day) as window_base;".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_trend() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Year".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            DrilldownSql {
+                alias_postfix: "Product".into(),
+                foreign_key: "product_id".into(),
+                primary_key: "product_id".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "product_id".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+        let mut trend = TrendSql {
+            time_drill: drills[0].clone(),
+            mea: "final_m0".into(),
+            periods: 0,
+            granularity: None,
+        };
+
+        let sql = standard_sql(&table, &[], &drills, &meas, &CalcSql { trend: Some(&trend), ..Default::default() });
+        assert!(sql.starts_with("select year_Year, product_id_Product, final_m0, trend_slope, trend_intercept, trend_intercept + trend_slope * trend_x as trend, 0 as forecast from ("));
+        assert!(sql.contains("row_number() over (partition by product_id_Product order by year_Year) as trend_x"));
+        assert!(sql.contains("sum(trend_x * final_m0) over (partition by product_id_Product) as trend_sxy"));
+        assert!(!sql.contains("union all"));
+
+        trend.periods = 2;
+        let sql = standard_sql(&table, &[], &drills, &meas, &CalcSql { trend: Some(&trend), ..Default::default() });
+        assert!(sql.contains("max(year_Year) over (partition by product_id_Product) as trend_last"));
+        assert!(sql.contains("union all select distinct trend_last + trend_h as year_Year, product_id_Product, null as final_m0, trend_slope, trend_intercept, \
+            trend_intercept + trend_slope * (trend_periods + trend_h) as trend, trend_h as forecast"));
+        assert!(sql.ends_with("cross join (select 1 as trend_h union all select 2 as trend_h) as trend_horizon;"));
+
+        trend.granularity = Some(TimeGranularity::Month);
+        let sql = standard_sql(&table, &[], &drills, &meas, &CalcSql { trend: Some(&trend), ..Default::default() });
+        assert!(sql.contains("union all select distinct floor((floor(trend_last / 100) * 12 + mod(trend_last, 100) - 1 + trend_h) / 12) * 100 \
+            + mod(floor(trend_last / 100) * 12 + mod(trend_last, 100) - 1 + trend_h, 12) + 1 as year_Year"));
+
+        trend.granularity = Some(TimeGranularity::Day);
+        let sql = standard_sql(&table, &[], &drills, &meas, &CalcSql { trend: Some(&trend), ..Default::default() });
+        assert!(sql.contains("union all select distinct null as year_Year, product_id_Product"));
+    }
+
+    #[test]
+    fn test_standard_sql_top_others() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Product".into(),
+                foreign_key: "product_id".into(),
+                primary_key: "product_id".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "product_id".into(),
+                        name_column: Some("product_name".into()),
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+        let top = Some(TopSql {
+            n: 5,
+            by_column: "product_id_Product".into(),
+            sort_columns: vec!["final_m0".into()],
+            sort_direction: SortDirection::Desc,
+            others: true,
+        });
+
+        let ranked = "select *, row_number() over (order by final_m0 desc) as top_rank from (\
+            select sales.product_id as product_id_Product, sales.product_name as product_name_Product, sum(quantity) as final_m0 from sales \
+            group by sales.product_id, sales.product_name) as top_base";
+
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { top: top.as_ref(), ..Default::default() }),
+            format!("select product_id_Product, product_name_Product, final_m0 from ({}) as top_ranked where top_rank <= 5 \
+                union all select null as product_id_Product, 'Other' as product_name_Product, sum(final_m0) as final_m0 from ({}) as top_ranked where top_rank > 5;",
+                ranked,
+                ranked,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_totals() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Year".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            DrilldownSql {
+                alias_postfix: "Product".into(),
+                foreign_key: "product_id".into(),
+                primary_key: "product_id".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "product_id".into(),
+                        name_column: Some("product_name".into()),
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { totals: &drills[..], ..Default::default() }),
+            "select sales.year, sales.product_id, sales.product_name, sum(quantity), 0 as total from sales group by sales.year, sales.product_id, sales.product_name \
+            union all select sales.year, null, 'Total', sum(quantity), 1 as total from sales group by sales.year \
+            union all select null, null, 'Total', sum(quantity), 1 as total from sales;".to_owned()
+        );
+
+        // the grand total is added when not all drills are in totals
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { totals: &drills[..1], ..Default::default() }),
+            "select sales.year, sales.product_id, sales.product_name, sum(quantity), 0 as total from sales group by sales.year, sales.product_id, sales.product_name \
+            union all select null, sales.product_id, sales.product_name, sum(quantity), 1 as total from sales group by sales.product_id, sales.product_name \
+            union all select null, null, 'Total', sum(quantity), 1 as total from sales;".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_filter() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Year".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() },
+            MeasureSql { aggregator: Aggregator::Max, column: "price".into() },
+        ];
+        let filters = vec![
+            FilterSql {
+                by_column: "final_m1".into(),
+                constraint: Constraint { comparison: Comparison::GreaterThan, n: 2.0 },
+                operator: None,
+                constraint2: None,
+            },
+        ];
+        let filter = Some(FilterExprSql::And(
+            Box::new(FilterExprSql::Not(Box::new(FilterExprSql::Between("final_m0".into(), 10.0, 20.0)))),
+            Box::new(FilterExprSql::Or(
+                Box::new(FilterExprSql::IsNull("final_m1".into())),
+                Box::new(FilterExprSql::Compare("final_m0".into(), Constraint { comparison: Comparison::NotEqual, n: 0.0 })),
+            )),
+        ));
+
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { filters: &filters, filter: filter.as_ref(), ..Default::default() }),
+            "select * from (\
+            select sales.year as year_Year, sum(quantity) as final_m0, max(price) as final_m1 from sales group by sales.year\
+            ) as filter_base where final_m1 > 2 and (not (final_m0 between 10 and 20) and (final_m1 is null or final_m0 <> 0));".to_owned()
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_group() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let drills = vec![
+            DrilldownSql {
+                alias_postfix: "Year".into(),
+                foreign_key: "year".into(),
+                primary_key: "year".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "year".into(),
+                        name_column: None,
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+            DrilldownSql {
+                alias_postfix: "Geography".into(),
+                foreign_key: "country_id".into(),
+                primary_key: "country_id".into(),
+                inline_table: None,
+                table: Table { name: "sales".into(), schema: None, primary_key: None },
+                level_columns: vec![
+                    LevelColumn {
+                        key_column: "country_id".into(),
+                        name_column: Some("country_name".into()),
+                        date_key: None,
+                    },
+                ],
+                property_columns: vec![],
+            },
+        ];
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+        let group = Some(GroupSql {
+            by_column: "country_id_Geography".into(),
+            member_type: MemberType::NonText,
+            groups: vec![
+                MemberGroup { name: "Americas".into(), members: vec!["1".into(), "2".into()] },
+                MemberGroup { name: "Europe's".into(), members: vec!["3".into()] },
+            ],
+            keep_others: false,
+        });
+
+        let cases = "when country_id_Geography in (1, 2) then 'Americas' when country_id_Geography in (3) then 'Europe''s'";
+
+        assert_eq!(
+            standard_sql(&table, &[], &drills, &meas, &CalcSql { group: group.as_ref(), ..Default::default() }),
+            format!("select year_Year, country_id_Geography_group as country_id_Geography, country_name_Geography_group as country_name_Geography, \
+                sum(group_m0) as final_m0 from (\
+                select year_Year, case {} else concat(country_id_Geography) end as country_id_Geography_group, \
+                case {} else country_name_Geography end as country_name_Geography_group, final_m0 as group_m0 from (\
+                select sales.year as year_Year, sales.country_id as country_id_Geography, sales.country_name as country_name_Geography, sum(quantity) as final_m0 \
+                from sales group by sales.year, sales.country_id, sales.country_name\
+                ) as group_base where country_id_Geography in (1, 2, 3)\
+                ) as group_inner group by year_Year, country_id_Geography_group, country_name_Geography_group;",
+                cases,
+                cases,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_standard_sql_bins() {
+        let table = TableSql {
+            name: "sales".into(),
+            primary_key: None,
+        };
+        let meas = vec![
+            MeasureSql { aggregator: Aggregator::Sum, column: "quantity".into() }
+        ];
+        let bins = Some(BinsSql {
+            column: "age".into(),
+            table: "sales".into(),
+            bins: Bins::Edges(vec![0.0, 18.0, f64::INFINITY]),
+        });
+
+        assert_eq!(
+            standard_sql(&table, &[], &[], &meas, &CalcSql { bins: bins.as_ref(), ..Default::default() }),
+            "select case when age >= 0 and age < 18 then '0 - 18' when age >= 18 then '18 - inf' else null end as bin_label, \
+            case when age >= 0 and age < 18 then 0 when age >= 18 then 18 else null end as bin_lower, \
+            case when age >= 0 and age < 18 then 18 when age >= 18 then null else null end as bin_upper, \
+            sum(quantity) from sales group by bin_label, bin_lower, bin_upper;".to_owned()
+        );
+
+        let bins = Some(BinsSql {
+            column: "age".into(),
+            table: "sales".into(),
+            bins: Bins::EqualWidth(4),
+        });
+        let sql = standard_sql(&table, &[], &[], &meas, &CalcSql { bins: bins.as_ref(), ..Default::default() });
+        assert!(sql.contains("least(floor((age - (select min(age) from sales)) / (select (max(age) - min(age)) / 4 from sales)), 3)"));
+    }
+}
@@ -9,20 +9,35 @@ use crate::db_config::Database;
 use crate::handlers::{
     aggregate_handler,
     aggregate_default_handler,
+    aggregate_post_handler,
+    aggregate_post_default_handler,
     aggregate_stream_handler,
     aggregate_stream_default_handler,
+    batch_handler,
     diagnosis_handler,
     diagnosis_default_handler,
+    export_create_handler,
+    export_status_handler,
+    export_download_handler,
     logic_layer_default_handler,
     logic_layer_handler,
+    logic_layer_post_default_handler,
+    logic_layer_post_handler,
     logic_layer_non_unique_levels_handler,
     logic_layer_non_unique_levels_default_handler,
     logic_layer_members_handler,
     logic_layer_members_default_handler,
     flush_handler,
+    graphql_handler,
+    graphql_schema_handler,
     index_handler,
+    mdx_handler,
+    mdx_default_handler,
+    mdx_post_handler,
+    mdx_post_default_handler,
     metadata_handler,
     metadata_all_handler,
+    openapi_handler,
     members_handler,
     members_default_handler,
     logic_layer_relations_handler,
@@ -30,6 +45,8 @@ use crate::handlers::{
     logic_layer_relations_non_unique_levels_default_handler,
     logic_layer_relations_non_unique_levels_handler
 };
+use crate::exports::ExportJobs;
+use crate::graphql::GraphqlSchema;
 use crate::logic_layer::{Cache, LogicLayerConfig};
 
 use std::sync::{Arc, RwLock};
@@ -53,6 +70,8 @@ pub struct EnvVars {
     pub schema_source: SchemaSource,
     pub jwt_secret: Option<String>,
     pub flush_secret: Option<String>,
+    pub batch_concurrency: usize,
+    pub batch_max_queries: usize,
 }
 
 /// Holds [ActixWeb State](https://actix.rs/docs/application/).
@@ -65,8 +84,10 @@ pub struct AppState {
     pub db_type: Database,
     pub env_vars: EnvVars,
     pub schema: Arc<RwLock<Schema>>,
+    pub graphql_schema: Arc<RwLock<GraphqlSchema>>,
     pub cache: Arc<RwLock<Cache>>,
     pub logic_layer_config: Option<Arc<RwLock<LogicLayerConfig>>>,
+    pub exports: Arc<RwLock<ExportJobs>>,
     // TODO is there a way to access this that's not through state? Tried using closures to
     // capture, but the handlers need to implement Fn, not FnOnce (which happens once capturing
     // variables from environment
@@ -81,8 +102,10 @@ pub fn create_app(
         db_type: Database,
         env_vars: EnvVars,
         schema: Arc<RwLock<Schema>>,
+        graphql_schema: Arc<RwLock<GraphqlSchema>>,
         cache: Arc<RwLock<Cache>>,
         logic_layer_config: Option<Arc<RwLock<LogicLayerConfig>>>,
+        exports: Arc<RwLock<ExportJobs>>,
         streaming_response: bool,
         has_unique_levels_properties: CubeHasUniqueLevelsAndProperties,
     ) -> App<AppState>
@@ -95,8 +118,10 @@ pub fn create_app(
                 db_type,
                 env_vars,
                 schema,
+                graphql_schema,
                 cache,
                 logic_layer_config,
+                exports,
                 has_unique_levels_properties: has_unique_levels_properties.clone(),
         })
         .middleware(middleware::Logger::default())
@@ -106,6 +131,9 @@ pub fn create_app(
         .resource("/", |r| {
             r.method(Method::GET).with(index_handler)
         })
+        .resource("/openapi.json", |r| {
+            r.method(Method::GET).with(openapi_handler)
+        })
         .resource("/cubes", |r| {
             r.method(Method::GET).with(metadata_all_handler)
         })
@@ -120,6 +148,30 @@ pub fn create_app(
         .resource("/cubes/{cube}/members.{format}", |r| {
             r.method(Method::GET).with(members_handler)
         })
+        .resource("/batch", |r| {
+            r.method(Method::POST).with(batch_handler)
+        })
+        .resource("/mdx", |r| {
+            r.method(Method::GET).with(mdx_default_handler);
+            r.method(Method::POST).with(mdx_post_default_handler)
+        })
+        .resource("/mdx.{format}", |r| {
+            r.method(Method::GET).with(mdx_handler);
+            r.method(Method::POST).with(mdx_post_handler)
+        })
+        .resource("/graphql", |r| {
+            r.method(Method::GET).with(graphql_schema_handler);
+            r.method(Method::POST).with(graphql_handler)
+        })
+        .resource("/exports", |r| {
+            r.method(Method::POST).with(export_create_handler)
+        })
+        .resource("/exports/{id}", |r| {
+            r.method(Method::GET).with(export_status_handler)
+        })
+        .resource("/exports/{id}/download", |r| {
+            r.method(Method::GET).with(export_download_handler)
+        })
 
         // Data Quality Assurance
         .resource("/diagnosis", |r| {
@@ -138,18 +190,22 @@ pub fn create_app(
     let app = if streaming_response {
         app
             .resource("/cubes/{cube}/aggregate", |r| {
-                r.method(Method::GET).with(aggregate_stream_default_handler)
+                r.method(Method::GET).with(aggregate_stream_default_handler);
+                r.method(Method::POST).with(aggregate_post_default_handler)
             })
             .resource("/cubes/{cube}/aggregate.{format}", |r| {
-                r.method(Method::GET).with(aggregate_stream_handler)
+                r.method(Method::GET).with(aggregate_stream_handler);
+                r.method(Method::POST).with(aggregate_post_handler)
             })
     } else {
         app
             .resource("/cubes/{cube}/aggregate", |r| {
-                r.method(Method::GET).with(aggregate_default_handler)
+                r.method(Method::GET).with(aggregate_default_handler);
+                r.method(Method::POST).with(aggregate_post_default_handler)
             })
             .resource("/cubes/{cube}/aggregate.{format}", |r| {
-                r.method(Method::GET).with(aggregate_handler)
+                r.method(Method::GET).with(aggregate_handler);
+                r.method(Method::POST).with(aggregate_post_handler)
             })
     };
 
@@ -158,10 +214,12 @@ pub fn create_app(
             // Logic Layer
             app
                 .resource("/data", |r| {
-                    r.method(Method::GET).with(logic_layer_default_handler)
+                    r.method(Method::GET).with(logic_layer_default_handler);
+                    r.method(Method::POST).with(logic_layer_post_default_handler)
                 })
                 .resource("/data.{format}", |r| {
-                    r.method(Method::GET).with(logic_layer_handler)
+                    r.method(Method::GET).with(logic_layer_handler);
+                    r.method(Method::POST).with(logic_layer_post_handler)
                 })
                 .resource("/members", |r| {
                     r.method(Method::GET).with(logic_layer_members_default_handler)
@@ -180,10 +238,12 @@ pub fn create_app(
             // No Logic Layer, give error instead
             app
                 .resource("/data", |r| {
-                    r.method(Method::GET).with(logic_layer_non_unique_levels_default_handler)
+                    r.method(Method::GET).with(logic_layer_non_unique_levels_default_handler);
+                    r.method(Method::POST).with(logic_layer_non_unique_levels_default_handler)
                 })
                 .resource("/data.{format}", |r| {
-                    r.method(Method::GET).with(logic_layer_non_unique_levels_handler)
+                    r.method(Method::GET).with(logic_layer_non_unique_levels_handler);
+                    r.method(Method::POST).with(logic_layer_non_unique_levels_handler)
                 })
                 .resource("/members", |r| {
                     r.method(Method::GET).with(logic_layer_non_unique_levels_default_handler)
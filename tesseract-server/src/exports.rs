@@ -0,0 +1,364 @@
+//! Asynchronous export jobs.
+//!
+//! An export job streams the result of an aggregate query into a file in the
+//! export directory, and the file can be downloaded once the job is done.
+//! Jobs are held in memory, so they don't survive a restart; leftover files
+//! are removed on startup. The queue is limited, and jobs that run for
+//! longer than the timeout fail.
+
+use failure::{Error, bail, format_err};
+use log::*;
+use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tesseract_core::format::FormatType;
+use uuid::Uuid;
+
+const FILE_PREFIX: &str = "export-";
+
+
+/// Settings for export jobs.
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub dir: PathBuf,
+    pub max_running: usize,
+    pub max_queued: usize,
+    pub ttl: Duration,
+    pub timeout: Duration,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ExportJob {
+    pub id: String,
+    pub cube: String,
+    pub format: String,
+    pub min_auth_level: i32,
+    pub status: ExportStatus,
+    pub rows: usize,
+    pub bytes: usize,
+    pub error: Option<String>,
+    pub path: PathBuf,
+    pub started: Option<SystemTime>,
+    pub finished: Option<SystemTime>,
+}
+
+
+/// The work of a job, held in the queue until a running slot is free.
+#[derive(Debug, Clone)]
+pub struct ExportTask {
+    pub id: String,
+    pub sql: String,
+    pub headers: Vec<String>,
+    pub format: FormatType,
+    pub path: PathBuf,
+}
+
+
+#[derive(Debug)]
+pub struct ExportJobs {
+    config: ExportConfig,
+    jobs: HashMap<String, ExportJob>,
+    queue: VecDeque<ExportTask>,
+    running: usize,
+}
+
+impl ExportJobs {
+    /// Creates the export directory if needed, and removes files left
+    /// behind by jobs from an earlier run.
+    pub fn new(config: ExportConfig) -> Result<Self, Error> {
+        fs::create_dir_all(&config.dir)?;
+
+        for entry in fs::read_dir(&config.dir)? {
+            let path = entry?.path();
+            let is_export = path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(FILE_PREFIX))
+                .unwrap_or(false);
+
+            if is_export {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        Ok(ExportJobs {
+            config,
+            jobs: HashMap::new(),
+            queue: VecDeque::new(),
+            running: 0,
+        })
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.config.ttl
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ExportJob> {
+        self.jobs.get(id)
+    }
+
+    /// Queues a new job, and returns its id. Fails if `max_queued` jobs are
+    /// already waiting.
+    pub fn add(
+        &mut self,
+        cube: String,
+        format: String,
+        format_type: FormatType,
+        min_auth_level: i32,
+        sql: String,
+        headers: Vec<String>,
+    ) -> Result<String, Error>
+    {
+        if self.queue.len() >= self.config.max_queued {
+            bail!("Too many exports are queued, try again later");
+        }
+
+        let id = Uuid::new_v4().to_simple().to_string();
+
+        let extension = match format_type {
+            FormatType::Csv => "csv",
+            _ => "json",
+        };
+        let path = self.config.dir.join(format!("{}{}.{}", FILE_PREFIX, id, extension));
+
+        self.jobs.insert(id.clone(), ExportJob {
+            id: id.clone(),
+            cube,
+            format,
+            min_auth_level,
+            status: ExportStatus::Queued,
+            rows: 0,
+            bytes: 0,
+            error: None,
+            path: path.clone(),
+            started: None,
+            finished: None,
+        });
+
+        self.queue.push_back(ExportTask {
+            id: id.clone(),
+            sql,
+            headers,
+            format: format_type,
+            path,
+        });
+
+        Ok(id)
+    }
+
+    /// Takes the next queued job if fewer than `max_running` jobs are
+    /// running, and marks it as running.
+    pub fn next_task(&mut self) -> Option<ExportTask> {
+        if self.running >= self.config.max_running {
+            return None;
+        }
+
+        let task = self.queue.pop_front()?;
+
+        if let Some(job) = self.jobs.get_mut(&task.id) {
+            job.status = ExportStatus::Running;
+            job.started = Some(SystemTime::now());
+        }
+        self.running += 1;
+
+        Some(task)
+    }
+
+    /// Adds to the progress of a running job. Returns false if the job
+    /// isn't running anymore, so that its query can be stopped.
+    pub fn add_progress(&mut self, id: &str, rows: usize, bytes: usize) -> bool {
+        match self.jobs.get_mut(id) {
+            Some(job) if job.status == ExportStatus::Running => {
+                job.rows += rows;
+                job.bytes += bytes;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Marks a running job as done, or as failed if there's an error. The
+    /// file of a failed job is removed. Jobs that timed out are already
+    /// finished, and are left as they are.
+    pub fn finish(&mut self, id: &str, res: Result<(), Error>) {
+        let job = match self.jobs.get_mut(id) {
+            Some(job) if job.status == ExportStatus::Running => job,
+            _ => return,
+        };
+        self.running = self.running.saturating_sub(1);
+        job.finished = Some(SystemTime::now());
+
+        match res {
+            Ok(()) => {
+                job.status = ExportStatus::Done;
+            },
+            Err(err) => {
+                job.status = ExportStatus::Failed;
+                job.error = Some(err.to_string());
+
+                if job.path.exists() {
+                    if let Err(err) = fs::remove_file(&job.path) {
+                        warn!("Could not remove export file {:?}: {}", job.path, err);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Fails jobs that have been running for longer than `timeout`, which
+    /// frees their running slots, and removes jobs that finished more than
+    /// `ttl` ago, along with their files.
+    pub fn cleanup(&mut self) {
+        let ttl = self.config.ttl;
+        let timeout = self.config.timeout;
+        let now = SystemTime::now();
+
+        let timed_out: Vec<String> = self.jobs.values()
+            .filter(|job| {
+                job.status == ExportStatus::Running &&
+                    job.started
+                        .and_then(|started| now.duration_since(started).ok())
+                        .map(|age| age > timeout)
+                        .unwrap_or(false)
+            })
+            .map(|job| job.id.clone())
+            .collect();
+
+        for id in timed_out {
+            warn!("Export {} timed out", id);
+            self.finish(&id, Err(format_err!("Export timed out after {} seconds", timeout.as_secs())));
+        }
+
+        let expired: Vec<String> = self.jobs.values()
+            .filter(|job| {
+                job.finished
+                    .and_then(|finished| now.duration_since(finished).ok())
+                    .map(|age| age > ttl)
+                    .unwrap_or(false)
+            })
+            .map(|job| job.id.clone())
+            .collect();
+
+        for id in expired {
+            if let Some(job) = self.jobs.remove(&id) {
+                if job.path.exists() {
+                    if let Err(err) = fs::remove_file(&job.path) {
+                        warn!("Could not remove export file {:?}: {}", job.path, err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use failure::format_err;
+
+    #[test]
+    fn test_export_jobs() {
+        let dir = std::env::temp_dir().join(format!("tesseract-exports-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("export-old.csv"), "a,b\n").unwrap();
+        fs::write(dir.join("other.csv"), "a,b\n").unwrap();
+
+        let mut exports = ExportJobs::new(ExportConfig {
+            dir: dir.clone(),
+            max_running: 1,
+            max_queued: 10,
+            ttl: Duration::from_secs(0),
+            timeout: Duration::from_secs(3600),
+        }).unwrap();
+
+        assert!(!dir.join("export-old.csv").exists());
+        assert!(dir.join("other.csv").exists());
+
+        let a = exports.add("sales".into(), "csv".into(), FormatType::Csv, 0, "select 1".into(), vec![]).unwrap();
+        let b = exports.add("sales".into(), "jsonrecords".into(), FormatType::JsonRecords, 0, "select 2".into(), vec![]).unwrap();
+        assert!(exports.get(&b).unwrap().path.to_str().unwrap().ends_with(".json"));
+
+        // only one job runs at a time
+        let task = exports.next_task().unwrap();
+        assert_eq!(task.id, a);
+        assert!(exports.next_task().is_none());
+        assert_eq!(exports.get(&a).unwrap().status, ExportStatus::Running);
+        assert_eq!(exports.get(&b).unwrap().status, ExportStatus::Queued);
+
+        fs::write(&task.path, "a,b\n").unwrap();
+        exports.add_progress(&a, 1, 4);
+        exports.finish(&a, Ok(()));
+        assert_eq!(exports.get(&a).unwrap().status, ExportStatus::Done);
+        assert_eq!(exports.get(&a).unwrap().rows, 1);
+
+        let task = exports.next_task().unwrap();
+        assert_eq!(task.id, b);
+        exports.finish(&b, Err(format_err!("db error")));
+        assert_eq!(exports.get(&b).unwrap().status, ExportStatus::Failed);
+        assert_eq!(exports.get(&b).unwrap().error, Some("db error".to_owned()));
+
+        // finished jobs expire immediately with a ttl of 0
+        std::thread::sleep(Duration::from_millis(10));
+        exports.cleanup();
+        assert!(exports.get(&a).is_none());
+        assert!(exports.get(&b).is_none());
+        assert!(!task.path.exists());
+        assert!(!dir.join(format!("export-{}.csv", a)).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_export_limits() {
+        let dir = std::env::temp_dir().join(format!("tesseract-exports-limits-test-{}", std::process::id()));
+
+        let mut exports = ExportJobs::new(ExportConfig {
+            dir: dir.clone(),
+            max_running: 1,
+            max_queued: 1,
+            ttl: Duration::from_secs(3600),
+            timeout: Duration::from_secs(0),
+        }).unwrap();
+
+        let add = |exports: &mut ExportJobs| {
+            exports.add("sales".into(), "csv".into(), FormatType::Csv, 0, "select 1".into(), vec![])
+        };
+
+        // one job may wait in the queue, besides the running ones
+        let a = add(&mut exports).unwrap();
+        assert!(add(&mut exports).is_err());
+        let task = exports.next_task().unwrap();
+        assert_eq!(task.id, a);
+        let b = add(&mut exports).unwrap();
+        assert!(exports.add_progress(&a, 1, 4));
+
+        // the running job times out, which frees its slot
+        std::thread::sleep(Duration::from_millis(10));
+        exports.cleanup();
+        let job = exports.get(&a).unwrap();
+        assert_eq!(job.status, ExportStatus::Failed);
+        assert_eq!(job.error, Some("Export timed out after 0 seconds".to_owned()));
+        assert!(!exports.add_progress(&a, 1, 4));
+
+        // its query finishing later doesn't change it, nor the running count
+        exports.finish(&a, Ok(()));
+        assert_eq!(exports.get(&a).unwrap().status, ExportStatus::Failed);
+        assert_eq!(exports.next_task().unwrap().id, b);
+        assert!(exports.next_task().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
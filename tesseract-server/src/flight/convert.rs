@@ -0,0 +1,231 @@
+//! Conversions from cube metadata and `DataFrame`s to Arrow schemas and
+//! record batches, and their IPC encoding in Flight messages.
+
+use arrow_array::{
+    ArrayRef, RecordBatch,
+    Float32Array, Float64Array, Int16Array, Int32Array, Int64Array, Int8Array,
+    StringArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow_ipc::writer::{write_message, DictionaryTracker, IpcDataGenerator, IpcWriteOptions};
+use arrow_schema::{ArrowError, DataType, Field, Schema as ArrowSchema};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tesseract_core::{ColumnData, DataFrame};
+use tesseract_core::names::LevelName;
+use tesseract_core::schema::metadata::CubeMetadata;
+
+use super::proto::FlightData;
+
+
+/// The schema of a cube: a field for each level, named by its full name,
+/// and one for each measure. The kind of each field and its annotations are
+/// in the field metadata.
+pub fn cube_schema(cube: &CubeMetadata) -> ArrowSchema {
+    let mut fields = vec![];
+
+    for dimension in &cube.dimensions {
+        for hierarchy in &dimension.hierarchies {
+            for level in &hierarchy.levels {
+                let name = LevelName::new(&dimension.name, &hierarchy.name, &level.name);
+                let metadata = vec![
+                    ("tesseract.kind".to_owned(), "level".to_owned()),
+                    ("tesseract.annotations".to_owned(), to_json(&level.annotations)),
+                ];
+                fields.push(
+                    Field::new(name.to_string(), DataType::Utf8, true)
+                        .with_metadata(metadata.into_iter().collect())
+                );
+            }
+        }
+    }
+
+    for measure in &cube.measures {
+        let data_type = if measure.aggregator.name == "count" {
+            DataType::Int64
+        } else {
+            DataType::Float64
+        };
+        let metadata = vec![
+            ("tesseract.kind".to_owned(), "measure".to_owned()),
+            ("tesseract.aggregator".to_owned(), measure.aggregator.name.clone()),
+            ("tesseract.annotations".to_owned(), to_json(&measure.annotations)),
+        ];
+        fields.push(
+            Field::new(measure.name.clone(), data_type, true)
+                .with_metadata(metadata.into_iter().collect())
+        );
+    }
+
+    let metadata: HashMap<_, _> = vec![
+        ("tesseract.cube".to_owned(), cube.name.clone()),
+        ("tesseract.min_auth_level".to_owned(), cube.min_auth_level.to_string()),
+        ("tesseract.annotations".to_owned(), to_json(&cube.annotations)),
+    ].into_iter().collect();
+
+    ArrowSchema::new_with_metadata(fields, metadata)
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+
+/// The schema of a query result with no rows to take types from.
+pub fn text_schema(headers: &[String]) -> ArrowSchema {
+    ArrowSchema::new(
+        headers.iter()
+            .map(|header| Field::new(header.clone(), DataType::Utf8, true))
+            .collect::<Vec<_>>()
+    )
+}
+
+
+/// Converts a chunk of a query result to a record batch. Columns are named
+/// by `headers`; their types come from the `DataFrame`.
+pub fn record_batch(headers: &[String], df: DataFrame) -> Result<RecordBatch, ArrowError> {
+    if headers.len() != df.columns.len() {
+        return Err(ArrowError::SchemaError(format!(
+            "{} headers for {} columns", headers.len(), df.columns.len()
+        )));
+    }
+
+    let (fields, arrays): (Vec<_>, Vec<_>) = headers.iter()
+        .zip(df.columns)
+        .map(|(header, column)| {
+            let (array, nullable) = column_array(column.column_data);
+            let field = Field::new(header.clone(), array.data_type().clone(), nullable);
+            (field, array)
+        })
+        .unzip();
+
+    RecordBatch::try_new(Arc::new(ArrowSchema::new(fields)), arrays)
+}
+
+/// The array for a column, and whether the column is nullable.
+fn column_array(column_data: ColumnData) -> (ArrayRef, bool) {
+    match column_data {
+        ColumnData::Int8(ns) =>    (Arc::new(Int8Array::from(ns)), false),
+        ColumnData::Int16(ns) =>   (Arc::new(Int16Array::from(ns)), false),
+        ColumnData::Int32(ns) =>   (Arc::new(Int32Array::from(ns)), false),
+        ColumnData::Int64(ns) =>   (Arc::new(Int64Array::from(ns)), false),
+        ColumnData::UInt8(ns) =>   (Arc::new(UInt8Array::from(ns)), false),
+        ColumnData::UInt16(ns) =>  (Arc::new(UInt16Array::from(ns)), false),
+        ColumnData::UInt32(ns) =>  (Arc::new(UInt32Array::from(ns)), false),
+        ColumnData::UInt64(ns) =>  (Arc::new(UInt64Array::from(ns)), false),
+        ColumnData::Float32(ns) => (Arc::new(Float32Array::from(ns)), false),
+        ColumnData::Float64(ns) => (Arc::new(Float64Array::from(ns)), false),
+        ColumnData::Text(ss) =>    (Arc::new(StringArray::from(ss)), false),
+        ColumnData::NullableInt8(ns) =>    (Arc::new(Int8Array::from(ns)), true),
+        ColumnData::NullableInt16(ns) =>   (Arc::new(Int16Array::from(ns)), true),
+        ColumnData::NullableInt32(ns) =>   (Arc::new(Int32Array::from(ns)), true),
+        ColumnData::NullableInt64(ns) =>   (Arc::new(Int64Array::from(ns)), true),
+        ColumnData::NullableUInt8(ns) =>   (Arc::new(UInt8Array::from(ns)), true),
+        ColumnData::NullableUInt16(ns) =>  (Arc::new(UInt16Array::from(ns)), true),
+        ColumnData::NullableUInt32(ns) =>  (Arc::new(UInt32Array::from(ns)), true),
+        ColumnData::NullableUInt64(ns) =>  (Arc::new(UInt64Array::from(ns)), true),
+        ColumnData::NullableFloat32(ns) => (Arc::new(Float32Array::from(ns)), true),
+        ColumnData::NullableFloat64(ns) => (Arc::new(Float64Array::from(ns)), true),
+        ColumnData::NullableText(ss) =>    (Arc::new(StringArray::from(ss)), true),
+    }
+}
+
+
+/// A schema as an IPC encapsulated message, as `FlightInfo` and
+/// `SchemaResult` carry it.
+pub fn schema_bytes(schema: &ArrowSchema) -> Result<Vec<u8>, ArrowError> {
+    let options = IpcWriteOptions::default();
+    let mut tracker = DictionaryTracker::new(false);
+    let encoded = IpcDataGenerator::default()
+        .schema_to_bytes_with_dictionary_tracker(schema, &mut tracker, &options);
+    let mut bytes = vec![];
+    write_message(&mut bytes, encoded, &options)?;
+    Ok(bytes)
+}
+
+
+/// Encodes a stream of record batches as `FlightData`: the schema first,
+/// then each batch.
+pub struct FlightEncoder {
+    generator: IpcDataGenerator,
+    tracker: DictionaryTracker,
+    options: IpcWriteOptions,
+}
+
+impl FlightEncoder {
+    pub fn new() -> Self {
+        FlightEncoder {
+            generator: IpcDataGenerator::default(),
+            tracker: DictionaryTracker::new(false),
+            options: IpcWriteOptions::default(),
+        }
+    }
+
+    pub fn schema(&mut self, schema: &ArrowSchema) -> FlightData {
+        let encoded = self.generator
+            .schema_to_bytes_with_dictionary_tracker(schema, &mut self.tracker, &self.options);
+
+        FlightData {
+            data_header: encoded.ipc_message,
+            data_body: encoded.arrow_data,
+            ..Default::default()
+        }
+    }
+
+    /// The batch, preceded by any dictionaries it needs.
+    pub fn batch(&mut self, batch: &RecordBatch) -> Result<Vec<FlightData>, ArrowError> {
+        let (dictionaries, batch) = self.generator
+            .encoded_batch(batch, &mut self.tracker, &self.options)?;
+
+        Ok(dictionaries.into_iter()
+            .chain(Some(batch))
+            .map(|encoded| FlightData {
+                data_header: encoded.ipc_message,
+                data_body: encoded.arrow_data,
+                ..Default::default()
+            })
+            .collect())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::Array;
+    use arrow_ipc::reader::StreamReader;
+    use tesseract_core::Column;
+
+    #[test]
+    fn test_record_batch() {
+        let headers = vec!["Year".to_owned(), "Quantity".to_owned()];
+        let df = DataFrame::from_vec(vec![
+            Column::new("year".to_owned(), ColumnData::Text(vec!["2018".to_owned(), "2019".to_owned()])),
+            Column::new("quantity".to_owned(), ColumnData::NullableFloat64(vec![Some(1.5), None])),
+        ]);
+
+        let batch = record_batch(&headers, df).unwrap();
+        let schema = batch.schema();
+        assert_eq!(schema.field(0), &Field::new("Year", DataType::Utf8, false));
+        assert_eq!(schema.field(1), &Field::new("Quantity", DataType::Float64, true));
+        assert_eq!(batch.num_rows(), 2);
+        assert!(batch.column(1).is_null(1));
+
+        assert!(record_batch(&headers[..1], DataFrame::from_vec(vec![])).is_err());
+
+        // the encoded messages read back as an IPC stream
+        let mut encoder = FlightEncoder::new();
+        let mut stream = vec![];
+        let messages = Some(encoder.schema(&schema)).into_iter()
+            .chain(encoder.batch(&batch).unwrap());
+        for data in messages {
+            stream.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+            stream.extend_from_slice(&(data.data_header.len() as i32).to_le_bytes());
+            stream.extend_from_slice(&data.data_header);
+            stream.extend_from_slice(&data.data_body);
+        }
+        let batches: Vec<_> = StreamReader::try_new(&stream[..], None).unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches, vec![batch]);
+    }
+}
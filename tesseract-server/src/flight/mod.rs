@@ -0,0 +1,334 @@
+//! Arrow Flight (gRPC) endpoint, for clients that want query results as
+//! Arrow record batches.
+//!
+//! - `ListFlights` lists the cubes the user can access, and `GetFlightInfo`
+//!   and `GetSchema` describe a cube, given a path descriptor with its name.
+//!   Cube schemas have a field for each level and measure (see `convert`).
+//! - `DoGet` runs the query encoded in the ticket, a JSON object like
+//!   `{"cube": "sales", "query": {"measures": ["Quantity"]}}` where `query`
+//!   is the same as a JSON body sent to `/cubes/{cube}/aggregate` (see
+//!   `AggregateQueryBody`). The result is streamed as record batches.
+//!
+//! The JWT token is read from the `x-tesseract-jwt-token` request metadata.
+//!
+//! The gRPC server runs on its own thread and tokio runtime. Queries are
+//! handed to the actix system to run on the backend, and result chunks come
+//! back through bounded channels, so that the backend stream waits for
+//! slow clients instead of buffering the whole result.
+
+// tonic's `Status` is the error of every call
+#![allow(clippy::result_large_err)]
+
+mod convert;
+mod proto;
+mod service;
+
+use actix::Arbiter;
+use failure::{Error, format_err};
+use futures::sync::mpsc as mpsc01;
+use futures::{Future, Sink, Stream};
+use log::*;
+use serde_derive::Deserialize;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use tesseract_core::{Backend, DataFrame, Query as TsQuery, Schema};
+use tesseract_core::schema::metadata::CubeMetadata;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::codegen::BoxStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::auth::{user_auth_level, validate_web_token, X_TESSERACT_JWT_TOKEN};
+use crate::handlers::{query_sql, AggregateQueryBody};
+use crate::logic_layer::Cache;
+use self::convert::{cube_schema, record_batch, schema_bytes, text_schema, FlightEncoder};
+use self::proto::{
+    Criteria, DescriptorType, FlightData, FlightDescriptor, FlightInfo, SchemaResult, Ticket,
+};
+use self::service::FlightServiceServer;
+
+
+/// What the Flight service needs from the server's state.
+pub struct FlightState {
+    pub debug: bool,
+    pub backend: Box<dyn Backend + Sync + Send>,
+    pub schema: Arc<RwLock<Schema>>,
+    pub cache: Arc<RwLock<Cache>>,
+    pub jwt_secret: Option<String>,
+}
+
+
+/// Work to run in the actix system, where backend futures can run.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Result chunks, and encoded messages, buffered for each `DoGet`.
+const DO_GET_BUFFER: usize = 4;
+
+
+/// Binds the listener and starts the gRPC server on its own thread. Queries
+/// run once the actix system runs.
+pub fn start_server(addr: &str, state: FlightState) -> Result<(), Error> {
+    let socket_addr = addr.parse::<SocketAddr>()
+        .map_err(|_| format_err!("could not parse flight listen address {}", addr))?;
+    let listener = std::net::TcpListener::bind(socket_addr)
+        .map_err(|err| format_err!("cannot bind flight listener to {}: {}", addr, err))?;
+    listener.set_nonblocking(true)?;
+
+    let (jobs, job_queue) = mpsc01::unbounded::<Job>();
+    Arbiter::spawn(job_queue.for_each(|job| {
+        job();
+        Ok(())
+    }));
+
+    let handler = FlightHandler { state, jobs: Mutex::new(jobs) };
+
+    thread::Builder::new()
+        .name("flight".to_owned())
+        .spawn(move || {
+            let res = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .map_err(Error::from)
+                .and_then(|runtime| runtime.block_on(async move {
+                    let listener = tokio::net::TcpListener::from_std(listener)?;
+                    Server::builder()
+                        .add_service(FlightServiceServer::new(handler))
+                        .serve_with_incoming(TcpListenerStream::new(listener))
+                        .await?;
+                    Ok(())
+                }));
+
+            if let Err(err) = res {
+                error!("Flight server error: {}", err);
+            }
+        })?;
+
+    Ok(())
+}
+
+
+/// A query, as encoded in a `DoGet` ticket.
+#[derive(Debug, Clone, Deserialize)]
+struct FlightQuery {
+    cube: String,
+    #[serde(default)]
+    query: AggregateQueryBody,
+}
+
+
+/// Answers the Flight calls; see `service` for the routing.
+pub struct FlightHandler {
+    state: FlightState,
+    jobs: Mutex<mpsc01::UnboundedSender<Job>>,
+}
+
+impl FlightHandler {
+    fn list_flights(&self, req: Request<Criteria>) -> Result<Response<BoxStream<FlightInfo>>, Status> {
+        let token = request_token(&req);
+        let auth_level = user_auth_level(&self.state.jwt_secret, &token);
+        let cubes = self.state.schema.read().unwrap().metadata(auth_level).cubes;
+
+        let infos: Vec<_> = cubes.iter()
+            .map(|cube| flight_info(cube).map(Ok))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Response::new(Box::pin(tokio_stream::iter(infos))))
+    }
+
+    fn get_flight_info(&self, req: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        let cube = self.descriptor_cube(&req)?;
+        flight_info(&cube).map(Response::new)
+    }
+
+    fn get_schema(&self, req: Request<FlightDescriptor>) -> Result<Response<SchemaResult>, Status> {
+        let cube = self.descriptor_cube(&req)?;
+        let schema = schema_bytes(&cube_schema(&cube))
+            .map_err(|err| Status::internal(err.to_string()))?;
+
+        Ok(Response::new(SchemaResult { schema }))
+    }
+
+    fn do_get(&self, req: Request<Ticket>) -> Result<Response<BoxStream<FlightData>>, Status> {
+        let token = request_token(&req);
+        let flight_query: FlightQuery = serde_json::from_slice(&req.get_ref().ticket)
+            .map_err(|err| Status::invalid_argument(format!("Invalid ticket: {}", err)))?;
+
+        let (sql, headers) = self.query_sql(&token, flight_query)?;
+
+        // Results are sent back from the actix system, chunk by chunk, and
+        // the backend stream waits while the channel is full. It stops when
+        // the client goes away and the receivers are dropped.
+        let (df_tx, df_rx) = mpsc01::channel(DO_GET_BUFFER);
+        let backend = self.state.backend.box_clone();
+        let job: Job = Box::new(move || {
+            Arbiter::spawn(
+                backend.exec_sql_stream(sql)
+                    .then(|res| Ok::<_, ()>(res.and_then(|df_res| df_res)))
+                    .forward(df_tx.sink_map_err(|_| ()))
+                    .map(|_| ())
+            );
+        });
+        self.jobs.lock().unwrap()
+            .unbounded_send(job)
+            .map_err(|_| Status::unavailable("Server is shutting down"))?;
+
+        // the futures 0.1 receiver can only be waited on from a thread
+        let (data_tx, data_rx) = mpsc::channel(DO_GET_BUFFER);
+        let debug = self.state.debug;
+        tokio::task::spawn_blocking(move || send_results(headers, df_rx, data_tx, debug));
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(data_rx))))
+    }
+
+    /// The cube named by a path descriptor, if the user can access it.
+    fn descriptor_cube(&self, req: &Request<FlightDescriptor>) -> Result<CubeMetadata, Status> {
+        let descriptor = req.get_ref();
+        if descriptor.r#type != DescriptorType::Path as i32 || descriptor.path.len() != 1 {
+            return Err(Status::invalid_argument(
+                "Descriptors must be a path with the name of a cube; queries are run with DoGet"
+            ));
+        }
+
+        let cube_name = &descriptor.path[0];
+        let cube = self.state.schema.read().unwrap()
+            .cube_metadata(cube_name)
+            .ok_or_else(|| Status::not_found(format!("Cube {} not found", cube_name)))?;
+
+        if !validate_web_token(&self.state.jwt_secret, &request_token(req), cube.min_auth_level) {
+            return Err(Status::permission_denied("This cube is not public"));
+        }
+
+        Ok(cube)
+    }
+
+    /// Turns a ticket's query into SQL and result headers, if the user can
+    /// access its cube.
+    fn query_sql(&self, token: &str, flight_query: FlightQuery) -> Result<(String, Vec<String>), Status> {
+        let FlightQuery { cube, query } = flight_query;
+
+        let schema = self.state.schema.read().unwrap();
+        let cube_obj = schema.get_cube_by_name(&cube)
+            .map_err(|err| Status::not_found(err.to_string()))?;
+
+        if !validate_web_token(&self.state.jwt_secret, token, cube_obj.min_auth_level) {
+            return Err(Status::permission_denied("This cube is not public"));
+        }
+
+        let ts_query: TsQuery = query.try_into()
+            .map_err(|err: Error| Status::invalid_argument(err.to_string()))?;
+
+        let cache = self.state.cache.read().unwrap();
+        query_sql(&schema, &cache, &*self.state.backend, &cube, &ts_query)
+            .map_err(|err| Status::invalid_argument(err.to_string()))
+    }
+}
+
+
+fn request_token<T>(req: &Request<T>) -> String {
+    req.metadata()
+        .get(X_TESSERACT_JWT_TOKEN)
+        .and_then(|token| token.to_str().ok())
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// Cubes are flights named by a path; they have no endpoints, since their
+/// data is only fetched through queries.
+fn flight_info(cube: &CubeMetadata) -> Result<FlightInfo, Status> {
+    let schema = schema_bytes(&cube_schema(cube))
+        .map_err(|err| Status::internal(err.to_string()))?;
+
+    Ok(FlightInfo {
+        schema,
+        flight_descriptor: Some(FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            path: vec![cube.name.clone()],
+            ..Default::default()
+        }),
+        total_records: -1,
+        total_bytes: -1,
+        ..Default::default()
+    })
+}
+
+
+/// Encodes result chunks as they arrive, waiting while the client is
+/// behind. The schema is taken from the first chunk, and sent before its
+/// batch.
+fn send_results(
+    headers: Vec<String>,
+    dfs: mpsc01::Receiver<Result<DataFrame, Error>>,
+    data: mpsc::Sender<Result<FlightData, Status>>,
+    debug: bool,
+    )
+{
+    let mut encoder = FlightEncoder::new();
+    let mut has_schema = false;
+
+    // the receiver never fails, it only ends
+    for df in dfs.wait().filter_map(Result::ok) {
+        let messages = df
+            .map_err(|err| {
+                error!("Flight query error: {}", err);
+                if debug {
+                    Status::internal(err.to_string())
+                } else {
+                    Status::internal("Internal Server Error 1010")
+                }
+            })
+            .and_then(|df| {
+                let batch = record_batch(&headers, df)
+                    .map_err(|err| Status::internal(err.to_string()))?;
+
+                let mut messages = vec![];
+                if !has_schema {
+                    messages.push(encoder.schema(&batch.schema()));
+                    has_schema = true;
+                }
+                messages.extend(
+                    encoder.batch(&batch).map_err(|err| Status::internal(err.to_string()))?
+                );
+                Ok(messages)
+            });
+
+        match messages {
+            Ok(messages) => {
+                for message in messages {
+                    if data.blocking_send(Ok(message)).is_err() {
+                        return;
+                    }
+                }
+            },
+            Err(status) => {
+                let _ = data.blocking_send(Err(status));
+                return;
+            },
+        }
+    }
+
+    if !has_schema {
+        let _ = data.blocking_send(Ok(encoder.schema(&text_schema(&headers))));
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flight_query() {
+        let ticket = br#"{"cube": "sales", "query": {"drilldowns": [{"dimension": "Year", "hierarchy": "Year", "level": "Year"}], "measures": ["Quantity"]}}"#;
+        let flight_query: FlightQuery = serde_json::from_slice(ticket).unwrap();
+        assert_eq!(flight_query.cube, "sales");
+
+        let ts_query: TsQuery = flight_query.query.try_into().unwrap();
+        assert_eq!(ts_query.drilldowns.len(), 1);
+        assert_eq!(ts_query.measures.len(), 1);
+
+        assert!(serde_json::from_slice::<FlightQuery>(br#"{"query": {}}"#).is_err());
+    }
+}
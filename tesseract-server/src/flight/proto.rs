@@ -0,0 +1,88 @@
+//! Messages of the Arrow Flight protocol (`arrow.flight.protocol` in
+//! Flight.proto), for the calls that are served.
+//!
+//! They're written out by hand, with the tags of the `.proto` file, so that
+//! building doesn't need `protoc`.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Criteria {
+    #[prost(bytes="vec", tag="1")]
+    pub expression: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightDescriptor {
+    #[prost(enumeration="DescriptorType", tag="1")]
+    pub r#type: i32,
+    #[prost(bytes="vec", tag="2")]
+    pub cmd: Vec<u8>,
+    #[prost(string, repeated, tag="3")]
+    pub path: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum DescriptorType {
+    Unknown = 0,
+    Path = 1,
+    Cmd = 2,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightInfo {
+    /// An IPC encapsulated `Schema` message
+    #[prost(bytes="vec", tag="1")]
+    pub schema: Vec<u8>,
+    #[prost(message, optional, tag="2")]
+    pub flight_descriptor: Option<FlightDescriptor>,
+    #[prost(message, repeated, tag="3")]
+    pub endpoint: Vec<FlightEndpoint>,
+    #[prost(int64, tag="4")]
+    pub total_records: i64,
+    #[prost(int64, tag="5")]
+    pub total_bytes: i64,
+    #[prost(bool, tag="6")]
+    pub ordered: bool,
+    #[prost(bytes="vec", tag="7")]
+    pub app_metadata: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightEndpoint {
+    #[prost(message, optional, tag="1")]
+    pub ticket: Option<Ticket>,
+    #[prost(message, repeated, tag="2")]
+    pub location: Vec<Location>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Location {
+    #[prost(string, tag="1")]
+    pub uri: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Ticket {
+    #[prost(bytes="vec", tag="1")]
+    pub ticket: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SchemaResult {
+    /// An IPC encapsulated `Schema` message
+    #[prost(bytes="vec", tag="1")]
+    pub schema: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FlightData {
+    #[prost(message, optional, tag="1")]
+    pub flight_descriptor: Option<FlightDescriptor>,
+    /// A flatbuffer IPC `Message`
+    #[prost(bytes="vec", tag="2")]
+    pub data_header: Vec<u8>,
+    #[prost(bytes="vec", tag="3")]
+    pub app_metadata: Vec<u8>,
+    #[prost(bytes="vec", tag="1000")]
+    pub data_body: Vec<u8>,
+}
@@ -0,0 +1,127 @@
+//! gRPC routing of `arrow.flight.protocol.FlightService`, as `tonic-build`
+//! would generate it for the served calls. Other calls get `Unimplemented`.
+
+use std::convert::Infallible;
+use std::future;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, BoxStream, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::{Code, Request, Response, Status};
+
+use super::FlightHandler;
+use super::proto::{Criteria, FlightData, FlightDescriptor, FlightInfo, SchemaResult, Ticket};
+
+
+#[derive(Clone)]
+pub struct FlightServiceServer {
+    handler: Arc<FlightHandler>,
+}
+
+impl FlightServiceServer {
+    pub fn new(handler: FlightHandler) -> Self {
+        FlightServiceServer { handler: Arc::new(handler) }
+    }
+}
+
+impl NamedService for FlightServiceServer {
+    const NAME: &'static str = "arrow.flight.protocol.FlightService";
+}
+
+impl<B> Service<http::Request<B>> for FlightServiceServer
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let handler = self.handler.clone();
+
+        match req.uri().path() {
+            "/arrow.flight.protocol.FlightService/ListFlights" => {
+                Box::pin(async move {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.server_streaming(ListFlights(handler), req).await)
+                })
+            },
+            "/arrow.flight.protocol.FlightService/GetFlightInfo" => {
+                Box::pin(async move {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.unary(GetFlightInfo(handler), req).await)
+                })
+            },
+            "/arrow.flight.protocol.FlightService/GetSchema" => {
+                Box::pin(async move {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.unary(GetSchema(handler), req).await)
+                })
+            },
+            "/arrow.flight.protocol.FlightService/DoGet" => {
+                Box::pin(async move {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.server_streaming(DoGet(handler), req).await)
+                })
+            },
+            _ => {
+                Box::pin(future::ready(Ok(
+                    Status::new(Code::Unimplemented, "Not supported by tesseract").into_http()
+                )))
+            },
+        }
+    }
+}
+
+
+struct ListFlights(Arc<FlightHandler>);
+
+impl ServerStreamingService<Criteria> for ListFlights {
+    type Response = FlightInfo;
+    type ResponseStream = BoxStream<FlightInfo>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, req: Request<Criteria>) -> Self::Future {
+        Box::pin(future::ready(self.0.list_flights(req)))
+    }
+}
+
+struct GetFlightInfo(Arc<FlightHandler>);
+
+impl UnaryService<FlightDescriptor> for GetFlightInfo {
+    type Response = FlightInfo;
+    type Future = BoxFuture<Response<FlightInfo>, Status>;
+
+    fn call(&mut self, req: Request<FlightDescriptor>) -> Self::Future {
+        Box::pin(future::ready(self.0.get_flight_info(req)))
+    }
+}
+
+struct GetSchema(Arc<FlightHandler>);
+
+impl UnaryService<FlightDescriptor> for GetSchema {
+    type Response = SchemaResult;
+    type Future = BoxFuture<Response<SchemaResult>, Status>;
+
+    fn call(&mut self, req: Request<FlightDescriptor>) -> Self::Future {
+        Box::pin(future::ready(self.0.get_schema(req)))
+    }
+}
+
+struct DoGet(Arc<FlightHandler>);
+
+impl ServerStreamingService<Ticket> for DoGet {
+    type Response = FlightData;
+    type ResponseStream = BoxStream<FlightData>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, req: Request<Ticket>) -> Self::Future {
+        Box::pin(future::ready(self.0.do_get(req)))
+    }
+}
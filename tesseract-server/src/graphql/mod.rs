@@ -0,0 +1,12 @@
+//! GraphQL interface to the cubes, generated from the schema.
+//!
+//! Each cube is a field of the `Query` type, which takes drilldowns, cuts
+//! and measures as arguments and returns a list of rows. Row fields are the
+//! cube's levels and measures, typed from the level key types and measure
+//! aggregators.
+
+mod parse;
+mod schema;
+
+pub use self::parse::{parse_document, Field, Operation, Value};
+pub use self::schema::{GraphqlSchema, substitute_variables};
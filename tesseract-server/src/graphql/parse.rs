@@ -0,0 +1,495 @@
+//! Parser for GraphQL query documents.
+//!
+//! Only the parts of the language that are needed to query cubes are
+//! supported: query operations with variables, fields with aliases and
+//! arguments, and nested selections. Fragments, directives, mutations and
+//! subscriptions are rejected.
+
+use failure::{Error, bail, format_err};
+use std::iter::Peekable;
+use std::str::Chars;
+
+
+/// An input value. Variables are replaced before a field is executed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Variable(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Builds a value from a JSON variable. Strings stay strings, and are
+    /// accepted wherever an enum value is expected.
+    pub fn from_json(json: &serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Boolean(*b),
+            serde_json::Value::Number(n) => {
+                match n.as_i64() {
+                    Some(i) => Value::Int(i),
+                    None => Value::Float(n.as_f64().unwrap_or(0.0)),
+                }
+            },
+            serde_json::Value::String(s) => Value::String(s.clone()),
+            serde_json::Value::Array(items) => {
+                Value::List(items.iter().map(Value::from_json).collect())
+            },
+            serde_json::Value::Object(fields) => {
+                Value::Object(fields.iter().map(|(k, v)| (k.clone(), Value::from_json(v))).collect())
+            },
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+    pub selection: Vec<Field>,
+}
+
+impl Field {
+    /// The key of this field in the response.
+    pub fn response_key(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+
+    pub fn argument(&self, name: &str) -> Option<&Value> {
+        self.arguments.iter()
+            .find(|(arg_name, _)| arg_name == name)
+            .map(|(_, value)| value)
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub name: Option<String>,
+    /// Variable names and their default values
+    pub variables: Vec<(String, Option<Value>)>,
+    pub selection: Vec<Field>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+
+fn tokenize(src: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = src.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            // commas and the byte order mark are insignificant
+            ' ' | '\t' | '\n' | '\r' | ',' | '\u{feff}' => {
+                chars.next();
+            },
+            '#' => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' || c == '\r' {
+                        break;
+                    }
+                }
+            },
+            '!' | '$' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' | '&' => {
+                chars.next();
+                tokens.push(Token::Punct(c));
+            },
+            '.' => {
+                let dots: String = (0..3).filter_map(|_| chars.next()).collect();
+                if dots != "..." {
+                    bail!("Unexpected `{}`", dots);
+                }
+                tokens.push(Token::Spread);
+            },
+            '"' => {
+                chars.next();
+                tokens.push(Token::Str(tokenize_string(&mut chars)?));
+            },
+            '-' | '0'..='9' => {
+                tokens.push(tokenize_number(&mut chars)?);
+            },
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '_' || c.is_ascii_alphanumeric() {
+                        name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Name(name));
+            },
+            c => bail!("Unexpected character `{}`", c),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn tokenize_string(chars: &mut Peekable<Chars>) -> Result<String, Error> {
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        if chars.peek() == Some(&'"') {
+            bail!("Block strings are not supported");
+        }
+        return Ok(String::new());
+    }
+
+    let mut s = String::new();
+
+    loop {
+        match chars.next() {
+            None | Some('\n') | Some('\r') => bail!("Unterminated string"),
+            Some('"') => return Ok(s),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| chars.next()).collect();
+                        u32::from_str_radix(&hex, 16).ok()
+                            .and_then(std::char::from_u32)
+                            .ok_or_else(|| format_err!("Invalid unicode escape `\\u{}`", hex))?
+                    },
+                    _ => bail!("Invalid escape in string"),
+                };
+                s.push(escaped);
+            },
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+fn tokenize_number(chars: &mut Peekable<Chars>) -> Result<Token, Error> {
+    let mut num = String::new();
+    let mut is_float = false;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '0'..='9' | '-' | '+' => num.push(c),
+            '.' | 'e' | 'E' => {
+                is_float = true;
+                num.push(c);
+            },
+            _ => break,
+        }
+        chars.next();
+    }
+
+    if is_float {
+        num.parse().map(Token::Float).map_err(|_| format_err!("Invalid number `{}`", num))
+    } else {
+        num.parse().map(Token::Int).map_err(|_| format_err!("Invalid number `{}`", num))
+    }
+}
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, Error> {
+        let token = self.tokens.get(self.pos)
+            .cloned()
+            .ok_or_else(|| format_err!("Unexpected end of document"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), Error> {
+        match self.next()? {
+            Token::Punct(p) if p == c => Ok(()),
+            token => bail!("Expected `{}`, found {:?}", c, token),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => bail!("Expected a name, found {:?}", token),
+        }
+    }
+
+    fn operation(&mut self) -> Result<Operation, Error> {
+        if self.is_punct('{') {
+            return Ok(Operation {
+                name: None,
+                variables: vec![],
+                selection: self.selection_set()?,
+            });
+        }
+
+        match self.name()?.as_str() {
+            "query" => (),
+            "mutation" | "subscription" => bail!("Only queries are supported"),
+            "fragment" => bail!("Fragments are not supported"),
+            other => bail!("Unexpected `{}`", other),
+        }
+
+        let name = match self.peek() {
+            Some(Token::Name(_)) => Some(self.name()?),
+            _ => None,
+        };
+
+        let mut variables = vec![];
+        if self.is_punct('(') {
+            self.next()?;
+            while !self.is_punct(')') {
+                self.expect_punct('$')?;
+                let var_name = self.name()?;
+                self.expect_punct(':')?;
+                self.var_type()?;
+
+                let default = if self.is_punct('=') {
+                    self.next()?;
+                    Some(self.value(true)?)
+                } else {
+                    None
+                };
+
+                variables.push((var_name, default));
+            }
+            self.next()?;
+        }
+
+        if self.is_punct('@') {
+            bail!("Directives are not supported");
+        }
+
+        Ok(Operation {
+            name,
+            variables,
+            selection: self.selection_set()?,
+        })
+    }
+
+    /// Types of variables aren't checked; arguments are coerced when
+    /// they are read instead.
+    fn var_type(&mut self) -> Result<(), Error> {
+        if self.is_punct('[') {
+            self.next()?;
+            self.var_type()?;
+            self.expect_punct(']')?;
+        } else {
+            self.name()?;
+        }
+
+        if self.is_punct('!') {
+            self.next()?;
+        }
+
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Field>, Error> {
+        self.expect_punct('{')?;
+
+        let mut fields = vec![];
+        while !self.is_punct('}') {
+            if self.peek() == Some(&Token::Spread) {
+                bail!("Fragments are not supported");
+            }
+            fields.push(self.field()?);
+        }
+        self.next()?;
+
+        if fields.is_empty() {
+            bail!("Selection sets cannot be empty");
+        }
+
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, Error> {
+        let mut alias = None;
+        let mut name = self.name()?;
+
+        if self.is_punct(':') {
+            self.next()?;
+            alias = Some(name);
+            name = self.name()?;
+        }
+
+        let mut arguments = vec![];
+        if self.is_punct('(') {
+            self.next()?;
+            while !self.is_punct(')') {
+                let arg_name = self.name()?;
+                self.expect_punct(':')?;
+                arguments.push((arg_name, self.value(false)?));
+            }
+            self.next()?;
+        }
+
+        if self.is_punct('@') {
+            bail!("Directives are not supported");
+        }
+
+        let selection = if self.is_punct('{') {
+            self.selection_set()?
+        } else {
+            vec![]
+        };
+
+        Ok(Field { alias, name, arguments, selection })
+    }
+
+    fn value(&mut self, is_const: bool) -> Result<Value, Error> {
+        let value = match self.next()? {
+            Token::Punct('$') => {
+                if is_const {
+                    bail!("Variables cannot be used in default values");
+                }
+                Value::Variable(self.name()?)
+            },
+            Token::Int(i) => Value::Int(i),
+            Token::Float(f) => Value::Float(f),
+            Token::Str(s) => Value::String(s),
+            Token::Name(name) => {
+                match name.as_str() {
+                    "true" => Value::Boolean(true),
+                    "false" => Value::Boolean(false),
+                    "null" => Value::Null,
+                    _ => Value::Enum(name),
+                }
+            },
+            Token::Punct('[') => {
+                let mut items = vec![];
+                while !self.is_punct(']') {
+                    items.push(self.value(is_const)?);
+                }
+                self.next()?;
+                Value::List(items)
+            },
+            Token::Punct('{') => {
+                let mut fields = vec![];
+                while !self.is_punct('}') {
+                    let field_name = self.name()?;
+                    self.expect_punct(':')?;
+                    fields.push((field_name, self.value(is_const)?));
+                }
+                self.next()?;
+                Value::Object(fields)
+            },
+            token => bail!("Expected a value, found {:?}", token),
+        };
+
+        Ok(value)
+    }
+}
+
+
+/// Parses a GraphQL document into its operations.
+pub fn parse_document(src: &str) -> Result<Vec<Operation>, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+
+    let mut operations = vec![];
+    while parser.peek().is_some() {
+        operations.push(parser.operation()?);
+    }
+
+    if operations.is_empty() {
+        bail!("Document has no operations");
+    }
+
+    Ok(operations)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_document() {
+        let doc = r#"
+            # comment
+            query Sales($year: String = "2017", $mea: [SalesMeasure!]!) {
+                byYear: sales(
+                    drilldowns: [Year],
+                    cuts: [{level: Year, members: [$year, "2018"], exclude: false}],
+                    measures: $mea,
+                ) {
+                    Year
+                    total: Quantity
+                }
+                __typename
+            }
+        "#;
+        let ops = parse_document(doc).unwrap();
+        assert_eq!(ops.len(), 1);
+
+        let op = &ops[0];
+        assert_eq!(op.name, Some("Sales".to_owned()));
+        assert_eq!(op.variables, vec![
+            ("year".to_owned(), Some(Value::String("2017".to_owned()))),
+            ("mea".to_owned(), None),
+        ]);
+
+        let field = &op.selection[0];
+        assert_eq!(field.response_key(), "byYear");
+        assert_eq!(field.name, "sales");
+        assert_eq!(field.argument("drilldowns"), Some(&Value::List(vec![Value::Enum("Year".to_owned())])));
+        assert_eq!(field.argument("cuts"), Some(&Value::List(vec![Value::Object(vec![
+            ("level".to_owned(), Value::Enum("Year".to_owned())),
+            ("members".to_owned(), Value::List(vec![
+                Value::Variable("year".to_owned()),
+                Value::String("2018".to_owned()),
+            ])),
+            ("exclude".to_owned(), Value::Boolean(false)),
+        ])])));
+        assert_eq!(field.argument("measures"), Some(&Value::Variable("mea".to_owned())));
+        assert_eq!(field.selection[1].response_key(), "total");
+        assert_eq!(field.selection[1].name, "Quantity");
+        assert_eq!(op.selection[1].name, "__typename");
+
+        let ops = parse_document(r#"{ sales(limit: -1.5e2, name: "a\"bé") { Year } }"#).unwrap();
+        assert_eq!(ops[0].selection[0].argument("limit"), Some(&Value::Float(-150.0)));
+        assert_eq!(ops[0].selection[0].argument("name"), Some(&Value::String("a\"bé".to_owned())));
+
+        assert!(parse_document("mutation { sales { Year } }").is_err());
+        assert!(parse_document("{ sales { ...rows } }").is_err());
+        assert!(parse_document("{ sales { Year @include(if: true) } }").is_err());
+        assert!(parse_document("{ sales { Year }").is_err());
+        assert!(parse_document("{ }").is_err());
+        assert!(parse_document("").is_err());
+    }
+}
@@ -0,0 +1,699 @@
+use failure::{Error, bail, format_err};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::{HashMap, HashSet};
+use tesseract_core::{Query as TsQuery, DataFrame, ColumnData};
+use tesseract_core::names::{Cut, Drilldown, LevelName, Mask, Measure as MeasureName};
+use tesseract_core::query_ir::MemberType;
+use tesseract_core::schema::{Cube, Schema, Aggregator};
+
+use super::parse::{Field, Value};
+
+
+/// The GraphQL schema of all cubes, generated from the tesseract schema.
+/// Each cube is a field of the `Query` type.
+#[derive(Debug, Clone)]
+pub struct GraphqlSchema {
+    pub sdl: String,
+    cubes: HashMap<String, GraphqlCube>,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    Int,
+    Float,
+    String,
+}
+
+impl ScalarType {
+    fn name(self) -> &'static str {
+        match self {
+            ScalarType::Int => "Int",
+            ScalarType::Float => "Float",
+            ScalarType::String => "String",
+        }
+    }
+
+    /// Coerces a result value to this type, so that results match the
+    /// schema regardless of the database column types.
+    fn coerce(self, value: JsonValue) -> JsonValue {
+        match (self, value) {
+            (_, JsonValue::Null) => JsonValue::Null,
+            (ScalarType::String, JsonValue::String(s)) => JsonValue::String(s),
+            (ScalarType::String, value) => JsonValue::String(value.to_string()),
+            (ScalarType::Float, JsonValue::Number(n)) => JsonValue::Number(n),
+            (ScalarType::Float, JsonValue::String(s)) => {
+                s.parse::<f64>().ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(JsonValue::Number)
+                    .unwrap_or(JsonValue::Null)
+            },
+            (ScalarType::Int, JsonValue::Number(n)) => {
+                n.as_i64()
+                    .or_else(|| n.as_f64().map(|f| f.round() as i64))
+                    .map(JsonValue::from)
+                    .unwrap_or(JsonValue::Null)
+            },
+            (ScalarType::Int, JsonValue::String(s)) => {
+                s.parse::<i64>().ok()
+                    .map(JsonValue::from)
+                    .unwrap_or(JsonValue::Null)
+            },
+            _ => JsonValue::Null,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct GraphqlLevel {
+    /// Enum value, and field name of the level's label in the row type
+    name: String,
+    /// Field name of the level's key, if the level has a separate name column
+    id_name: Option<String>,
+    level_name: LevelName,
+    key_type: ScalarType,
+}
+
+
+#[derive(Debug, Clone)]
+struct GraphqlMeasure {
+    /// Enum value, and field name in the row type
+    name: String,
+    measure: String,
+    measure_type: ScalarType,
+}
+
+
+#[derive(Debug, Clone)]
+struct GraphqlCube {
+    cube: String,
+    type_name: String,
+    levels: Vec<GraphqlLevel>,
+    measures: Vec<GraphqlMeasure>,
+}
+
+
+/// Where a selected field of a row comes from.
+#[derive(Debug, Clone, PartialEq)]
+enum OutputSource {
+    Column(usize, ScalarType),
+    Typename(String),
+}
+
+
+/// A cube field resolved into a tesseract query, and how to read the
+/// selected fields from the result.
+#[derive(Debug, Clone)]
+pub struct CubeQuery {
+    pub cube: String,
+    pub query: TsQuery,
+    outputs: Vec<(String, OutputSource)>,
+}
+
+impl CubeQuery {
+    /// Turns a query result into the list of rows for the response.
+    pub fn rows(&self, df: &DataFrame) -> JsonValue {
+        let rows = (0..df.len())
+            .map(|row_idx| {
+                let mut row = Map::new();
+                for (key, source) in &self.outputs {
+                    let value = match source {
+                        OutputSource::Column(col_idx, scalar_type) => {
+                            let value = df.columns.get(*col_idx)
+                                .map(|col| column_value(&col.column_data, row_idx))
+                                .unwrap_or(JsonValue::Null);
+                            scalar_type.coerce(value)
+                        },
+                        OutputSource::Typename(name) => JsonValue::String(name.clone()),
+                    };
+                    row.insert(key.clone(), value);
+                }
+                JsonValue::Object(row)
+            })
+            .collect();
+
+        JsonValue::Array(rows)
+    }
+}
+
+
+impl GraphqlSchema {
+    pub fn from_schema(schema: &Schema) -> Self {
+        let mut cubes = HashMap::new();
+        let mut used_fields = HashSet::new();
+        let mut used_types: HashSet<String> = ["Query", "String", "Int", "Float", "Boolean", "ID"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let mut query_fields = vec![];
+        let mut types = vec![];
+
+        for cube in &schema.cubes {
+            let field_name = unique_name(graphql_name(&cube.name), &mut used_fields);
+            let type_name = unique_name(graphql_name(&cube.name), &mut used_types);
+            for suffix in &["Row", "Level", "Measure", "Cut"] {
+                used_types.insert(format!("{}{}", type_name, suffix));
+            }
+
+            let gql_cube = GraphqlCube::from_cube(cube, type_name);
+
+            query_fields.push(format!(
+                "  {}(drilldowns: [{t}Level!], cuts: [{t}Cut!], measures: [{t}Measure!]): [{t}Row!]",
+                field_name,
+                t = gql_cube.type_name,
+            ));
+            types.push(gql_cube.sdl());
+
+            cubes.insert(field_name, gql_cube);
+        }
+
+        let mut sdl = "type Query {\n".to_owned();
+        for field in query_fields {
+            sdl.push_str(&field);
+            sdl.push('\n');
+        }
+        sdl.push_str("}\n");
+        for t in types {
+            sdl.push('\n');
+            sdl.push_str(&t);
+        }
+
+        GraphqlSchema {
+            sdl,
+            cubes,
+        }
+    }
+
+    /// Resolves a field of the `Query` type into a tesseract query. Its
+    /// arguments must already have variables replaced.
+    pub fn cube_query(&self, field: &Field) -> Result<CubeQuery, Error> {
+        let cube = self.cubes.get(&field.name)
+            .ok_or_else(|| format_err!("Cannot query field `{}` on type `Query`", field.name))?;
+
+        cube.query(field)
+    }
+}
+
+
+impl GraphqlCube {
+    fn from_cube(cube: &Cube, type_name: String) -> Self {
+        let mut level_name_counts: HashMap<&str, usize> = HashMap::new();
+        for dim in &cube.dimensions {
+            for hier in &dim.hierarchies {
+                for level in &hier.levels {
+                    *level_name_counts.entry(&level.name).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // row fields of levels and measures share one namespace
+        let mut used = HashSet::new();
+        used.insert("__typename".to_owned());
+
+        let mut levels = vec![];
+        for dim in &cube.dimensions {
+            for hier in &dim.hierarchies {
+                for level in &hier.levels {
+                    // levels are named by their level name if it is unique
+                    // in the cube, and with their dimension and hierarchy otherwise
+                    let full_name = if level_name_counts[level.name.as_str()] == 1 {
+                        level.name.clone()
+                    } else if dim.name == hier.name {
+                        format!("{} {}", dim.name, level.name)
+                    } else {
+                        format!("{} {} {}", dim.name, hier.name, level.name)
+                    };
+
+                    let name = unique_name(graphql_name(&full_name), &mut used);
+
+                    let key_type = match level.key_type {
+                        Some(MemberType::NonText) => ScalarType::Float,
+                        _ => ScalarType::String,
+                    };
+
+                    let id_name = if level.name_column.is_some() {
+                        Some(unique_name(format!("{}_ID", name), &mut used))
+                    } else {
+                        None
+                    };
+
+                    levels.push(GraphqlLevel {
+                        name,
+                        id_name,
+                        level_name: LevelName::new(dim.name.clone(), hier.name.clone(), level.name.clone()),
+                        key_type,
+                    });
+                }
+            }
+        }
+
+        let measures = cube.measures.iter()
+            .map(|measure| {
+                let measure_type = match measure.aggregator {
+                    Aggregator::Count => ScalarType::Int,
+                    _ => ScalarType::Float,
+                };
+
+                GraphqlMeasure {
+                    name: unique_name(graphql_name(&measure.name), &mut used),
+                    measure: measure.name.clone(),
+                    measure_type,
+                }
+            })
+            .collect();
+
+        GraphqlCube {
+            cube: cube.name.clone(),
+            type_name,
+            levels,
+            measures,
+        }
+    }
+
+    fn sdl(&self) -> String {
+        let t = &self.type_name;
+
+        let mut sdl = format!("type {}Row {{\n", t);
+        for level in &self.levels {
+            if let Some(ref id_name) = level.id_name {
+                sdl.push_str(&format!("  {}: {}\n", id_name, level.key_type.name()));
+                sdl.push_str(&format!("  {}: String\n", level.name));
+            } else {
+                sdl.push_str(&format!("  {}: {}\n", level.name, level.key_type.name()));
+            }
+        }
+        for measure in &self.measures {
+            sdl.push_str(&format!("  {}: {}\n", measure.name, measure.measure_type.name()));
+        }
+        sdl.push_str("}\n");
+
+        sdl.push_str(&format!("\nenum {}Level {{\n", t));
+        for level in &self.levels {
+            sdl.push_str(&format!("  {}\n", level.name));
+        }
+        sdl.push_str("}\n");
+
+        if !self.measures.is_empty() {
+            sdl.push_str(&format!("\nenum {}Measure {{\n", t));
+            for measure in &self.measures {
+                sdl.push_str(&format!("  {}\n", measure.name));
+            }
+            sdl.push_str("}\n");
+        }
+
+        sdl.push_str(&format!(
+            "\ninput {}Cut {{\n  level: {}Level!\n  members: [String!]!\n  exclude: Boolean\n}}\n",
+            t, t,
+        ));
+
+        sdl
+    }
+
+    /// Builds the query for a cube field. Drilldowns and measures that
+    /// aren't given as arguments are taken from the selected fields.
+    fn query(&self, field: &Field) -> Result<CubeQuery, Error> {
+        if field.selection.is_empty() {
+            bail!("Field `{}` of type `[{}Row!]` must have a selection of subfields", field.name, self.type_name);
+        }
+
+        for (arg_name, _) in &field.arguments {
+            if !["drilldowns", "cuts", "measures"].contains(&arg_name.as_str()) {
+                bail!("Unknown argument `{}` on field `{}`", arg_name, field.name);
+            }
+        }
+
+        // selected fields, as a level or a measure
+        let mut selected_levels = vec![];
+        let mut selected_measures = vec![];
+        for sub_field in &field.selection {
+            if !sub_field.selection.is_empty() || !sub_field.arguments.is_empty() {
+                bail!("Field `{}` is a scalar and takes no arguments or subfields", sub_field.name);
+            }
+            if sub_field.name == "__typename" {
+                continue;
+            }
+            if let Some(level) = self.find_level_field(&sub_field.name) {
+                if !selected_levels.contains(&level.name) {
+                    selected_levels.push(level.name.clone());
+                }
+            } else if let Some(measure) = self.find_measure(&sub_field.name) {
+                if !selected_measures.contains(&measure.name) {
+                    selected_measures.push(measure.name.clone());
+                }
+            } else {
+                bail!("Cannot query field `{}` on type `{}Row`", sub_field.name, self.type_name);
+            }
+        }
+
+        let drill_names = match field.argument("drilldowns") {
+            Some(value) => enum_list(value, "drilldowns")?,
+            None => selected_levels.clone(),
+        };
+        let measure_names = match field.argument("measures") {
+            Some(value) => enum_list(value, "measures")?,
+            None => selected_measures.clone(),
+        };
+
+        let drill_levels = drill_names.iter()
+            .map(|name| {
+                self.levels.iter()
+                    .find(|level| &level.name == name)
+                    .ok_or_else(|| format_err!("Value `{}` is not a level of `{}`", name, self.type_name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let measures = measure_names.iter()
+            .map(|name| {
+                self.find_measure(name)
+                    .ok_or_else(|| format_err!("Value `{}` is not a measure of `{}`", name, self.type_name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for name in &selected_levels {
+            if !drill_names.contains(name) {
+                bail!("Field `{}` needs `{}` in drilldowns", name, name);
+            }
+        }
+        for name in &selected_measures {
+            if !measure_names.contains(name) {
+                bail!("Field `{}` needs `{}` in measures", name, name);
+            }
+        }
+        if measures.is_empty() {
+            bail!("Field `{}` needs at least one measure", field.name);
+        }
+
+        let cuts = match field.argument("cuts") {
+            Some(value) => self.cuts(value)?,
+            None => vec![],
+        };
+
+        // columns of the result are the drilldowns, with a key column
+        // first if the level has a name column, then the measures
+        let mut columns: HashMap<&str, (usize, ScalarType)> = HashMap::new();
+        let mut col_idx = 0;
+        for level in &drill_levels {
+            if let Some(ref id_name) = level.id_name {
+                columns.insert(id_name, (col_idx, level.key_type));
+                columns.insert(&level.name, (col_idx + 1, ScalarType::String));
+                col_idx += 2;
+            } else {
+                columns.insert(&level.name, (col_idx, level.key_type));
+                col_idx += 1;
+            }
+        }
+        for measure in &measures {
+            columns.insert(&measure.name, (col_idx, measure.measure_type));
+            col_idx += 1;
+        }
+
+        let outputs = field.selection.iter()
+            .map(|sub_field| {
+                let source = if sub_field.name == "__typename" {
+                    OutputSource::Typename(format!("{}Row", self.type_name))
+                } else {
+                    let (col_idx, scalar_type) = columns[sub_field.name.as_str()];
+                    OutputSource::Column(col_idx, scalar_type)
+                };
+                (sub_field.response_key().to_owned(), source)
+            })
+            .collect();
+
+        let mut query = TsQuery::new();
+        query.drilldowns = drill_levels.iter()
+            .map(|level| Drilldown(level.level_name.clone()))
+            .collect();
+        query.measures = measures.iter()
+            .map(|measure| MeasureName::new(measure.measure.clone()))
+            .collect();
+        query.cuts = cuts;
+
+        Ok(CubeQuery {
+            cube: self.cube.clone(),
+            query,
+            outputs,
+        })
+    }
+
+    fn cuts(&self, value: &Value) -> Result<Vec<Cut>, Error> {
+        list_items(value).iter()
+            .map(|item| {
+                let fields = match item {
+                    Value::Object(fields) => fields,
+                    _ => bail!("Argument `cuts` expects `{}Cut` objects", self.type_name),
+                };
+
+                let mut level = None;
+                let mut members = None;
+                let mut exclude = false;
+
+                for (name, value) in fields {
+                    match (name.as_str(), value) {
+                        ("level", value) => level = Some(enum_value(value, "level")?),
+                        ("members", value) => {
+                            let items = list_items(value).iter()
+                                .map(|member| {
+                                    match member {
+                                        Value::String(s) | Value::Enum(s) => Ok(s.clone()),
+                                        Value::Int(i) => Ok(i.to_string()),
+                                        Value::Float(f) => Ok(f.to_string()),
+                                        _ => bail!("Cut members must be strings"),
+                                    }
+                                })
+                                .collect::<Result<Vec<_>, _>>()?;
+                            members = Some(items);
+                        },
+                        ("exclude", Value::Boolean(b)) => exclude = *b,
+                        ("exclude", Value::Null) => (),
+                        (name, _) => bail!("Unknown field `{}` in `{}Cut`", name, self.type_name),
+                    }
+                }
+
+                let level = level.ok_or_else(|| format_err!("Cuts need a `level`"))?;
+                let members = members.ok_or_else(|| format_err!("Cuts need `members`"))?;
+                if members.is_empty() {
+                    bail!("Cuts need at least one member");
+                }
+
+                let level = self.levels.iter()
+                    .find(|l| l.name == level)
+                    .ok_or_else(|| format_err!("Value `{}` is not a level of `{}`", level, self.type_name))?;
+
+                let mask = if exclude { Mask::Exclude } else { Mask::Include };
+
+                Ok(Cut {
+                    level_name: level.level_name.clone(),
+                    members,
+                    mask,
+                    for_match: false,
+                    range: None,
+                    property: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Finds the level of a row field, which is either its label or its key.
+    fn find_level_field(&self, name: &str) -> Option<&GraphqlLevel> {
+        self.levels.iter()
+            .find(|level| level.name == name || level.id_name.as_ref().map(|n| n == name).unwrap_or(false))
+    }
+
+    fn find_measure(&self, name: &str) -> Option<&GraphqlMeasure> {
+        self.measures.iter()
+            .find(|measure| measure.name == name)
+    }
+}
+
+
+/// Replaces variables in a field's arguments with their values.
+pub fn substitute_variables(field: &Field, variables: &HashMap<String, Value>) -> Field {
+    fn substitute(value: &Value, variables: &HashMap<String, Value>) -> Value {
+        match value {
+            Value::Variable(name) => variables.get(name).cloned().unwrap_or(Value::Null),
+            Value::List(items) => Value::List(items.iter().map(|v| substitute(v, variables)).collect()),
+            Value::Object(fields) => {
+                Value::Object(fields.iter().map(|(k, v)| (k.clone(), substitute(v, variables))).collect())
+            },
+            value => value.clone(),
+        }
+    }
+
+    Field {
+        alias: field.alias.clone(),
+        name: field.name.clone(),
+        arguments: field.arguments.iter()
+            .map(|(name, value)| (name.clone(), substitute(value, variables)))
+            .collect(),
+        selection: field.selection.clone(),
+    }
+}
+
+
+/// A single value is accepted where a list is expected, as in GraphQL
+/// input coercion.
+fn list_items(value: &Value) -> Vec<Value> {
+    match value {
+        Value::List(items) => items.clone(),
+        Value::Null => vec![],
+        value => vec![value.clone()],
+    }
+}
+
+fn enum_value(value: &Value, arg: &str) -> Result<String, Error> {
+    match value {
+        Value::Enum(s) | Value::String(s) => Ok(s.clone()),
+        _ => bail!("Argument `{}` expects enum values", arg),
+    }
+}
+
+fn enum_list(value: &Value, arg: &str) -> Result<Vec<String>, Error> {
+    list_items(value).iter()
+        .map(|item| enum_value(item, arg))
+        .collect()
+}
+
+
+/// Turns a name from the schema into a GraphQL name, which can only have
+/// letters, digits and underscores, and can't start with a digit.
+fn graphql_name(name: &str) -> String {
+    let mut gql_name: String = name.trim().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if gql_name.is_empty() || gql_name.starts_with(|c: char| c.is_ascii_digit()) {
+        gql_name.insert(0, '_');
+    }
+
+    gql_name
+}
+
+/// Adds a numeric suffix to a name until it isn't in `used`.
+fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    let mut unique = name.clone();
+    let mut n = 2;
+
+    while used.contains(&unique) {
+        unique = format!("{}_{}", name, n);
+        n += 1;
+    }
+
+    used.insert(unique.clone());
+    unique
+}
+
+
+fn column_value(column_data: &ColumnData, row_idx: usize) -> JsonValue {
+    match column_data {
+        ColumnData::Int8(ns) =>    ns[row_idx].into(),
+        ColumnData::Int16(ns) =>   ns[row_idx].into(),
+        ColumnData::Int32(ns) =>   ns[row_idx].into(),
+        ColumnData::Int64(ns) =>   ns[row_idx].into(),
+        ColumnData::UInt8(ns) =>   ns[row_idx].into(),
+        ColumnData::UInt16(ns) =>  ns[row_idx].into(),
+        ColumnData::UInt32(ns) =>  ns[row_idx].into(),
+        ColumnData::UInt64(ns) =>  ns[row_idx].into(),
+        ColumnData::Float32(ns) => ns[row_idx].into(),
+        ColumnData::Float64(ns) => ns[row_idx].into(),
+        ColumnData::Text(ss) =>    ss[row_idx].clone().into(),
+        ColumnData::NullableInt8(ns) =>    ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableInt16(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableInt32(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableInt64(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableUInt8(ns) =>   ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableUInt16(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableUInt32(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableUInt64(ns) =>  ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableFloat32(ns) => ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableFloat64(ns) => ns[row_idx].map(|n| n.into()).unwrap_or(JsonValue::Null),
+        ColumnData::NullableText(ss) =>    ss[row_idx].clone().map(|s| s.into()).unwrap_or(JsonValue::Null),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tesseract_core::Column;
+    use crate::graphql::parse_document;
+
+    #[test]
+    fn test_graphql_schema() {
+        let s = r##"
+        <Schema name="Test">
+            <Cube name="Sales Data">
+                <Table name="sales" />
+                <Dimension name="Geography" foreign_key="geo_id">
+                    <Hierarchy name="Geography" primary_key="geo_id">
+                        <Table name="dim_geo" />
+                        <Level name="State" key_column="state_id" name_column="state_name" key_type="text" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Year" foreign_key="year" type="time">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Ship Date" foreign_key="ship_year">
+                    <Hierarchy name="Ship Date">
+                        <Level name="Year" key_column="ship_year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Orders" column="order_id" aggregator="count" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+        let gql_schema = GraphqlSchema::from_schema(&schema);
+
+        assert!(gql_schema.sdl.contains(
+            "  Sales_Data(drilldowns: [Sales_DataLevel!], cuts: [Sales_DataCut!], measures: [Sales_DataMeasure!]): [Sales_DataRow!]"
+        ));
+        assert!(gql_schema.sdl.contains(
+            "type Sales_DataRow {\n  State_ID: String\n  State: String\n  Year_Year: Float\n  Ship_Date_Year: Float\n  Quantity: Float\n  Orders: Int\n}"
+        ));
+        assert!(gql_schema.sdl.contains("enum Sales_DataLevel {\n  State\n  Year_Year\n  Ship_Date_Year\n}"));
+
+        let doc = r#"{
+            Sales_Data(cuts: [{level: Year_Year, members: [2017, "2018"]}], measures: [Quantity, Orders]) {
+                State
+                id: State_ID
+                Orders
+                __typename
+            }
+        }"#;
+        let ops = parse_document(doc).unwrap();
+        let cube_query = gql_schema.cube_query(&ops[0].selection[0]).unwrap();
+
+        assert_eq!(cube_query.cube, "Sales Data");
+        assert_eq!(cube_query.query.drilldowns, vec!["Geography.Geography.State".parse().unwrap()]);
+        assert_eq!(cube_query.query.measures, vec!["Quantity".parse().unwrap(), "Orders".parse().unwrap()]);
+        assert_eq!(cube_query.query.cuts[0].level_name, LevelName::new("Year", "Year", "Year"));
+        assert_eq!(cube_query.query.cuts[0].members, vec!["2017".to_owned(), "2018".to_owned()]);
+
+        let df = DataFrame::from_vec(vec![
+            Column::new("State ID".to_owned(), ColumnData::Text(vec!["ca".to_owned()])),
+            Column::new("State".to_owned(), ColumnData::Text(vec!["California".to_owned()])),
+            Column::new("Quantity".to_owned(), ColumnData::Float64(vec![10.5])),
+            Column::new("Orders".to_owned(), ColumnData::UInt64(vec![3])),
+        ]);
+        assert_eq!(cube_query.rows(&df), serde_json::json!([
+            { "State": "California", "id": "ca", "Orders": 3, "__typename": "Sales_DataRow" }
+        ]));
+
+        let errors = [
+            "{ Missing { State } }",
+            "{ Sales_Data { State } }",
+            "{ Sales_Data { Unknown } }",
+            "{ Sales_Data(drilldowns: [Year_Year]) { State Quantity } }",
+            "{ Sales_Data(cuts: [{level: State}]) { Quantity } }",
+            "{ Sales_Data(limit: 10) { Quantity } }",
+        ];
+        for doc in errors.iter() {
+            let ops = parse_document(doc).unwrap();
+            assert!(gql_schema.cube_query(&ops[0].selection[0]).is_err(), "{}", doc);
+        }
+    }
+}
@@ -3,27 +3,30 @@ use actix_web::{
     FutureResponse,
     HttpRequest,
     HttpResponse,
+    Json,
     Path,
 };
 
-use failure::Error;
+use failure::{Error, bail};
 use futures::future::{self, Future};
 use lazy_static::lazy_static;
 use log::*;
 use serde_derive::{Serialize, Deserialize};
 use serde_qs as qs;
 use std::convert::{TryFrom, TryInto};
+use std::fmt::Debug;
 use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::Query as TsQuery;
-
-use crate::handlers::util::validate_members;
+use tesseract_core::{Query as TsQuery, Schema};
+use tesseract_core::query::{GroupQuery, TopQuery};
 
 use crate::app::AppState;
 use crate::errors::ServerError;
+use super::query_body::AggregateQueryBody;
 use super::util::{
-    boxed_error_http_response, verify_authorization,
+    self, boxed_error_http_response, verify_authorization,
     format_to_content_type, generate_source_data,
-    get_redis_cache_key, check_redis_cache, insert_into_redis_cache
+    get_redis_cache_key, get_redis_cache_key_json,
+    check_redis_cache, insert_into_redis_cache
 };
 use r2d2_redis::{redis};
 
@@ -47,11 +50,63 @@ pub fn aggregate_handler(
 }
 
 
-/// Performs data aggregation.
+/// Handles default aggregation of a JSON query body when a format is not
+/// specified. Default format is CSV.
+pub fn aggregate_post_default_handler(
+    (req, cube, body): (HttpRequest<AppState>, Path<String>, Json<AggregateQueryBody>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let cube_format = (cube.into_inner(), "csv".to_owned());
+    do_aggregate_body(req, cube_format, body.into_inner())
+}
+
+
+/// Handles aggregation of a JSON query body when a format is specified.
+pub fn aggregate_post_handler(
+    (req, cube_format, body): (HttpRequest<AppState>, Path<(String, String)>, Json<AggregateQueryBody>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_aggregate_body(req, cube_format.into_inner(), body.into_inner())
+}
+
+
+/// Performs data aggregation for a query sent in the query string.
 pub fn do_aggregate(
     req: HttpRequest<AppState>,
     cube_format: (String, String),
     ) -> FutureResponse<HttpResponse>
+{
+    let query = req.query_string();
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let agg_query_res = QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&query);
+    let agg_query = ok_or_404!(agg_query_res);
+
+    run_aggregate(req, cube_format, agg_query, false)
+}
+
+
+/// Performs data aggregation for a query sent as a JSON body.
+pub fn do_aggregate_body(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    agg_query: AggregateQueryBody,
+    ) -> FutureResponse<HttpResponse>
+{
+    run_aggregate(req, cube_format, agg_query, true)
+}
+
+
+/// Validates and executes an aggregate query. `from_body` selects how the
+/// Redis cache key is built, since body queries have no query string.
+fn run_aggregate<Q>(
+    req: HttpRequest<AppState>,
+    cube_format: (String, String),
+    agg_query: Q,
+    from_body: bool,
+    ) -> FutureResponse<HttpResponse>
+    where Q: TryInto<TsQuery, Error = Error> + serde::Serialize + Debug
 {
     let (cube, format) = cube_format;
 
@@ -68,18 +123,15 @@ pub fn do_aggregate(
 
     info!("cube: {}, format: {:?}", cube, format);
 
-    let query = req.query_string();
-    lazy_static!{
-        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
-    }
-    let agg_query_res = QS_NON_STRICT.deserialize_str::<AggregateQueryOpt>(&query);
-    let agg_query = ok_or_404!(agg_query_res);
-
     info!("query opts:{:?}", agg_query);
 
     // Check if this query is already cached
     let redis_pool = req.state().redis_pool.clone();
-    let redis_cache_key = get_redis_cache_key("core", &req, &cube, &format);
+    let redis_cache_key = if from_body {
+        ok_or_404!(get_redis_cache_key_json("core", &cube, &format, &agg_query))
+    } else {
+        get_redis_cache_key("core", &req, &cube, &format)
+    };
 
     if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key) {
         return res;
@@ -88,30 +140,9 @@ pub fn do_aggregate(
     // Gets the Source Data
     let source_data = Some(generate_source_data(&cube_obj));
 
-    // Turn AggregateQueryOpt into Query
-    let ts_query: Result<TsQuery, _> = agg_query.try_into();
-    let ts_query = ok_or_404!(ts_query);
-
-    // sql injection mitigation on query:
-    // - Check that cut members exist in members cache
-    // this is in braces to explicitly the scope in which
-    // req is borrowed, since req is moved later in the `map_err`
-    {
-        let cache = req.state().cache.read().unwrap();
-        let cube_cache = some_or_404!(cache.find_cube_info(&cube), format!("Cube {} not found", cube));
-        ok_or_404!(validate_members(&ts_query.cuts, &cube_cache));
-    }
-
-    let query_ir_headers = schema.sql_query(&cube, &ts_query, None);
-    let (query_ir, headers) = ok_or_404!(query_ir_headers);
-
-    let sql = req.state()
-        .backend
-        .generate_sql(query_ir);
+    let sql_headers = aggregate_sql(req.state(), schema, &cube, agg_query);
+    let (sql, headers) = ok_or_404!(sql_headers);
 
-    info!("Sql query: {}", sql);
-    info!("Headers: {:?}", headers);
-    
     req.state()
         .backend
         .exec_sql(sql)
@@ -141,22 +172,65 @@ pub fn do_aggregate(
 }
 
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Turns an aggregate query on a cube, from the query string or a JSON
+/// body, into SQL and result headers.
+pub(crate) fn aggregate_sql<Q>(
+    state: &AppState,
+    schema: &Schema,
+    cube: &str,
+    agg_query: Q,
+    ) -> Result<(String, Vec<String>), Error>
+    where Q: TryInto<TsQuery, Error = Error>
+{
+    // Turn the query options into Query
+    let ts_query: TsQuery = agg_query.try_into()?;
+
+    query_sql(state, schema, cube, &ts_query)
+}
+
+
+/// Turns a query on a cube into SQL and result headers, after checking that
+/// its cut members exist.
+pub(crate) fn query_sql(
+    state: &AppState,
+    schema: &Schema,
+    cube: &str,
+    ts_query: &TsQuery,
+    ) -> Result<(String, Vec<String>), Error>
+{
+    let cache = state.cache.read().unwrap();
+
+    util::query_sql(schema, &cache, &*state.backend, cube, ts_query)
+}
+
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct AggregateQueryOpt {
     drilldowns: Option<Vec<String>>,
     cuts: Option<Vec<String>>,
     measures: Option<Vec<String>>,
     properties: Option<Vec<String>>,
     filters: Option<Vec<String>>,
+    filter: Option<String>,
     captions: Option<Vec<String>>,
     parents: Option<bool>,
     top: Option<String>,
+    top_others: Option<bool>,
     top_where: Option<String>,
     sort: Option<String>,
     limit: Option<String>,
     growth: Option<String>,
+    cumulative: Option<String>,
+    moving_avg: Option<String>,
+    index: Option<String>,
+    rank: Option<String>,
+    trend: Option<String>,
+    totals: Option<Vec<String>>,
     rca: Option<String>,
     rate: Option<String>,
+    group: Option<String>,
+    group_others: Option<bool>,
+    bins: Option<String>,
     debug: Option<bool>,
     exclude_default_members: Option<bool>,
 //    distinct: Option<bool>,
@@ -211,11 +285,21 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
         let filters = filters?;
         let captions = captions?;
 
+        let filter = agg_query_opt.filter
+            .map(|f| f.parse())
+            .transpose()?;
+
         let parents = agg_query_opt.parents.unwrap_or(false);
 
-        let top = agg_query_opt.top
+        let mut top: Option<TopQuery> = agg_query_opt.top
             .map(|t| t.parse())
             .transpose()?;
+        if agg_query_opt.top_others.unwrap_or(false) {
+            match top {
+                Some(ref mut top) => top.others = true,
+                None => bail!("top_others requires a top query"),
+            }
+        }
         let top_where = agg_query_opt.top_where
             .map(|t| t.parse())
             .transpose()?;
@@ -230,6 +314,33 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             .map(|g| g.parse())
             .transpose()?;
 
+        let cumulative = agg_query_opt.cumulative
+            .map(|c| c.parse())
+            .transpose()?;
+
+        let moving_avg = agg_query_opt.moving_avg
+            .map(|m| m.parse())
+            .transpose()?;
+
+        let index = agg_query_opt.index
+            .map(|i| i.parse())
+            .transpose()?;
+
+        let rank = agg_query_opt.rank
+            .map(|r| r.parse())
+            .transpose()?;
+
+        let trend = agg_query_opt.trend
+            .map(|t| t.parse())
+            .transpose()?;
+
+        let totals: Result<Vec<_>, _> = agg_query_opt.totals
+            .map(|ts| {
+                ts.iter().map(|t| t.parse()).collect()
+            })
+            .unwrap_or(Ok(vec![]));
+        let totals = totals?;
+
         let rca = agg_query_opt.rca
             .map(|r| r.parse())
             .transpose()?;
@@ -238,6 +349,20 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             .map(|r| r.parse())
             .transpose()?;
 
+        let mut group: Option<GroupQuery> = agg_query_opt.group
+            .map(|g| g.parse())
+            .transpose()?;
+        if let Some(keep_others) = agg_query_opt.group_others {
+            match group {
+                Some(ref mut group) => group.keep_others = keep_others,
+                None => bail!("group_others requires a group query"),
+            }
+        }
+
+        let bins = agg_query_opt.bins
+            .map(|b| b.parse())
+            .transpose()?;
+
         let debug = agg_query_opt.debug.unwrap_or(false);
         let sparse = agg_query_opt.sparse.unwrap_or(false);
         let exclude_default_members = agg_query_opt.exclude_default_members.unwrap_or(false);
@@ -250,6 +375,7 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             parents,
             properties,
             filters,
+            filter,
             captions,
             top,
             top_where,
@@ -257,8 +383,16 @@ impl TryFrom<AggregateQueryOpt> for TsQuery {
             limit,
             rca,
             growth,
+            cumulative,
+            moving_avg,
+            index,
+            rank,
+            trend,
+            totals,
             debug,
             rate,
+            group,
+            bins,
             sparse,
             exclude_default_members,
         })
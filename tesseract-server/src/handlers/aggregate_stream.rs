@@ -15,7 +15,7 @@ use tesseract_core::Query as TsQuery;
 
 use crate::app::AppState;
 use super::aggregate::AggregateQueryOpt;
-use super::util::{boxed_error_http_response, verify_authorization, format_to_content_type};
+use super::util::{boxed_error_http_response, boxed_error_string, verify_authorization, format_to_content_type};
 
 
 /// Handles default aggregation when a format is not specified.
@@ -55,6 +55,9 @@ pub fn do_aggregate(
     }
 
     let format = ok_or_404!(format.parse::<FormatType>());
+    if !format.is_streamable() {
+        return boxed_error_string("Only csv, jsonrecords and jsonarrays can be streamed".to_owned());
+    }
 
     info!("cube: {}, format: {:?}", cube, format);
 
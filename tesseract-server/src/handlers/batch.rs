@@ -0,0 +1,223 @@
+use actix_web::{
+    AsyncResponder,
+    Error as ActixError,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Json,
+};
+use actix_web::http::StatusCode;
+use futures::future::{self, Future};
+use futures::stream::{self, Stream};
+use log::*;
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+use tesseract_core::format::{format_records, FormatType};
+
+use crate::app::AppState;
+use super::aggregate::aggregate_sql;
+use super::query_body::AggregateQueryBody;
+use super::util::{
+    boxed_error_http_response, verify_authorization, generate_source_data,
+    get_redis_cache_key_json, get_from_redis_cache, insert_into_redis_cache
+};
+
+
+/// One aggregate query in a batch. `query` takes the same fields as a JSON
+/// body sent to `/cubes/{cube}/aggregate`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchQuery {
+    cube: String,
+    format: Option<String>,
+    #[serde(default)]
+    query: AggregateQueryBody,
+}
+
+
+/// The result of one query in a batch. Results in JSON formats are embedded
+/// as JSON, and csv results as a string.
+#[derive(Debug, Serialize)]
+pub struct BatchResult {
+    cube: String,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BatchResult {
+    fn ok(cube: String, format: &FormatType, res: String) -> Self {
+        let data = match format {
+            FormatType::Csv => Value::String(res),
+            _ => serde_json::from_str(&res).unwrap_or(Value::String(res)),
+        };
+
+        BatchResult {
+            cube,
+            status: StatusCode::OK.as_u16(),
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    fn error(cube: String, status: StatusCode, error: String) -> Self {
+        BatchResult {
+            cube,
+            status: status.as_u16(),
+            data: None,
+            error: Some(error),
+        }
+    }
+}
+
+
+/// Runs a batch of aggregate queries, at most `TESSERACT_BATCH_CONCURRENCY`
+/// at a time. Responds with the result or error of each query, in order.
+/// Batches of more than `TESSERACT_BATCH_MAX_QUERIES` are refused.
+pub fn batch_handler(
+    (req, queries): (HttpRequest<AppState>, Json<Vec<BatchQuery>>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let concurrency = req.state().env_vars.batch_concurrency;
+    let max_queries = req.state().env_vars.batch_max_queries;
+
+    if queries.len() > max_queries {
+        return boxed_error_http_response(
+            HttpResponse::BadRequest().json(format!("A batch can have at most {} queries", max_queries))
+        );
+    }
+
+    info!("Batch of {} queries, concurrency: {}", queries.len(), concurrency);
+
+    // Queries are deferred so that only `concurrency` of them hit the
+    // backend at once
+    let queries: Vec<_> = queries.into_inner().into_iter()
+        .map(|batch_query| {
+            let req = req.clone();
+            future::lazy(move || run_batch_query(&req, batch_query))
+        })
+        .collect();
+
+    stream::iter_ok(queries)
+        .buffered(concurrency)
+        .collect()
+        .map(|results| HttpResponse::Ok().json(results))
+        .responder()
+}
+
+
+/// Runs one query of a batch. Failures are returned as a `BatchResult` so
+/// that they don't fail the rest of the batch.
+fn run_batch_query(
+    req: &HttpRequest<AppState>,
+    batch_query: BatchQuery,
+    ) -> Box<dyn Future<Item=BatchResult, Error=ActixError>>
+{
+    let BatchQuery { cube, format, query } = batch_query;
+
+    macro_rules! ok_or_result {
+        ($expr:expr, $status:expr) => {
+            match $expr {
+                Ok(val) => val,
+                Err(err) => {
+                    return Box::new(future::ok(BatchResult::error(cube, $status, err.to_string())));
+                }
+            }
+        };
+    }
+
+    let schema = req.state().schema.read().unwrap();
+    let cube_obj = ok_or_result!(schema.get_cube_by_name(&cube), StatusCode::NOT_FOUND);
+
+    if verify_authorization(req, cube_obj.min_auth_level).is_err() {
+        return Box::new(future::ok(BatchResult::error(
+            cube,
+            StatusCode::UNAUTHORIZED,
+            "This cube is not public".to_owned(),
+        )));
+    }
+
+    let format = format.unwrap_or_else(|| "jsonrecords".to_owned());
+    let format = ok_or_result!(format.parse::<FormatType>(), StatusCode::NOT_FOUND);
+
+    // Shares cache entries with JSON bodies sent to the aggregate endpoint
+    let redis_pool = req.state().redis_pool.clone();
+    let redis_cache_key = ok_or_result!(
+        get_redis_cache_key_json("core", &cube, &format, &query),
+        StatusCode::NOT_FOUND
+    );
+
+    if let Some(res) = get_from_redis_cache(&redis_pool, &redis_cache_key) {
+        return Box::new(future::ok(BatchResult::ok(cube, &format, res)));
+    }
+
+    let source_data = Some(generate_source_data(&cube_obj));
+
+    let (sql, headers) = ok_or_result!(
+        aggregate_sql(req.state(), &schema, &cube, query),
+        StatusCode::NOT_FOUND
+    );
+
+    let debug = req.state().debug;
+
+    Box::new(req.state()
+        .backend
+        .exec_sql(sql)
+        .then(move |df| {
+            let df = match df {
+                Ok(df) => df,
+                Err(err) => {
+                    let cause = if debug {
+                        err.to_string()
+                    } else {
+                        "Internal Server Error 1010".to_owned()
+                    };
+                    return Ok(BatchResult::error(cube, StatusCode::INTERNAL_SERVER_ERROR, cause));
+                },
+            };
+
+            match format_records(&headers, df, format.clone(), source_data, false) {
+                Ok(res) => {
+                    // Try to insert this result in the Redis cache, if available
+                    insert_into_redis_cache(&res, &redis_pool, &redis_cache_key);
+
+                    Ok(BatchResult::ok(cube, &format, res))
+                },
+                Err(err) => Ok(BatchResult::error(cube, StatusCode::NOT_FOUND, err.to_string())),
+            }
+        }))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_batch_result() {
+        let queries: Vec<BatchQuery> = serde_json::from_value(json!([
+            { "cube": "sales", "query": { "drilldowns": [{ "dimension": "Year", "hierarchy": "Year", "level": "Year" }], "measures": ["Quantity"] } },
+            { "cube": "sales", "format": "csv" },
+        ])).unwrap();
+        assert!(serde_json::from_value::<BatchQuery>(json!({ "cube": "sales", "query": { "drilldowns": ["Year.Year"] } })).is_err());
+        assert_eq!(queries[0].format, None);
+        assert_eq!(queries[1].format, Some("csv".to_owned()));
+
+        let res = BatchResult::ok("sales".to_owned(), &FormatType::JsonRecords, r#"{"data":[]}"#.to_owned());
+        assert_eq!(
+            serde_json::to_value(&res).unwrap(),
+            json!({ "cube": "sales", "status": 200, "data": { "data": [] } })
+        );
+
+        let res = BatchResult::ok("sales".to_owned(), &FormatType::Csv, "Year,Quantity\n".to_owned());
+        assert_eq!(res.data, Some(json!("Year,Quantity\n")));
+
+        let res = BatchResult::error("sales".to_owned(), StatusCode::UNAUTHORIZED, "This cube is not public".to_owned());
+        assert_eq!(
+            serde_json::to_value(&res).unwrap(),
+            json!({ "cube": "sales", "status": 401, "error": "This cube is not public" })
+        );
+    }
+}
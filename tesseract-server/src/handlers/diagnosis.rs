@@ -12,7 +12,7 @@ use futures::future::Future;
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
-use serde_derive::Deserialize;
+use serde_derive::{Serialize, Deserialize};
 use url::Url;
 
 use tesseract_core::names::{Property, LevelName};
@@ -44,7 +44,7 @@ pub fn diagnosis_handler(
 }
 
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct DiagnosisQueryOpt {
     pub cube: Option<String>,
 }
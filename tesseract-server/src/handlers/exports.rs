@@ -0,0 +1,288 @@
+use actix::Arbiter;
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+    Json,
+    Path,
+    Responder,
+    Result as ActixResult,
+};
+use actix_web::fs::NamedFile;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use failure::{Error, bail};
+use futures::future::{self, Future};
+use futures::Stream;
+use log::*;
+use serde_derive::{Serialize, Deserialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, RwLock};
+use std::time::UNIX_EPOCH;
+use tesseract_core::Backend;
+use tesseract_core::format::FormatType;
+use tesseract_core::format_stream::format_records_stream;
+
+use crate::app::AppState;
+use crate::exports::{ExportJob, ExportJobs, ExportStatus, ExportTask};
+use super::aggregate::aggregate_sql;
+use super::query_body::AggregateQueryBody;
+use super::util::{verify_authorization, format_to_content_type};
+
+
+/// Body of an export request. `query` takes the same fields as a JSON body
+/// sent to `/cubes/{cube}/aggregate`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportQuery {
+    cube: String,
+    format: Option<String>,
+    #[serde(default)]
+    query: AggregateQueryBody,
+}
+
+
+#[derive(Debug, Serialize)]
+struct ExportJobResponse {
+    id: String,
+    cube: String,
+    format: String,
+    status: ExportStatus,
+    rows: usize,
+    bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Unix time in seconds when the job and its file are removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download: Option<String>,
+}
+
+impl ExportJobResponse {
+    fn new(job: &ExportJob, exports: &ExportJobs) -> Self {
+        let expires = job.finished
+            .map(|finished| finished + exports.ttl())
+            .and_then(|expires| expires.duration_since(UNIX_EPOCH).ok())
+            .map(|expires| expires.as_secs());
+
+        let download = if job.status == ExportStatus::Done {
+            Some(format!("/exports/{}/download", job.id))
+        } else {
+            None
+        };
+
+        ExportJobResponse {
+            id: job.id.clone(),
+            cube: job.cube.clone(),
+            format: job.format.clone(),
+            status: job.status,
+            rows: job.rows,
+            bytes: job.bytes,
+            error: job.error.clone(),
+            expires,
+            download,
+        }
+    }
+}
+
+
+/// Queues an export of an aggregate query. Responds with the job, whose
+/// status is at `/exports/{id}`.
+pub fn export_create_handler(
+    (req, body): (HttpRequest<AppState>, Json<ExportQuery>)
+    ) -> HttpResponse
+{
+    let ExportQuery { cube, format, query } = body.into_inner();
+
+    let schema = req.state().schema.read().unwrap();
+    let cube_obj = match schema.get_cube_by_name(&cube) {
+        Ok(cube_obj) => cube_obj,
+        Err(err) => return HttpResponse::NotFound().json(err.to_string()),
+    };
+
+    if let Err(err) = verify_authorization(&req, cube_obj.min_auth_level) {
+        return err;
+    }
+
+    let format = format.unwrap_or_else(|| "csv".to_owned());
+    let format_type = match format.parse::<FormatType>() {
+        Ok(format_type) => format_type,
+        Err(err) => return HttpResponse::NotFound().json(err.to_string()),
+    };
+    if !format_type.is_streamable() {
+        return HttpResponse::NotFound().json(format!("Format {} can't be exported; use csv, jsonrecords or jsonarrays", format));
+    }
+
+    let (sql, headers) = match aggregate_sql(req.state(), &schema, &cube, query) {
+        Ok(sql_headers) => sql_headers,
+        Err(err) => return HttpResponse::NotFound().json(err.to_string()),
+    };
+
+    let exports = req.state().exports.clone();
+
+    // slots of jobs that timed out are freed before queueing
+    exports.write().unwrap().cleanup();
+    start_queued_exports(&exports, &*req.state().backend);
+
+    let res = {
+        let mut exports = exports.write().unwrap();
+
+        let id = match exports.add(cube.clone(), format, format_type, cube_obj.min_auth_level, sql, headers) {
+            Ok(id) => id,
+            Err(err) => return HttpResponse::build(StatusCode::TOO_MANY_REQUESTS).json(err.to_string()),
+        };
+        info!("Queued export {} of cube {}", id, cube);
+
+        let job = exports.get(&id).expect("export job was just added");
+
+        HttpResponse::Accepted()
+            .header("Location", format!("/exports/{}", id))
+            .json(ExportJobResponse::new(job, &exports))
+    };
+
+    start_queued_exports(&exports, &*req.state().backend);
+
+    res
+}
+
+
+/// Gets the status and progress of an export job. Queued jobs are started
+/// if jobs that timed out freed their slots.
+pub fn export_status_handler(
+    (req, id): (HttpRequest<AppState>, Path<String>)
+    ) -> HttpResponse
+{
+    req.state().exports.write().unwrap().cleanup();
+    start_queued_exports(&req.state().exports, &*req.state().backend);
+
+    let exports = req.state().exports.read().unwrap();
+
+    let job = match exports.get(&id) {
+        Some(job) => job,
+        None => return HttpResponse::NotFound().json(format!("Export {} not found", id)),
+    };
+
+    if let Err(err) = verify_authorization(&req, job.min_auth_level) {
+        return err;
+    }
+
+    HttpResponse::Ok().json(ExportJobResponse::new(job, &exports))
+}
+
+
+/// Downloads the file of a finished export job.
+pub fn export_download_handler(
+    (req, id): (HttpRequest<AppState>, Path<String>)
+    ) -> ActixResult<HttpResponse>
+{
+    let job = {
+        let mut exports = req.state().exports.write().unwrap();
+        exports.cleanup();
+
+        match exports.get(&id) {
+            Some(job) => job.clone(),
+            None => return Ok(HttpResponse::NotFound().json(format!("Export {} not found", id))),
+        }
+    };
+
+    if let Err(err) = verify_authorization(&req, job.min_auth_level) {
+        return Ok(err);
+    }
+
+    if job.status != ExportStatus::Done {
+        return Ok(HttpResponse::NotFound().json(format!("Export {} is not done", id)));
+    }
+
+    let format_type = job.format.parse::<FormatType>()
+        .map_err(|err| actix_web::error::ErrorInternalServerError(err.to_string()))?;
+
+    let filename = job.path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("export")
+        .to_owned();
+
+    NamedFile::open(&job.path)?
+        .set_content_type(format_to_content_type(&format_type).0)
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .respond_to(&req)
+        .map_err(Into::into)
+}
+
+
+/// Starts queued export jobs while fewer than the running limit are running.
+fn start_queued_exports(
+    exports: &Arc<RwLock<ExportJobs>>,
+    backend: &(dyn Backend + Sync + Send),
+    )
+{
+    loop {
+        let task = match exports.write().unwrap().next_task() {
+            Some(task) => task,
+            None => break,
+        };
+
+        info!("Starting export {}", task.id);
+
+        Arbiter::spawn(run_export(task, exports.clone(), backend.box_clone()));
+    }
+}
+
+
+/// Streams the result of an export job's query into its file. When the job
+/// finishes, the next queued jobs are started.
+fn run_export(
+    task: ExportTask,
+    exports: Arc<RwLock<ExportJobs>>,
+    backend: Box<dyn Backend + Sync + Send>,
+    ) -> Box<dyn Future<Item=(), Error=()>>
+{
+    let ExportTask { id, sql, headers, format, path } = task;
+
+    let file = match File::create(&path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            exports.write().unwrap().finish(&id, Err(err.into()));
+            start_queued_exports(&exports, &*backend);
+            return Box::new(future::ok(()));
+        },
+    };
+
+    let rows_exports = exports.clone();
+    let rows_id = id.clone();
+    let df_stream = backend
+        .exec_sql_stream(sql)
+        .map(move |df_res| {
+            if let Ok(ref df) = df_res {
+                rows_exports.write().unwrap().add_progress(&rows_id, df.len(), 0);
+            }
+            df_res
+        });
+
+    let bytes_exports = exports.clone();
+    let bytes_id = id.clone();
+
+    // the query is dropped once the job timed out
+    Box::new(format_records_stream(headers, df_stream, format, false)
+        .fold(file, move |mut file, bytes| {
+            file.write_all(&bytes)?;
+            if !bytes_exports.write().unwrap().add_progress(&bytes_id, 0, bytes.len()) {
+                bail!("Export {} is not running anymore", bytes_id);
+            }
+            Ok::<_, Error>(file)
+        })
+        .and_then(|mut file| file.flush().map_err(Error::from))
+        .then(move |res| {
+            match res {
+                Ok(()) => info!("Finished export {}", id),
+                Err(ref err) => error!("Export {} failed: {}", id, err),
+            }
+
+            exports.write().unwrap().finish(&id, res);
+            start_queued_exports(&exports, &*backend);
+
+            Ok::<(), ()>(())
+        }))
+}
@@ -11,6 +11,7 @@ use actix_web::{
 };
 
 use crate::app::{AppState, SchemaSource};
+use crate::graphql::GraphqlSchema;
 use crate::schema_config;
 
 
@@ -57,7 +58,9 @@ pub fn flush_handler(req: HttpRequest<AppState>) -> ActixResult<HttpResponse> {
             },
         };
 
-        // Update shared schema
+        // Update shared schema, and the GraphQL schema generated from it
+        *req.state().graphql_schema.write().unwrap() = GraphqlSchema::from_schema(&schema);
+
         let mut w = req.state().schema.write().unwrap();
         *w = schema.clone();
 
@@ -0,0 +1,188 @@
+use actix_web::{
+    AsyncResponder,
+    Error as ActixError,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Json,
+};
+use failure::{Error, bail, format_err};
+use futures::future::{self, Future};
+use log::*;
+use serde_derive::Deserialize;
+use serde_json::{json, Map, Value as JsonValue};
+use std::collections::HashMap;
+
+use crate::app::AppState;
+use crate::graphql::{parse_document, substitute_variables, Field, Operation, Value};
+use super::util::{verify_authorization, query_sql};
+
+
+#[derive(Debug, Deserialize)]
+pub struct GraphqlRequest {
+    query: String,
+    variables: Option<Map<String, JsonValue>>,
+    #[serde(rename="operationName")]
+    operation_name: Option<String>,
+}
+
+
+/// Responds with the GraphQL schema of the cubes, in schema definition
+/// language.
+pub fn graphql_schema_handler(req: HttpRequest<AppState>) -> HttpResponse {
+    let sdl = req.state().graphql_schema.read().unwrap().sdl.clone();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(sdl)
+}
+
+
+/// Executes a GraphQL query. Each cube field runs as a separate aggregate
+/// query; a failed field is null in `data` and has an entry in `errors`.
+pub fn graphql_handler(
+    (req, body): (HttpRequest<AppState>, Json<GraphqlRequest>)
+    ) -> FutureResponse<HttpResponse>
+{
+    let body = body.into_inner();
+
+    let operation = match select_operation(&body) {
+        Ok(operation) => operation,
+        Err(err) => {
+            return Box::new(future::ok(
+                HttpResponse::BadRequest().json(json!({ "errors": [{ "message": err.to_string() }] }))
+            ));
+        },
+    };
+
+    // variables that aren't given take their default values
+    let mut variables: HashMap<String, Value> = HashMap::new();
+    for (name, default) in &operation.variables {
+        let value = body.variables.as_ref()
+            .and_then(|vars| vars.get(name))
+            .map(Value::from_json)
+            .or_else(|| default.clone())
+            .unwrap_or(Value::Null);
+        variables.insert(name.clone(), value);
+    }
+
+    let mut keys = vec![];
+    let mut results = vec![];
+
+    for field in &operation.selection {
+        let key = field.response_key().to_owned();
+        if keys.contains(&key) {
+            return Box::new(future::ok(
+                HttpResponse::BadRequest().json(json!({ "errors": [{ "message": format!("Duplicate field `{}`", key) }] }))
+            ));
+        }
+        keys.push(key.clone());
+
+        let field = substitute_variables(field, &variables);
+        results.push(resolve_field(&req, key, field));
+    }
+
+    future::join_all(results)
+        .map(|results| {
+            let mut data = Map::new();
+            let mut errors = vec![];
+
+            for (key, res) in results {
+                match res {
+                    Ok(value) => {
+                        data.insert(key, value);
+                    },
+                    Err(err) => {
+                        errors.push(json!({ "message": err, "path": [key.clone()] }));
+                        data.insert(key, JsonValue::Null);
+                    },
+                }
+            }
+
+            let mut res = Map::new();
+            res.insert("data".to_owned(), JsonValue::Object(data));
+            if !errors.is_empty() {
+                res.insert("errors".to_owned(), JsonValue::Array(errors));
+            }
+
+            HttpResponse::Ok().json(JsonValue::Object(res))
+        })
+        .responder()
+}
+
+
+fn select_operation(body: &GraphqlRequest) -> Result<Operation, Error> {
+    let mut operations = parse_document(&body.query)?;
+
+    match body.operation_name {
+        Some(ref name) => {
+            operations.into_iter()
+                .find(|op| op.name.as_ref() == Some(name))
+                .ok_or_else(|| format_err!("Unknown operation `{}`", name))
+        },
+        None => {
+            if operations.len() > 1 {
+                bail!("operationName is required for documents with several operations");
+            }
+            Ok(operations.remove(0))
+        },
+    }
+}
+
+
+/// Runs the query of one cube field.
+fn resolve_field(
+    req: &HttpRequest<AppState>,
+    key: String,
+    field: Field,
+    ) -> Box<dyn Future<Item=(String, Result<JsonValue, String>), Error=ActixError>>
+{
+    if field.name == "__typename" {
+        return Box::new(future::ok((key, Ok(JsonValue::String("Query".to_owned())))));
+    }
+
+    macro_rules! ok_or_field_error {
+        ($expr:expr) => {
+            match $expr {
+                Ok(val) => val,
+                Err(err) => return Box::new(future::ok((key, Err(err.to_string())))),
+            }
+        };
+    }
+
+    let cube_query = ok_or_field_error!(req.state().graphql_schema.read().unwrap().cube_query(&field));
+
+    let schema = req.state().schema.read().unwrap();
+    let cube = ok_or_field_error!(schema.get_cube_by_name(&cube_query.cube));
+
+    if verify_authorization(req, cube.min_auth_level).is_err() {
+        return Box::new(future::ok((key, Err("This cube is not public".to_owned()))));
+    }
+
+    info!("GraphQL field {}, cube: {}", key, cube_query.cube);
+
+    let (sql, _headers) = {
+        let cache = req.state().cache.read().unwrap();
+        ok_or_field_error!(query_sql(&schema, &cache, &*req.state().backend, &cube_query.cube, &cube_query.query))
+    };
+
+    let debug = req.state().debug;
+
+    Box::new(req.state()
+        .backend
+        .exec_sql(sql)
+        .then(move |df| {
+            let res = match df {
+                Ok(df) => Ok(cube_query.rows(&df)),
+                Err(err) => {
+                    if debug {
+                        Err(err.to_string())
+                    } else {
+                        Err("Internal Server Error 1010".to_owned())
+                    }
+                },
+            };
+
+            Ok((key, res))
+        }))
+}
@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::str;
 
-use actix_web::{AsyncResponder, FutureResponse, HttpRequest, HttpResponse, Path, Request};
+use actix_web::{AsyncResponder, FutureResponse, HttpRequest, HttpResponse, Json, Path, Request};
 use failure::{Error, format_err, bail};
 use futures::future;
 use futures::future::*;
@@ -9,25 +9,29 @@ use lazy_static::lazy_static;
 use log::*;
 use r2d2_redis::{redis};
 use serde_qs as qs;
-use serde_derive::Deserialize;
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
 use url::Url;
 
-use tesseract_core::names::{Cut, Drilldown, Property, Measure, LevelName, Mask};
+use tesseract_core::names::{Cut, Drilldown, Property, Measure, LevelName, Mask, MemberRange};
 use tesseract_core::format::{format_records, FormatType};
-use tesseract_core::query::{FilterQuery, GrowthQuery, RcaQuery, TopQuery, RateQuery};
+use tesseract_core::query::{FilterQuery, FilterExpr, GrowthQuery, CumulativeQuery, MovingAvgQuery, IndexQuery, RankQuery, RankMethod, TrendQuery, RcaQuery, TopQuery, RateQuery, GroupQuery, MemberGroup, BinsQuery};
 use tesseract_core::{Query as TsQuery, MeaOrCalc, DataFrame, Column, ColumnData, is_same_columndata_type};
 use tesseract_core::schema::{Cube, DimensionType};
+use tesseract_core::query_ir::{check_member, MemberType};
 
 use crate::app::AppState;
 use crate::errors::ServerError;
-use crate::logic_layer::{LogicLayerConfig, CubeCache, Time};
+use crate::logic_layer::{LogicLayerConfig, CubeCache, Time, TimePrecision};
 use super::super::util::{
     boxed_error_string, boxed_error_http_response,
     verify_authorization, format_to_content_type, generate_source_data,
     validate_members,
-    get_redis_cache_key, check_redis_cache, insert_into_redis_cache
+    get_redis_cache_key, get_redis_cache_key_json,
+    check_redis_cache, insert_into_redis_cache
 };
 use crate::handlers::logic_layer::{query_geoservice, GeoserviceQuery};
+use crate::handlers::query_body::AggregateQueryBody;
 
 
 macro_rules! some_or_bail {
@@ -68,7 +72,25 @@ pub fn logic_layer_handler(
 }
 
 
-#[derive(Debug, Clone, Deserialize)]
+/// Handles aggregation of a JSON query body when a format is not specified.
+pub fn logic_layer_post_default_handler(
+    (req, _cube, body): (HttpRequest<AppState>, Path<()>, Json<Value>)
+) -> FutureResponse<HttpResponse>
+{
+    logic_layer_body_aggregation(req, "jsonrecords".to_owned(), body.into_inner())
+}
+
+
+/// Handles aggregation of a JSON query body when a format is specified.
+pub fn logic_layer_post_handler(
+    (req, cube_format, body): (HttpRequest<AppState>, Path<(String)>, Json<Value>)
+) -> FutureResponse<HttpResponse>
+{
+    logic_layer_body_aggregation(req, cube_format.to_owned(), body.into_inner())
+}
+
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct LogicLayerQueryOpt {
     pub cube: String,
     pub drilldowns: Option<String>,
@@ -78,12 +100,23 @@ pub struct LogicLayerQueryOpt {
     measures: Option<String>,
     properties: Option<String>,
     filters: Option<String>,
+    filter: Option<String>,
     parents: Option<bool>,
     top: Option<String>,
+    top_others: Option<bool>,
     top_where: Option<String>,
     sort: Option<String>,
     limit: Option<String>,
     growth: Option<String>,
+    cumulative: Option<String>,
+    ytd: Option<String>,
+    qtd: Option<String>,
+    mtd: Option<String>,
+    moving_avg: Option<String>,
+    index: Option<String>,
+    rank: Option<String>,
+    trend: Option<String>,
+    totals: Option<String>,
     rca: Option<String>,
     debug: Option<bool>,
     exclude: Option<String>,
@@ -93,6 +126,9 @@ pub struct LogicLayerQueryOpt {
     //    nonempty: Option<bool>,
     sparse: Option<bool>,
     rate: Option<String>,
+    group: Option<String>,
+    group_others: Option<bool>,
+    bins: Option<String>,
 }
 
 
@@ -167,6 +203,86 @@ impl LogicLayerQueryOpt {
 }
 
 
+/// JSON body of a logic layer query: an aggregate query body (see
+/// `AggregateQueryBody`), with the `cube` and optional `locale`s of the
+/// logic layer. Levels may also be given by their logic layer names.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogicLayerQueryBody {
+    pub cube: String,
+    locale: Vec<String>,
+    query: AggregateQueryBody,
+}
+
+impl LogicLayerQueryBody {
+    /// `cube` and `locale` are taken out of `body`, and the rest must be an
+    /// aggregate query body.
+    pub fn from_json(body: Value) -> Result<Self, Error> {
+        let mut fields = match body {
+            Value::Object(fields) => fields,
+            _ => bail!("Query body must be a JSON object"),
+        };
+
+        let cube = match fields.remove("cube") {
+            Some(Value::String(cube)) => cube,
+            _ => bail!("Query body needs a `cube` name"),
+        };
+        let locale = match fields.remove("locale") {
+            Some(locale) => serde_json::from_value(locale)?,
+            None => vec![],
+        };
+
+        Ok(LogicLayerQueryBody {
+            cube,
+            locale,
+            query: serde_json::from_value(Value::Object(fields))?,
+        })
+    }
+
+    /// Looks up logic layer level names, and adds the captions in `locale`
+    /// for the drilldowns, like a query string would.
+    fn ts_query(self, cube: &Cube, cube_cache: &CubeCache) -> Result<TsQuery, Error> {
+        let level_map = &cube_cache.level_map;
+        let mut query = self.query.into_query(|name| {
+            level_map.get(name)
+                .cloned()
+                .ok_or_else(|| format_err!("Unrecognized level {}.", name))
+        })?;
+
+        let rca_drills = query.rca.iter().flat_map(|rca| vec![&rca.drill_1, &rca.drill_2]);
+        let mut captions = vec![];
+
+        for drill in query.drilldowns.iter().chain(rca_drills) {
+            let level = some_or_bail!(cube.get_level(&drill.0));
+            captions.extend(level.get_captions(&drill.0, &self.locale));
+
+            if query.parents {
+                captions.extend(get_parent_captions(cube, &drill.0, &self.locale));
+            }
+        }
+        query.captions.extend(captions);
+
+        Ok(query)
+    }
+}
+
+
+/// Query string options or a JSON body.
+#[derive(Debug)]
+enum LogicLayerRequest {
+    Opt(LogicLayerQueryOpt),
+    Body(LogicLayerQueryBody),
+}
+
+impl LogicLayerRequest {
+    fn cube(&self) -> &str {
+        match self {
+            LogicLayerRequest::Opt(opt) => &opt.cube,
+            LogicLayerRequest::Body(body) => &body.cube,
+        }
+    }
+}
+
+
 macro_rules! consolidate_column_data {
     ($col_data:expr, $col_type:ty) => {{
         $col_data.iter().map(|x| {
@@ -194,12 +310,44 @@ pub fn logic_layer_aggregation(
     req: HttpRequest<AppState>,
     format: String,
 ) -> FutureResponse<HttpResponse>
+{
+    let query = req.query_string();
+
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+
+    let agg_query_res = QS_NON_STRICT.deserialize_str::<LogicLayerQueryOpt>(query);
+    let agg_query = ok_or_404!(agg_query_res);
+
+    run_logic_layer_aggregation(req, format, LogicLayerRequest::Opt(agg_query))
+}
+
+
+/// Performs data aggregation for a query sent as a JSON body.
+pub fn logic_layer_body_aggregation(
+    req: HttpRequest<AppState>,
+    format: String,
+    body: Value,
+) -> FutureResponse<HttpResponse>
+{
+    let agg_query = ok_or_404!(LogicLayerQueryBody::from_json(body));
+
+    run_logic_layer_aggregation(req, format, LogicLayerRequest::Body(agg_query))
+}
+
+
+/// Validates and executes a logic layer query.
+fn run_logic_layer_aggregation(
+    req: HttpRequest<AppState>,
+    format: String,
+    agg_query: LogicLayerRequest,
+) -> FutureResponse<HttpResponse>
 {
     let format = ok_or_404!(format.parse::<FormatType>());
 
     info!("Format: {:?}", format);
 
-    let query = req.query_string();
     let schema = req.state().schema.read().unwrap();
     let debug = req.state().debug;
 
@@ -208,23 +356,16 @@ pub fn logic_layer_aggregation(
         None => None
     };
 
-    lazy_static!{
-        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
-    }
-
-    let agg_query_res = QS_NON_STRICT.deserialize_str::<LogicLayerQueryOpt>(query);
-    let agg_query = ok_or_404!(agg_query_res);
-
     // Check to see if the logic layer config has a alias with the
     // provided cube name
     let cube_name = match logic_layer_config.clone() {
         Some(llc) => {
-            match llc.substitute_cube_name(agg_query.cube.clone()) {
+            match llc.substitute_cube_name(agg_query.cube().to_owned()) {
                 Ok(cn) => cn,
-                Err(_) => agg_query.cube.clone()
+                Err(_) => agg_query.cube().to_owned()
             }
         },
-        None => agg_query.cube.clone()
+        None => agg_query.cube().to_owned()
     };
 
     let cube = ok_or_404!(schema.get_cube_by_name(&cube_name));
@@ -235,7 +376,13 @@ pub fn logic_layer_aggregation(
 
     // Check if this query is already cached
     let redis_pool = req.state().redis_pool.clone();
-    let redis_cache_key = get_redis_cache_key("logic-layer", &req, &cube_name, &format);
+    // body queries have no query string
+    let redis_cache_key = match agg_query {
+        LogicLayerRequest::Opt(_) => get_redis_cache_key("logic-layer", &req, &cube_name, &format),
+        LogicLayerRequest::Body(ref body) => {
+            ok_or_404!(get_redis_cache_key_json("logic-layer", &cube_name, &format, body))
+        },
+    };
 
     if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key) {
         return res;
@@ -253,12 +400,24 @@ pub fn logic_layer_aggregation(
     // Gets the Source Data
     let source_data = Some(generate_source_data(&cube));
 
-    // Turn AggregateQueryOpt into TsQuery
-    let ts_queries = generate_ts_queries(
-        agg_query.clone(), &cube, &cube_cache,
-        &logic_layer_config, &req.state().env_vars.geoservice_url
-    );
-    let (ts_queries, header_map) = ok_or_404!(ts_queries);
+    // Turn the query options or body into TsQuery
+    let (ts_queries, header_map, exclude_map) = match agg_query {
+        LogicLayerRequest::Opt(agg_query) => {
+            let exclude_map = agg_query.deserialize_exclude();
+            let ts_queries = generate_ts_queries(
+                agg_query, &cube, &cube_cache,
+                &logic_layer_config, &req.state().env_vars.geoservice_url
+            );
+            let (ts_queries, header_map) = ok_or_404!(ts_queries);
+
+            (ts_queries, header_map, exclude_map)
+        },
+        LogicLayerRequest::Body(body) => {
+            let ts_query = ok_or_404!(body.ts_query(&cube, &cube_cache));
+
+            (vec![ts_query], HashMap::new(), HashMap::new())
+        },
+    };
 
     if ts_queries.len() == 0 {
         return boxed_error_string("Unable to generate queries".to_string())
@@ -315,8 +474,6 @@ pub fn logic_layer_aggregation(
 
     debug!("Headers: {:?}", final_headers);
 
-    let exclude_map = agg_query.deserialize_exclude();
-
     // Joins all the futures for each TsQuery
     let futs: JoinAll<Vec<Box<dyn Future<Item=DataFrame, Error=Error>>>> = join_all(sql_strings
             .iter()
@@ -679,7 +836,21 @@ pub fn generate_ts_queries(
         }).collect())
         .unwrap_or(Ok(vec![]))?;
 
-    let top: Option<TopQuery> = agg_query_opt.top.clone()
+    let filter: Option<FilterExpr> = agg_query_opt.filter
+        .map(|f| f.parse::<FilterExpr>())
+        .transpose()?;
+
+    if let Some(ref filter) = filter {
+        for mea_or_calc in filter.mea_or_calcs() {
+            if let MeaOrCalc::Mea(mea) = mea_or_calc {
+                if !cube.measures.iter().any(|m| m.name == mea.0) {
+                    return Err(format_err!("The measure name '{}' in the `filter` param is not valid.", mea.0));
+                }
+            }
+        }
+    }
+
+    let mut top: Option<TopQuery> = agg_query_opt.top.clone()
         .map(|t| {
             let top_split: Vec<String> = t.split(',').map(|s| s.to_string()).collect();
 
@@ -699,6 +870,12 @@ pub fn generate_ts_queries(
             ))
         })
         .transpose()?;
+    if agg_query_opt.top_others.unwrap_or(false) {
+        match top {
+            Some(ref mut top) => top.others = true,
+            None => return Err(format_err!("top_others requires a top param.")),
+        }
+    }
     let top_where = agg_query_opt.top_where
         .map(|t| t.parse())
         .transpose()?;
@@ -715,27 +892,224 @@ pub fn generate_ts_queries(
 
             if gro_split.len() == 1 {
                 return Err(format_err!("Please provide a growth measure name."));
-            } else if gro_split.len() != 2 {
-                return Err(format_err!("Bad formatting for growth param."));
             }
 
             let level_key = gro_split[0].clone();
-            let measure = gro_split[1].clone();
 
             let level_name = some_or_bail!(level_map.get(&level_key));
 
-            let growth = GrowthQuery::new(
+            let time_drill = Drilldown::new(
                 level_name.dimension.clone(),
                 level_name.hierarchy.clone(),
                 level_name.level.clone(),
-                measure
             );
 
+            // measures, then `lag.n` or `cagr` options
+            let args: Vec<&str> = gro_split[1..].iter().map(|s| s.as_str()).collect();
+            let growth = GrowthQuery::from_args(time_drill, &args)?;
+
             Some(growth)
         },
         None => None
     };
 
+    let cumulative = match agg_query_opt.cumulative {
+        Some(c) => {
+            let cum_split: Vec<String> = c.split(',').map(|s| s.to_string()).collect();
+
+            if cum_split.len() == 1 {
+                return Err(format_err!("Please provide a cumulative measure name."));
+            } else if cum_split.len() != 2 {
+                return Err(format_err!("Bad formatting for cumulative param."));
+            }
+
+            let level_key = cum_split[0].clone();
+            let measure = cum_split[1].clone();
+
+            let level_name = some_or_bail!(level_map.get(&level_key));
+
+            let cumulative = CumulativeQuery::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                level_name.level.clone(),
+                measure
+            );
+
+            Some(cumulative)
+        },
+        None => None
+    };
+
+    // Period-to-date totals are cumulative over the drilldown on the time dimension
+    let to_date_params = vec![
+        (&agg_query_opt.ytd, TimePrecision::Year),
+        (&agg_query_opt.qtd, TimePrecision::Quarter),
+        (&agg_query_opt.mtd, TimePrecision::Month),
+    ];
+    let to_date_params: Vec<_> = to_date_params.into_iter()
+        .filter_map(|(measure, precision)| measure.clone().map(|m| (m, precision)))
+        .collect();
+
+    let cumulative = match (cumulative, &to_date_params[..]) {
+        (cumulative, []) => cumulative,
+        (None, [(measure, precision)]) => {
+            let time_drill = drilldowns.iter()
+                .find(|d| {
+                    cube.dimensions.iter()
+                        .any(|dim| dim.name == d.0.dimension && dim.dim_type == DimensionType::Time)
+                })
+                .ok_or_else(|| format_err!("Period to date needs a drilldown on a time dimension."))?;
+
+            let mut cumulative = CumulativeQuery::new(
+                time_drill.0.dimension.clone(),
+                time_drill.0.hierarchy.clone(),
+                time_drill.0.level.clone(),
+                measure.clone(),
+            );
+            cumulative.to_date = Some(precision.period_to_date()?);
+
+            Some(cumulative)
+        },
+        _ => return Err(format_err!("Only one of cumulative, ytd, qtd and mtd can be used.")),
+    };
+
+    let moving_avg = match agg_query_opt.moving_avg {
+        Some(m) => {
+            let mov_split: Vec<String> = m.split(',').map(|s| s.to_string()).collect();
+
+            if mov_split.len() != 3 {
+                return Err(format_err!("Bad formatting for moving_avg param; expected level,measure,window."));
+            }
+
+            let level_key = mov_split[0].clone();
+            let measure = mov_split[1].clone();
+            let window = mov_split[2].parse::<u64>()
+                .map_err(|_| format_err!("moving_avg window must be a positive integer."))?;
+
+            let level_name = some_or_bail!(level_map.get(&level_key));
+
+            let moving_avg = MovingAvgQuery::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                level_name.level.clone(),
+                measure,
+                window,
+            );
+
+            Some(moving_avg)
+        },
+        None => None
+    };
+
+    let index = match agg_query_opt.index {
+        Some(i) => {
+            let index_split: Vec<String> = i.split(',').map(|s| s.to_string()).collect();
+
+            if index_split.len() != 3 {
+                return Err(format_err!("Bad formatting for index param; expected level,member,measure."));
+            }
+
+            let level_key = index_split[0].clone();
+            let base = index_split[1].clone();
+            let measure = index_split[2].clone();
+
+            let level_name = some_or_bail!(level_map.get(&level_key));
+
+            // base is inlined into the sql, so it must be a known member
+            let members = some_or_bail!(cube_cache.members_for_level(level_name));
+            if !members.contains(&base) {
+                return Err(format_err!("Index base {} is not a member of {}.", base, level_key));
+            }
+
+            let index = IndexQuery::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                level_name.level.clone(),
+                base,
+                measure,
+            );
+
+            Some(index)
+        },
+        None => None
+    };
+
+    let rank = match agg_query_opt.rank {
+        Some(r) => {
+            let rank_split: Vec<String> = r.split(',').map(|s| s.to_string()).collect();
+
+            if rank_split.len() != 3 && rank_split.len() != 4 {
+                return Err(format_err!("Bad formatting for rank param; expected level,measure,asc|desc[,dense]."));
+            }
+
+            let level_key = rank_split[0].clone();
+            let measure = rank_split[1].clone();
+            let direction = rank_split[2].parse()?;
+            let method = match rank_split.get(3) {
+                Some(method) => method.parse()?,
+                None => RankMethod::Standard,
+            };
+
+            let level_name = some_or_bail!(level_map.get(&level_key));
+
+            let rank = RankQuery::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                level_name.level.clone(),
+                measure,
+                direction,
+                method,
+            );
+
+            Some(rank)
+        },
+        None => None
+    };
+
+    let trend = match agg_query_opt.trend {
+        Some(t) => {
+            let trend_split: Vec<String> = t.split(',').map(|s| s.to_string()).collect();
+
+            if trend_split.len() != 2 && trend_split.len() != 3 {
+                return Err(format_err!("Bad formatting for trend param; expected level,measure[,periods]."));
+            }
+
+            let level_key = trend_split[0].clone();
+            let measure = trend_split[1].clone();
+            let periods = match trend_split.get(2) {
+                Some(periods) => periods.parse::<u64>()
+                    .map_err(|_| format_err!("trend periods must be a non-negative integer."))?,
+                None => 0,
+            };
+
+            let level_name = some_or_bail!(level_map.get(&level_key));
+
+            let trend = TrendQuery::new(
+                level_name.dimension.clone(),
+                level_name.hierarchy.clone(),
+                level_name.level.clone(),
+                measure,
+                periods,
+            );
+
+            Some(trend)
+        },
+        None => None
+    };
+
+    let totals = match agg_query_opt.totals {
+        Some(t) => {
+            LogicLayerQueryOpt::deserialize_args(t).iter()
+                .map(|level_key| {
+                    level_map.get(level_key)
+                        .cloned()
+                        .ok_or_else(|| format_err!("Unrecognized level in totals: {}", level_key))
+                })
+                .collect::<Result<Vec<LevelName>, _>>()?
+        },
+        None => vec![]
+    };
+
     let rca = match agg_query_opt.rca {
         Some(r) => {
             let rca_split: Vec<String> = r.split(",").map(|s| s.to_string()).collect();
@@ -803,6 +1177,58 @@ pub fn generate_ts_queries(
         None => None
     };
 
+    // `Level:Name=members;NamedSet`, where a group without members is a
+    // named set of the level from the logic layer config
+    let mut group = match agg_query_opt.group {
+        Some(group) => {
+            let group_split: Vec<&str> = group.splitn(2, ':').collect();
+
+            if group_split.len() != 2 {
+                bail!("Bad formatting for group param, expected 'Level:Name=members;...'.");
+            }
+
+            let level_name = match level_map.get(group_split[0]) {
+                Some(level_name) => level_name.clone(),
+                None => bail!("Unrecognized level in group param.")
+            };
+
+            let mut groups: Vec<MemberGroup> = vec![];
+
+            for g in group_split[1].split(';') {
+                if g.contains('=') {
+                    groups.push(g.parse()?);
+                    continue;
+                }
+
+                let members = ll_config.as_ref()
+                    .and_then(|ll_conf| ll_conf.find_named_set(group_split[0], g));
+
+                match members {
+                    Some(members) => groups.push(MemberGroup { name: g.to_string(), members }),
+                    None => bail!("Group {} has no members and is not a named set for {}.", g, group_split[0])
+                }
+            }
+
+            Some(GroupQuery::new(level_name, groups))
+        },
+        None => None
+    };
+    if let Some(keep_others) = agg_query_opt.group_others {
+        match group {
+            Some(ref mut group) => group.keep_others = keep_others,
+            None => return Err(format_err!("group_others requires a group param.")),
+        }
+    }
+
+    let bins: Option<BinsQuery> = agg_query_opt.bins
+        .map(|b| b.parse())
+        .transpose()?;
+    if let Some(ref bins) = bins {
+        if !cube.measures.iter().any(|m| m.name == bins.measure.0) {
+            bail!("Unrecognized measure {} in bins.", bins.measure);
+        }
+    }
+
     let debug = agg_query_opt.debug.unwrap_or(false);
     let sparse = agg_query_opt.sparse.unwrap_or(false);
     let exclude_default_members = agg_query_opt.exclude_default_members.unwrap_or(false);
@@ -814,6 +1240,9 @@ pub fn generate_ts_queries(
         &cuts_map, &cube, &cube_cache, &level_map, &property_map, &geoservice_url
     )?;
 
+    // Property cuts apply to every query
+    let property_cuts = resolve_property_cuts(&cuts_map, &cube_cache, &level_map, &property_map)?;
+
     // Groups together cuts for the same dimension
     // This is needed so we can generate all the possible cut combinations in the next step
     let mut dimension_cuts: Vec<Vec<Cut>> = vec![];
@@ -829,18 +1258,39 @@ pub fn generate_ts_queries(
         let num_level_cuts = level_cuts_map.len();
 
         for (level_name, level_cuts) in level_cuts_map.iter() {
-            let cut = Cut {
-                level_name: level_name.clone(),
-                members: level_cuts.clone(),
-                mask: Mask::Include,
-                for_match: false
-            };
+            // Ranges can't be combined with member lists in one cut,
+            // so each range gets its own cut
+            let mut members = vec![];
+            for level_cut in level_cuts {
+                match MemberRange::parse(level_cut)? {
+                    Some(range) => {
+                        inner_cuts.push(Cut {
+                            level_name: level_name.clone(),
+                            members: vec![],
+                            mask: Mask::Include,
+                            for_match: false,
+                            range: Some(range),
+                            property: None,
+                        });
+                    },
+                    None => members.push(level_cut.clone()),
+                }
+            }
 
-            inner_cuts.push(cut.clone());
+            if !members.is_empty() {
+                inner_cuts.push(Cut {
+                    level_name: level_name.clone(),
+                    members,
+                    mask: Mask::Include,
+                    for_match: false,
+                    range: None,
+                    property: None,
+                });
+            }
 
             if num_level_cuts > 1 {
                 // We're doing multiple cuts on this dimension
-                added_drilldowns.push(cut.level_name.clone());
+                added_drilldowns.push(level_name.clone());
             }
         }
 
@@ -856,7 +1306,7 @@ pub fn generate_ts_queries(
     if cut_combinations.len() == 0 {
         queries.push(TsQuery {
             drilldowns: drilldowns.clone(),
-            cuts: vec![],
+            cuts: property_cuts.clone(),
             measures: measures.clone(),
             parents: parents.clone(),
             properties: properties.clone(),
@@ -867,10 +1317,19 @@ pub fn generate_ts_queries(
             limit: limit.clone(),
             rca: rca.clone(),
             growth: growth.clone(),
+            cumulative: cumulative.clone(),
+            moving_avg: moving_avg.clone(),
+            index: index.clone(),
+            rank: rank.clone(),
+            trend: trend.clone(),
+            totals: totals.clone(),
             debug: debug.clone(),
             exclude_default_members: exclude_default_members.clone(),
             filters: filters.clone(),
+            filter: filter.clone(),
             rate: rate.clone(),
+            group: group.clone(),
+            bins: bins.clone(),
             sparse: sparse.clone(),
         });
     } else {
@@ -892,10 +1351,13 @@ pub fn generate_ts_queries(
                 }
             }
 
+            let mut cuts = cut_combination.clone();
+            cuts.extend_from_slice(&property_cuts);
+
             // Populate queries vector
             queries.push(TsQuery {
                 drilldowns: drills,
-                cuts: cut_combination.clone(),
+                cuts,
                 measures: measures.clone(),
                 parents: parents.clone(),
                 properties: properties.clone(),
@@ -906,10 +1368,19 @@ pub fn generate_ts_queries(
                 limit: limit.clone(),
                 rca: rca.clone(),
                 growth: growth.clone(),
+                cumulative: cumulative.clone(),
+                moving_avg: moving_avg.clone(),
+                index: index.clone(),
+                rank: rank.clone(),
+                trend: trend.clone(),
+                totals: totals.clone(),
                 debug: debug.clone(),
                 exclude_default_members: exclude_default_members.clone(),
                 filters: filters.clone(),
+                filter: filter.clone(),
                 rate: rate.clone(),
+                group: group.clone(),
+                bins: bins.clone(),
                 sparse: sparse.clone(),
             });
         }
@@ -977,6 +1448,15 @@ pub fn clean_cuts_map(
                     return Err(format_err!("Malformatted time cut"));
                 }
 
+                // Ranges use the same syntax as cuts, e.g. `year.[2010:2015]`
+                if MemberRange::parse(&tc[1])?.is_some() {
+                    let precision = TimePrecision::from_str(tc[0].clone())?;
+                    let level = cube_cache.get_time_level_name(&precision)?;
+
+                    agg_query_opt_cuts.insert(level, tc[1].clone());
+                    continue;
+                }
+
                 let time = match Time::from_key_value(tc[0].clone(), tc[1].clone()) {
                     Ok(time) => time,
                     Err(err) => return Err(format_err!("{}", err.to_string()))
@@ -1029,6 +1509,49 @@ pub fn clean_cuts_map(
 }
 
 
+/// Resolves cuts whose key is a unique property name into cuts on that
+/// property. Keys that also name a dimension or level are left to
+/// `resolve_cuts`.
+pub fn resolve_property_cuts(
+        cuts_map: &HashMap<String, String>,
+        cube_cache: &CubeCache,
+        level_map: &HashMap<String, LevelName>,
+        property_map: &HashMap<String, Property>,
+) -> Result<Vec<Cut>, Error> {
+    let mut property_cuts: Vec<Cut> = vec![];
+
+    for (cut_key, cut_values) in cuts_map.iter() {
+        if cut_values.is_empty()
+            || level_map.contains_key(cut_key)
+            || cube_cache.dimension_caches.contains_key(cut_key)
+        {
+            continue;
+        }
+
+        let property = match property_map.get(cut_key) {
+            Some(property) => property,
+            None => continue
+        };
+
+        // property values aren't cached, so can't be checked against members
+        let members: Vec<String> = cut_values.split(",").map(|s| s.to_string()).collect();
+        for member in &members {
+            check_member(member, &MemberType::Text)?;
+        }
+
+        property_cuts.push(Cut {
+            level_name: property.level_name.clone(),
+            members,
+            mask: Mask::Include,
+            for_match: false,
+            range: None,
+            property: Some(property.property.clone()),
+        });
+    }
+
+    Ok(property_cuts)
+}
+
 /// Implements logic to resolve logic layer cuts (including those with operations)
 /// into a HashMap separating cuts for each dimension. Doing so helps generate all
 /// the possible cut combinations in the next step.
@@ -1067,6 +1590,19 @@ pub fn resolve_cuts(
         let cut_values: Vec<String> = cut_values.split(",").map(|s| s.to_string()).collect();
 
         for cut_value in &cut_values {
+            // Ranges are passed through as-is, and only apply to levels
+            if MemberRange::parse(cut_value)?.is_some() {
+                let level_name = match level_map.get(cut_key) {
+                    Some(level_name) => level_name.clone(),
+                    None => return Err(format_err!("Range cuts must be on a level, {} is not a level.", cut_key))
+                };
+
+                level_matches.push(level_name.clone());
+                header_map.entry(level_name.level.clone()).or_insert(level_name.dimension.clone());
+                dimension_cuts_map = add_cut_entries(dimension_cuts_map, &level_name, vec![cut_value.clone()]);
+                continue;
+            }
+
             let elements: Vec<String> = cut_value.clone().split(":").map(|s| s.to_string()).collect();
 
             let cut = match elements.get(0) {
@@ -1315,3 +1851,31 @@ pub fn get_parent_captions(cube: &Cube, level_name: &LevelName, locales: &Vec<St
 
     captions
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_body_from_json() {
+        let body = LogicLayerQueryBody::from_json(json!({
+            "cube": "sales",
+            "locale": ["es"],
+            "drilldowns": ["Year", { "dimension": "Product", "hierarchy": "Product", "level": "Product" }],
+            "cuts": [{ "level": "Year", "members": ["2017", "2018"] }],
+            "measures": ["Quantity"],
+            "parents": true,
+        })).unwrap();
+
+        assert_eq!(body.cube, "sales");
+        assert_eq!(body.locale, vec!["es".to_owned()]);
+
+        // the rest is an aggregate query body
+        assert!(LogicLayerQueryBody::from_json(json!({ "cube": "sales", "drilldowns": "Year,Product" })).is_err());
+        assert!(LogicLayerQueryBody::from_json(json!({ "cube": "sales", "Year": "2017" })).is_err());
+        assert!(LogicLayerQueryBody::from_json(json!({ "drilldowns": ["Year"] })).is_err());
+        assert!(LogicLayerQueryBody::from_json(json!(["sales"])).is_err());
+    }
+}
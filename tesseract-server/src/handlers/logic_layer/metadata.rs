@@ -146,8 +146,8 @@ pub fn get_members(
     debug!("{:?}", level_name);
 
     let members_sql_and_headers = match members_query.locale {
-        Some(locale) => schema.members_locale_sql(&cube_name, &level_name, &locale),
-        None => schema.members_sql(&cube_name, &level_name)
+        Some(locale) => schema.members_locale_sql(&cube_name, &level_name, &locale, req.state().backend.as_ref()),
+        None => schema.members_sql(&cube_name, &level_name, req.state().backend.as_ref())
     };
 
     let (members_sql, header) = match members_sql_and_headers {
@@ -180,7 +180,7 @@ pub fn get_members(
 }
 
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct MembersQueryOpt {
     pub cube: String,
     pub level: String,
@@ -5,13 +5,18 @@ mod relations;
 
 pub use self::aggregate::logic_layer_handler;
 pub use self::aggregate::logic_layer_default_handler;
+pub use self::aggregate::logic_layer_post_handler;
+pub use self::aggregate::logic_layer_post_default_handler;
+pub use self::aggregate::LogicLayerQueryOpt;
 pub use self::geoservice::GeoserviceQuery;
 pub use self::geoservice::GeoServiceResponseJson;
 pub use self::geoservice::query_geoservice;
 pub use self::metadata::logic_layer_members_handler;
 pub use self::metadata::logic_layer_members_default_handler;
+pub use self::metadata::MembersQueryOpt as LogicLayerMembersQueryOpt;
 pub use self::relations::logic_layer_relations_handler;
 pub use self::relations::logic_layer_relations_default_handler;
+pub use self::relations::LogicLayerRelationQueryOpt;
 
 use actix_web::{HttpRequest, HttpResponse, Path, ResponseError};
 use crate::app::AppState;
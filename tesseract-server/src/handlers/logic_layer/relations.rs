@@ -11,7 +11,7 @@ use failure::{Error, format_err};
 use lazy_static::lazy_static;
 use log::*;
 use serde_qs as qs;
-use serde_derive::Deserialize;
+use serde_derive::{Serialize, Deserialize};
 use url::Url;
 
 use tesseract_core::names::{Property, LevelName};
@@ -43,7 +43,7 @@ pub fn logic_layer_relations_handler(
 }
 
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct LogicLayerRelationQueryOpt {
     pub cube: String,
     #[serde(flatten)]
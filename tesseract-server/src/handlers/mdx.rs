@@ -0,0 +1,139 @@
+use actix_web::{
+    AsyncResponder,
+    FutureResponse,
+    HttpRequest,
+    HttpResponse,
+    Path,
+};
+use futures::future::{self, Future};
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::{Serialize, Deserialize};
+use serde_qs as qs;
+use tesseract_core::format::{format_records, FormatType};
+use tesseract_core::mdx::MdxQuery;
+
+use crate::app::AppState;
+use crate::errors::ServerError;
+use super::aggregate::query_sql;
+use super::util::{
+    boxed_error_http_response, verify_authorization,
+    format_to_content_type, generate_source_data,
+    get_redis_cache_key_json, check_redis_cache, insert_into_redis_cache
+};
+
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MdxQueryOpt {
+    query: String,
+}
+
+
+/// Handles an MDX query in the `query` param when a format is not
+/// specified. Default format is CSV.
+pub fn mdx_default_handler(req: HttpRequest<AppState>) -> FutureResponse<HttpResponse> {
+    do_mdx_query_string(req, "csv".to_owned())
+}
+
+
+/// Handles an MDX query in the `query` param when a format is specified.
+pub fn mdx_handler(
+    (req, format): (HttpRequest<AppState>, Path<String>)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_mdx_query_string(req, format.into_inner())
+}
+
+
+/// Handles an MDX query sent as the request body when a format is not
+/// specified. Default format is CSV.
+pub fn mdx_post_default_handler(
+    (req, body): (HttpRequest<AppState>, String)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_mdx(req, "csv".to_owned(), MdxQueryOpt { query: body })
+}
+
+
+/// Handles an MDX query sent as the request body when a format is specified.
+pub fn mdx_post_handler(
+    (req, format, body): (HttpRequest<AppState>, Path<String>, String)
+    ) -> FutureResponse<HttpResponse>
+{
+    do_mdx(req, format.into_inner(), MdxQueryOpt { query: body })
+}
+
+
+fn do_mdx_query_string(
+    req: HttpRequest<AppState>,
+    format: String,
+    ) -> FutureResponse<HttpResponse>
+{
+    lazy_static!{
+        static ref QS_NON_STRICT: qs::Config = qs::Config::new(5, false);
+    }
+    let mdx_query_res = QS_NON_STRICT.deserialize_str::<MdxQueryOpt>(req.query_string());
+    let mdx_query = ok_or_404!(mdx_query_res);
+
+    do_mdx(req, format, mdx_query)
+}
+
+
+/// Translates an MDX query into an aggregate query on its cube, and executes
+/// it. Cuts are validated and results cached as for the aggregate endpoint.
+fn do_mdx(
+    req: HttpRequest<AppState>,
+    format: String,
+    mdx_query: MdxQueryOpt,
+    ) -> FutureResponse<HttpResponse>
+{
+    let MdxQuery { cube, query: ts_query } = ok_or_404!(mdx_query.query.parse::<MdxQuery>());
+
+    let schema = &req.state().schema.read().unwrap().clone();
+    let cube_obj = ok_or_404!(schema.get_cube_by_name(&cube));
+
+    if let Err(err) = verify_authorization(&req, cube_obj.min_auth_level) {
+        return boxed_error_http_response(err);
+    }
+
+    let format = ok_or_404!(format.parse::<FormatType>());
+
+    info!("MDX query on cube: {}, format: {:?}", cube, format);
+
+    let redis_pool = req.state().redis_pool.clone();
+    let redis_cache_key = ok_or_404!(get_redis_cache_key_json("mdx", &cube, &format, &mdx_query));
+
+    if let Some(res) = check_redis_cache(&format, &redis_pool, &redis_cache_key) {
+        return res;
+    }
+
+    let source_data = Some(generate_source_data(&cube_obj));
+
+    let (sql, headers) = ok_or_404!(query_sql(req.state(), schema, &cube, &ts_query));
+
+    req.state()
+        .backend
+        .exec_sql(sql)
+        .and_then(move |df| {
+            let content_type = format_to_content_type(&format);
+
+            match format_records(&headers, df, format, source_data, false) {
+                Ok(res) => {
+                    insert_into_redis_cache(&res, &redis_pool, &redis_cache_key);
+
+                    Ok(HttpResponse::Ok()
+                        .set(content_type)
+                        .body(res))
+                },
+                Err(err) => Ok(HttpResponse::NotFound().json(err.to_string())),
+            }
+        })
+        .map_err(move |e| {
+            if req.state().debug {
+                ServerError::Db { cause: e.to_string() }.into()
+            } else {
+                ServerError::Db { cause: "Internal Server Error 1010".to_owned() }.into()
+            }
+        })
+        .responder()
+}
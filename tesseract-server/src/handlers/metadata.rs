@@ -10,7 +10,7 @@ use actix_web::{
 use futures::future::{self, Future};
 use lazy_static::lazy_static;
 use log::*;
-use serde_derive::Deserialize;
+use serde_derive::{Serialize, Deserialize};
 use serde_qs as qs;
 use tesseract_core::format::{format_records, FormatType};
 use tesseract_core::names::{LevelName, Property};
@@ -180,7 +180,7 @@ pub fn do_members(
     info!("Members for cube: {}, level: {}", cube, level);
 
     let members_sql_and_headers = req.state().schema.read().unwrap()
-        .members_sql(&cube, &level);
+        .members_sql(&cube, &level, req.state().backend.as_ref());
 
     let (members_sql, header) = ok_or_400!(members_sql_and_headers);
 
@@ -198,7 +198,7 @@ pub fn do_members(
 }
 
 
-#[derive(Debug, Deserialize)]
-struct MembersQueryOpt {
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MembersQueryOpt {
     level: String,
 }
@@ -2,31 +2,54 @@
 mod util;
 mod aggregate;
 mod aggregate_stream;
+mod batch;
 mod diagnosis;
+mod exports;
 mod flush;
+mod graphql;
 mod index;
+mod mdx;
 mod metadata;
+mod openapi;
+mod query_body;
 pub mod logic_layer;
 
 pub use self::aggregate::aggregate_handler;
 pub use self::aggregate::aggregate_default_handler;
+pub use self::aggregate::aggregate_post_handler;
+pub use self::aggregate::aggregate_post_default_handler;
+pub use self::query_body::AggregateQueryBody;
 pub use self::aggregate_stream::aggregate_handler as aggregate_stream_handler;
 pub use self::aggregate_stream::aggregate_default_handler as aggregate_stream_default_handler;
+pub use self::batch::batch_handler;
 pub use self::diagnosis::diagnosis_handler;
 pub use self::diagnosis::diagnosis_default_handler;
 pub use self::logic_layer::logic_layer_handler;
 pub use self::logic_layer::logic_layer_default_handler;
+pub use self::logic_layer::logic_layer_post_handler;
+pub use self::logic_layer::logic_layer_post_default_handler;
 pub use self::logic_layer::logic_layer_non_unique_levels_handler;
 pub use self::logic_layer::logic_layer_non_unique_levels_default_handler;
 pub use self::logic_layer::logic_layer_members_handler;
 pub use self::logic_layer::logic_layer_members_default_handler;
+pub use self::exports::export_create_handler;
+pub use self::exports::export_status_handler;
+pub use self::exports::export_download_handler;
 pub use self::flush::flush_handler;
+pub use self::graphql::graphql_handler;
+pub use self::graphql::graphql_schema_handler;
 pub use self::index::index_handler;
+pub use self::mdx::mdx_handler;
+pub use self::mdx::mdx_default_handler;
+pub use self::mdx::mdx_post_handler;
+pub use self::mdx::mdx_post_default_handler;
 pub use self::metadata::members_handler;
 pub use self::metadata::members_default_handler;
 pub use self::metadata::metadata_handler;
 pub use self::metadata::metadata_all_handler;
+pub use self::openapi::openapi_handler;
 pub use self::logic_layer::logic_layer_relations_handler;
 pub use self::logic_layer::logic_layer_relations_default_handler;
 pub use self::logic_layer::logic_layer_relations_non_unique_levels_default_handler;
 pub use self::logic_layer::logic_layer_relations_non_unique_levels_handler;
+pub use self::util::{query_sql, validate_members};
@@ -0,0 +1,583 @@
+use actix_web::{
+    HttpRequest,
+    HttpResponse,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use tesseract_core::{CubeHasUniqueLevelsAndProperties, Schema, DEFAULT_ALLOWED_ACCESS};
+use tesseract_core::schema::Cube;
+
+use crate::app::AppState;
+use super::aggregate::AggregateQueryOpt;
+use super::diagnosis::DiagnosisQueryOpt;
+use super::mdx::MdxQueryOpt;
+use super::logic_layer::{LogicLayerQueryOpt, LogicLayerMembersQueryOpt, LogicLayerRelationQueryOpt};
+use super::metadata::MembersQueryOpt;
+use super::util::get_user_auth_level;
+
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// Formats accepted in the `{format}` path segment.
+const FORMATS: &[&str] = &["csv", "jsonrecords", "jsonarrays", "sdmx", "jsonstat"];
+
+
+/// Responds with an OpenAPI description of the HTTP API. Cubes that the
+/// user isn't authorized to see are left out, the same as in `/cubes`.
+pub fn openapi_handler(req: HttpRequest<AppState>) -> HttpResponse {
+    let user_auth_level = get_user_auth_level(&req);
+    let schema = req.state().schema.read().unwrap();
+
+    let cubes: Vec<&Cube> = schema.cubes.iter()
+        .filter(|cube| {
+            match user_auth_level {
+                Some(auth_level) => auth_level >= cube.min_auth_level && auth_level >= DEFAULT_ALLOWED_ACCESS,
+                None => true,
+            }
+        })
+        .collect();
+
+    let logic_layer = match req.state().has_unique_levels_properties {
+        CubeHasUniqueLevelsAndProperties::True => true,
+        CubeHasUniqueLevelsAndProperties::False { .. } => false,
+    };
+
+    HttpResponse::Ok().json(openapi_document(&schema, &cubes, logic_layer))
+}
+
+
+/// Type of a query parameter, as accepted by the query option struct.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParamType {
+    String,
+    Boolean,
+    Array,
+}
+
+impl ParamType {
+    fn example(self) -> Value {
+        match self {
+            ParamType::String => json!("x"),
+            ParamType::Boolean => json!(true),
+            ParamType::Array => json!(["x"]),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct QueryParam {
+    name: String,
+    param_type: ParamType,
+    required: bool,
+}
+
+/// Derives the query parameters of a query option struct.
+///
+/// Field names are taken from the serialized default value, where optional
+/// fields are null and required fields aren't. The type of each field is
+/// found by trying to deserialize a value of each type into it.
+fn query_params<T>() -> Vec<QueryParam>
+    where T: Default + Serialize + DeserializeOwned
+{
+    let fields = match serde_json::to_value(T::default()) {
+        Ok(Value::Object(fields)) => fields,
+        _ => return vec![],
+    };
+
+    // required fields keep their default value while probing the others
+    let required: Map<String, Value> = fields.iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    fields.iter()
+        .map(|(name, value)| {
+            let param_type = [ParamType::String, ParamType::Boolean, ParamType::Array].iter()
+                .cloned()
+                .find(|param_type| {
+                    let mut probe = required.clone();
+                    probe.insert(name.clone(), param_type.example());
+                    serde_json::from_value::<T>(Value::Object(probe)).is_ok()
+                })
+                .unwrap_or(ParamType::String);
+
+            QueryParam {
+                name: name.clone(),
+                param_type,
+                required: !value.is_null(),
+            }
+        })
+        .collect()
+}
+
+
+/// Builds the OpenAPI document for the given cubes. Paths of the logic
+/// layer are only included when it's enabled.
+fn openapi_document(schema: &Schema, cubes: &[&Cube], logic_layer: bool) -> Value {
+    let cube_names: Vec<String> = cubes.iter().map(|cube| cube.name.clone()).collect();
+    let cube_schema = enum_schema(cube_names);
+
+    let mut paths = Map::new();
+    let mut components = Map::new();
+
+    paths.insert("/cubes".to_owned(), json!({
+        "get": operation("Metadata for all cubes", vec![]),
+    }));
+    paths.insert("/cubes/{cube}".to_owned(), json!({
+        "get": operation("Metadata for one cube", vec![path_param("cube", &cube_schema)]),
+    }));
+
+    let mut members_params = vec![path_param("cube", &cube_schema), format_param()];
+    members_params.extend(parameters(&query_params::<MembersQueryOpt>(), &[]));
+    paths.insert("/cubes/{cube}/members.{format}".to_owned(), json!({
+        "get": operation("Members of a level", members_params),
+    }));
+
+    for cube in cubes {
+        let component = component_name(&cube.name);
+
+        let mut levels = vec![];
+        let mut properties = vec![];
+        for dimension in &cube.dimensions {
+            for hierarchy in &dimension.hierarchies {
+                for level in &hierarchy.levels {
+                    levels.push(qualified_name(&[&dimension.name, &hierarchy.name, &level.name]));
+
+                    for property in level.properties.iter().flatten() {
+                        properties.push(qualified_name(&[&dimension.name, &hierarchy.name, &level.name, &property.name]));
+                    }
+                }
+            }
+        }
+        let measures = cube.measures.iter().map(|measure| measure.name.clone()).collect();
+
+        let mut items = vec![];
+        for (field, suffix, names) in vec![("drilldowns", "Level", levels), ("measures", "Measure", measures), ("properties", "Property", properties)] {
+            let name = format!("{}{}", component, suffix);
+            components.insert(name.clone(), enum_schema(names));
+            items.push((field, json!({ "$ref": format!("#/components/schemas/{}", name) })));
+        }
+
+        let measure_schema = json!({ "$ref": format!("#/components/schemas/{}Measure", component) });
+
+        let agg_params = query_params::<AggregateQueryOpt>();
+
+        let mut get_params = vec![format_param()];
+        get_params.extend(parameters(&agg_params, &items));
+
+        let mut post = operation(&format!("Aggregate query on {}, with a JSON body", cube.name), vec![format_param()]);
+        post["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": aggregate_body_schema(&level_name_schema(), &measure_schema) } },
+        });
+
+        paths.insert(format!("/cubes/{}/aggregate.{{format}}", encode_path_segment(&cube.name)), json!({
+            "get": operation(&format!("Aggregate query on {}", cube.name), get_params),
+            "post": post,
+        }));
+    }
+
+    components.extend(aggregate_body_components());
+
+    let mut mdx_params = vec![format_param()];
+    mdx_params.extend(parameters(&query_params::<MdxQueryOpt>(), &[]));
+    let mut mdx_post = operation("MDX query, with the query as the body", vec![format_param()]);
+    mdx_post["requestBody"] = json!({
+        "required": true,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    });
+    paths.insert("/mdx.{format}".to_owned(), json!({
+        "get": operation("MDX query", mdx_params),
+        "post": mdx_post,
+    }));
+
+    if logic_layer {
+        let cube_items = [("cube", cube_schema.clone())];
+
+        let ll_params = query_params::<LogicLayerQueryOpt>();
+
+        let mut get_params = vec![format_param()];
+        get_params.extend(parameters(&ll_params, &cube_items));
+        get_params.push(cuts_param());
+
+        // levels may also be logic layer names, and `cube` and `locale` are
+        // taken out before the rest is read as an aggregate query body
+        let level = json!({ "oneOf": [level_name_schema(), { "type": "string" }] });
+        let mut body = aggregate_body_schema(&level, &json!({ "type": "string" }));
+        body["properties"]["cube"] = cube_schema.clone();
+        body["properties"]["locale"] = json!({ "type": "array", "items": { "type": "string" } });
+        body["required"] = json!(["cube"]);
+
+        let mut post = operation("Logic layer query, with a JSON aggregate query body", vec![format_param()]);
+        post["requestBody"] = json!({
+            "required": true,
+            "content": { "application/json": { "schema": body } },
+        });
+
+        paths.insert("/data.{format}".to_owned(), json!({
+            "get": operation("Logic layer query", get_params),
+            "post": post,
+        }));
+
+        let mut members_params = vec![format_param()];
+        members_params.extend(parameters(&query_params::<LogicLayerMembersQueryOpt>(), &cube_items));
+        paths.insert("/members.{format}".to_owned(), json!({
+            "get": operation("Members of a level, with the logic layer", members_params),
+        }));
+
+        let mut relations_params = vec![format_param()];
+        relations_params.extend(parameters(&query_params::<LogicLayerRelationQueryOpt>(), &cube_items));
+        relations_params.push(cuts_param());
+        paths.insert("/relations.{format}".to_owned(), json!({
+            "get": operation("Relations of members", relations_params),
+        }));
+    }
+
+    let mut diagnosis_params = vec![format_param()];
+    diagnosis_params.extend(parameters(&query_params::<DiagnosisQueryOpt>(), &[("cube", cube_schema)]));
+    paths.insert("/diagnosis.{format}".to_owned(), json!({
+        "get": operation("Diagnosis of the schema against the database", diagnosis_params),
+    }));
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": schema.name,
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": components },
+    })
+}
+
+
+fn operation(summary: &str, parameters: Vec<Value>) -> Value {
+    json!({
+        "summary": summary,
+        "parameters": parameters,
+        "responses": {
+            "200": { "description": "Successful response" },
+            "default": { "description": "Error message" },
+        },
+    })
+}
+
+
+fn path_param(name: &str, schema: &Value) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": schema,
+    })
+}
+
+
+fn format_param() -> Value {
+    path_param("format", &enum_schema(FORMATS.iter().map(|format| format.to_string()).collect()))
+}
+
+
+/// Logic layer cuts are query parameters named after a level, with a
+/// comma-separated list of members.
+fn cuts_param() -> Value {
+    json!({
+        "name": "cuts",
+        "in": "query",
+        "style": "form",
+        "explode": true,
+        "schema": {
+            "type": "object",
+            "additionalProperties": { "type": "string" },
+        },
+    })
+}
+
+
+/// Schema of a parameter. `items` holds schemas for some parameters by
+/// name, which replace the string schema of the parameter or of its items.
+fn param_schema(param: &QueryParam, items: &[(&str, Value)]) -> Value {
+    let string_schema = items.iter()
+        .find(|(name, _)| *name == param.name)
+        .map(|(_, schema)| schema.clone())
+        .unwrap_or_else(|| json!({ "type": "string" }));
+
+    match param.param_type {
+        ParamType::String => string_schema,
+        ParamType::Boolean => json!({ "type": "boolean" }),
+        ParamType::Array => json!({ "type": "array", "items": string_schema }),
+    }
+}
+
+
+/// Query parameters; arrays are sent as repeated `name[]` parameters.
+fn parameters(params: &[QueryParam], items: &[(&str, Value)]) -> Vec<Value> {
+    params.iter()
+        .map(|param| {
+            let name = match param.param_type {
+                ParamType::Array => format!("{}[]", param.name),
+                _ => param.name.clone(),
+            };
+
+            json!({
+                "name": name,
+                "in": "query",
+                "required": param.required,
+                "schema": param_schema(param, items),
+            })
+        })
+        .collect()
+}
+
+
+/// Schema of an aggregate query JSON body (see `AggregateQueryBody`), with
+/// `level` and `measure` as the schemas of level and measure names. Names
+/// that may also be calculations are plain strings.
+fn aggregate_body_schema(level: &Value, measure: &Value) -> Value {
+    let property = json!({ "$ref": "#/components/schemas/Property" });
+    let constraint = json!({ "$ref": "#/components/schemas/Constraint" });
+    let string = json!({ "type": "string" });
+    let strings = json!({ "type": "array", "items": string });
+    let boolean = json!({ "type": "boolean" });
+    let integer = json!({ "type": "integer", "minimum": 0 });
+    let direction = enum_schema(vec!["asc".to_owned(), "desc".to_owned()]);
+
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "drilldowns": { "type": "array", "items": level },
+            "cuts": { "type": "array", "items": object(json!({
+                "level": level,
+                "members": strings,
+                "range": { "type": "object", "properties": { "start": string, "end": string } },
+                "property": string,
+                "exclude": boolean,
+                "for_match": boolean,
+            }), &["level"]) },
+            "measures": { "type": "array", "items": measure },
+            "properties": { "type": "array", "items": property },
+            "filters": { "type": "array", "items": object(json!({
+                "measure": string,
+                "constraint": constraint,
+                "operator": enum_schema(vec!["and".to_owned(), "or".to_owned()]),
+                "constraint2": constraint,
+            }), &["measure", "constraint"]) },
+            "filter": { "$ref": "#/components/schemas/FilterExpr" },
+            "captions": { "type": "array", "items": property },
+            "parents": boolean,
+            "top": object(json!({
+                "n": integer,
+                "by_dimension": level,
+                "measure": string,
+                "direction": direction,
+                "others": boolean,
+            }), &["n", "by_dimension", "measure", "direction"]),
+            "top_where": object(json!({ "measure": string, "constraint": constraint }), &["measure", "constraint"]),
+            "sort": object(json!({ "measure": string, "direction": direction }), &["measure", "direction"]),
+            "limit": object(json!({ "n": integer, "offset": integer }), &["n"]),
+            "rca": object(json!({ "drill_1": level, "drill_2": level, "measure": measure }), &["drill_1", "drill_2", "measure"]),
+            "growth": object(json!({
+                "time_drill": level,
+                "measures": { "type": "array", "items": measure },
+                "lag": { "type": "integer", "minimum": 1 },
+                "cagr": boolean,
+            }), &["time_drill", "measures"]),
+            "cumulative": object(json!({
+                "time_drill": level,
+                "measure": measure,
+                "to_date": enum_schema(vec!["ytd".to_owned(), "qtd".to_owned(), "mtd".to_owned()]),
+            }), &["time_drill", "measure"]),
+            "moving_avg": object(json!({ "time_drill": level, "measure": measure, "window": { "type": "integer", "minimum": 1 } }), &["time_drill", "measure", "window"]),
+            "index": object(json!({ "drill": level, "base": string, "measure": measure }), &["drill", "base", "measure"]),
+            "rank": object(json!({
+                "drill": level,
+                "measure": measure,
+                "direction": direction,
+                "method": enum_schema(vec!["standard".to_owned(), "dense".to_owned()]),
+            }), &["drill", "measure", "direction"]),
+            "trend": object(json!({ "time_drill": level, "measure": measure, "periods": integer }), &["time_drill", "measure"]),
+            "totals": { "type": "array", "items": level },
+            "rate": object(json!({ "level": level, "members": strings }), &["level", "members"]),
+            "group": object(json!({
+                "level": level,
+                "groups": { "type": "array", "items": object(json!({ "name": string, "members": strings }), &["name"]) },
+                "others": boolean,
+            }), &["level", "groups"]),
+            "bins": object(json!({
+                "measure": measure,
+                "edges": { "type": "array", "items": { "type": "number" } },
+                "width": { "type": "integer", "minimum": 1 },
+            }), &["measure"]),
+            "debug": boolean,
+            "sparse": boolean,
+            "exclude_default_members": boolean,
+        },
+    })
+}
+
+
+fn level_name_schema() -> Value {
+    json!({ "$ref": "#/components/schemas/LevelName" })
+}
+
+
+/// Schemas shared by the aggregate query bodies of all cubes.
+fn aggregate_body_components() -> Map<String, Value> {
+    let string = json!({ "type": "string" });
+    let number = json!({ "type": "number" });
+    let comparison = enum_schema(["eq", "neq", "lt", "lte", "gt", "gte"].iter().map(|c| c.to_string()).collect());
+    let expr = json!({ "$ref": "#/components/schemas/FilterExpr" });
+    let pair = json!({ "type": "array", "items": expr, "minItems": 2, "maxItems": 2 });
+
+    let mut components = Map::new();
+    components.insert("LevelName".to_owned(), object(json!({
+        "dimension": string,
+        "hierarchy": string,
+        "level": string,
+    }), &["dimension", "hierarchy", "level"]));
+    components.insert("Property".to_owned(), object(json!({
+        "level_name": { "$ref": "#/components/schemas/LevelName" },
+        "property": string,
+    }), &["level_name", "property"]));
+    components.insert("Constraint".to_owned(), object(json!({
+        "comparison": comparison,
+        "n": number,
+    }), &["comparison", "n"]));
+    components.insert("FilterExpr".to_owned(), json!({
+        "oneOf": [
+            object(json!({ "compare": object(json!({ "measure": string, "comparison": comparison, "n": number }), &["measure", "comparison", "n"]) }), &["compare"]),
+            object(json!({ "between": object(json!({ "measure": string, "low": number, "high": number }), &["measure", "low", "high"]) }), &["between"]),
+            object(json!({ "is_null": string }), &["is_null"]),
+            object(json!({ "not": expr }), &["not"]),
+            object(json!({ "and": pair }), &["and"]),
+            object(json!({ "or": pair }), &["or"]),
+        ],
+    }));
+    components
+}
+
+
+/// An object schema without other properties than `properties`.
+fn object(properties: Value, required: &[&str]) -> Value {
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+
+/// A string schema with an enumeration, which can't be empty.
+fn enum_schema(values: Vec<String>) -> Value {
+    if values.is_empty() {
+        json!({ "type": "string" })
+    } else {
+        json!({ "type": "string", "enum": values })
+    }
+}
+
+
+/// Joins names with `.`, with brackets around each name if one of them has
+/// a `.` itself.
+fn qualified_name(names: &[&str]) -> String {
+    if names.iter().any(|name| name.contains('.')) {
+        names.iter().map(|name| format!("[{}]", name)).collect::<Vec<_>>().join(".")
+    } else {
+        names.join(".")
+    }
+}
+
+
+/// Component names may only have letters, digits, `.`, `-` and `_`.
+fn component_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+
+fn encode_path_segment(segment: &str) -> String {
+    segment.bytes()
+        .map(|b| {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query_params() {
+        let params = query_params::<AggregateQueryOpt>();
+        assert_eq!(params[0], QueryParam { name: "drilldowns".into(), param_type: ParamType::Array, required: false });
+        assert!(params.contains(&QueryParam { name: "parents".into(), param_type: ParamType::Boolean, required: false }));
+        assert!(params.contains(&QueryParam { name: "top".into(), param_type: ParamType::String, required: false }));
+
+        // flattened cuts aren't fields
+        let params = query_params::<LogicLayerQueryOpt>();
+        assert_eq!(params[0], QueryParam { name: "cube".into(), param_type: ParamType::String, required: true });
+        assert!(params.iter().all(|param| param.name != "cuts"));
+        assert!(params.contains(&QueryParam { name: "sparse".into(), param_type: ParamType::Boolean, required: false }));
+    }
+
+    #[test]
+    fn test_openapi_document() {
+        let s = r##"
+        <Schema name="Test">
+            <Cube name="Sales Data">
+                <Table name="sales" />
+                <Dimension name="Geography" foreign_key="geo_id">
+                    <Hierarchy name="Geography" primary_key="geo_id">
+                        <Table name="dim_geo" />
+                        <Level name="State" key_column="state_id" name_column="state_name">
+                            <Property name="Abbreviation" column="state_abbr" />
+                        </Level>
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+            </Cube>
+        </Schema>
+        "##;
+        let schema = Schema::from_xml(s).unwrap();
+        let cubes: Vec<&Cube> = schema.cubes.iter().collect();
+        let doc = openapi_document(&schema, &cubes, false);
+
+        assert_eq!(doc["info"]["title"], json!("Test"));
+        assert_eq!(doc["components"]["schemas"]["Sales_DataLevel"]["enum"], json!(["Geography.Geography.State"]));
+        assert_eq!(doc["components"]["schemas"]["Sales_DataMeasure"]["enum"], json!(["Quantity"]));
+        assert_eq!(doc["components"]["schemas"]["Sales_DataProperty"]["enum"], json!(["Geography.Geography.State.Abbreviation"]));
+
+        let get = &doc["paths"]["/cubes/Sales%20Data/aggregate.{format}"]["get"];
+        assert_eq!(get["parameters"][0]["schema"]["enum"], json!(["csv", "jsonrecords", "jsonarrays", "sdmx", "jsonstat"]));
+        assert_eq!(get["parameters"][1], json!({
+            "name": "drilldowns[]",
+            "in": "query",
+            "required": false,
+            "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Sales_DataLevel" } },
+        }));
+
+        let body = &doc["paths"]["/cubes/Sales%20Data/aggregate.{format}"]["post"]["requestBody"]["content"]["application/json"]["schema"];
+        assert_eq!(body["properties"]["drilldowns"]["items"], json!({ "$ref": "#/components/schemas/LevelName" }));
+        assert_eq!(body["properties"]["measures"]["items"], json!({ "$ref": "#/components/schemas/Sales_DataMeasure" }));
+        assert_eq!(body["properties"]["trend"]["required"], json!(["time_drill", "measure"]));
+        assert_eq!(doc["components"]["schemas"]["LevelName"]["required"], json!(["dimension", "hierarchy", "level"]));
+
+        assert_eq!(doc["paths"]["/cubes/{cube}"]["get"]["parameters"][0]["schema"]["enum"], json!(["Sales Data"]));
+        assert!(doc["paths"].get("/data.{format}").is_none());
+
+        let doc = openapi_document(&schema, &cubes, true);
+        let body = &doc["paths"]["/data.{format}"]["post"]["requestBody"]["content"]["application/json"]["schema"];
+        assert_eq!(body["properties"]["drilldowns"]["items"]["oneOf"][1], json!({ "type": "string" }));
+        assert_eq!(body["properties"]["cube"]["enum"], json!(["Sales Data"]));
+        assert_eq!(body["required"], json!(["cube"]));
+    }
+}
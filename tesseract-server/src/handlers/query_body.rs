@@ -0,0 +1,705 @@
+//! JSON body of an aggregate query.
+//!
+//! Unlike `AggregateQueryOpt`, whose fields are query string values, each
+//! field maps onto a field of `tesseract_core::Query`: level names, cuts
+//! and calculations are objects, so nothing is parsed out of strings. Level
+//! names are `{"dimension": .., "hierarchy": .., "level": ..}`, and
+//! measure names may also be calculation names where the query allows it
+//! (`sort`, `top`, `filters`).
+//!
+//! The logic layer also accepts its own level names as strings, see
+//! `AggregateQueryBody::into_query`.
+
+use failure::{Error, bail, ensure};
+use serde_derive::{Serialize, Deserialize};
+use std::convert::TryFrom;
+use std::fmt;
+use tesseract_core::{Query as TsQuery, MeaOrCalc, FilterQuery};
+use tesseract_core::names::{Cut, Drilldown, LevelName, Mask, Measure, MemberRange, Property};
+use tesseract_core::query::{
+    Bins, BinsQuery, Calculation, Comparison, Constraint, CumulativeQuery,
+    FilterExpr, GroupQuery, GrowthMode, GrowthQuery, IndexQuery, LimitQuery,
+    MemberGroup, MovingAvgQuery, Operator, PeriodToDate, RankMethod, RankQuery,
+    RateQuery, RcaQuery, SortDirection, SortQuery, TopQuery, TopWhereQuery,
+    TrendQuery,
+};
+
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateQueryBody {
+    #[serde(default)]
+    drilldowns: Vec<LevelRef>,
+    #[serde(default)]
+    cuts: Vec<CutBody>,
+    #[serde(default)]
+    measures: Vec<String>,
+    #[serde(default)]
+    properties: Vec<Property>,
+    #[serde(default)]
+    filters: Vec<FilterBody>,
+    filter: Option<FilterExprBody>,
+    #[serde(default)]
+    captions: Vec<Property>,
+    #[serde(default)]
+    parents: bool,
+    top: Option<TopBody>,
+    top_where: Option<TopWhereBody>,
+    sort: Option<SortBody>,
+    limit: Option<LimitBody>,
+    rca: Option<RcaBody>,
+    growth: Option<GrowthBody>,
+    cumulative: Option<CumulativeBody>,
+    moving_avg: Option<MovingAvgBody>,
+    index: Option<IndexBody>,
+    rank: Option<RankBody>,
+    trend: Option<TrendBody>,
+    #[serde(default)]
+    totals: Vec<LevelRef>,
+    rate: Option<RateBody>,
+    group: Option<GroupBody>,
+    bins: Option<BinsBody>,
+    #[serde(default)]
+    debug: bool,
+    #[serde(default)]
+    sparse: bool,
+    #[serde(default)]
+    exclude_default_members: bool,
+}
+
+/// A level by its full name, or by its logic layer name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LevelRef {
+    Name(LevelName),
+    Unique(String),
+}
+
+impl fmt::Display for LevelRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelRef::Name(level_name) => write!(f, "{}", level_name),
+            LevelRef::Unique(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Members of the level, or a range of them. Members are matched against
+/// `property` instead of the key if it is set.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CutBody {
+    level: LevelRef,
+    #[serde(default)]
+    members: Vec<String>,
+    range: Option<MemberRange>,
+    property: Option<String>,
+    #[serde(default)]
+    exclude: bool,
+    #[serde(default)]
+    for_match: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComparisonBody {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConstraintBody {
+    comparison: ComparisonBody,
+    n: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperatorBody {
+    And,
+    Or,
+}
+
+/// A constraint on a measure, optionally joined with a second one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterBody {
+    measure: String,
+    constraint: ConstraintBody,
+    operator: Option<OperatorBody>,
+    constraint2: Option<ConstraintBody>,
+}
+
+/// `{"and": [a, b]}`, `{"not": a}`,
+/// `{"compare": {"measure": "Quantity", "comparison": "gt", "n": 100}}`,
+/// `{"between": {"measure": "Price", "low": 10, "high": 20}}` or
+/// `{"is_null": "rca"}`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterExprBody {
+    Compare {
+        measure: String,
+        comparison: ComparisonBody,
+        n: f64,
+    },
+    Between {
+        measure: String,
+        low: f64,
+        high: f64,
+    },
+    IsNull(String),
+    Not(Box<FilterExprBody>),
+    And(Box<FilterExprBody>, Box<FilterExprBody>),
+    Or(Box<FilterExprBody>, Box<FilterExprBody>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirectionBody {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopBody {
+    n: u64,
+    by_dimension: LevelRef,
+    measure: String,
+    direction: SortDirectionBody,
+    #[serde(default)]
+    others: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopWhereBody {
+    measure: String,
+    constraint: ConstraintBody,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SortBody {
+    measure: String,
+    direction: SortDirectionBody,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitBody {
+    n: u64,
+    offset: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RcaBody {
+    drill_1: LevelRef,
+    drill_2: LevelRef,
+    measure: String,
+}
+
+/// Period over period growth with `lag` (default 1), or CAGR with `cagr`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrowthBody {
+    time_drill: LevelRef,
+    measures: Vec<String>,
+    lag: Option<u64>,
+    #[serde(default)]
+    cagr: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeriodToDateBody {
+    Ytd,
+    Qtd,
+    Mtd,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CumulativeBody {
+    time_drill: LevelRef,
+    measure: String,
+    to_date: Option<PeriodToDateBody>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MovingAvgBody {
+    time_drill: LevelRef,
+    measure: String,
+    window: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexBody {
+    drill: LevelRef,
+    base: String,
+    measure: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankMethodBody {
+    Standard,
+    Dense,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RankBody {
+    drill: LevelRef,
+    measure: String,
+    direction: SortDirectionBody,
+    method: Option<RankMethodBody>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrendBody {
+    time_drill: LevelRef,
+    measure: String,
+    #[serde(default)]
+    periods: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateBody {
+    level: LevelRef,
+    members: Vec<String>,
+}
+
+/// Members that aren't in any group are kept, unless `others` is false.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupBody {
+    level: LevelRef,
+    groups: Vec<MemberGroupBody>,
+    others: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MemberGroupBody {
+    name: String,
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+/// Either increasing `edges`, or a number of equal `width` buckets.
+/// Values are checked with the rest of the query in `Schema::sql_query`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BinsBody {
+    measure: String,
+    edges: Option<Vec<f64>>,
+    width: Option<u64>,
+}
+
+
+/// Calculation names become calculations, same as in the query string.
+/// They are resolved to measures of the same name in `Schema::sql_query`.
+fn mea_or_calc(name: String) -> MeaOrCalc {
+    match name.parse::<Calculation>() {
+        Ok(calc) => MeaOrCalc::Calc(calc),
+        Err(_) => MeaOrCalc::Mea(Measure::new(name)),
+    }
+}
+
+impl From<ComparisonBody> for Comparison {
+    fn from(comparison: ComparisonBody) -> Self {
+        match comparison {
+            ComparisonBody::Eq => Comparison::Equal,
+            ComparisonBody::Neq => Comparison::NotEqual,
+            ComparisonBody::Lt => Comparison::LessThan,
+            ComparisonBody::Lte => Comparison::LessThanOrEqual,
+            ComparisonBody::Gt => Comparison::GreaterThan,
+            ComparisonBody::Gte => Comparison::GreaterThanOrEqual,
+        }
+    }
+}
+
+impl From<ConstraintBody> for Constraint {
+    fn from(constraint: ConstraintBody) -> Self {
+        Constraint {
+            comparison: constraint.comparison.into(),
+            n: constraint.n,
+        }
+    }
+}
+
+impl From<SortDirectionBody> for SortDirection {
+    fn from(direction: SortDirectionBody) -> Self {
+        match direction {
+            SortDirectionBody::Asc => SortDirection::Asc,
+            SortDirectionBody::Desc => SortDirection::Desc,
+        }
+    }
+}
+
+impl CutBody {
+    fn into_cut(self, level_name: LevelName) -> Result<Cut, Error> {
+        if self.range.is_some() {
+            ensure!(self.members.is_empty(), "Cut on {} cannot have both members and a range", self.level);
+            ensure!(!self.for_match, "Cut on a range cannot use for_match");
+        } else {
+            ensure!(!self.members.is_empty(), "Cut on {} has no members", self.level);
+        }
+
+        Ok(Cut {
+            level_name,
+            members: self.members,
+            mask: if self.exclude { Mask::Exclude } else { Mask::Include },
+            for_match: self.for_match,
+            range: self.range,
+            property: self.property,
+        })
+    }
+}
+
+impl TryFrom<FilterBody> for FilterQuery {
+    type Error = Error;
+
+    fn try_from(filter: FilterBody) -> Result<Self, Self::Error> {
+        let operator = match filter.operator {
+            Some(OperatorBody::And) => Some(Operator::And),
+            Some(OperatorBody::Or) => Some(Operator::Or),
+            None => None,
+        };
+        if operator.is_some() != filter.constraint2.is_some() {
+            bail!("Filter on {} needs both an operator and a second constraint, or neither", filter.measure);
+        }
+
+        Ok(FilterQuery {
+            by_mea_or_calc: mea_or_calc(filter.measure),
+            constraint: filter.constraint.into(),
+            operator,
+            constraint2: filter.constraint2.map(|c| c.into()),
+        })
+    }
+}
+
+impl From<FilterExprBody> for FilterExpr {
+    fn from(expr: FilterExprBody) -> Self {
+        match expr {
+            FilterExprBody::Compare { measure, comparison, n } => {
+                FilterExpr::Compare(mea_or_calc(measure), Constraint { comparison: comparison.into(), n })
+            },
+            FilterExprBody::Between { measure, low, high } => {
+                FilterExpr::Between(mea_or_calc(measure), low, high)
+            },
+            FilterExprBody::IsNull(measure) => FilterExpr::IsNull(mea_or_calc(measure)),
+            FilterExprBody::Not(e) => FilterExpr::Not(Box::new((*e).into())),
+            FilterExprBody::And(l, r) => FilterExpr::And(Box::new((*l).into()), Box::new((*r).into())),
+            FilterExprBody::Or(l, r) => FilterExpr::Or(Box::new((*l).into()), Box::new((*r).into())),
+        }
+    }
+}
+
+impl GrowthBody {
+    fn into_query(self, time_drill: LevelName) -> GrowthQuery {
+        GrowthQuery {
+            time_drill: Drilldown(time_drill),
+            meas: self.measures.into_iter().map(Measure::new).collect(),
+            lag: self.lag.unwrap_or(1),
+            mode: if self.cagr { GrowthMode::Cagr } else { GrowthMode::PeriodOverPeriod },
+        }
+    }
+}
+
+impl TryFrom<BinsBody> for BinsQuery {
+    type Error = Error;
+
+    fn try_from(bins: BinsBody) -> Result<Self, Self::Error> {
+        let measure = Measure::new(bins.measure);
+
+        let bins = match (bins.edges, bins.width) {
+            (Some(edges), None) => Bins::Edges(edges),
+            (None, Some(n)) => Bins::EqualWidth(n),
+            _ => bail!("Bins need either edges or a width"),
+        };
+
+        Ok(BinsQuery {
+            measure,
+            bins,
+        })
+    }
+}
+
+impl TryFrom<AggregateQueryBody> for TsQuery {
+    type Error = Error;
+
+    fn try_from(body: AggregateQueryBody) -> Result<Self, Self::Error> {
+        body.into_query(|name| bail!("Level {} must be an object with a dimension, hierarchy and level", name))
+    }
+}
+
+impl AggregateQueryBody {
+    /// `lookup` finds the full name of a level given by a string, which
+    /// only the logic layer accepts.
+    pub fn into_query<F>(self, lookup: F) -> Result<TsQuery, Error>
+        where F: Fn(&str) -> Result<LevelName, Error>
+    {
+        let level = |level: LevelRef| match level {
+            LevelRef::Name(level_name) => Ok(level_name),
+            LevelRef::Unique(name) => lookup(&name),
+        };
+        let drill = |level_ref: LevelRef| level(level_ref).map(Drilldown);
+
+        let drilldowns = self.drilldowns.into_iter()
+            .map(drill)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let cuts = self.cuts.into_iter()
+            .map(|c| {
+                let level_name = level(c.level.clone())?;
+                c.into_cut(level_name)
+            })
+            .collect::<Result<Vec<Cut>, _>>()?;
+
+        let filters = self.filters.into_iter()
+            .map(FilterQuery::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let top = self.top.map(|top| -> Result<_, Error> {
+            Ok(TopQuery {
+                n: top.n,
+                by_dimension: level(top.by_dimension)?,
+                sort_mea_or_calc: vec![mea_or_calc(top.measure)],
+                sort_direction: top.direction.into(),
+                others: top.others,
+            })
+        }).transpose()?;
+
+        let top_where = self.top_where.map(|top_where| {
+            TopWhereQuery {
+                by_mea_or_calc: mea_or_calc(top_where.measure),
+                constraint: top_where.constraint.into(),
+            }
+        });
+
+        let sort = self.sort.map(|sort| {
+            SortQuery {
+                direction: sort.direction.into(),
+                measure: mea_or_calc(sort.measure),
+            }
+        });
+
+        let limit = self.limit.map(|limit| {
+            LimitQuery {
+                offset: limit.offset,
+                n: limit.n,
+            }
+        });
+
+        let rca = self.rca.map(|rca| -> Result<_, Error> {
+            Ok(RcaQuery {
+                drill_1: drill(rca.drill_1)?,
+                drill_2: drill(rca.drill_2)?,
+                mea: Measure::new(rca.measure),
+            })
+        }).transpose()?;
+
+        let growth = self.growth.map(|growth| -> Result<_, Error> {
+            let time_drill = level(growth.time_drill.clone())?;
+            Ok(growth.into_query(time_drill))
+        }).transpose()?;
+
+        let cumulative = self.cumulative.map(|cumulative| -> Result<_, Error> {
+            Ok(CumulativeQuery {
+                time_drill: drill(cumulative.time_drill)?,
+                mea: Measure::new(cumulative.measure),
+                to_date: cumulative.to_date.map(|to_date| match to_date {
+                    PeriodToDateBody::Ytd => PeriodToDate::Year,
+                    PeriodToDateBody::Qtd => PeriodToDate::Quarter,
+                    PeriodToDateBody::Mtd => PeriodToDate::Month,
+                }),
+            })
+        }).transpose()?;
+
+        let moving_avg = self.moving_avg.map(|moving_avg| -> Result<_, Error> {
+            Ok(MovingAvgQuery {
+                time_drill: drill(moving_avg.time_drill)?,
+                mea: Measure::new(moving_avg.measure),
+                window: moving_avg.window,
+            })
+        }).transpose()?;
+
+        let index = self.index.map(|index| -> Result<_, Error> {
+            Ok(IndexQuery {
+                drill: drill(index.drill)?,
+                base: index.base,
+                mea: Measure::new(index.measure),
+            })
+        }).transpose()?;
+
+        let rank = self.rank.map(|rank| -> Result<_, Error> {
+            Ok(RankQuery {
+                drill: drill(rank.drill)?,
+                mea: Measure::new(rank.measure),
+                direction: rank.direction.into(),
+                method: match rank.method {
+                    Some(RankMethodBody::Dense) => RankMethod::Dense,
+                    Some(RankMethodBody::Standard) | None => RankMethod::Standard,
+                },
+            })
+        }).transpose()?;
+
+        let trend = self.trend.map(|trend| -> Result<_, Error> {
+            Ok(TrendQuery {
+                time_drill: drill(trend.time_drill)?,
+                mea: Measure::new(trend.measure),
+                periods: trend.periods,
+            })
+        }).transpose()?;
+
+        let totals = self.totals.into_iter()
+            .map(level)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let rate = self.rate.map(|rate| -> Result<_, Error> {
+            Ok(RateQuery::new(level(rate.level)?, rate.members))
+        }).transpose()?;
+
+        let group = self.group.map(|group| -> Result<_, Error> {
+            let groups = group.groups.into_iter()
+                .map(|g| MemberGroup { name: g.name, members: g.members })
+                .collect();
+
+            let mut query = GroupQuery::new(level(group.level)?, groups);
+            query.keep_others = group.others.unwrap_or(true);
+            Ok(query)
+        }).transpose()?;
+
+        let bins = self.bins
+            .map(BinsQuery::try_from)
+            .transpose()?;
+
+        Ok(TsQuery {
+            drilldowns,
+            cuts,
+            measures: self.measures.into_iter().map(Measure::new).collect(),
+            parents: self.parents,
+            properties: self.properties,
+            filters,
+            filter: self.filter.map(|f| f.into()),
+            captions: self.captions,
+            top,
+            top_where,
+            sort,
+            limit,
+            rca,
+            growth,
+            cumulative,
+            moving_avg,
+            index,
+            rank,
+            trend,
+            totals,
+            debug: self.debug,
+            rate,
+            group,
+            bins,
+            sparse: self.sparse,
+            exclude_default_members: self.exclude_default_members,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_aggregate_query_body() {
+        let body: AggregateQueryBody = serde_json::from_value(json!({
+            "drilldowns": [{ "dimension": "Year", "hierarchy": "Year", "level": "Year" }],
+            "cuts": [
+                { "level": { "dimension": "Geography", "hierarchy": "Geography", "level": "State" }, "members": ["CA", "a,b"], "exclude": true },
+                { "level": { "dimension": "Year", "hierarchy": "Year", "level": "Year" }, "range": { "start": "2010", "end": null } },
+            ],
+            "measures": ["Quantity"],
+            "filter": { "or": [
+                { "compare": { "measure": "Quantity", "comparison": "gt", "n": 100 } },
+                { "not": { "is_null": "growth" } },
+            ] },
+            "sort": { "measure": "rank", "direction": "desc" },
+            "growth": { "time_drill": { "dimension": "Year", "hierarchy": "Year", "level": "Year" }, "measures": ["Quantity"], "cagr": true },
+            "trend": { "time_drill": { "dimension": "Year", "hierarchy": "Year", "level": "Year" }, "measure": "Quantity", "periods": 2 },
+            "bins": { "measure": "Quantity", "edges": [0, 10, 100] },
+        })).unwrap();
+
+        let query = TsQuery::try_from(body).unwrap();
+        assert_eq!(query.drilldowns, vec![Drilldown::new("Year", "Year", "Year")]);
+        assert_eq!(query.cuts[0].members, vec!["CA".to_owned(), "a,b".to_owned()]);
+        assert_eq!(query.cuts[0].mask, Mask::Exclude);
+        assert_eq!(query.cuts[1].range, Some(MemberRange { start: Some("2010".to_owned()), end: None }));
+        assert_eq!(query.filter, Some("Quantity > 100 or not growth is null".parse().unwrap()));
+        assert_eq!(query.sort.unwrap().measure, MeaOrCalc::Calc(Calculation::Rank));
+        assert_eq!(query.growth.unwrap().mode, GrowthMode::Cagr);
+        assert_eq!(query.trend.unwrap().periods, 2);
+        assert_eq!(query.bins.unwrap().bins, Bins::Edges(vec![0.0, 10.0, 100.0]));
+    }
+
+    #[test]
+    fn test_aggregate_query_body_lookup() {
+        let body: AggregateQueryBody = serde_json::from_value(json!({
+            "drilldowns": ["Year", { "dimension": "Geography", "hierarchy": "Geography", "level": "State" }],
+            "cuts": [{ "level": "Year", "members": ["2018"] }],
+            "measures": ["Quantity"],
+        })).unwrap();
+
+        let lookup = |name: &str| match name {
+            "Year" => Ok(LevelName::new("Date", "Date", "Year")),
+            _ => bail!("Unrecognized level {}", name),
+        };
+        let query = body.clone().into_query(lookup).unwrap();
+        assert_eq!(query.drilldowns, vec![Drilldown::new("Date", "Date", "Year"), Drilldown::new("Geography", "Geography", "State")]);
+        assert_eq!(query.cuts[0].level_name, LevelName::new("Date", "Date", "Year"));
+
+        // only the logic layer looks up level names
+        assert!(TsQuery::try_from(body).is_err());
+    }
+
+    #[test]
+    fn test_aggregate_query_body_errors() {
+        let year = json!({ "dimension": "Year", "hierarchy": "Year", "level": "Year" });
+
+        let invalid = vec![
+            // query string values are not accepted
+            json!({ "drilldowns": ["Year.Year"] }),
+            json!({ "unknown": true }),
+            json!({ "cuts": [{ "level": year, "members": [] }] }),
+            json!({ "cuts": [{ "level": year, "members": ["2010"], "range": { "start": "2010", "end": null } }] }),
+            json!({ "filters": [{ "measure": "Quantity", "constraint": { "comparison": "gt", "n": 1 }, "operator": "and" }] }),
+            json!({ "growth": { "time_drill": year, "measures": ["Quantity"], "lag": 2, "cagr": true } }),
+            json!({ "growth": { "time_drill": year, "measures": [] } }),
+            json!({ "moving_avg": { "time_drill": year, "measure": "Quantity", "window": 0 } }),
+            json!({ "trend": { "time_drill": year, "measure": "Quantity", "periods": 1000 } }),
+            json!({ "bins": { "measure": "Quantity", "edges": [10, 0] } }),
+            json!({ "bins": { "measure": "Quantity", "edges": [0, 10], "width": 2 } }),
+        ];
+
+        // values are checked by `Query::validate`, as in `Schema::sql_query`
+        for body in invalid {
+            let query = serde_json::from_value::<AggregateQueryBody>(body.clone())
+                .map_err(Error::from)
+                .and_then(TsQuery::try_from)
+                .and_then(|query| query.validate());
+            assert!(query.is_err(), "{}", body);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use actix_web::{
     FutureResponse,
     HttpRequest,
@@ -7,18 +7,21 @@ use actix_web::{
 use futures::future::{self};
 use actix_web::http::header::ContentType;
 use log::*;
+use serde::Serialize;
 use mime;
 use r2d2_redis::{r2d2, redis, RedisConnectionManager};
 
 use tesseract_core::format::FormatType;
-use tesseract_core::schema::Cube;
-use tesseract_core::schema::metadata::SourceMetadata;
+use tesseract_core::schema::{Annotation, Cube};
+use tesseract_core::schema::metadata::{SourceDimensionMetadata, SourceMetadata};
 
 use crate::app::AppState;
 
 use failure::{bail, format_err, Error};
+use tesseract_core::{Backend, Query as TsQuery, Schema};
 use tesseract_core::names::Cut;
-use crate::logic_layer::CubeCache;
+use tesseract_core::query_ir::{check_member, MemberType};
+use crate::logic_layer::{Cache, CubeCache};
 use crate::auth::{validate_web_token, extract_token, user_auth_level};
 
 pub(crate) fn format_to_content_type(format_type: &FormatType) -> ContentType {
@@ -26,6 +29,10 @@ pub(crate) fn format_to_content_type(format_type: &FormatType) -> ContentType {
         FormatType::Csv => ContentType(mime::TEXT_CSV_UTF_8),
         FormatType::JsonRecords => ContentType(mime::APPLICATION_JSON),
         FormatType::JsonArrays => ContentType(mime::APPLICATION_JSON),
+        FormatType::Sdmx => ContentType(
+            "application/vnd.sdmx.data+json; version=1.0".parse().expect("valid mime type")
+        ),
+        FormatType::JsonStat => ContentType(mime::APPLICATION_JSON),
     }
 }
 
@@ -51,7 +58,27 @@ pub fn generate_source_data(cube: &Cube) -> SourceMetadata {
     for measure in cube.measures.iter() {
         measures.push(measure.name.clone());
     }
-    let annotations = match cube.annotations.clone(){
+    let dimensions = cube.dimensions.iter()
+        .map(|dimension| {
+            SourceDimensionMetadata {
+                name: dimension.name.clone(),
+                levels: dimension.hierarchies.iter()
+                    .flat_map(|hierarchy| hierarchy.levels.iter().map(|level| level.name.clone()))
+                    .collect(),
+                annotations: annotations_map(&dimension.annotations),
+            }
+        })
+        .collect();
+    SourceMetadata {
+        name: cube_name.clone(),
+        measures: measures.clone(),
+        annotations: annotations_map(&cube.annotations),
+        dimensions,
+    }
+}
+
+fn annotations_map(annotations: &Option<Vec<Annotation>>) -> Option<HashMap<String, String>> {
+    match annotations.clone(){
         Some(annotations) => {
             let mut anotate_hashmap = HashMap::new();
             for annotation in annotations.iter(){
@@ -60,11 +87,6 @@ pub fn generate_source_data(cube: &Cube) -> SourceMetadata {
             Some(anotate_hashmap)
         },
         None => None
-    };
-    SourceMetadata {
-        name: cube_name.clone(),
-        measures: measures.clone(),
-        annotations: annotations.clone(),
     }
 }
 
@@ -141,6 +163,14 @@ pub fn validate_members(cuts: &[Cut], cube_cache: &CubeCache) -> Result<(), Erro
         // get level cache
         let member_cache = cube_cache.members_for_level(&cut.level_name)
             .ok_or_else(|| format_err!("Level not found in cache"))?;
+        // the cache only holds level keys, not property values, which are
+        // always text
+        if cut.property.is_some() {
+            for member in &cut.members {
+                check_member(member, &MemberType::Text)?;
+            }
+            continue;
+        }
         for member in &cut.members {
             if !member_cache.contains(member) {
                 bail!("Cut member not found");
@@ -151,6 +181,34 @@ pub fn validate_members(cuts: &[Cut], cube_cache: &CubeCache) -> Result<(), Erro
 }
 
 
+/// Turns a query on a cube into SQL and result headers, after checking that
+/// its cut members exist. It only takes what it needs from the state, so
+/// that the Postgres and Flight front ends can use it too.
+pub fn query_sql(
+    schema: &Schema,
+    cache: &Cache,
+    backend: &dyn Backend,
+    cube: &str,
+    ts_query: &TsQuery,
+    ) -> Result<(String, Vec<String>), Error>
+{
+    // sql injection mitigation on query:
+    // - Check that cut members exist in members cache
+    let cube_cache = cache.find_cube_info(&cube.to_owned())
+        .ok_or_else(|| format_err!("Cube {} not found", cube))?;
+    validate_members(&ts_query.cuts, cube_cache)?;
+
+    let (query_ir, headers) = schema.sql_query(cube, ts_query, None)?;
+
+    let sql = backend.generate_sql(query_ir);
+
+    info!("Sql query: {}", sql);
+    info!("Headers: {:?}", headers);
+
+    Ok((sql, headers))
+}
+
+
 /// Gets the Redis cache key for a given query.
 /// The sorting of query param keys is an attempt to increase cache hits.
 pub fn get_redis_cache_key(prefix: &str, req: &HttpRequest<AppState>, cube: &str, format: &FormatType) -> String {
@@ -166,13 +224,40 @@ pub fn get_redis_cache_key(prefix: &str, req: &HttpRequest<AppState>, cube: &str
         })
         .collect();
 
-    let format_str = match format {
+    format!("{}/{}/{}/{}", prefix, cube, cache_format_str(format), qry_strings.join("&"))
+}
+
+
+/// Gets the Redis cache key for a query sent as a JSON body. Fields are
+/// sorted and unset fields dropped, so equivalent bodies share a key.
+pub fn get_redis_cache_key_json<T: Serialize>(
+    prefix: &str,
+    cube: &str,
+    format: &FormatType,
+    query: &T,
+) -> Result<String, Error>
+{
+    let fields: BTreeMap<String, serde_json::Value> = match serde_json::to_value(query)? {
+        serde_json::Value::Object(fields) => {
+            fields.into_iter()
+                .filter(|(k, v)| !v.is_null() && k != "x-tesseract-jwt-token")
+                .collect()
+        },
+        _ => bail!("Query body must be a JSON object"),
+    };
+
+    Ok(format!("{}/{}/{}/{}", prefix, cube, cache_format_str(format), serde_json::to_string(&fields)?))
+}
+
+
+fn cache_format_str(format: &FormatType) -> &'static str {
+    match format {
         FormatType::Csv => "csv",
         FormatType::JsonArrays => "jsonarrays",
         FormatType::JsonRecords => "jsonrecords",
-    };
-
-    format!("{}/{}/{}/{}", prefix, cube, format_str, qry_strings.join("&"))
+        FormatType::Sdmx => "sdmx",
+        FormatType::JsonStat => "jsonstat",
+    }
 }
 
 
@@ -182,6 +267,23 @@ pub fn check_redis_cache(
         redis_pool: &Option<r2d2::Pool<RedisConnectionManager>>,
         redis_cache_key: &str
 ) -> Option<FutureResponse<HttpResponse>> {
+    get_from_redis_cache(redis_pool, redis_cache_key)
+        .map(|result_str| {
+            let content_type = format_to_content_type(&format);
+            let response = HttpResponse::Ok()
+                .set(content_type)
+                .body(result_str);
+
+            Box::new(future::result(Ok(response))) as FutureResponse<HttpResponse>
+        })
+}
+
+
+/// Gets a cached result from Redis, if there is one.
+pub fn get_from_redis_cache(
+        redis_pool: &Option<r2d2::Pool<RedisConnectionManager>>,
+        redis_cache_key: &str
+) -> Option<String> {
     if let Some(rpool) = redis_pool {
         let conn_result = rpool.get();
 
@@ -189,13 +291,7 @@ pub fn check_redis_cache(
             let redis_cache_result = redis::cmd("GET").arg(redis_cache_key).query(&mut *conn);
 
             if let Ok(result_str) = redis_cache_result {
-                let result_str: &String = &result_str;
-                let content_type = format_to_content_type(&format);
-                let response = HttpResponse::Ok()
-                    .set(content_type)
-                    .body(result_str);
-
-                return Some(Box::new(future::result(Ok(response))));
+                return Some(result_str);
             }
         } else {
             debug!("Failed to get redis pool handle!");
@@ -4,4 +4,8 @@ pub mod handlers;
 pub mod logic_layer;
 pub mod schema_config;
 pub mod errors;
+pub mod exports;
+pub mod flight;
+pub mod graphql;
+pub mod pgwire;
 pub mod auth;
\ No newline at end of file
@@ -8,7 +8,8 @@ use serde_derive::Deserialize;
 
 use tesseract_core::{Schema, Backend};
 use tesseract_core::names::{LevelName, Property};
-use tesseract_core::schema::{Level, Cube, InlineTable};
+use tesseract_core::query::PeriodToDate;
+use tesseract_core::schema::{Level, Cube, Dimension, Hierarchy, InlineTable, TimeGranularity};
 
 use crate::logic_layer::{LogicLayerConfig};
 
@@ -65,6 +66,16 @@ impl TimePrecision {
             _ => Err(format_err!("Wrong type for time precision argument."))
         }
     }
+
+    /// Period that a period-to-date total restarts on at this precision.
+    pub fn period_to_date(&self) -> Result<PeriodToDate, Error> {
+        match self {
+            TimePrecision::Year => Ok(PeriodToDate::Year),
+            TimePrecision::Quarter => Ok(PeriodToDate::Quarter),
+            TimePrecision::Month => Ok(PeriodToDate::Month),
+            _ => Err(format_err!("Period to date is only supported for years, quarters and months."))
+        }
+    }
 }
 
 
@@ -212,6 +223,21 @@ impl CubeCache {
         Ok((ln, val))
     }
 
+    /// Name of the level for a time precision, for range cuts on time.
+    pub fn get_time_level_name(&self, precision: &TimePrecision) -> Result<String, Error> {
+        let level = match precision {
+            TimePrecision::Year => &self.year_level,
+            TimePrecision::Quarter => &self.quarter_level,
+            TimePrecision::Month => &self.month_level,
+            TimePrecision::Week => &self.week_level,
+            TimePrecision::Day => &self.day_level,
+            TimePrecision::Time => &self.time_level,
+        };
+
+        self.get_level_name(level.clone())
+            .ok_or_else(|| format_err!("Unable to get requested time precision level name."))
+    }
+
     pub fn get_level_name(&self, level: Option<Level>) -> Option<String> {
         match level {
             Some(l) => Some(l.name),
@@ -322,7 +348,36 @@ pub fn populate_cache(
                 };
 
                 for level in &hierarchy.levels {
-                    if time_column_names.contains(&level.name) {
+                    // Levels derived from a date column are keyed by an
+                    // expression rather than by a physical column
+                    let key_column = level_key_sql(dimension, hierarchy, level, &backend);
+
+                    if let Some(granularity) = &level.granularity {
+                        // Derived time levels are recognised by type, not by name
+                        let val = get_distinct_values(
+                            &key_column, &table, backend.clone(), sys
+                        )?;
+
+                        match granularity {
+                            TimeGranularity::Year => {
+                                year_level = Some(level.clone());
+                                year_values = Some(val);
+                            },
+                            TimeGranularity::Quarter => {
+                                quarter_level = Some(level.clone());
+                                quarter_values = Some(val);
+                            },
+                            TimeGranularity::Month => {
+                                month_level = Some(level.clone());
+                                month_values = Some(val);
+                            },
+                            TimeGranularity::Day => {
+                                day_level = Some(level.clone());
+                                day_values = Some(val);
+                            },
+                            TimeGranularity::FiscalYear | TimeGranularity::FiscalQuarter => (),
+                        }
+                    } else if time_column_names.contains(&level.name) {
                         let val = get_distinct_values(
                             &level.key_column, &table, backend.clone(), sys
                         )?;
@@ -458,16 +513,24 @@ pub fn populate_cache(
                         // Database table
 
                         if parent_levels.len() >= 1 {
+                            let parent_key_column = level_key_sql(
+                                dimension, hierarchy, &parent_levels[parent_levels.len() - 1], &backend
+                            );
+
                             parent_map = Some(get_parent_data(
-                                &parent_levels[parent_levels.len() - 1], &level,
+                                &parent_key_column, &key_column,
                                 table, backend.clone(), sys
                             )?);
                         }
 
                         match child_level {
                             Some(child_level) => {
+                                let child_key_column = level_key_sql(
+                                    dimension, hierarchy, &child_level, &backend
+                                );
+
                                 children_map = Some(get_children_data(
-                                    &level, &child_level,
+                                    &key_column, &child_key_column,
                                     table, backend.clone(), sys
                                 )?);
                             },
@@ -476,7 +539,7 @@ pub fn populate_cache(
 
                         // Get all IDs for this level
                         distinct_ids = get_distinct_values(
-                            &level.key_column, &table, backend.clone(), sys
+                            &key_column, &table, backend.clone(), sys
                         )?;
                     }
 
@@ -746,8 +809,8 @@ pub fn get_inline_children_data(
 
 
 pub fn get_parent_data(
-        parent_level: &Level,
-        current_level: &Level,
+        parent_key_column: &str,
+        current_key_column: &str,
         table: &str,
         backend: Box<dyn Backend + Sync + Send>,
         sys: &mut SystemRunner
@@ -758,7 +821,7 @@ pub fn get_parent_data(
         .exec_sql(
             format!(
                 "select distinct {0}, {1} from {2} group by {0}, {1} order by {0}, {1}",
-                parent_level.key_column, current_level.key_column, table,
+                parent_key_column, current_key_column, table,
             ).to_string()
         );
 
@@ -781,8 +844,8 @@ pub fn get_parent_data(
 
 
 pub fn get_children_data(
-        current_level: &Level,
-        child_level: &Level,
+        current_key_column: &str,
+        child_key_column: &str,
         table: &str,
         backend: Box<dyn Backend + Sync + Send>,
         sys: &mut SystemRunner
@@ -793,7 +856,7 @@ pub fn get_children_data(
         .exec_sql(
             format!(
                 "select distinct {0}, {1} from {2} group by {0}, {1} order by {0}, {1}",
-                current_level.key_column, child_level.key_column, table,
+                current_key_column, child_key_column, table,
             ).to_string()
         );
 
@@ -831,6 +894,21 @@ pub fn get_children_data(
 }
 
 
+/// Returns the SQL used to select the keys of a level. Levels derived from a
+/// date column use the backend's date key expression.
+pub fn level_key_sql(
+        dimension: &Dimension,
+        hierarchy: &Hierarchy,
+        level: &Level,
+        backend: &Box<dyn Backend + Sync + Send>,
+) -> String {
+    match dimension.date_key(hierarchy, level) {
+        Some(date_key) => backend.date_key_sql(&date_key),
+        None => level.key_column.clone(),
+    }
+}
+
+
 /// Queries the database to get all the distinct values for a given level.
 pub fn get_distinct_values(
         column: &str,
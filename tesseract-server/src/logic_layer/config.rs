@@ -267,6 +267,17 @@ impl LogicLayerConfig {
         }
     }
 
+    /// Given a level name and a set name, returns the values of the matching
+    /// named set, if there is one.
+    pub fn find_named_set(&self, level_name: &str, set_name: &str) -> Option<Vec<String>> {
+        self.named_sets.iter()
+            .flatten()
+            .filter(|named_set| named_set.level_name == level_name)
+            .flat_map(|named_set| named_set.sets.iter())
+            .find(|set| set.set_name == set_name)
+            .map(|set| set.values.clone())
+    }
+
     /// Returns a unique name definition for a given cube level if there is one.
     pub fn find_unique_cube_level_name(
         &self, cube_name: &String, level_name: &LevelName
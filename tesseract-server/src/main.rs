@@ -22,6 +22,10 @@
 mod app;
 mod db_config;
 mod errors;
+mod exports;
+mod flight;
+mod graphql;
+mod pgwire;
 mod auth;
 pub mod handlers;
 mod logic_layer;
@@ -29,15 +33,21 @@ mod schema_config;
 
 use actix_web::server;
 use dotenv::dotenv;
-use failure::{Error, format_err};
+use failure::{Error, bail, format_err};
 use log::*;
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 use url::Url;
 
 use std::sync::{Arc, RwLock};
 
 use crate::app::{EnvVars, SchemaSource, create_app};
+use crate::exports::{ExportConfig, ExportJobs};
+use crate::flight::FlightState;
+use crate::graphql::GraphqlSchema;
+use crate::pgwire::PgState;
 use r2d2_redis::{r2d2, RedisConnectionManager};
 
 fn main() -> Result<(), Error> {
@@ -47,6 +57,16 @@ fn main() -> Result<(), Error> {
     dotenv().ok();
     let opt = Opt::from_args();
 
+    // converting a Mondrian schema doesn't start the server
+    if let Some(mondrian_path) = opt.convert_mondrian {
+        let (schema_json, warnings) = schema_config::convert_mondrian_schema(&mondrian_path)?;
+        for warning in warnings {
+            eprintln!("warning: {}", warning);
+        }
+        println!("{}", schema_json);
+        return Ok(());
+    }
+
     // debug is boolean, but env var is Result.
     // cli opt overrides env var if env_var is false
     let env_var_debug = env::var("TESSERACT_DEBUG")
@@ -94,6 +114,71 @@ fn main() -> Result<(), Error> {
     // flush
     let flush_secret = env::var("TESSERACT_FLUSH_SECRET").ok();
 
+    // Batch queries
+    let batch_concurrency = match env::var("TESSERACT_BATCH_CONCURRENCY") {
+        Ok(val) => {
+            let n = val.parse::<usize>()
+                .map_err(|_| format_err!("could not parse integer from env_var TESSERACT_BATCH_CONCURRENCY"))?;
+            if n == 0 {
+                bail!("TESSERACT_BATCH_CONCURRENCY must be at least 1");
+            }
+            n
+        },
+        Err(_) => 4,
+    };
+    let batch_max_queries = match env::var("TESSERACT_BATCH_MAX_QUERIES") {
+        Ok(val) => {
+            val.parse::<usize>()
+                .map_err(|_| format_err!("could not parse integer from env_var TESSERACT_BATCH_MAX_QUERIES"))?
+        },
+        Err(_) => 100,
+    };
+
+    // Export jobs
+    let export_dir = env::var("TESSERACT_EXPORT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir().join("tesseract-exports"));
+    let export_max_running = match env::var("TESSERACT_EXPORT_MAX_RUNNING") {
+        Ok(val) => {
+            let n = val.parse::<usize>()
+                .map_err(|_| format_err!("could not parse integer from env_var TESSERACT_EXPORT_MAX_RUNNING"))?;
+            if n == 0 {
+                bail!("TESSERACT_EXPORT_MAX_RUNNING must be at least 1");
+            }
+            n
+        },
+        Err(_) => 2,
+    };
+    let export_max_queued = match env::var("TESSERACT_EXPORT_MAX_QUEUED") {
+        Ok(val) => {
+            val.parse::<usize>()
+                .map_err(|_| format_err!("could not parse integer from env_var TESSERACT_EXPORT_MAX_QUEUED"))?
+        },
+        Err(_) => 100,
+    };
+    let export_ttl = match env::var("TESSERACT_EXPORT_TTL") {
+        Ok(val) => {
+            val.parse::<u64>()
+                .map_err(|_| format_err!("could not parse integer from env_var TESSERACT_EXPORT_TTL"))?
+        },
+        Err(_) => 86400, // default one day
+    };
+    let export_timeout = match env::var("TESSERACT_EXPORT_TIMEOUT") {
+        Ok(val) => {
+            val.parse::<u64>()
+                .map_err(|_| format_err!("could not parse integer from env_var TESSERACT_EXPORT_TIMEOUT"))?
+        },
+        Err(_) => 3600, // default one hour
+    };
+    let exports = ExportJobs::new(ExportConfig {
+        dir: export_dir.clone(),
+        max_running: export_max_running,
+        max_queued: export_max_queued,
+        ttl: Duration::from_secs(export_ttl),
+        timeout: Duration::from_secs(export_timeout),
+    })?;
+    let exports_arc = Arc::new(RwLock::new(exports));
+
     // Database
     let db_url_full = env::var("TESSERACT_DATABASE_URL")
         .or(opt.database_url.ok_or(format_err!("")))
@@ -124,6 +209,7 @@ fn main() -> Result<(), Error> {
     schema.validate()?;
     let mut has_unique_levels_properties = schema.has_unique_levels_properties();
     let schema_arc = Arc::new(RwLock::new(schema.clone()));
+    let graphql_schema_arc = Arc::new(RwLock::new(GraphqlSchema::from_schema(&schema)));
     let jwt_status = if jwt_secret.is_some() {
         "ON"
     } else {
@@ -136,6 +222,8 @@ fn main() -> Result<(), Error> {
         schema_source,
         jwt_secret,
         flush_secret,
+        batch_concurrency,
+        batch_max_queries,
     };
 
     // Logic Layer Config
@@ -195,6 +283,30 @@ fn main() -> Result<(), Error> {
         None => None,
     };
 
+    // Postgres wire protocol listener, if enabled
+    let pg_listen_addr = env::var("TESSERACT_PG_LISTEN_ADDR").ok();
+    if let Some(ref pg_addr) = pg_listen_addr {
+        pgwire::start_listener(pg_addr, PgState {
+            debug,
+            backend: db.clone(),
+            schema: schema_arc.clone(),
+            cache: cache_arc.clone(),
+            jwt_secret: env_vars.jwt_secret.clone(),
+        })?;
+    }
+
+    // Arrow Flight server, if enabled
+    let flight_listen_addr = env::var("TESSERACT_FLIGHT_LISTEN_ADDR").ok();
+    if let Some(ref flight_addr) = flight_listen_addr {
+        flight::start_server(flight_addr, FlightState {
+            debug,
+            backend: db.clone(),
+            schema: schema_arc.clone(),
+            cache: cache_arc.clone(),
+            jwt_secret: env_vars.jwt_secret.clone(),
+        })?;
+    }
+
     // Initialize Server
     server::new(
         move|| create_app(
@@ -207,8 +319,10 @@ fn main() -> Result<(), Error> {
                 db_type.clone(),
                 env_vars.clone(),
                 schema_arc.clone(),
+                graphql_schema_arc.clone(),
                 cache_arc.clone(),
                 logic_layer_config.clone(),
+                exports_arc.clone(),
                 streaming_response,
                 has_unique_levels_properties.clone(),
             )
@@ -220,6 +334,13 @@ fn main() -> Result<(), Error> {
     println!("Tesseract listening on: {}", server_addr);
     println!("Tesseract database:     {}, {}", db_url, db_type_viz);
     println!("Tesseract schema path:  {}", schema_path);
+    println!("Tesseract export dir:   {}", export_dir.display());
+    if let Some(pg_addr) = pg_listen_addr {
+        println!("Tesseract postgres:     {}", pg_addr);
+    }
+    if let Some(flight_addr) = flight_listen_addr {
+        println!("Tesseract flight:       {}", flight_addr);
+    }
 
     println!("Tesseract JWT token protection: {}", jwt_status);
 
@@ -254,4 +375,9 @@ struct Opt {
 
     #[structopt(long="streaming")]
     streaming_response: bool,
+
+    /// Converts a Mondrian schema file to a tesseract JSON schema, printed
+    /// to stdout, and exits.
+    #[structopt(long="convert-mondrian")]
+    convert_mondrian: Option<String>,
 }
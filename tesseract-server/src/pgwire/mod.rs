@@ -0,0 +1,436 @@
+//! Postgres wire protocol front end, for tools that only query through SQL.
+//!
+//! Each cube is a table with a column for each level and measure, and a
+//! restricted `SELECT` is translated into an aggregate query on the cube
+//! (see `sql`). Clients log in with the JWT token as password when JWT
+//! protection is on, and cube auth levels apply as in the REST API.
+//! Connections are not encrypted; `SslRequest`s are refused.
+
+mod protocol;
+mod sql;
+
+use actix::Arbiter;
+use failure::{Error, format_err};
+use futures::future::{self, Future, Loop};
+use futures::{Sink, Stream};
+use log::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tesseract_core::{Backend, ColumnData, DataFrame, Schema};
+use tokio_codec::Decoder;
+use tokio_tcp::{TcpListener, TcpStream};
+
+use crate::auth::validate_web_token;
+use crate::handlers::query_sql;
+use crate::logic_layer::Cache;
+use self::protocol::{BackendMessage, FrontendMessage, PgCodec};
+use self::sql::{CubeQuery, OutputColumn, Select, Statement, Unsupported};
+
+/// Identifies connections in `BackendKeyData`; cancelling isn't supported.
+static NEXT_PROCESS_ID: AtomicUsize = AtomicUsize::new(1);
+
+
+/// What a connection needs from the server's state.
+pub struct PgState {
+    pub debug: bool,
+    pub backend: Box<dyn Backend + Sync + Send>,
+    pub schema: Arc<RwLock<Schema>>,
+    pub cache: Arc<RwLock<Cache>>,
+    pub jwt_secret: Option<String>,
+}
+
+
+/// Binds the listener, and accepts connections once the actix system runs.
+pub fn start_listener(addr: &str, state: PgState) -> Result<(), Error> {
+    let socket_addr = addr.parse::<SocketAddr>()
+        .map_err(|_| format_err!("could not parse postgres listen address {}", addr))?;
+    let listener = TcpListener::bind(&socket_addr)
+        .map_err(|err| format_err!("cannot bind postgres listener to {}: {}", addr, err))?;
+
+    let state = Arc::new(state);
+
+    Arbiter::spawn(
+        listener.incoming()
+            .map_err(|err| error!("Postgres listener error: {}", err))
+            .for_each(move |socket| {
+                Arbiter::spawn(handle_connection(socket, state.clone()));
+                Ok(())
+            })
+    );
+
+    Ok(())
+}
+
+
+/// A statement error, with its SQLSTATE code.
+#[derive(Debug)]
+struct PgError {
+    code: &'static str,
+    message: String,
+}
+
+impl PgError {
+    fn new<S: Into<String>>(code: &'static str, message: S) -> Self {
+        PgError { code, message: message.into() }
+    }
+
+    fn syntax(err: Error) -> Self {
+        PgError::new("42601", err.to_string())
+    }
+
+    fn message(self) -> BackendMessage {
+        BackendMessage::ErrorResponse { code: self.code, message: self.message }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Startup,
+    Password,
+    Ready,
+}
+
+struct Connection {
+    state: Arc<PgState>,
+    phase: Phase,
+    token: String,
+    statements: HashMap<String, Statement>,
+    portals: HashMap<String, Statement>,
+    /// After an error in the extended query flow, messages are skipped
+    /// until the next `Sync`.
+    skip_until_sync: bool,
+}
+
+type Replies = Box<dyn Future<Item=(Connection, Vec<BackendMessage>, bool), Error=Error>>;
+
+fn handle_connection(socket: TcpStream, state: Arc<PgState>) -> impl Future<Item=(), Error=()> {
+    let framed = PgCodec::default().framed(socket);
+    let conn = Connection {
+        state,
+        phase: Phase::Startup,
+        token: String::new(),
+        statements: HashMap::new(),
+        portals: HashMap::new(),
+        skip_until_sync: false,
+    };
+
+    future::loop_fn((framed, conn), |(framed, conn)| {
+        framed.into_future()
+            .map_err(|(err, _)| Error::from(err))
+            .and_then(|(msg, framed)| {
+                let msg = match msg {
+                    Some(msg) => msg,
+                    None => return future::Either::A(future::ok(Loop::Break(()))),
+                };
+
+                future::Either::B(conn.handle(msg)
+                    .and_then(|(conn, replies, close)| {
+                        framed.send(replies)
+                            .map_err(Error::from)
+                            .map(move |framed| {
+                                if close {
+                                    Loop::Break(())
+                                } else {
+                                    Loop::Continue((framed, conn))
+                                }
+                            })
+                    }))
+            })
+    })
+    .map_err(|err| debug!("Postgres connection closed: {}", err))
+}
+
+
+impl Connection {
+    /// Replies to a message; the flag closes the connection.
+    fn handle(mut self, msg: FrontendMessage) -> Replies {
+        if self.skip_until_sync {
+            match msg {
+                FrontendMessage::Sync => self.skip_until_sync = false,
+                FrontendMessage::Terminate => return Box::new(future::ok((self, vec![], true))),
+                _ => return Box::new(future::ok((self, vec![], false))),
+            }
+            return Box::new(future::ok((self, vec![BackendMessage::ReadyForQuery], false)));
+        }
+
+        match (self.phase, msg) {
+            (Phase::Startup, FrontendMessage::SslRequest) => {
+                Box::new(future::ok((self, vec![BackendMessage::SslRefused], false)))
+            },
+            (Phase::Startup, FrontendMessage::Startup { .. }) => {
+                if self.state.jwt_secret.is_some() {
+                    self.phase = Phase::Password;
+                    Box::new(future::ok((self, vec![BackendMessage::AuthenticationCleartextPassword], false)))
+                } else {
+                    let replies = self.start_session();
+                    Box::new(future::ok((self, replies, false)))
+                }
+            },
+            (Phase::Password, FrontendMessage::Password(token)) => {
+                self.token = token;
+                let replies = self.start_session();
+                Box::new(future::ok((self, replies, false)))
+            },
+            (Phase::Ready, FrontendMessage::Query(sql)) => self.simple_query(&sql),
+            (Phase::Ready, FrontendMessage::Parse { name, query }) => {
+                let replies = match Statement::parse(&query) {
+                    Ok(statement) => {
+                        self.statements.insert(name, statement);
+                        vec![BackendMessage::ParseComplete]
+                    },
+                    Err(err) => self.extended_error(PgError::syntax(err)),
+                };
+                Box::new(future::ok((self, replies, false)))
+            },
+            (Phase::Ready, FrontendMessage::Bind { portal, statement, params, binary_results }) => {
+                let replies = match self.statements.get(&statement).cloned() {
+                    Some(_) if params > 0 => {
+                        self.extended_error(PgError::new("0A000", "Parameters are not supported"))
+                    },
+                    Some(_) if binary_results => {
+                        self.extended_error(PgError::new("0A000", "Binary results are not supported"))
+                    },
+                    Some(statement) => {
+                        self.portals.insert(portal, statement);
+                        vec![BackendMessage::BindComplete]
+                    },
+                    None => {
+                        self.extended_error(PgError::new("26000", format!("Prepared statement \"{}\" does not exist", statement)))
+                    },
+                };
+                Box::new(future::ok((self, replies, false)))
+            },
+            (Phase::Ready, FrontendMessage::Describe { kind, name }) => {
+                let statement = if kind == b'S' {
+                    self.statements.get(&name).cloned()
+                } else {
+                    self.portals.get(&name).cloned()
+                };
+
+                let mut replies = vec![];
+                if kind == b'S' && statement.is_some() {
+                    replies.push(BackendMessage::ParameterDescription);
+                }
+                match statement {
+                    Some(Statement::Select(select)) => {
+                        match self.cube_query(&select) {
+                            Ok(cube_query) => replies.push(row_description(&cube_query.columns)),
+                            Err(err) => replies = self.extended_error(err),
+                        }
+                    },
+                    Some(_) => replies.push(BackendMessage::NoData),
+                    None => replies = self.extended_error(PgError::new("26000", format!("\"{}\" does not exist", name))),
+                }
+                Box::new(future::ok((self, replies, false)))
+            },
+            (Phase::Ready, FrontendMessage::Execute { portal }) => {
+                match self.portals.get(&portal).cloned() {
+                    Some(statement) => {
+                        Box::new(self.execute(statement, false)
+                            .map(|(mut conn, replies, failed)| {
+                                if failed {
+                                    conn.skip_until_sync = true;
+                                }
+                                (conn, replies, false)
+                            }))
+                    },
+                    None => {
+                        let replies = self.extended_error(PgError::new("34000", format!("Portal \"{}\" does not exist", portal)));
+                        Box::new(future::ok((self, replies, false)))
+                    },
+                }
+            },
+            (Phase::Ready, FrontendMessage::Close { kind, name }) => {
+                if kind == b'S' {
+                    self.statements.remove(&name);
+                } else {
+                    self.portals.remove(&name);
+                }
+                Box::new(future::ok((self, vec![BackendMessage::CloseComplete], false)))
+            },
+            (Phase::Ready, FrontendMessage::Sync) => {
+                self.portals.clear();
+                Box::new(future::ok((self, vec![BackendMessage::ReadyForQuery], false)))
+            },
+            (_, FrontendMessage::Flush) => Box::new(future::ok((self, vec![], false))),
+            (_, FrontendMessage::Terminate) | (_, FrontendMessage::CancelRequest) => {
+                Box::new(future::ok((self, vec![], true)))
+            },
+            (Phase::Ready, FrontendMessage::Other(msg_type)) => {
+                let err = PgError::new("0A000", format!("Message type `{}` is not supported", msg_type as char));
+                let replies = self.extended_error(err);
+                Box::new(future::ok((self, replies, false)))
+            },
+            (_, msg) => {
+                let err = PgError::new("08P01", format!("Unexpected message {:?}", msg));
+                Box::new(future::ok((self, vec![err.message()], true)))
+            },
+        }
+    }
+
+    fn start_session(&mut self) -> Vec<BackendMessage> {
+        self.phase = Phase::Ready;
+
+        let process_id = NEXT_PROCESS_ID.fetch_add(1, Ordering::SeqCst) as i32;
+
+        let mut replies = vec![BackendMessage::AuthenticationOk];
+        for (name, value) in &[
+            ("server_version", "9.6.0"),
+            ("server_encoding", "UTF8"),
+            ("client_encoding", "UTF8"),
+            ("DateStyle", "ISO, MDY"),
+            ("integer_datetimes", "on"),
+            ("standard_conforming_strings", "on"),
+        ] {
+            replies.push(BackendMessage::ParameterStatus(name.to_string(), value.to_string()));
+        }
+        replies.push(BackendMessage::BackendKeyData { process_id, secret_key: 0 });
+        replies.push(BackendMessage::ReadyForQuery);
+
+        replies
+    }
+
+    fn extended_error(&mut self, err: PgError) -> Vec<BackendMessage> {
+        self.skip_until_sync = true;
+        vec![err.message()]
+    }
+
+    fn simple_query(self, sql: &str) -> Replies {
+        let statement = match Statement::parse(sql) {
+            Ok(statement) => statement,
+            Err(err) => {
+                let replies = vec![PgError::syntax(err).message(), BackendMessage::ReadyForQuery];
+                return Box::new(future::ok((self, replies, false)));
+            },
+        };
+
+        Box::new(self.execute(statement, true)
+            .map(|(conn, mut replies, _)| {
+                replies.push(BackendMessage::ReadyForQuery);
+                (conn, replies, false)
+            }))
+    }
+
+    /// Runs a statement. The flag of the result is whether it failed. The
+    /// simple query flow describes the rows before sending them.
+    fn execute(self, statement: Statement, describe: bool) -> Replies {
+        let select = match statement {
+            Statement::Select(select) => select,
+            Statement::Command(tag) => return Box::new(future::ok((self, vec![BackendMessage::CommandComplete(tag)], false))),
+            Statement::Empty => return Box::new(future::ok((self, vec![BackendMessage::EmptyQueryResponse], false))),
+        };
+
+        let (cube_query, sql) = match self.cube_query(&select).and_then(|cube_query| self.sql(cube_query)) {
+            Ok(res) => res,
+            Err(err) => return Box::new(future::ok((self, vec![err.message()], true))),
+        };
+
+        let debug = self.state.debug;
+
+        Box::new(self.state.backend
+            .exec_sql(sql)
+            .then(move |df| {
+                let replies = match df {
+                    Ok(df) => {
+                        let mut replies = vec![];
+                        if describe {
+                            replies.push(row_description(&cube_query.columns));
+                        }
+                        replies.extend(data_rows(&cube_query.columns, &df));
+                        replies.push(BackendMessage::CommandComplete(format!("SELECT {}", df.len())));
+                        return Ok((self, replies, false));
+                    },
+                    Err(err) => {
+                        let message = if debug {
+                            err.to_string()
+                        } else {
+                            "Internal Server Error 1010".to_owned()
+                        };
+                        vec![PgError::new("XX000", message).message()]
+                    },
+                };
+                Ok((self, replies, true))
+            }))
+    }
+
+    /// Translates a select, if the user can access its cube.
+    fn cube_query(&self, select: &Select) -> Result<CubeQuery, PgError> {
+        let schema = self.state.schema.read().unwrap();
+        let cube = select.cube(&schema)
+            .map_err(|err| PgError::new("42P01", err.to_string()))?;
+
+        if !validate_web_token(&self.state.jwt_secret, &self.token, cube.min_auth_level) {
+            return Err(PgError::new("42501", "This cube is not public"));
+        }
+
+        select.cube_query(cube)
+            .map_err(|err| {
+                let code = if err.downcast_ref::<Unsupported>().is_some() { "0A000" } else { "42703" };
+                PgError::new(code, err.to_string())
+            })
+    }
+
+    /// Checks the cut members, and generates the SQL of a query.
+    fn sql(&self, cube_query: CubeQuery) -> Result<(CubeQuery, String), PgError> {
+        let schema = self.state.schema.read().unwrap();
+        let cache = self.state.cache.read().unwrap();
+        let (sql, _headers) = query_sql(&schema, &cache, &*self.state.backend, &cube_query.cube, &cube_query.query)
+            .map_err(|err| PgError::new("22023", err.to_string()))?;
+
+        Ok((cube_query, sql))
+    }
+}
+
+
+fn row_description(columns: &[OutputColumn]) -> BackendMessage {
+    BackendMessage::RowDescription(
+        columns.iter()
+            .map(|column| (column.name.clone(), column.type_oid))
+            .collect()
+    )
+}
+
+fn data_rows(columns: &[OutputColumn], df: &DataFrame) -> Vec<BackendMessage> {
+    (0..df.len())
+        .map(|row_idx| {
+            BackendMessage::DataRow(
+                columns.iter()
+                    .map(|column| {
+                        df.columns.get(column.col_idx)
+                            .and_then(|col| column_text(&col.column_data, row_idx))
+                    })
+                    .collect()
+            )
+        })
+        .collect()
+}
+
+fn column_text(column_data: &ColumnData, row_idx: usize) -> Option<String> {
+    match column_data {
+        ColumnData::Int8(ns) =>    Some(ns[row_idx].to_string()),
+        ColumnData::Int16(ns) =>   Some(ns[row_idx].to_string()),
+        ColumnData::Int32(ns) =>   Some(ns[row_idx].to_string()),
+        ColumnData::Int64(ns) =>   Some(ns[row_idx].to_string()),
+        ColumnData::UInt8(ns) =>   Some(ns[row_idx].to_string()),
+        ColumnData::UInt16(ns) =>  Some(ns[row_idx].to_string()),
+        ColumnData::UInt32(ns) =>  Some(ns[row_idx].to_string()),
+        ColumnData::UInt64(ns) =>  Some(ns[row_idx].to_string()),
+        ColumnData::Float32(ns) => Some(ns[row_idx].to_string()),
+        ColumnData::Float64(ns) => Some(ns[row_idx].to_string()),
+        ColumnData::Text(ss) =>    Some(ss[row_idx].clone()),
+        ColumnData::NullableInt8(ns) =>    ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableInt16(ns) =>   ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableInt32(ns) =>   ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableInt64(ns) =>   ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableUInt8(ns) =>   ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableUInt16(ns) =>  ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableUInt32(ns) =>  ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableUInt64(ns) =>  ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableFloat32(ns) => ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableFloat64(ns) => ns[row_idx].map(|n| n.to_string()),
+        ColumnData::NullableText(ss) =>    ss[row_idx].clone(),
+    }
+}
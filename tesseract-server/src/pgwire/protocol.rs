@@ -0,0 +1,348 @@
+//! Messages of the Postgres frontend/backend protocol, version 3.0.
+//!
+//! Only the messages needed for the simple and extended query flows are
+//! decoded. Results are always sent in text format.
+
+use bytes::{Buf, BufMut, BytesMut};
+use std::io::{self, Cursor};
+use tokio_codec::{Decoder, Encoder};
+
+const PROTOCOL_VERSION: i32 = 196_608; // 3.0
+const SSL_REQUEST_CODE: i32 = 80_877_103;
+const CANCEL_REQUEST_CODE: i32 = 80_877_102;
+const MAX_MESSAGE_LEN: usize = 1 << 24;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrontendMessage {
+    SslRequest,
+    Startup { params: Vec<(String, String)> },
+    CancelRequest,
+    Password(String),
+    Query(String),
+    Parse { name: String, query: String },
+    Bind { portal: String, statement: String, params: usize, binary_results: bool },
+    /// `kind` is `S` for a statement and `P` for a portal
+    Describe { kind: u8, name: String },
+    Execute { portal: String },
+    Close { kind: u8, name: String },
+    Sync,
+    Flush,
+    Terminate,
+    /// Any other message, by its type byte
+    Other(u8),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendMessage {
+    /// Reply to an `SslRequest`, which isn't a regular message
+    SslRefused,
+    AuthenticationOk,
+    AuthenticationCleartextPassword,
+    ParameterStatus(String, String),
+    BackendKeyData { process_id: i32, secret_key: i32 },
+    ReadyForQuery,
+    /// Field names and type oids
+    RowDescription(Vec<(String, u32)>),
+    /// A statement without parameters
+    ParameterDescription,
+    DataRow(Vec<Option<String>>),
+    CommandComplete(String),
+    EmptyQueryResponse,
+    ErrorResponse { code: &'static str, message: String },
+    ParseComplete,
+    BindComplete,
+    CloseComplete,
+    NoData,
+}
+
+
+/// Decodes frontend messages, and encodes batches of backend messages so
+/// that each batch is written at once.
+#[derive(Debug, Default)]
+pub struct PgCodec {
+    started: bool,
+}
+
+impl Decoder for PgCodec {
+    type Item = FrontendMessage;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<FrontendMessage>, io::Error> {
+        // startup messages have no type byte
+        let header_len = if self.started { 5 } else { 4 };
+        if src.len() < header_len {
+            return Ok(None);
+        }
+
+        let msg_type = if self.started { Some(src[0]) } else { None };
+        let len_start = header_len - 4;
+        let len = Cursor::new(&src[len_start..header_len]).get_i32_be();
+        if len < 4 || len as usize > MAX_MESSAGE_LEN {
+            return Err(invalid_data(format!("invalid message length {}", len)));
+        }
+
+        let total_len = len_start + len as usize;
+        if src.len() < total_len {
+            src.reserve(total_len - src.len());
+            return Ok(None);
+        }
+
+        let msg = src.split_to(total_len);
+        let mut body = Body(&msg[header_len..]);
+
+        let msg = match msg_type {
+            None => {
+                match body.i32()? {
+                    SSL_REQUEST_CODE => FrontendMessage::SslRequest,
+                    CANCEL_REQUEST_CODE => FrontendMessage::CancelRequest,
+                    PROTOCOL_VERSION => {
+                        let mut params = vec![];
+                        loop {
+                            let key = body.cstr()?;
+                            if key.is_empty() {
+                                break;
+                            }
+                            params.push((key, body.cstr()?));
+                        }
+                        self.started = true;
+                        FrontendMessage::Startup { params }
+                    },
+                    version => return Err(invalid_data(format!("unsupported protocol version {}", version))),
+                }
+            },
+            Some(b'p') => FrontendMessage::Password(body.cstr()?),
+            Some(b'Q') => FrontendMessage::Query(body.cstr()?),
+            Some(b'P') => {
+                let name = body.cstr()?;
+                let query = body.cstr()?;
+                FrontendMessage::Parse { name, query }
+            },
+            Some(b'B') => {
+                let portal = body.cstr()?;
+                let statement = body.cstr()?;
+                let n_formats = body.i16()?;
+                for _ in 0..n_formats {
+                    body.i16()?;
+                }
+                let params = body.i16()?;
+                for _ in 0..params {
+                    let len = body.i32()?;
+                    if len > 0 {
+                        body.skip(len as usize)?;
+                    }
+                }
+                let n_result_formats = body.i16()?;
+                let mut binary_results = false;
+                for _ in 0..n_result_formats {
+                    binary_results |= body.i16()? == 1;
+                }
+                FrontendMessage::Bind { portal, statement, params: params.max(0) as usize, binary_results }
+            },
+            Some(b'D') => {
+                let kind = body.u8()?;
+                FrontendMessage::Describe { kind, name: body.cstr()? }
+            },
+            Some(b'E') => FrontendMessage::Execute { portal: body.cstr()? },
+            Some(b'C') => {
+                let kind = body.u8()?;
+                FrontendMessage::Close { kind, name: body.cstr()? }
+            },
+            Some(b'S') => FrontendMessage::Sync,
+            Some(b'H') => FrontendMessage::Flush,
+            Some(b'X') => FrontendMessage::Terminate,
+            Some(other) => FrontendMessage::Other(other),
+        };
+
+        Ok(Some(msg))
+    }
+}
+
+impl Encoder for PgCodec {
+    type Item = Vec<BackendMessage>;
+    type Error = io::Error;
+
+    fn encode(&mut self, msgs: Vec<BackendMessage>, dst: &mut BytesMut) -> Result<(), io::Error> {
+        for msg in msgs {
+            encode_message(msg, dst);
+        }
+        Ok(())
+    }
+}
+
+fn encode_message(msg: BackendMessage, dst: &mut BytesMut) {
+    let mut body: Vec<u8> = vec![];
+
+    let msg_type = match msg {
+        BackendMessage::SslRefused => {
+            dst.reserve(1);
+            dst.put_u8(b'N');
+            return;
+        },
+        BackendMessage::AuthenticationOk => {
+            body.put_i32_be(0);
+            b'R'
+        },
+        BackendMessage::AuthenticationCleartextPassword => {
+            body.put_i32_be(3);
+            b'R'
+        },
+        BackendMessage::ParameterStatus(name, value) => {
+            put_cstr(&mut body, &name);
+            put_cstr(&mut body, &value);
+            b'S'
+        },
+        BackendMessage::BackendKeyData { process_id, secret_key } => {
+            body.put_i32_be(process_id);
+            body.put_i32_be(secret_key);
+            b'K'
+        },
+        BackendMessage::ReadyForQuery => {
+            body.put_u8(b'I');
+            b'Z'
+        },
+        BackendMessage::RowDescription(fields) => {
+            body.put_i16_be(fields.len() as i16);
+            for (name, type_oid) in fields {
+                put_cstr(&mut body, &name);
+                body.put_i32_be(0); // table oid
+                body.put_i16_be(0); // column number
+                body.put_u32_be(type_oid);
+                body.put_i16_be(-1); // type size
+                body.put_i32_be(-1); // type modifier
+                body.put_i16_be(0); // text format
+            }
+            b'T'
+        },
+        BackendMessage::ParameterDescription => {
+            body.put_i16_be(0);
+            b't'
+        },
+        BackendMessage::DataRow(values) => {
+            body.put_i16_be(values.len() as i16);
+            for value in values {
+                match value {
+                    Some(value) => {
+                        body.put_i32_be(value.len() as i32);
+                        body.put_slice(value.as_bytes());
+                    },
+                    None => {
+                        body.put_i32_be(-1);
+                    },
+                }
+            }
+            b'D'
+        },
+        BackendMessage::CommandComplete(tag) => {
+            put_cstr(&mut body, &tag);
+            b'C'
+        },
+        BackendMessage::EmptyQueryResponse => b'I',
+        BackendMessage::ErrorResponse { code, message } => {
+            for (field, value) in &[(b'S', "ERROR"), (b'V', "ERROR"), (b'C', code), (b'M', message.as_str())] {
+                body.put_u8(*field);
+                put_cstr(&mut body, value);
+            }
+            body.put_u8(0);
+            b'E'
+        },
+        BackendMessage::ParseComplete => b'1',
+        BackendMessage::BindComplete => b'2',
+        BackendMessage::CloseComplete => b'3',
+        BackendMessage::NoData => b'n',
+    };
+
+    dst.reserve(5 + body.len());
+    dst.put_u8(msg_type);
+    dst.put_i32_be(4 + body.len() as i32);
+    dst.extend_from_slice(&body);
+}
+
+fn put_cstr(dst: &mut Vec<u8>, s: &str) {
+    dst.put_slice(s.as_bytes());
+    dst.put_u8(0);
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+/// Reads the fields of a message body.
+struct Body<'a>(&'a [u8]);
+
+impl<'a> Body<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], io::Error> {
+        if self.0.len() < n {
+            return Err(invalid_data("message is too short".to_owned()));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), io::Error> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, io::Error> {
+        Ok(Cursor::new(self.take(2)?).get_i16_be())
+    }
+
+    fn i32(&mut self) -> Result<i32, io::Error> {
+        Ok(Cursor::new(self.take(4)?).get_i32_be())
+    }
+
+    fn cstr(&mut self) -> Result<String, io::Error> {
+        let end = self.0.iter().position(|&b| b == 0)
+            .ok_or_else(|| invalid_data("string is not terminated".to_owned()))?;
+        let s = String::from_utf8(self.take(end)?.to_vec())
+            .map_err(|_| invalid_data("string is not utf-8".to_owned()))?;
+        self.skip(1)?;
+        Ok(s)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codec() {
+        let mut codec = PgCodec::default();
+
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0, 0, 0, 8, 4, 210, 22, 47]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(FrontendMessage::SslRequest));
+
+        let startup = b"\x00\x03\x00\x00user\x00me\x00\x00";
+        buf.put_i32_be(4 + startup.len() as i32);
+        buf.extend_from_slice(startup);
+        // a partial query waits for the rest
+        buf.extend_from_slice(b"Q\x00\x00\x00\x0dSELECT");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(FrontendMessage::Startup { params: vec![("user".to_owned(), "me".to_owned())] })
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b" 1\x00");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(FrontendMessage::Query("SELECT 1".to_owned())));
+        assert!(buf.is_empty());
+
+        let mut out = BytesMut::new();
+        codec.encode(vec![
+            BackendMessage::RowDescription(vec![("a".to_owned(), 25)]),
+            BackendMessage::DataRow(vec![Some("x".to_owned()), None]),
+            BackendMessage::ReadyForQuery,
+        ], &mut out).unwrap();
+        assert_eq!(&out[..], &b"T\x00\x00\x00\x1a\x00\x01a\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x19\xff\xff\xff\xff\xff\xff\x00\x00\
+            D\x00\x00\x00\x0f\x00\x02\x00\x00\x00\x01x\xff\xff\xff\xff\
+            Z\x00\x00\x00\x05I"[..]);
+    }
+}
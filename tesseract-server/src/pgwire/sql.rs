@@ -0,0 +1,947 @@
+//! The SQL accepted by the Postgres front end, and its translation into
+//! tesseract queries.
+//!
+//! Each cube is a table with a column for each level and measure, and a key
+//! column for each level with a name column:
+//!
+//! ```sql
+//! SELECT "State", SUM("Quantity") FROM "Sales Data"
+//! WHERE "Year Year" IN (2018, 2019) AND "State ID" <> 'CA'
+//! GROUP BY "State"
+//! ORDER BY "Quantity" DESC
+//! LIMIT 10
+//! ```
+//!
+//! Levels in the select list or `GROUP BY` are drilldowns, and conditions on
+//! level keys are cuts. Measures may be wrapped in the aggregate function of
+//! their aggregator, which is then ignored: measures are always aggregated
+//! as the schema says.
+
+use failure::{Error, Fail, bail, ensure, format_err};
+use std::collections::{HashMap, HashSet};
+use tesseract_core::Query as TsQuery;
+use tesseract_core::names::{Cut, Drilldown, LevelName, Mask, Measure as MeasureName};
+use tesseract_core::query::{LimitQuery, MeaOrCalc, SortDirection, SortQuery};
+use tesseract_core::query_ir::MemberType;
+use tesseract_core::schema::{Cube, Schema, Aggregator};
+
+/// Postgres type oids of the columns.
+pub const INT8_OID: u32 = 20;
+pub const TEXT_OID: u32 = 25;
+pub const FLOAT8_OID: u32 = 701;
+pub const NUMERIC_OID: u32 = 1700;
+
+const AGGREGATE_FUNCTIONS: &[&str] = &["sum", "avg", "min", "max", "count"];
+
+
+/// A statement that refers to existing columns, but can't be translated
+/// into a query.
+#[derive(Debug, Fail)]
+#[fail(display = "{}", _0)]
+pub struct Unsupported(pub String);
+
+
+/// A parsed statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Select),
+    /// Session and transaction commands that clients send on their own,
+    /// which are accepted and do nothing. Holds the command tag.
+    Command(String),
+    Empty,
+}
+
+impl Statement {
+    pub fn parse(sql: &str) -> Result<Self, Error> {
+        let tokens = tokenize(sql)?;
+        let mut parser = Parser { tokens, pos: 0 };
+
+        if parser.eat_symbol(';') || parser.at_end() {
+            ensure!(parser.at_end(), "Only one statement is supported");
+            return Ok(Statement::Empty);
+        }
+
+        let keyword = match parser.peek() {
+            Some(Token::Ident(word, false)) => word.to_uppercase(),
+            _ => bail!("Expected a statement"),
+        };
+
+        let tag = match keyword.as_str() {
+            "SELECT" => return Ok(Statement::Select(parser.select()?)),
+            "SET" => "SET",
+            "RESET" => "RESET",
+            "BEGIN" | "START" => "BEGIN",
+            "COMMIT" | "END" => "COMMIT",
+            "ROLLBACK" => "ROLLBACK",
+            "DISCARD" => "DISCARD ALL",
+            _ => bail!("Only SELECT statements are supported, found {}", keyword),
+        };
+
+        Ok(Statement::Command(tag.to_owned()))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub columns: Vec<SelectItem>,
+    pub table: Identifier,
+    /// Conditions on a column, with their members and whether they exclude
+    /// the members.
+    pub conditions: Vec<(Identifier, Vec<String>, bool)>,
+    pub group_by: Vec<Identifier>,
+    pub order_by: Option<(Identifier, SortDirection)>,
+    /// Number of rows, and offset
+    pub limit: Option<(u64, Option<u64>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectItem {
+    pub column: Identifier,
+    /// Aggregate function around the column, lowercased
+    pub function: Option<String>,
+    pub alias: Option<String>,
+}
+
+/// A name, and whether it was quoted. Unquoted names match case
+/// insensitively.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub quoted: bool,
+}
+
+impl Identifier {
+    fn matches(&self, name: &str) -> bool {
+        if self.quoted {
+            self.name == name
+        } else {
+            self.name.eq_ignore_ascii_case(name)
+        }
+    }
+}
+
+
+/// A select translated into a query on its cube, and how to read the
+/// selected columns from the result.
+#[derive(Debug, Clone)]
+pub struct CubeQuery {
+    pub cube: String,
+    pub query: TsQuery,
+    pub columns: Vec<OutputColumn>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputColumn {
+    pub name: String,
+    pub type_oid: u32,
+    /// Index of the column in the query result
+    pub col_idx: usize,
+}
+
+
+/// The columns of a cube's table.
+#[derive(Debug, Clone)]
+pub struct CubeTable {
+    pub cube: String,
+    pub columns: Vec<TableColumn>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableColumn {
+    pub name: String,
+    pub type_oid: u32,
+    pub source: ColumnSource,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnSource {
+    /// A level's names if `names`, and its keys otherwise. A level with a
+    /// name column has both columns.
+    Level { level_name: LevelName, has_name_column: bool, names: bool },
+    Measure(String),
+}
+
+impl ColumnSource {
+    fn level_name(&self) -> Option<&LevelName> {
+        match self {
+            ColumnSource::Level { level_name, .. } => Some(level_name),
+            ColumnSource::Measure(_) => None,
+        }
+    }
+}
+
+impl CubeTable {
+    /// Levels are named by their level name if it is unique in the cube, and
+    /// with their dimension and hierarchy otherwise, as in GraphQL. A level
+    /// with a name column holds the names, and its keys are in the level's
+    /// column with an ` ID` suffix, as in the REST API.
+    pub fn from_cube(cube: &Cube) -> Self {
+        let mut level_name_counts: HashMap<&str, usize> = HashMap::new();
+        for dim in &cube.dimensions {
+            for hier in &dim.hierarchies {
+                for level in &hier.levels {
+                    *level_name_counts.entry(&level.name).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut used = HashSet::new();
+        let mut columns = vec![];
+
+        for dim in &cube.dimensions {
+            for hier in &dim.hierarchies {
+                for level in &hier.levels {
+                    let full_name = if level_name_counts[level.name.as_str()] == 1 {
+                        level.name.clone()
+                    } else if dim.name == hier.name {
+                        format!("{} {}", dim.name, level.name)
+                    } else {
+                        format!("{} {} {}", dim.name, hier.name, level.name)
+                    };
+
+                    let key_type_oid = match level.key_type {
+                        Some(MemberType::NonText) => NUMERIC_OID,
+                        _ => TEXT_OID,
+                    };
+                    let level_name = LevelName::new(dim.name.clone(), hier.name.clone(), level.name.clone());
+                    let has_name_column = level.name_column.is_some();
+
+                    if has_name_column {
+                        columns.push(TableColumn {
+                            name: unique_name(format!("{} ID", full_name), &mut used),
+                            type_oid: key_type_oid,
+                            source: ColumnSource::Level { level_name: level_name.clone(), has_name_column, names: false },
+                        });
+                    }
+
+                    columns.push(TableColumn {
+                        name: unique_name(full_name, &mut used),
+                        type_oid: if has_name_column { TEXT_OID } else { key_type_oid },
+                        source: ColumnSource::Level { level_name, has_name_column, names: has_name_column },
+                    });
+                }
+            }
+        }
+
+        for measure in &cube.measures {
+            let type_oid = match measure.aggregator {
+                Aggregator::Count => INT8_OID,
+                _ => FLOAT8_OID,
+            };
+
+            columns.push(TableColumn {
+                name: unique_name(measure.name.clone(), &mut used),
+                type_oid,
+                source: ColumnSource::Measure(measure.name.clone()),
+            });
+        }
+
+        CubeTable {
+            cube: cube.name.clone(),
+            columns,
+        }
+    }
+
+    fn column(&self, ident: &Identifier) -> Result<&TableColumn, Error> {
+        self.columns.iter()
+            .find(|column| ident.matches(&column.name))
+            .ok_or_else(|| format_err!("Column \"{}\" does not exist in \"{}\"", ident.name, self.cube))
+    }
+}
+
+
+impl Select {
+    /// Finds the cube of the select in the schema.
+    pub fn cube<'a>(&self, schema: &'a Schema) -> Result<&'a Cube, Error> {
+        schema.cubes.iter()
+            .find(|cube| self.table.matches(&cube.name))
+            .ok_or_else(|| format_err!("Relation \"{}\" does not exist", self.table.name))
+    }
+
+    pub fn cube_query(&self, cube: &Cube) -> Result<CubeQuery, Error> {
+        let table = CubeTable::from_cube(cube);
+
+        let mut selected = vec![];
+        for item in &self.columns {
+            selected.push((table.column(&item.column)?, item));
+        }
+
+        // levels of the select list must be grouped, as in SQL; without
+        // GROUP BY, they are grouped anyway
+        let mut drill_columns: Vec<&TableColumn> = vec![];
+        if self.group_by.is_empty() {
+            for (column, _) in &selected {
+                if let ColumnSource::Level { .. } = column.source {
+                    drill_columns.push(column);
+                }
+            }
+        } else {
+            for ident in &self.group_by {
+                let column = table.column(ident)?;
+                ensure!(
+                    matches!(column.source, ColumnSource::Level { .. }),
+                    "Cannot GROUP BY measure \"{}\"", column.name
+                );
+                drill_columns.push(column);
+            }
+            // the key and name columns of a level are grouped together
+            for (column, _) in &selected {
+                if let Some(level_name) = column.source.level_name() {
+                    ensure!(
+                        drill_columns.iter().any(|drill| drill.source.level_name() == Some(level_name)),
+                        "Column \"{}\" must appear in the GROUP BY clause", column.name
+                    );
+                }
+            }
+        }
+        let mut drill_levels = HashSet::new();
+        drill_columns.retain(|column| drill_levels.insert(column.source.level_name()));
+
+        let mut measure_columns: Vec<&TableColumn> = selected.iter()
+            .map(|(column, _)| *column)
+            .filter(|column| matches!(column.source, ColumnSource::Measure(_)))
+            .collect();
+        dedup_columns(&mut measure_columns);
+        ensure!(!measure_columns.is_empty(), "At least one measure must be selected");
+
+        // aggregate functions must be the one the measure is aggregated with
+        for (column, item) in &selected {
+            let function = match item.function {
+                Some(ref function) => function,
+                None => continue,
+            };
+            let aggregator = match column.source {
+                ColumnSource::Measure(ref measure) => {
+                    cube.measures.iter()
+                        .find(|mea| mea.name == *measure)
+                        .map(|mea| &mea.aggregator)
+                },
+                ColumnSource::Level { .. } => None,
+            };
+            if aggregator.and_then(aggregate_function) != Some(function.as_str()) {
+                return Err(Unsupported(format!(
+                    "{}(\"{}\") is not supported, measures can only be aggregated as the schema says",
+                    function.to_uppercase(), column.name
+                )).into());
+            }
+        }
+
+        // columns of the result are the drilldowns, with a key column first
+        // if the level has a name column, then the measures
+        let mut col_indices: HashMap<&str, usize> = HashMap::new();
+        let mut col_idx = 0;
+        let mut query = TsQuery::new();
+
+        for column in &drill_columns {
+            if let ColumnSource::Level { ref level_name, has_name_column, .. } = column.source {
+                for table_column in &table.columns {
+                    if table_column.source.level_name() == Some(level_name) {
+                        let names = matches!(table_column.source, ColumnSource::Level { names: true, .. });
+                        col_indices.insert(&table_column.name, col_idx + names as usize);
+                    }
+                }
+                col_idx += if has_name_column { 2 } else { 1 };
+                query.drilldowns.push(Drilldown(level_name.clone()));
+            }
+        }
+        for column in &measure_columns {
+            if let ColumnSource::Measure(ref measure) = column.source {
+                col_indices.insert(&column.name, col_idx);
+                col_idx += 1;
+                query.measures.push(MeasureName::new(measure.clone()));
+            }
+        }
+
+        for (ident, members, exclude) in &self.conditions {
+            let column = table.column(ident)?;
+            // the members cache only has keys, so names can't be checked
+            let level_name = match column.source {
+                ColumnSource::Level { ref level_name, names: false, .. } => level_name.clone(),
+                ColumnSource::Level { .. } => {
+                    return Err(Unsupported(format!(
+                        "Conditions on names in \"{}\" are not supported, use its key column \"{} ID\"",
+                        column.name, column.name
+                    )).into());
+                },
+                ColumnSource::Measure(_) => {
+                    return Err(Unsupported(format!("Conditions on measure \"{}\" are not supported", column.name)).into());
+                },
+            };
+            let mask = if *exclude { Mask::Exclude } else { Mask::Include };
+
+            // conditions on the same level are merged, as long as they agree
+            match query.cuts.iter_mut().find(|cut| cut.level_name == level_name) {
+                Some(cut) => {
+                    ensure!(cut.mask == mask, "Conditions on \"{}\" must all include or all exclude members", column.name);
+                    if *exclude {
+                        cut.members.extend(members.iter().cloned());
+                    } else {
+                        cut.members.retain(|member| members.contains(member));
+                    }
+                },
+                None => {
+                    query.cuts.push(Cut {
+                        level_name,
+                        members: members.clone(),
+                        mask,
+                        for_match: false,
+                        range: None,
+                        property: None,
+                    });
+                },
+            }
+        }
+        ensure!(
+            query.cuts.iter().all(|cut| !cut.members.is_empty()),
+            "Conditions on the same column have no members in common"
+        );
+
+        if let Some((ref ident, ref direction)) = self.order_by {
+            let column = table.column(ident)?;
+            match column.source {
+                ColumnSource::Measure(ref measure) if col_indices.contains_key(column.name.as_str()) => {
+                    query.sort = Some(SortQuery {
+                        direction: direction.clone(),
+                        measure: MeaOrCalc::Mea(MeasureName::new(measure.clone())),
+                    });
+                },
+                _ => {
+                    return Err(Unsupported(format!("ORDER BY only supports selected measures, found \"{}\"", column.name)).into());
+                },
+            }
+        }
+
+        query.limit = self.limit.map(|(n, offset)| LimitQuery { offset, n });
+
+        let columns = selected.iter()
+            .map(|(column, item)| {
+                OutputColumn {
+                    name: item.alias.clone().unwrap_or_else(|| column.name.clone()),
+                    type_oid: column.type_oid,
+                    col_idx: col_indices[column.name.as_str()],
+                }
+            })
+            .collect();
+
+        Ok(CubeQuery {
+            cube: cube.name.clone(),
+            query,
+            columns,
+        })
+    }
+}
+
+
+/// The SQL function of an aggregator, if it has one.
+fn aggregate_function(aggregator: &Aggregator) -> Option<&'static str> {
+    match aggregator {
+        Aggregator::Sum => Some("sum"),
+        Aggregator::Count => Some("count"),
+        Aggregator::Average => Some("avg"),
+        Aggregator::Max => Some("max"),
+        Aggregator::Min => Some("min"),
+        _ => None,
+    }
+}
+
+fn dedup_columns(columns: &mut Vec<&TableColumn>) {
+    let mut seen = HashSet::new();
+    columns.retain(|column| seen.insert(column.name.clone()));
+}
+
+/// Adds a numeric suffix to a name until it isn't in `used`.
+fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    let mut unique = name.clone();
+    let mut n = 2;
+
+    while used.contains(&unique) {
+        unique = format!("{} {}", name, n);
+        n += 1;
+    }
+
+    used.insert(unique.clone());
+    unique
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Name, and whether it was quoted
+    Ident(String, bool),
+    Str(String),
+    Number(String),
+    Symbol(char),
+    /// `<>` or `!=`
+    NotEqual,
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = sql.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => { chars.next(); },
+            '-' if sql_comment_follows(&mut chars.clone()) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            '\'' | '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        // a doubled quote is an escaped quote
+                        Some(q) if q == c => {
+                            if chars.peek() == Some(&c) {
+                                chars.next();
+                                s.push(c);
+                            } else {
+                                break;
+                            }
+                        },
+                        Some(other) => s.push(other),
+                        None => bail!("Unterminated quote in `{}`", sql),
+                    }
+                }
+                if c == '\'' {
+                    tokens.push(Token::Str(s));
+                } else {
+                    tokens.push(Token::Ident(s, true));
+                }
+            },
+            '<' | '!' => {
+                chars.next();
+                match (c, chars.next()) {
+                    ('<', Some('>')) | ('!', Some('=')) => tokens.push(Token::NotEqual),
+                    _ => bail!("Unsupported operator in `{}`", sql),
+                }
+            },
+            ',' | '(' | ')' | '=' | '*' | '.' | ';' => {
+                chars.next();
+                tokens.push(Token::Symbol(c));
+            },
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut s = String::new();
+                s.push(c);
+                chars.next();
+                while let Some(&d) = chars.peek() {
+                    if d.is_ascii_digit() || d == '.' {
+                        s.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Number(s));
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = String::new();
+                while let Some(&d) = chars.peek() {
+                    if d.is_alphanumeric() || d == '_' {
+                        s.push(d);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(s, false));
+            },
+            _ => bail!("Unexpected character `{}` in `{}`", c, sql),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn sql_comment_follows<I: Iterator<Item=char>>(chars: &mut I) -> bool {
+    chars.next() == Some('-') && chars.next() == Some('-')
+}
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), Error> {
+        ensure!(self.eat_symbol(symbol), "Expected `{}` {}", symbol, self.position());
+        Ok(())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(word, false)) => word.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        ensure!(self.eat_keyword(keyword), "Expected {} {}", keyword, self.position());
+        Ok(())
+    }
+
+    fn position(&self) -> String {
+        match self.peek() {
+            Some(token) => format!("at {:?}", token),
+            None => "at the end of the statement".to_owned(),
+        }
+    }
+
+    fn identifier(&mut self) -> Result<Identifier, Error> {
+        match self.peek().cloned() {
+            Some(Token::Ident(name, quoted)) => {
+                self.pos += 1;
+                Ok(Identifier { name, quoted })
+            },
+            _ => bail!("Expected a name {}", self.position()),
+        }
+    }
+
+    /// A column name, which may be qualified by the table name.
+    fn column(&mut self) -> Result<Identifier, Error> {
+        let mut ident = self.identifier()?;
+        if self.eat_symbol('.') {
+            ident = self.identifier()?;
+        }
+        Ok(ident)
+    }
+
+    fn literal(&mut self) -> Result<String, Error> {
+        match self.peek().cloned() {
+            Some(Token::Str(s)) | Some(Token::Number(s)) => {
+                self.pos += 1;
+                Ok(s)
+            },
+            _ => bail!("Expected a string or number {}", self.position()),
+        }
+    }
+
+    fn select(&mut self) -> Result<Select, Error> {
+        self.expect_keyword("SELECT")?;
+
+        let mut columns = vec![];
+        loop {
+            ensure!(!self.eat_symbol('*'), "SELECT * is not supported, select levels and measures by name");
+            let (function, column) = self.select_item_column()?;
+
+            // `AS` is optional before an alias
+            let has_alias = self.eat_keyword("AS")
+                || (!self.is_keyword("FROM") && matches!(self.peek(), Some(Token::Ident(..))));
+            let alias = if has_alias {
+                Some(self.identifier()?.name)
+            } else {
+                None
+            };
+
+            columns.push(SelectItem { column, function, alias });
+
+            if !self.eat_symbol(',') {
+                break;
+            }
+        }
+
+        self.expect_keyword("FROM")?;
+        let mut table = self.identifier()?;
+        // the only schema is `public`
+        if self.eat_symbol('.') {
+            ensure!(table.matches("public"), "Schema \"{}\" does not exist", table.name);
+            table = self.identifier()?;
+        }
+        // a table alias only qualifies columns, so it is skipped
+        if self.eat_keyword("AS") {
+            self.identifier()?;
+        } else if let Some(Token::Ident(_, quoted)) = self.peek() {
+            let is_clause = ["WHERE", "GROUP", "ORDER", "LIMIT"].iter().any(|kw| self.is_keyword(kw));
+            if *quoted || !is_clause {
+                self.identifier()?;
+            }
+        }
+
+        let mut conditions = vec![];
+        if self.eat_keyword("WHERE") {
+            loop {
+                conditions.push(self.condition()?);
+                if !self.eat_keyword("AND") {
+                    break;
+                }
+            }
+        }
+
+        let mut group_by = vec![];
+        if self.eat_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            loop {
+                group_by.push(self.column()?);
+                if !self.eat_symbol(',') {
+                    break;
+                }
+            }
+        }
+
+        let mut order_by = None;
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            let column = self.column()?;
+            let direction = if self.eat_keyword("DESC") {
+                SortDirection::Desc
+            } else {
+                self.eat_keyword("ASC");
+                SortDirection::Asc
+            };
+            ensure!(!self.eat_symbol(','), "ORDER BY supports only one column");
+            order_by = Some((column, direction));
+        }
+
+        let mut limit = None;
+        if self.eat_keyword("LIMIT") {
+            let n = self.literal()?.parse::<u64>()
+                .map_err(|_| format_err!("LIMIT must be a positive integer"))?;
+            let offset = if self.eat_keyword("OFFSET") {
+                Some(self.literal()?.parse::<u64>().map_err(|_| format_err!("OFFSET must be a positive integer"))?)
+            } else {
+                None
+            };
+            limit = Some((n, offset));
+        }
+
+        self.eat_symbol(';');
+        ensure!(self.at_end(), "Unexpected {:?}, only one statement is supported", self.peek());
+
+        Ok(Select {
+            columns,
+            table,
+            conditions,
+            group_by,
+            order_by,
+            limit,
+        })
+    }
+
+    /// A column, or an aggregate function of a column.
+    fn select_item_column(&mut self) -> Result<(Option<String>, Identifier), Error> {
+        let is_function = matches!(
+            (self.peek(), self.tokens.get(self.pos + 1)),
+            (Some(Token::Ident(_, false)), Some(Token::Symbol('(')))
+        );
+
+        if is_function {
+            let function = self.identifier()?;
+            ensure!(
+                AGGREGATE_FUNCTIONS.iter().any(|f| function.matches(f)),
+                "Function {} is not supported", function.name
+            );
+            self.expect_symbol('(')?;
+            let column = self.column()?;
+            self.expect_symbol(')')?;
+            Ok((Some(function.name.to_lowercase()), column))
+        } else {
+            Ok((None, self.column()?))
+        }
+    }
+
+    /// `column = value`, `column <> value`, `column IN (values)` or
+    /// `column NOT IN (values)`.
+    fn condition(&mut self) -> Result<(Identifier, Vec<String>, bool), Error> {
+        let column = self.column()?;
+
+        if self.eat_symbol('=') {
+            return Ok((column, vec![self.literal()?], false));
+        }
+        if self.peek() == Some(&Token::NotEqual) {
+            self.pos += 1;
+            return Ok((column, vec![self.literal()?], true));
+        }
+
+        let exclude = self.eat_keyword("NOT");
+        self.expect_keyword("IN")?;
+        self.expect_symbol('(')?;
+
+        let mut members = vec![];
+        loop {
+            members.push(self.literal()?);
+            if !self.eat_symbol(',') {
+                break;
+            }
+        }
+        self.expect_symbol(')')?;
+
+        Ok((column, members, exclude))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_schema() -> Schema {
+        let s = r##"
+        <Schema name="Test">
+            <Cube name="Sales Data">
+                <Table name="sales" />
+                <Dimension name="Geography" foreign_key="geo_id">
+                    <Hierarchy name="Geography" primary_key="geo_id">
+                        <Table name="dim_geo" />
+                        <Level name="State" key_column="state_id" name_column="state_name" key_type="text" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Year" foreign_key="year" type="time">
+                    <Hierarchy name="Year">
+                        <Level name="Year" key_column="year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Dimension name="Ship Date" foreign_key="ship_year">
+                    <Hierarchy name="Ship Date">
+                        <Level name="Year" key_column="ship_year" key_type="nontext" />
+                    </Hierarchy>
+                </Dimension>
+                <Measure name="Quantity" column="quantity" aggregator="sum" />
+                <Measure name="Orders" column="order_id" aggregator="count" />
+            </Cube>
+        </Schema>
+        "##;
+        Schema::from_xml(s).unwrap()
+    }
+
+    fn select(sql: &str) -> Select {
+        match Statement::parse(sql).unwrap() {
+            Statement::Select(select) => select,
+            other => panic!("not a select: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        assert_eq!(Statement::parse(" ; ").unwrap(), Statement::Empty);
+        assert_eq!(Statement::parse("SET extra_float_digits = 3").unwrap(), Statement::Command("SET".to_owned()));
+        assert_eq!(Statement::parse("begin").unwrap(), Statement::Command("BEGIN".to_owned()));
+        assert!(Statement::parse("DELETE FROM sales").is_err());
+        assert!(Statement::parse("SELECT * FROM sales").is_err());
+        assert!(Statement::parse("SELECT a FROM sales; SELECT b FROM sales").is_err());
+        assert!(Statement::parse("SELECT a FROM sales WHERE a > 1").is_err());
+        assert!(Statement::parse("SELECT a FROM sales WHERE a = $1").is_err());
+
+        let s = select(r#"
+            SELECT "State" AS st, sum(t."Quantity") -- total
+            FROM public."Sales Data" t
+            WHERE "Year Year" IN (2018, '2019') AND "State" <> 'It''s'
+            GROUP BY "State"
+            ORDER BY "Quantity" DESC
+            LIMIT 10 OFFSET 5;
+        "#);
+        assert_eq!(s.columns[0].alias, Some("st".to_owned()));
+        assert_eq!(s.columns[1].column, Identifier { name: "Quantity".to_owned(), quoted: true });
+        assert_eq!(s.columns[1].function, Some("sum".to_owned()));
+        assert_eq!(s.table.name, "Sales Data");
+        assert_eq!(s.conditions[0].1, vec!["2018", "2019"]);
+        assert_eq!(s.conditions[1], (Identifier { name: "State".to_owned(), quoted: true }, vec!["It's".to_owned()], true));
+        assert_eq!(s.group_by.len(), 1);
+        assert_eq!(s.order_by.as_ref().unwrap().1, SortDirection::Desc);
+        assert_eq!(s.limit, Some((10, Some(5))));
+    }
+
+    #[test]
+    fn test_cube_query() {
+        let schema = test_schema();
+        let sql = r#"
+            SELECT quantity, "State", "Ship Date Year"
+            FROM "Sales Data"
+            WHERE "Year Year" IN (2018, 2019) AND "Year Year" = 2019
+            GROUP BY "Ship Date Year", "State"
+            ORDER BY Quantity
+        "#;
+        let s = select(sql);
+        let cube = s.cube(&schema).unwrap();
+        let cube_query = s.cube_query(cube).unwrap();
+
+        assert_eq!(cube_query.cube, "Sales Data");
+        let drills: Vec<_> = cube_query.query.drilldowns.iter().map(|d| d.0.level.clone()).collect();
+        assert_eq!(drills, vec!["Year", "State"]);
+        assert_eq!(cube_query.query.cuts.len(), 1);
+        assert_eq!(cube_query.query.cuts[0].members, vec!["2019"]);
+        assert!(cube_query.query.sort.is_some());
+
+        // Ship Date Year, then State ID and State, then Quantity
+        assert_eq!(cube_query.columns, vec![
+            OutputColumn { name: "Quantity".to_owned(), type_oid: FLOAT8_OID, col_idx: 3 },
+            OutputColumn { name: "State".to_owned(), type_oid: TEXT_OID, col_idx: 2 },
+            OutputColumn { name: "Ship Date Year".to_owned(), type_oid: NUMERIC_OID, col_idx: 0 },
+        ]);
+
+        let bad = |sql: &str| select(sql).cube_query(cube).is_err();
+        assert!(bad(r#"SELECT "State" FROM "Sales Data""#));
+        assert!(bad(r#"SELECT "State", "Orders" FROM "Sales Data" GROUP BY "Year Year""#));
+        assert!(bad(r#"SELECT "Nope", "Orders" FROM "Sales Data""#));
+        assert!(bad(r#"SELECT "Orders" FROM "Sales Data" WHERE "Quantity" = 1"#));
+        assert!(bad(r#"SELECT "Orders" FROM "Sales Data" WHERE "State ID" = 'a' AND "State ID" = 'b'"#));
+        assert!(bad(r#"SELECT "State", "Orders" FROM "Sales Data" ORDER BY "State""#));
+        assert!(select(r#"SELECT "Orders" FROM "Nope""#).cube(&schema).is_err());
+
+        let unsupported = |sql: &str| {
+            select(sql).cube_query(cube).unwrap_err().downcast_ref::<Unsupported>().is_some()
+        };
+        assert!(unsupported(r#"SELECT AVG("Quantity") FROM "Sales Data""#));
+        assert!(unsupported(r#"SELECT SUM("Orders") FROM "Sales Data""#));
+        assert!(unsupported(r#"SELECT COUNT("State"), "Orders" FROM "Sales Data""#));
+        assert!(unsupported(r#"SELECT "Orders" FROM "Sales Data" WHERE "State" = 'California'"#));
+        assert!(unsupported(r#"SELECT "Orders" FROM "Sales Data" WHERE "Quantity" = 1"#));
+    }
+
+    #[test]
+    fn test_key_columns() {
+        let schema = test_schema();
+        let s = select(r#"
+            SELECT "State ID", COUNT("Orders"), "State"
+            FROM "Sales Data"
+            WHERE "State ID" IN ('CA', 'NY')
+            GROUP BY "State"
+        "#);
+        let cube = s.cube(&schema).unwrap();
+        let cube_query = s.cube_query(cube).unwrap();
+
+        assert_eq!(cube_query.query.drilldowns.len(), 1);
+        assert_eq!(cube_query.query.cuts[0].members, vec!["CA", "NY"]);
+
+        // State ID and State, then Orders
+        assert_eq!(cube_query.columns, vec![
+            OutputColumn { name: "State ID".to_owned(), type_oid: TEXT_OID, col_idx: 0 },
+            OutputColumn { name: "Orders".to_owned(), type_oid: INT8_OID, col_idx: 2 },
+            OutputColumn { name: "State".to_owned(), type_oid: TEXT_OID, col_idx: 1 },
+        ]);
+
+        // levels without a name column have no key column
+        assert!(select(r#"SELECT "Year Year ID", "Orders" FROM "Sales Data""#).cube_query(cube).is_err());
+    }
+}
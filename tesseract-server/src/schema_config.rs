@@ -1,6 +1,7 @@
 use failure::{Error, format_err};
 
 use tesseract_core::Schema;
+use tesseract_core::schema::mondrian::MondrianSchema;
 
 
 /// Reads a schema from an XML or JSON file and converts it into a `tesseract_core::Schema` object.
@@ -32,3 +33,20 @@ pub fn read_schema(schema_path: &str) -> Result<Schema, Error> {
 
     Ok(schema)
 }
+
+
+/// Converts a Mondrian schema file into a tesseract JSON schema. Returns the
+/// JSON and the warnings about Mondrian constructs that were left out.
+pub fn convert_mondrian_schema(mondrian_path: &str) -> Result<(String, Vec<String>), Error> {
+    let mondrian_str = std::fs::read_to_string(&mondrian_path)
+        .map_err(|_| format_err!("Mondrian schema file not found at {}", mondrian_path))?;
+
+    let mondrian = MondrianSchema::from_xml(&mondrian_str)?;
+    let schema_json = serde_json::to_string_pretty(&mondrian.schema)?;
+
+    // the converted schema must load like any other schema
+    Schema::from_json(&schema_json)
+        .map_err(|err| format_err!("Converted schema is invalid: {}", err))?;
+
+    Ok((schema_json, mondrian.warnings))
+}
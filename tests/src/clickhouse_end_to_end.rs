@@ -4,6 +4,8 @@ use failure::{Error, format_err};
 use log::*;
 use std::env;
 use tesseract_olap::app::{EnvVars, SchemaSource, create_app};
+use tesseract_olap::exports::{ExportConfig, ExportJobs};
+use tesseract_olap::graphql::GraphqlSchema;
 use tesseract_olap::logic_layer;
 use tesseract_olap::{schema_config, db_config};
 use std::path::Path;
@@ -134,73 +136,163 @@ static schema_str: &str = r##"
 mod tests {
     use super::*;
     use actix_web::{test, App};
+    use actix_web::{actix, client};
+    use actix_web::http::StatusCode;
+    use futures::Future;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::Once;
     use tesseract_core::schema::Schema;
+    use tesseract_olap::pgwire::{self, PgState};
     use std::time;
 
-    #[test]
-    fn test_end_to_end() {
-        let db_url_full = env::var("TESSERACT_DATABASE_URL").unwrap_or_else(|_| "clickhouse://localhost:9000".into());
+    const ADDR: &str = "127.0.0.1:7777";
+    const PG_ADDR: &str = "127.0.0.1:7778";
 
-        let (db, db_url, db_type) = db_config::get_db(&db_url_full).unwrap();
-        let schema_source = SchemaSource::LocalSchema { filepath: "blah".to_string() };
+    static SERVER: Once = Once::new();
 
-        let env_vars = EnvVars {
-            database_url: db_url.clone(),
-            geoservice_url: None,
-            schema_source,
-            jwt_secret: None,
-            flush_secret: None,
-        };
+    /// Starts the server, and its Postgres listener, once for all tests.
+    fn start_server() {
+        SERVER.call_once(|| {
+            let db_url_full = env::var("TESSERACT_DATABASE_URL").unwrap_or_else(|_| "clickhouse://localhost:9000".into());
 
-        let mut schema = Schema::from_xml(&schema_str).unwrap();
-        schema.validate().expect("failed to validate schema");
-        let mut has_unique_levels_properties = schema.has_unique_levels_properties();
-        let logic_layer_config = match logic_layer::read_config_str(&ll_config_str) {
-            Ok(config_obj) => {
-                has_unique_levels_properties = config_obj.has_unique_levels_properties(&schema).unwrap();
-                Some(config_obj)
-            },
-            Err(err) => panic!("ERROR in logic layer")
-        };
- 
-        thread::spawn(move || {
-            let mut sys = actix::System::new("tesseract");
-
-            let cache = logic_layer::populate_cache(
-                schema.clone(), &logic_layer_config, db.clone(), &mut sys
-            ).map_err(|err| format_err!("Cache population error: {}", err)).unwrap();
-            let logic_layer_config = match logic_layer_config {
-                Some(ll_config) => Some(Arc::new(RwLock::new(ll_config))),
-                None => None
+            let (db, db_url, db_type) = db_config::get_db(&db_url_full).unwrap();
+            let schema_source = SchemaSource::LocalSchema { filepath: "blah".to_string() };
+
+            let env_vars = EnvVars {
+                database_url: db_url.clone(),
+                geoservice_url: None,
+                schema_source,
+                jwt_secret: None,
+                flush_secret: None,
+                batch_concurrency: 4,
+                batch_max_queries: 10,
+            };
+
+            let mut schema = Schema::from_xml(&schema_str).unwrap();
+            schema.validate().expect("failed to validate schema");
+            let mut has_unique_levels_properties = schema.has_unique_levels_properties();
+            let logic_layer_config = match logic_layer::read_config_str(&ll_config_str) {
+                Ok(config_obj) => {
+                    has_unique_levels_properties = config_obj.has_unique_levels_properties(&schema).unwrap();
+                    Some(config_obj)
+                },
+                Err(err) => panic!("ERROR in logic layer")
             };
-            let cache_arc = Arc::new(RwLock::new(cache));
-            let schema_arc = Arc::new(RwLock::new(schema.clone()));
+ 
+            thread::spawn(move || {
+                let mut sys = actix::System::new("tesseract");
+
+                let cache = logic_layer::populate_cache(
+                    schema.clone(), &logic_layer_config, db.clone(), &mut sys
+                ).map_err(|err| format_err!("Cache population error: {}", err)).unwrap();
+                let logic_layer_config = match logic_layer_config {
+                    Some(ll_config) => Some(Arc::new(RwLock::new(ll_config))),
+                    None => None
+                };
+                let cache_arc = Arc::new(RwLock::new(cache));
+                let schema_arc = Arc::new(RwLock::new(schema.clone()));
+                let graphql_schema_arc = Arc::new(RwLock::new(GraphqlSchema::from_schema(&schema)));
+                let exports_arc = Arc::new(RwLock::new(ExportJobs::new(ExportConfig {
+                    dir: env::temp_dir().join("tesseract-exports-end-to-end"),
+                    max_running: 1,
+                    max_queued: 10,
+                    ttl: time::Duration::from_secs(60),
+                    timeout: time::Duration::from_secs(60),
+                }).unwrap()));
     
-            server::new(
-                move|| create_app(
-                    false,
-                    db.clone(),
-                    None,
-                    db_type.clone(),
-                    env_vars.clone(),
-                    schema_arc.clone(),
-                    cache_arc.clone(),
-                    logic_layer_config.clone(),
-                    false,
-                    has_unique_levels_properties.clone(),
+                pgwire::start_listener(PG_ADDR, PgState {
+                    debug: true,
+                    backend: db.clone(),
+                    schema: schema_arc.clone(),
+                    cache: cache_arc.clone(),
+                    jwt_secret: None,
+                }).unwrap();
+
+                server::new(
+                    move|| create_app(
+                        false,
+                        db.clone(),
+                        None,
+                        db_type.clone(),
+                        env_vars.clone(),
+                        schema_arc.clone(),
+                        graphql_schema_arc.clone(),
+                        cache_arc.clone(),
+                        logic_layer_config.clone(),
+                        exports_arc.clone(),
+                        false,
+                        has_unique_levels_properties.clone(),
+                    )
                 )
-            )
-            .bind("127.0.0.1:7777")
-            .expect(&format!("cannot bind to {}", 7777))
-            .start();
-            sys.run();
+                .bind(ADDR)
+                .expect(&format!("cannot bind to {}", ADDR))
+                .start();
+                sys.run();
+            });
+
+            // Sleep for 1 second to wait for server boot
+            thread::sleep(time::Duration::from_secs(1));
         });
+    }
+
+    /// Sends a request with a JSON body, or a text body if it isn't JSON,
+    /// and returns the status and body of the response.
+    fn post(path: &str, body: &str) -> (StatusCode, String) {
+        let content_type = if body.starts_with('{') || body.starts_with('[') {
+            "application/json"
+        } else {
+            "text/plain"
+        };
+        let req = client::post(format!("http://{}{}", ADDR, path))
+            .header("User-Agent", "Actix-web")
+            .content_type(content_type)
+            .body(body.to_owned())
+            .unwrap();
+
+        send(req)
+    }
+
+    fn get(path: &str) -> (StatusCode, String) {
+        let req = client::get(format!("http://{}{}", ADDR, path))
+            .header("User-Agent", "Actix-web")
+            .finish()
+            .unwrap();
+
+        send(req)
+    }
+
+    fn send(req: client::ClientRequest) -> (StatusCode, String) {
+        let mut sys = actix::System::new("test");
+        sys.block_on(
+            req.send()
+                .map_err(Error::from)
+                .and_then(|response| {
+                    let status = response.status();
+                    response.body()
+                        .map_err(Error::from)
+                        .map(move |body| (status, String::from_utf8_lossy(&body).into_owned()))
+                })
+        ).expect("Failed to get test API response")
+    }
+
+    /// The value of a string field in a JSON response.
+    fn json_str_field(body: &str, field: &str) -> String {
+        let key = format!("\"{}\":\"", field);
+        let start = body.find(&key).expect("field not in response") + key.len();
+        body[start..].split('"').next().unwrap().to_owned()
+    }
+
+    const YEAR_2017_QUERY: &str = r#"{
+        "drilldowns": [{ "dimension": "Year", "hierarchy": "Year", "level": "Year" }],
+        "cuts": [{ "level": { "dimension": "Year", "hierarchy": "Year", "level": "Year" }, "members": ["2017"] }],
+        "measures": ["Quantity"]
+    }"#;
+
+    #[test]
+    fn test_end_to_end() {
+        start_server();
 
-        // Sleep for 1 second to wait for server boot
-        thread::sleep(time::Duration::from_secs(1));
-        
-        use actix_web::{actix, client};
-        use futures::Future;
         actix::run(
             || client::get("http://127.0.0.1:7777/data?cube=Sales&drilldowns=Year&measures=Quantity&Year=2017")
                 .header("User-Agent", "Actix-web")
@@ -218,4 +310,131 @@ mod tests {
         );
     
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_batch() {
+        start_server();
+
+        let body = format!(r#"[{{ "cube": "Sales", "query": {} }}, {{ "cube": "Nope" }}]"#, YEAR_2017_QUERY);
+        let (status, res) = post("/batch", &body);
+        assert_eq!(status, 200);
+        assert!(res.contains(r#""status":200"#));
+        assert!(res.contains(r#""Quantity":266.0"#));
+        assert!(res.contains(r#""status":404"#));
+
+        // batches are limited to `batch_max_queries`
+        let queries = vec![r#"{ "cube": "Sales" }"#; 11];
+        let (status, _) = post("/batch", &format!("[{}]", queries.join(",")));
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn test_exports() {
+        start_server();
+
+        let body = format!(r#"{{ "cube": "Sales", "format": "csv", "query": {} }}"#, YEAR_2017_QUERY);
+        let (status, res) = post("/exports", &body);
+        assert_eq!(status, 202);
+        let id = json_str_field(&res, "id");
+
+        let mut job_status = String::new();
+        for _ in 0..50 {
+            let (status, res) = get(&format!("/exports/{}", id));
+            assert_eq!(status, 200);
+            job_status = json_str_field(&res, "status");
+            if job_status != "queued" && job_status != "running" {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(100));
+        }
+        assert_eq!(job_status, "done");
+
+        let (status, res) = get(&format!("/exports/{}/download", id));
+        assert_eq!(status, 200);
+        assert!(res.starts_with("Year,Quantity"));
+        assert!(res.contains("2017,266"));
+    }
+
+    #[test]
+    fn test_graphql() {
+        start_server();
+
+        let body = r#"{
+            "query": "query ($year: String!) { Sales(cuts: [{level: Year, members: [$year]}]) { Year Quantity } }",
+            "variables": { "year": "2017" }
+        }"#;
+        let (status, res) = post("/graphql", body);
+        assert_eq!(status, 200);
+        assert!(res.contains(r#""Quantity":266.0"#));
+        assert!(!res.contains("errors"));
+    }
+
+    #[test]
+    fn test_mdx() {
+        start_server();
+
+        let body = "SELECT {[Measures].[Quantity]} ON COLUMNS, {[Year].[Year].&[2017]} ON ROWS FROM [Sales]";
+        let (status, res) = post("/mdx.jsonrecords", body);
+        assert_eq!(status, 200);
+        assert!(res.contains(r#""Quantity":266.0"#));
+    }
+
+    /// Reads backend messages up to `ReadyForQuery`, and returns the data
+    /// rows, failing on error responses.
+    fn pg_read_rows(stream: &mut TcpStream) -> Vec<Vec<String>> {
+        let mut rows = vec![];
+
+        loop {
+            let mut header = [0u8; 5];
+            stream.read_exact(&mut header).unwrap();
+            let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            let mut body = vec![0u8; len - 4];
+            stream.read_exact(&mut body).unwrap();
+
+            match header[0] {
+                b'Z' => return rows,
+                b'E' => panic!("Postgres error: {}", String::from_utf8_lossy(&body)),
+                b'D' => {
+                    let n_cols = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    let mut pos = 2;
+                    let mut row = vec![];
+                    for _ in 0..n_cols {
+                        let len = i32::from_be_bytes([body[pos], body[pos + 1], body[pos + 2], body[pos + 3]]);
+                        pos += 4;
+                        let len = if len < 0 { 0 } else { len as usize };
+                        row.push(String::from_utf8_lossy(&body[pos..pos + len]).into_owned());
+                        pos += len;
+                    }
+                    rows.push(row);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    #[test]
+    fn test_pgwire() {
+        start_server();
+
+        let mut stream = TcpStream::connect(PG_ADDR).unwrap();
+
+        // startup message, with protocol version 3.0
+        let mut startup = vec![];
+        startup.extend_from_slice(&196_608i32.to_be_bytes());
+        startup.extend_from_slice(b"user\0test\0\0");
+        stream.write_all(&((startup.len() + 4) as i32).to_be_bytes()).unwrap();
+        stream.write_all(&startup).unwrap();
+        assert!(pg_read_rows(&mut stream).is_empty());
+
+        let sql = r#"SELECT "Year", SUM("Quantity") FROM "Sales" WHERE "Year" = 2017 GROUP BY "Year""#;
+        let mut query = vec![b'Q'];
+        query.extend_from_slice(&((sql.len() + 5) as i32).to_be_bytes());
+        query.extend_from_slice(sql.as_bytes());
+        query.push(0);
+        stream.write_all(&query).unwrap();
+
+        assert_eq!(pg_read_rows(&mut stream), vec![vec!["2017".to_owned(), "266".to_owned()]]);
+
+        stream.write_all(&[b'X', 0, 0, 0, 4]).unwrap();
+    }
+}